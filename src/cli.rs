@@ -1,18 +1,35 @@
-use clap::Parser;
-use std::process;
+use clap::{Parser, Subcommand};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufReader, IsTerminal, Read, Seek, Write};
+use std::process::{self, Command, Stdio};
 
-use crate::error::Result;
-use crate::repodiff::RepoDiff;
+use crate::error::{RepoDiffError, Result};
+use crate::repodiff::{RepoDiff, MANIFEST_FILE_NAME};
+use crate::utils::config_manager::{Config, ConfigManager, MethodDetail};
+use crate::utils::diff_parser::{DiffParser, OutputFormat, PathPrefixes};
 use crate::utils::git_operations::GitOperations;
+use crate::utils::token_counter::TokenCounter;
+
+/// Name of the configuration file
+const CONFIG_FILE_NAME: &str = "config.json";
 
 /// Command-line arguments for RepoDiff
 #[derive(Parser, Debug)]
 #[command(author, version = env!("CARGO_PKG_VERSION"), about, long_about = None)]
 pub struct Args {
+    /// Subcommand to run instead of generating a diff
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
     /// The file to output the combined diff
     #[arg(short, long)]
     pub output_file: Option<String>,
 
+    /// Write one diff file per changed file under this directory, mirroring the repository layout, with a manifest
+    #[arg(long, conflicts_with_all = ["output_file", "chunk_tokens"])]
+    pub output_dir: Option<String>,
+
     /// The first commit hash
     #[arg(short = 'c', long = "commit1")]
     pub commit1: Option<String>,
@@ -21,26 +38,537 @@ pub struct Args {
     #[arg(short = 'd', long = "commit2")]
     pub commit2: Option<String>,
 
-    /// Compare the latest commit on the current branch to the latest common commit with another branch
-    #[arg(short, long)]
+    /// Compare the latest commit on the current branch to the latest common commit with another branch,
+    /// or with the configured default_base_branch if no branch is given
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "")]
     pub branch: Option<String>,
 
+    /// Compare the commit given with --commit1 against the working tree, including uncommitted changes
+    #[arg(long, requires = "commit1", conflicts_with_all = ["commit2", "branch", "use_previous", "since_tag", "ranges"])]
+    pub working_tree: bool,
+
+    /// With --working-tree, also include untracked files as added files
+    #[arg(long, requires = "working_tree")]
+    pub include_untracked: bool,
+
     /// Compare the specified commit with its parent (previous) commit
     #[arg(short = 'p', long = "previous", requires = "commit1", conflicts_with_all = ["commit2", "branch"])]
     pub use_previous: bool,
+
+    /// Compare the most recent tag, optionally matching a glob such as 'v*', with the latest commit
+    #[arg(long, value_name = "PATTERN", num_args = 0..=1, default_missing_value = "*", conflicts_with_all = ["commit1", "commit2", "branch"])]
+    pub since_tag: Option<String>,
+
+    /// Commit range to compare, e.g. 'abc123..def456'; repeat to combine several ranges in one output
+    #[arg(long = "range", value_name = "A..B", value_parser = parse_range, conflicts_with_all = ["commit1", "commit2", "branch", "since_tag", "chunk_tokens", "output_dir"])]
+    pub ranges: Vec<(String, String)>,
+
+    /// Only diff this file, e.g. with --commit1 alone to see how it changed since that commit
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["diff_file", "stdin"])]
+    pub file: Option<String>,
+
+    /// Process an existing unified diff file instead of running git diff
+    #[arg(long, conflicts_with_all = ["commit1", "commit2", "branch", "since_tag", "ranges", "stdin"])]
+    pub diff_file: Option<String>,
+
+    /// Read a unified diff from stdin instead of running git diff
+    #[arg(long, conflicts_with_all = ["commit1", "commit2", "branch", "since_tag", "ranges"])]
+    pub stdin: bool,
+
+    /// Prefix of the old paths in the diff given with --diff-file or --stdin, as passed to git diff --src-prefix
+    #[arg(long, value_name = "PREFIX")]
+    pub src_prefix: Option<String>,
+
+    /// Prefix of the new paths in the diff given with --diff-file or --stdin, as passed to git diff --dst-prefix
+    #[arg(long, value_name = "PREFIX")]
+    pub dst_prefix: Option<String>,
+
+    /// The diff given with --diff-file or --stdin was made with git diff --no-prefix
+    #[arg(long, conflicts_with_all = ["src_prefix", "dst_prefix"])]
+    pub no_prefix: bool,
+
+    /// Parse the diff given with --diff-file or --stdin and report its structure, without producing output
+    #[arg(long)]
+    pub validate: bool,
+
+    /// Skip all filtering and write the parsed diff as-is, without the instructions preamble
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Group files in the output under a heading per top-level directory
+    #[arg(long)]
+    pub group_by_dir: bool,
+
+    /// Prepend git's diff --stat histogram to the output
+    #[arg(long)]
+    pub git_stat: bool,
+
+    /// Precede each file with a label such as [Added], [Deleted], [Renamed from X] or [Modified]
+    #[arg(long)]
+    pub label_changes: bool,
+
+    /// Output format, overriding the config: text, markdown, signature-map, patch-series, symbol-index or html [default: text]
+    #[arg(long, value_name = "FORMAT", value_parser = parse_output_format)]
+    pub format: Option<OutputFormat>,
+
+    /// Only include files changed by commits from authors matching this pattern
+    #[arg(long, conflicts_with_all = ["diff_file", "stdin"])]
+    pub author: Option<String>,
+
+    /// Only include files matching this glob pattern, e.g. 'src/**/*.sql'
+    #[arg(long)]
+    pub only: Option<String>,
+
+    /// Only include files of this language, e.g. 'csharp', as mapped from their extensions
+    #[arg(long, value_name = "LANGUAGE")]
+    pub lang: Option<String>,
+
+    /// Include the full body of every changed C# method, overriding the config
+    #[arg(long, conflicts_with = "signatures_only")]
+    pub method_bodies: bool,
+
+    /// Include only the signatures of changed C# methods, overriding the config
+    #[arg(long)]
+    pub signatures_only: bool,
+
+    /// Only output the full diff of one changed C# method, e.g. 'src/Orders.cs::Save'
+    #[arg(long, value_name = "FILE::NAME", value_parser = parse_method_query, conflicts_with_all = ["ranges", "output_dir", "chunk_tokens"])]
+    pub method: Option<(String, String)>,
+
+    /// Only keep the changes of a file within a range of its new lines, e.g. 'src/Foo.cs:100-200'
+    #[arg(long, value_name = "PATH:START-END", value_parser = parse_range_in_file)]
+    pub range_in_file: Option<(String, usize, usize)>,
+
+    /// Only include files whose paths are listed, one per line, in this file
+    #[arg(long)]
+    pub files_from: Option<String>,
+
+    /// Maximum number of tokens the output may use; files that don't fit are omitted
+    #[arg(long)]
+    pub max_tokens: Option<usize>,
+
+    /// Tokens reserved for the rest of the prompt, subtracted from the maximum before budgeting
+    #[arg(long)]
+    pub reserved_tokens: Option<usize>,
+
+    /// Start each hunk with a comment counting its additions and deletions and naming the C# methods it changes
+    #[arg(long)]
+    pub annotate_hunks: bool,
+
+    /// Truncate the output at a file boundary so it's at most this many bytes, ending it with a notice
+    #[arg(long, value_name = "N", conflicts_with_all = ["output_dir", "chunk_tokens"])]
+    pub max_output_bytes: Option<usize>,
+
+    /// Split the output into part files of at most this many tokens, at file boundaries
+    #[arg(long)]
+    pub chunk_tokens: Option<usize>,
+
+    /// Write a JSON summary of the processed diff (token counts, change counts, model) to this file
+    #[arg(long)]
+    pub summary_json: Option<String>,
+
+    /// List the names of unchanged files in the directories of changed files
+    #[arg(long)]
+    pub include_unchanged_files: bool,
+
+    /// Append the whitespace errors, such as trailing whitespace, that git diff --check finds in the changed files
+    #[arg(long, conflicts_with_all = ["diff_file", "stdin"])]
+    pub check_whitespace: bool,
+
+    /// Write the instructions preamble to this file instead of the top of the output
+    #[arg(long, value_name = "PATH", conflicts_with = "raw")]
+    pub instructions_file: Option<String>,
+
+    /// Append an index of the changed files, with their changed C# methods, after the diff
+    #[arg(long)]
+    pub trailing_index: bool,
+
+    /// Append a summary of the C# methods whose signatures changed, separate from body-only changes
+    #[arg(long)]
+    pub signature_changes: bool,
+
+    /// Experimental. Group C# hunks under the method they change instead of by file
+    #[arg(long)]
+    pub group_by_symbol: bool,
+
+    /// Label files whose permissions or symlink target changed, e.g. `symlink <path> now points to <target>`
+    #[arg(long)]
+    pub show_mode_changes: bool,
+
+    /// Put the static content (preamble and file list) first and the diffs after, for prompt caching
+    #[arg(long)]
+    pub cache_friendly: bool,
+
+    /// Precede each file's section with a `// FILE-ID: <id>` comment holding a stable identifier derived from its path
+    #[arg(long)]
+    pub file_ids: bool,
+
+    /// Show a tree of the changed files, marked A/D/R/M, before the diff
+    #[arg(long)]
+    pub file_tree: bool,
+
+    /// Experimental: annotate up to N removed lines (default 100) with the commit and author that last changed them
+    #[arg(long, value_name = "N", num_args = 0..=1, default_missing_value = "100", conflicts_with_all = ["diff_file", "stdin", "raw"])]
+    pub blame: Option<usize>,
+
+    /// Replace file paths with anonymized names such as file_1.cs, writing the mapping to a .paths.json sidecar
+    #[arg(long, conflicts_with_all = ["git_stat", "include_unchanged_files", "ranges"])]
+    pub anonymize_paths: bool,
+
+    /// Emit absolute paths, joined with the repository root, in the file headers of the output
+    #[arg(long, conflicts_with = "anonymize_paths")]
+    pub absolute_paths: bool,
+
+    /// Print a SHA-256 hash of the output, for skipping re-sends of an identical diff
+    #[arg(long)]
+    pub hash: bool,
+
+    /// Cache the filtered files of each diff in DIR (default: a repodiff folder in the temp directory),
+    /// so repeated runs on an unchanged diff and configuration skip the filters
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = "")]
+    pub cache: Option<String>,
+
+    /// Also show the output in $PAGER (or less) when stdout is a terminal
+    #[arg(long, conflicts_with_all = ["output_dir", "chunk_tokens"])]
+    pub pager: bool,
+
+    /// Print the effective configuration, after command-line overrides, as JSON and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Write the effective configuration, after command-line overrides, beside the output for audits
+    #[arg(long)]
+    pub write_config: bool,
+}
+
+/// Subcommands for RepoDiff
+#[derive(Subcommand, Debug)]
+pub enum Commands {
+    /// Check that git, the config file and the tokenizer model are usable
+    Doctor,
 }
 
 /// Main entry point for the CLI
 pub fn run() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    
+    if let Some(Commands::Doctor) = args.command {
+        if !run_doctor() {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    if args.validate {
+        if !run_validate(&args)? {
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    
+    let config_manager = ConfigManager::new(CONFIG_FILE_NAME)?;
+    let config = effective_config(&args, config_manager.get_config());
+    args.branch = base_branch(&args, &config)?;
+    if args.print_config {
+        println!("{}", serde_json::to_string_pretty(&config)?);
+        return Ok(());
+    }
     
     // Initialize the RepoDiff tool
-    let mut repodiff = RepoDiff::new("config.json")?;
-    let git_ops = GitOperations::new();
+    let mut repodiff = RepoDiff::from_config(&config)?;
+    repodiff.set_directory_configs(Some(config_manager.get_directory_configs().clone()));
+    repodiff.set_raw(args.raw);
+    repodiff.set_git_stat(args.git_stat);
+    repodiff.set_include_unchanged_files(args.include_unchanged_files);
+    repodiff.set_check_whitespace(args.check_whitespace);
+    repodiff.set_include_untracked(args.include_untracked);
+    repodiff.set_anonymize_paths(args.anonymize_paths);
+    repodiff.set_write_config(args.write_config);
+    repodiff.set_instructions_file(args.instructions_file.clone());
+    repodiff.set_author(args.author.clone());
+    repodiff.set_file_path(args.file.clone());
+    repodiff.set_blame(args.blame);
+    repodiff.set_trailing_index(args.trailing_index);
+    repodiff.set_signature_changes(args.signature_changes);
+    repodiff.set_group_by_symbol(args.group_by_symbol);
+    repodiff.set_show_mode_changes(args.show_mode_changes);
+    repodiff.set_path_prefixes(path_prefixes(&args));
+    if let Some(files_from) = &args.files_from {
+        repodiff.set_only_paths(Some(read_path_list(files_from)?));
+    }
+    repodiff.set_method_query(args.method.clone());
+    repodiff.set_range_in_file(args.range_in_file.clone());
+    repodiff.set_chunk_tokens(args.chunk_tokens);
+    repodiff.set_cache_dir(args.cache.as_ref().map(|dir| {
+        if dir.is_empty() { RepoDiff::get_default_cache_dir() } else { dir.clone() }
+    }));
+    if let Some(budget) = repodiff.get_effective_budget() {
+        println!("Effective token budget: {}", budget);
+    }
+    repodiff.output_options_mut().group_by_dir = args.group_by_dir;
+    repodiff.output_options_mut().label_changes = args.label_changes;
+    repodiff.output_options_mut().cache_friendly = args.cache_friendly;
+    repodiff.output_options_mut().file_ids = args.file_ids;
+    repodiff.output_options_mut().file_tree = args.file_tree;
+    if args.absolute_paths {
+        repodiff.output_options_mut().path_root = Some(GitOperations::new().get_repo_root()?);
+    }
+    
+    // Set output file or default to the user's temporary directory
+    repodiff.set_split_into_dir(args.output_dir.is_some());
+    let output_file = if let Some(output_file) = args.output_file.clone().or(args.output_dir.clone()) {
+        output_file
+    } else {
+        let default_output = match &args.branch {
+            Some(branch) => RepoDiff::get_default_output_file_for_branch(branch),
+            None => RepoDiff::get_default_output_file(),
+        };
+        println!("No output file specified. Using temporary directory: {}", default_output);
+        default_output
+    };
+    
+    // Process the diff and get the token count
+    let token_count = if let Some(diff_file) = &args.diff_file {
+        repodiff.process_diff_reader(BufReader::new(fs::File::open(diff_file)?), &output_file)?
+    } else if args.stdin {
+        // Stdin can't be read twice, so it's spooled to a temporary file in case the
+        // diff isn't in path order and must be collected
+        let mut spool = tempfile::tempfile()?;
+        io::copy(&mut io::stdin().lock(), &mut spool)?;
+        spool.rewind()?;
+        repodiff.process_diff_reader(BufReader::new(spool), &output_file)?
+    } else if !args.ranges.is_empty() {
+        repodiff.process_ranges(&args.ranges, &output_file)?
+    } else if let (true, Some(commit)) = (args.working_tree, &args.commit1) {
+        println!("Comparing commit {} with the working tree.", &commit[..12.min(commit.len())]);
+        repodiff.process_diff_against_working_tree(commit, &output_file)?
+    } else {
+        let (commit1, commit2) = resolve_commits(&args, &GitOperations::new())?;
+        repodiff.process_diff(&commit1, &commit2, &output_file)?
+    };
+    
+    for warning in repodiff.get_warnings() {
+        eprintln!("Warning: {}", warning);
+    }
     
-    // Determine the commit hashes
-    let (commit1, commit2) = if let Some(branch) = args.branch {
-        let commit1 = git_ops.get_latest_common_commit_with_branch(&branch)?;
+    // Output results
+    if args.output_dir.is_some() {
+        println!("Processed diff written to {} ({} files, see {})", output_file, repodiff.get_diff_result().files_changed, MANIFEST_FILE_NAME);
+    } else if repodiff.get_chunks().is_empty() {
+        println!("Processed diff written to {}", output_file);
+    } else {
+        println!("Processed diff written to {} parts:", repodiff.get_chunks().len());
+        for chunk in repodiff.get_chunks() {
+            println!("  {} ({} tokens, {} files)", chunk.path, chunk.tokens, chunk.files.len());
+        }
+    }
+    if let Some(instructions_file) = &args.instructions_file {
+        println!("Instructions written to {}", instructions_file);
+    }
+    if args.anonymize_paths {
+        println!("Path mapping written to {}", RepoDiff::path_map_file_name(&output_file, args.output_dir.is_some()));
+    }
+    if args.write_config {
+        println!("Resolved config written to {}", RepoDiff::resolved_config_file_name(&output_file, args.output_dir.is_some()));
+    }
+    println!("Total number of tokens: {}", token_count);
+    if let Some(cost) = repodiff.get_estimated_cost() {
+        println!("Estimated cost: ${:.4}", cost);
+    }
+    println!("Tokens excluding instructions: {}", repodiff.get_content_token_count());
+    if let Some(change_scope) = repodiff.get_diff_result().change_scope {
+        println!("Change scope: {}", change_scope);
+    }
+    if let Some(omitted) = repodiff.get_omitted_summary() {
+        println!("{}", omitted);
+    }
+    if args.hash {
+        println!("SHA-256: {}", repodiff.get_output_hash());
+    }
+    
+    if let Some(summary_file) = &args.summary_json {
+        repodiff.get_diff_result().write_json(summary_file)?;
+        println!("Summary written to {}", summary_file);
+    }
+    
+    // Paging only makes sense for a person at a terminal, so it's skipped when piped
+    if args.pager && io::stdout().is_terminal() {
+        show_in_pager(&output_file)?;
+    }
+    
+    Ok(())
+} 
+
+/// Show a file in the user's pager, like git does
+///
+/// The pager is taken from `$PAGER`, defaulting to `less`. Unless `$LESS` is set,
+/// less is told to quit when the content fits on one screen and to keep colors.
+///
+/// # Arguments
+///
+/// * `path` - The file to show
+fn show_in_pager(path: &str) -> Result<()> {
+    let content = fs::read_to_string(path)?;
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.trim().is_empty())
+        .unwrap_or_else(|| "less".to_string());
+    let mut parts = pager.split_whitespace();
+    let program = parts.next().unwrap_or("less");
+    
+    let mut command = Command::new(program);
+    command.args(parts).stdin(Stdio::piped());
+    if std::env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let mut child = command.spawn()
+        .map_err(|e| RepoDiffError::GeneralError(format!("Failed to start pager '{}': {}", pager, e)))?;
+    
+    // Quitting the pager early closes the pipe, which isn't an error
+    if let Some(mut stdin) = child.stdin.take()
+        && let Err(e) = stdin.write_all(content.as_bytes())
+        && e.kind() != io::ErrorKind::BrokenPipe
+    {
+        return Err(e.into());
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Apply the command-line overrides of config settings
+///
+/// # Arguments
+///
+/// * `args` - The parsed command-line arguments
+/// * `config` - The configuration loaded from the config file
+pub fn effective_config(args: &Args, config: &Config) -> Config {
+    let mut config = config.clone();
+    if args.max_tokens.is_some() {
+        config.max_tokens = args.max_tokens;
+    }
+    if let Some(reserved_tokens) = args.reserved_tokens {
+        config.reserved_tokens = reserved_tokens;
+    }
+    if args.annotate_hunks {
+        config.annotate_hunks = true;
+    }
+    if args.method_bodies {
+        config.method_detail = Some(MethodDetail::Bodies);
+    } else if args.signatures_only {
+        config.method_detail = Some(MethodDetail::SignaturesOnly);
+    }
+    if args.max_output_bytes.is_some() {
+        config.max_output_bytes = args.max_output_bytes;
+    }
+    if args.only.is_some() {
+        config.only_pattern = args.only.clone();
+    }
+    if args.lang.is_some() {
+        config.only_language = args.lang.clone();
+    }
+    if let Some(format) = args.format {
+        config.format = format;
+    }
+    config
+}
+
+/// Get the branch to compare against, falling back to the configured default when `--branch` has no value
+///
+/// # Arguments
+///
+/// * `args` - The parsed command-line arguments
+/// * `config` - The effective configuration
+///
+/// # Returns
+///
+/// The branch to compare against, or None if `--branch` wasn't given
+pub fn base_branch(args: &Args, config: &Config) -> Result<Option<String>> {
+    match args.branch.as_deref() {
+        Some("") => config.default_base_branch.clone()
+            .map(Some)
+            .ok_or_else(|| RepoDiffError::ConfigError(
+                "--branch was given without a branch, and no default_base_branch is configured".to_string()
+            )),
+        branch => Ok(branch.map(str::to_string)),
+    }
+}
+
+/// Read a newline-delimited list of repository-relative paths
+///
+/// Blank lines are skipped, and a leading `./` is ignored.
+///
+/// # Arguments
+///
+/// * `path` - The file holding the list
+fn read_path_list(path: &str) -> Result<HashSet<String>> {
+    let content = fs::read_to_string(path)?;
+    Ok(content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.strip_prefix("./").unwrap_or(line).to_string())
+        .collect())
+}
+
+/// Parse a commit range of the form `A..B`
+///
+/// # Arguments
+///
+/// * `range` - The range given on the command line
+fn parse_range(range: &str) -> std::result::Result<(String, String), String> {
+    match range.split_once("..") {
+        Some((commit1, commit2)) if !commit1.is_empty() && !commit2.is_empty() && !commit2.starts_with('.') => {
+            Ok((commit1.to_string(), commit2.to_string()))
+        }
+        _ => Err(format!("expected a range of the form A..B, got '{}'", range)),
+    }
+}
+
+/// Parse a `--format` value, named as in the config's `format` setting
+///
+/// # Arguments
+///
+/// * `value` - The value given on the command line
+fn parse_output_format(value: &str) -> std::result::Result<OutputFormat, String> {
+    serde_json::from_value(serde_json::Value::String(value.to_string()))
+        .map_err(|_| format!("expected text, markdown, signature-map, patch-series, symbol-index or html, got '{}'", value))
+}
+
+/// Parse a `--method` value of the form `FILE::NAME` into the file path and method name
+///
+/// # Arguments
+///
+/// * `query` - The value given on the command line
+fn parse_method_query(query: &str) -> std::result::Result<(String, String), String> {
+    match query.rsplit_once("::") {
+        Some((path, name)) if !path.is_empty() && !name.is_empty() => Ok((path.to_string(), name.to_string())),
+        _ => Err(format!("expected a method of the form FILE::NAME, got '{}'", query)),
+    }
+}
+
+/// Parse a `--range-in-file` value of the form `PATH:START-END` into the path and its first and last line
+///
+/// # Arguments
+///
+/// * `value` - The value given on the command line
+fn parse_range_in_file(value: &str) -> std::result::Result<(String, usize, usize), String> {
+    let error = || format!("expected a line range of the form PATH:START-END, got '{}'", value);
+    let (path, range) = value.rsplit_once(':').ok_or_else(error)?;
+    let (start, end) = range.split_once('-').ok_or_else(error)?;
+    match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if !path.is_empty() && start >= 1 && start <= end => Ok((path.to_string(), start, end)),
+        _ => Err(error()),
+    }
+}
+
+/// Determine the pair of commits to compare from the command-line arguments
+///
+/// # Arguments
+///
+/// * `args` - The parsed command-line arguments
+/// * `git_ops` - Git operations used to resolve branches and parent commits
+fn resolve_commits(args: &Args, git_ops: &GitOperations) -> Result<(String, String)> {
+    if let Some(branch) = &args.branch {
+        let commit1 = git_ops.get_latest_common_commit_with_branch(branch)?;
         let commit2 = git_ops.get_latest_commit()?;
         
         // Print the commits being used for the comparison
@@ -51,9 +579,22 @@ pub fn run() -> Result<()> {
             &commit2[..12.min(commit2.len())]
         );
         
-        (commit1, commit2)
-    } else if args.use_previous && args.commit1.is_some() {
-        let commit2 = args.commit1.clone().unwrap();
+        Ok((commit1, commit2))
+    } else if let Some(pattern) = &args.since_tag {
+        let pattern = (pattern != "*").then_some(pattern.as_str());
+        let commit1 = git_ops.get_latest_tag(pattern)?;
+        let commit2 = git_ops.get_latest_commit()?;
+        
+        // Print the commits being used for the comparison
+        println!(
+            "Comparing tag {} with the latest commit on the current branch ({}).",
+            commit1,
+            &commit2[..12.min(commit2.len())]
+        );
+        
+        Ok((commit1, commit2))
+    } else if let (true, Some(commit)) = (args.use_previous, &args.commit1) {
+        let commit2 = commit.clone();
         let commit1 = git_ops.get_previous_commit(&commit2)?;
         
         // Print the commits being used for the comparison
@@ -63,31 +604,134 @@ pub fn run() -> Result<()> {
             &commit1[..12.min(commit1.len())]
         );
         
-        (commit1, commit2)
-    } else {
-        if args.commit1.is_none() || args.commit2.is_none() {
-            eprintln!("You must either provide two commit hashes using --commit1 and --commit2, or use the -b option to compare against another branch, or use -p with -c to compare with the previous commit.");
-            process::exit(1);
-        }
+        Ok((commit1, commit2))
+    } else if let (Some(commit1), Some(commit2)) = (&args.commit1, &args.commit2) {
+        Ok((commit1.clone(), commit2.clone()))
+    } else if let (Some(file), Some(commit1)) = (&args.file, &args.commit1) {
+        let commit2 = git_ops.get_latest_commit()?;
         
-        (args.commit1.unwrap(), args.commit2.unwrap())
-    };
-    
-    // Set output file or default to the user's temporary directory
-    let output_file = if let Some(output_file) = args.output_file {
-        output_file
+        // Print the commits being used for the comparison
+        println!(
+            "Comparing {} at commit {} with the latest commit on the current branch ({}).",
+            file,
+            &commit1[..12.min(commit1.len())],
+            &commit2[..12.min(commit2.len())]
+        );
+        
+        Ok((commit1.clone(), commit2))
     } else {
-        let default_output = RepoDiff::get_default_output_file();
-        println!("No output file specified. Using temporary directory: {}", default_output);
-        default_output
+        eprintln!("You must either provide two commit hashes using --commit1 and --commit2, or use the -b option to compare against another branch, or use -p with -c to compare with the previous commit, or use --since-tag to compare with the latest tag, or use --file with -c to see how a file changed since a commit, or provide a diff with --diff-file or --stdin.");
+        process::exit(1);
+    }
+}
+
+/// Get the path prefixes given for a diff read from a file or stdin
+///
+/// A prefix that isn't given keeps git's default.
+///
+/// # Arguments
+///
+/// * `args` - The parsed command-line arguments
+///
+/// # Returns
+///
+/// The prefixes, or None to detect them from the diff
+fn path_prefixes(args: &Args) -> Option<PathPrefixes> {
+    if args.no_prefix {
+        return Some(PathPrefixes::none());
+    }
+    if args.src_prefix.is_none() && args.dst_prefix.is_none() {
+        return None;
+    }
+    let default = PathPrefixes::default();
+    Some(PathPrefixes {
+        src: args.src_prefix.clone().unwrap_or(default.src),
+        dst: args.dst_prefix.clone().unwrap_or(default.dst),
+    })
+}
+
+/// Parse the diff given with --diff-file or --stdin and print a report of its structure
+///
+/// # Arguments
+///
+/// * `args` - The parsed command-line arguments
+///
+/// # Returns
+///
+/// True if the diff parsed without problems
+fn run_validate(args: &Args) -> Result<bool> {
+    let raw_diff = if let Some(diff_file) = &args.diff_file {
+        fs::read_to_string(diff_file)?
+    } else if args.stdin {
+        let mut raw_diff = String::new();
+        io::stdin().read_to_string(&mut raw_diff)?;
+        raw_diff
+    } else {
+        eprintln!("--validate needs a diff to check, given with --diff-file or --stdin.");
+        process::exit(1);
     };
     
-    // Process the diff and get the token count
-    let token_count = repodiff.process_diff(&commit1, &commit2, &output_file)?;
+    match DiffParser::validate_diff(&raw_diff) {
+        Ok(structure) => {
+            println!("{}, parse OK", structure);
+            Ok(true)
+        }
+        Err(e) => {
+            println!("{}", e);
+            Ok(false)
+        }
+    }
+}
+
+/// Check the environment and print a report
+///
+/// # Returns
+///
+/// True if all checks passed
+fn run_doctor() -> bool {
+    let mut all_ok = true;
     
-    // Output results
-    println!("Processed diff written to {}", output_file);
-    println!("Total number of tokens: {}", token_count);
+    // Git must be installed
+    all_ok &= report_check("git", GitOperations::new().git_version());
     
-    Ok(())
-} 
\ No newline at end of file
+    // The config file must be valid, and its model must be loadable
+    match ConfigManager::new(CONFIG_FILE_NAME) {
+        Ok(config_manager) => {
+            let config_result = config_manager.validate().map(|_| {
+                match config_manager.get_config_path() {
+                    Some(path) => format!("{} is valid", path.display()),
+                    None => format!("{} not found, using defaults", CONFIG_FILE_NAME),
+                }
+            });
+            all_ok &= report_check("config", config_result);
+            
+            let model = config_manager.get_tiktoken_model();
+            let tokenizer_result = TokenCounter::new(model).map(|_| format!("model '{}' loaded", model));
+            all_ok &= report_check("tokenizer", tokenizer_result);
+        }
+        Err(e) => {
+            all_ok &= report_check("config", Err(e));
+        }
+    }
+    
+    all_ok
+}
+
+/// Print the result of a single doctor check
+///
+/// # Arguments
+///
+/// * `name` - The name of the check
+/// * `result` - The check's outcome, with a description on success
+fn report_check(name: &str, result: Result<String>) -> bool {
+    match result {
+        Ok(message) => {
+            println!("\x1b[32m[ OK ]\x1b[0m {}: {}", name, message);
+            true
+        }
+        Err(e) => {
+            println!("\x1b[31m[FAIL]\x1b[0m {}: {}", name, e);
+            false
+        }
+    }
+}
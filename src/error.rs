@@ -19,6 +19,14 @@ pub enum RepoDiffError {
     #[error("Regex error: {0}")]
     RegexError(#[from] regex::Error),
 
+    /// Error in the configuration
+    #[error("Config error: {0}")]
+    ConfigError(String),
+
+    /// Error loading a tree-sitter grammar or parsing source code
+    #[error("Parse error: {0}")]
+    ParseError(String),
+
     /// Error with tiktoken
     #[error("Tiktoken error: {0}")]
     TiktokenError(String),
@@ -1,9 +1,14 @@
 use tree_sitter::{Parser, Node};
-use crate::utils::diff_parser::Hunk;
+use crate::error::{RepoDiffError, Result};
+use crate::filters::language_parser::LanguageParser;
+use crate::utils::config_manager::DEFAULT_CSHARP_MAX_DEPTH;
+use crate::utils::diff_parser::{DiffSide, Hunk};
 
 /// Represents a C# method in the code
 #[derive(Debug, PartialEq)]
 pub struct CSharpMethod {
+    /// Name of the method; accessors are named `Property.get`, `Property.set`, etc.
+    pub name: String,
     /// Start line of the method (1-indexed)
     pub start_line: usize,
     /// End line of the method (1-indexed)
@@ -16,6 +21,17 @@ pub struct CSharpMethod {
     pub has_changes: bool,
 }
 
+/// Represents a field declaration in the code
+#[derive(Debug, Clone)]
+pub struct CSharpField {
+    /// Names of the fields declared, e.g. both `a` and `b` in `int a, b;`
+    pub names: Vec<String>,
+    /// Start line of the declaration (1-indexed)
+    pub start_line: usize,
+    /// End line of the declaration (1-indexed)
+    pub end_line: usize,
+}
+
 /// Represents a C# file in the code
 #[derive(Debug)]
 pub struct CSharpFile {
@@ -27,19 +43,47 @@ pub struct CSharpFile {
     pub class_declarations: Vec<(usize, usize)>, // (start_line, end_line)
     /// Namespace declarations in the file
     pub namespace_declarations: Vec<(usize, usize)>, // (start_line, end_line)
+    /// Field declarations in the file
+    pub fields: Vec<CSharpField>,
 }
 
 /// Parser for C# code that extracts method information
 pub struct CSharpParser {
     parser: Parser,
+    /// Nodes nested deeper than this below the root are not searched for declarations
+    max_depth: usize,
+}
+
+impl Default for CSharpParser {
+    /// Create a new C# parser
+    ///
+    /// # Panics
+    ///
+    /// Panics if the bundled C# grammar can't be loaded; use `CSharpParser::new` to handle that case
+    fn default() -> Self {
+        Self::new().expect("Error loading C# grammar")
+    }
 }
 
 impl CSharpParser {
     /// Create a new C# parser
-    pub fn new() -> Self {
+    pub fn new() -> Result<Self> {
         let mut parser = Parser::new();
-        parser.set_language(tree_sitter_c_sharp::language()).expect("Error loading C# grammar");
-        CSharpParser { parser }
+        parser.set_language(tree_sitter_c_sharp::language())
+            .map_err(|e| RepoDiffError::ParseError(format!("Error loading C# grammar: {}", e)))?;
+        Ok(CSharpParser { parser, max_depth: DEFAULT_CSHARP_MAX_DEPTH })
+    }
+
+    /// Set the maximum depth of the syntax tree searched for declarations
+    ///
+    /// Declarations nested deeper than this, such as methods of deeply nested types,
+    /// are not found. This bounds the work done on pathologically nested code.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_depth` - The maximum depth below the root of the syntax tree
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
     }
 
     /// Parse C# code and extract method information
@@ -48,8 +92,20 @@ impl CSharpParser {
     ///
     /// * `code` - The C# code to parse
     /// * `hunks` - The diff hunks to identify changed methods
-    pub fn parse_file(&mut self, code: &str, hunks: &[Hunk]) -> CSharpFile {
-        let tree = self.parser.parse(code, None).expect("Failed to parse C# code");
+    pub fn parse_file(&mut self, code: &str, hunks: &[Hunk]) -> Result<CSharpFile> {
+        self.parse_file_side(code, hunks, DiffSide::New)
+    }
+
+    /// Parse one side of a changed C# file and extract method information
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The C# code of the given side of the diff
+    /// * `hunks` - The diff hunks to identify changed methods
+    /// * `side` - The side of the diff the code comes from, whose line numbers are matched against the hunks
+    pub fn parse_file_side(&mut self, code: &str, hunks: &[Hunk], side: DiffSide) -> Result<CSharpFile> {
+        let tree = self.parser.parse(code, None)
+            .ok_or_else(|| RepoDiffError::ParseError("Failed to parse C# code".to_string()))?;
         let root_node = tree.root_node();
         
         let mut file = CSharpFile {
@@ -57,27 +113,92 @@ impl CSharpParser {
             using_statements: Vec::new(),
             class_declarations: Vec::new(),
             namespace_declarations: Vec::new(),
+            fields: Vec::new(),
         };
 
         self.find_nodes(root_node, code, &mut file);
         
         // Mark methods that contain changes or have changes in their body
         for method in &mut file.methods {
-            method.has_changes = self.method_contains_changes(method, hunks);
+            method.has_changes = Self::lines_contain_changes_on_side(method.start_line, method.end_line, hunks, side);
         }
         
-        file
+        Ok(file)
     }
     
-    /// Find all method declarations in the AST
-    fn find_nodes(&self, node: Node, code: &str, file: &mut CSharpFile) {
+    /// Get the text of a node's `name` field
+    ///
+    /// Names are taken from the node's text rather than by slicing source lines,
+    /// so non-ASCII identifiers are always split on character boundaries.
+    fn node_name(node: Node, code: &str) -> String {
+        node.child_by_field_name("name")
+            .and_then(|n| n.utf8_text(code.as_bytes()).ok())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Get the keyword (`get`, `set`, `init`, ...) of an accessor declaration
+    fn accessor_keyword(node: Node, code: &str) -> String {
+        let mut cursor = node.walk();
+        node.children(&mut cursor)
+            .find(|child| matches!(child.kind(), "get" | "set" | "init" | "add" | "remove" | "identifier"))
+            .and_then(|child| child.utf8_text(code.as_bytes()).ok())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Collect the names declared by the variable declarators below a node
+    fn collect_declarator_names(node: Node, code: &str, names: &mut Vec<String>) {
+        // An explicit stack keeps deeply nested initializers from overflowing the call stack
+        let mut cursor = node.walk();
+        let mut stack: Vec<Node> = node.children(&mut cursor).collect();
+        stack.reverse();
+        while let Some(child) = stack.pop() {
+            if child.kind() == "variable_declarator" {
+                let mut declarator_cursor = child.walk();
+                let name = child.children(&mut declarator_cursor)
+                    .find(|n| n.kind() == "identifier")
+                    .and_then(|n| n.utf8_text(code.as_bytes()).ok());
+                names.extend(name.map(|name| name.to_string()));
+            } else {
+                let mut child_cursor = child.walk();
+                let len = stack.len();
+                stack.extend(child.children(&mut child_cursor));
+                stack[len..].reverse();
+            }
+        }
+    }
+
+    /// Find all declarations in the AST, in source order
+    ///
+    /// The tree is walked with an explicit stack rather than recursion, so adversarially
+    /// nested code can't overflow the call stack, and nodes deeper than the maximum
+    /// depth are skipped.
+    fn find_nodes(&self, root: Node, code: &str, file: &mut CSharpFile) {
+        let mut stack = vec![(root, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            Self::visit_node(node, code, file);
+            
+            if depth < self.max_depth {
+                // Children are pushed in reverse so they're visited in source order
+                let mut cursor = node.walk();
+                let len = stack.len();
+                stack.extend(node.children(&mut cursor).map(|child| (child, depth + 1)));
+                stack[len..].reverse();
+            }
+        }
+    }
+
+    /// Record the declaration a single node represents, if any
+    fn visit_node(node: Node, code: &str, file: &mut CSharpFile) {
         match node.kind() {
             "method_declaration" => {
                 let start_line = node.start_position().row + 1;
                 let end_line = node.end_position().row + 1;
                 
-                // Find the signature line by looking for the first child that's a method header
-                let signature_line = node.child_by_field_name("header")
+                // The signature is on the line declaring the method name, which
+                // follows any attribute lines
+                let signature_line = node.child_by_field_name("name")
                     .map(|n| n.start_position().row + 1)
                     .unwrap_or(start_line);
                 
@@ -86,6 +207,7 @@ impl CSharpParser {
                     .to_string();
                 
                 file.methods.push(CSharpMethod {
+                    name: Self::node_name(node, code),
                     start_line,
                     end_line,
                     signature_line,
@@ -97,6 +219,7 @@ impl CSharpParser {
                 let start_line = node.start_position().row + 1;
                 let end_line = node.end_position().row + 1;
                 let signature_line = start_line;
+                let name = Self::node_name(node, code);
 
                 // Check if this is an arrow expression property (=>)
                 let is_arrow_expr = node.child_by_field_name("value")
@@ -110,6 +233,7 @@ impl CSharpParser {
                         .to_string();
                     
                     file.methods.push(CSharpMethod {
+                        name,
                         start_line,
                         end_line,
                         signature_line,
@@ -123,6 +247,7 @@ impl CSharpParser {
                         .to_string();
                     
                     file.methods.push(CSharpMethod {
+                        name: name.clone(),
                         start_line,
                         end_line,
                         signature_line,
@@ -130,9 +255,10 @@ impl CSharpParser {
                         has_changes: false,
                     });
 
-                    // Then look for accessors within the property
-                    let mut cursor: tree_sitter::TreeCursor<'_> = node.walk();
-                    for child in node.children(&mut cursor) {
+                    // Then look for accessors within the property's accessor list
+                    let accessors = node.child_by_field_name("accessors").unwrap_or(node);
+                    let mut cursor: tree_sitter::TreeCursor<'_> = accessors.walk();
+                    for child in accessors.children(&mut cursor) {
                         if child.kind() == "accessor_declaration" {
                             let accessor_start = child.start_position().row + 1;
                             let accessor_end = child.end_position().row + 1;
@@ -141,6 +267,7 @@ impl CSharpParser {
                                 .to_string();
                             
                             file.methods.push(CSharpMethod {
+                                name: format!("{}.{}", name, Self::accessor_keyword(child, code)),
                                 start_line: accessor_start,
                                 end_line: accessor_end,
                                 signature_line: accessor_start,
@@ -166,55 +293,71 @@ impl CSharpParser {
                 let end_line = node.end_position().row + 1;
                 file.class_declarations.push((start_line, end_line));
             },
+            "field_declaration" => {
+                let mut names = Vec::new();
+                Self::collect_declarator_names(node, code, &mut names);
+                file.fields.push(CSharpField {
+                    names,
+                    start_line: node.start_position().row + 1,
+                    end_line: node.end_position().row + 1,
+                });
+            },
             _ => {}
         }
-        
-        let mut cursor = node.walk();
-        for child in node.children(&mut cursor) {
-            self.find_nodes(child, code, file);
-        }
-    }
-
-    /// Check if a method contains any changes from the diff hunks
-    fn method_contains_changes(&self, method: &CSharpMethod, hunks: &[Hunk]) -> bool {
-        for hunk in hunks {
-            let mut current_line = hunk.new_start;
-            
-            // Check if any line in the hunk is within this method's body
-            for line in &hunk.lines {
-                if current_line >= method.start_line && current_line <= method.end_line {
-                    // If it's a change line (+ or -) within the method body, mark the method as changed
-                    if line.starts_with('+') || line.starts_with('-') {
-                        return true;
-                    }
-                }
-                
-                // Only increment line count for non-deletion lines
-                if !line.starts_with('-') {
-                    current_line += 1;
-                }
-            }
-        }
-        false
     }
 
     /// Check if a node contains any changes from the diff hunks
     pub fn node_contains_changes(&self, start_line: usize, end_line: usize, hunks: &[Hunk]) -> bool {
+        Self::lines_contain_changes(start_line, end_line, hunks)
+    }
+
+    /// Check if a range of lines in the new file contains any changes from the diff hunks
+    ///
+    /// # Arguments
+    ///
+    /// * `start_line` - First line of the range (1-indexed)
+    /// * `end_line` - Last line of the range (1-indexed, inclusive)
+    /// * `hunks` - The diff hunks to check
+    pub fn lines_contain_changes(start_line: usize, end_line: usize, hunks: &[Hunk]) -> bool {
+        Self::lines_contain_changes_on_side(start_line, end_line, hunks, DiffSide::New)
+    }
+
+    /// Check if a range of lines on one side of the diff contains any changes from the diff hunks
+    ///
+    /// Changed lines of the other side are counted at the position where they would be.
+    ///
+    /// # Arguments
+    ///
+    /// * `start_line` - First line of the range (1-indexed)
+    /// * `end_line` - Last line of the range (1-indexed, inclusive)
+    /// * `hunks` - The diff hunks to check
+    /// * `side` - The side of the diff the line numbers refer to
+    pub fn lines_contain_changes_on_side(start_line: usize, end_line: usize, hunks: &[Hunk], side: DiffSide) -> bool {
         for hunk in hunks {
-            let mut current_line = hunk.new_start;
+            let mut current_line = side.start(hunk);
             
             for line in &hunk.lines {
-                if current_line >= start_line && current_line <= end_line {
-                    if line.starts_with('+') || line.starts_with('-') {
-                        return true;
-                    }
+                if current_line >= start_line
+                    && current_line <= end_line
+                    && (line.starts_with('+') || line.starts_with('-'))
+                {
+                    return true;
                 }
                 
-                if !line.starts_with('-') {
+                if side.contains(line) {
                     current_line += 1;
                 }
             }
         }
         false
     }
-} 
\ No newline at end of file
+}
+
+impl LanguageParser for CSharpParser {
+    fn changed_symbols(&mut self, code: &str, hunks: &[Hunk], side: DiffSide) -> Result<Vec<String>> {
+        Ok(self.parse_file_side(code, hunks, side)?.methods.into_iter()
+            .filter(|method| method.has_changes)
+            .map(|method| method.name)
+            .collect())
+    }
+}
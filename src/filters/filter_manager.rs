@@ -1,16 +1,115 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use fnmatch_regex::glob_to_regex;
-use crate::utils::config_manager::FilterRule;
-use crate::utils::diff_parser::Hunk;
-use crate::filters::csharp_parser::{CSharpParser, CSharpMethod};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::error::{RepoDiffError, Result};
+use crate::utils::config_manager::{AccessorGranularity, Config, FilterRule, MethodDetail};
+use crate::utils::diff_parser::{DiffParser, DiffSide, Hunk};
+use crate::utils::git_operations::FULL_FILE_CONTEXT;
+use crate::utils::token_counter::TokenCounter;
+use crate::filters::csharp_parser::{CSharpFile, CSharpParser, CSharpMethod};
+use crate::filters::language_parser::LanguageParser;
+use crate::filters::moved_methods::MovedMethodDetector;
+use crate::filters::python_parser::PythonParser;
+use crate::filters::razor_parser::RazorParser;
 use serde_json;
 
+/// Extra context lines requested from git beyond the largest configured value
+pub const UNIFIED_CONTEXT_BUFFER: usize = 3;
+
+/// Maximum number of files named in the summary of a deduplicated hunk before the rest are counted
+const DEDUPE_LISTED_FILES: usize = 10;
+
+/// Why a file was left out of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum OmitReason {
+    /// The file matched a negated (`!`) pattern
+    Excluded,
+    /// The file's processed diff had more lines than `max_file_lines`
+    OverSize,
+    /// Including the file would have exceeded the token budget
+    OverBudget,
+    /// Including the file would have exceeded the output size limit
+    OverOutputLimit,
+}
+
+impl fmt::Display for OmitReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OmitReason::Excluded => write!(f, "excluded"),
+            OmitReason::OverSize => write!(f, "over size"),
+            OmitReason::OverBudget => write!(f, "over budget"),
+            OmitReason::OverOutputLimit => write!(f, "over output limit"),
+        }
+    }
+}
+
+/// How a changed C# method differs between the old and new side of a diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MethodChange {
+    /// The signature line changed, e.g. its parameters, return type or visibility
+    SignatureChanged {
+        name: String,
+        old_signature: String,
+        new_signature: String,
+    },
+    /// Only the body changed
+    BodyOnly { name: String },
+    /// The method only exists on the new side
+    Added { name: String, signature: String },
+    /// The method only exists on the old side
+    Removed { name: String, signature: String },
+}
+
 /// Manages file pattern filters for controlling context lines in git diffs
 pub struct FilterManager {
     /// List of filter rules
     filters: Vec<FilterRule>,
-    /// C# parser
-    csharp_parser: CSharpParser,
+    /// C# parser, or None if the C# grammar could not be loaded
+    csharp_parser: Option<CSharpParser>,
+    /// Scanner for the C# code blocks of Razor files
+    razor_parser: RazorParser,
+    /// Scanner for the top-level functions and classes of Python files
+    python_parser: PythonParser,
+    /// Glob pattern identifying test files
+    test_file_pattern: Option<String>,
+    /// Context lines to use for test files instead of the matched rule's value
+    test_context_lines: Option<usize>,
+    /// Maps file extensions to languages, deciding which parser handles a file
+    extension_language_map: HashMap<String, String>,
+    /// Whether to collapse runs of blank context lines into a single blank line
+    collapse_blank_runs: bool,
+    /// Whether to collapse C# methods moved within a file
+    detect_moved_methods: bool,
+    /// Override of the method settings of the rules matching C# files
+    method_detail: Option<MethodDetail>,
+    /// Whether to replace repeated identical hunks with a reference to their first file
+    dedupe_identical_hunks: bool,
+    /// Whether the first occurrence of a deduplicated hunk lists every file sharing it
+    dedupe_occurrence_counts: bool,
+    /// Filter rules from directory configs, keyed by directory, which replace the root rules for files under them
+    directory_filters: HashMap<String, Vec<FilterRule>>,
+    /// Changed lines whose content matches any of these are treated as unchanged
+    ignore_line_patterns: Vec<Regex>,
+    /// Token counter used to annotate changed C# methods with their size, if enabled
+    method_token_counter: Option<TokenCounter>,
+    /// Whether to prefix the body lines of expanded C# methods with their offset from the signature
+    method_line_offsets: bool,
+    /// Whether to start each hunk with a comment describing its changes
+    annotate_hunks: bool,
+    /// Files whose processed diff has more lines than this are omitted
+    max_file_lines: Option<usize>,
+    /// Files left out of the last processed diff, with the reason for each
+    omitted_files: Vec<(String, OmitReason)>,
+    /// Files of the last processed diff matched by an opaque rule, whose content is hidden
+    opaque_files: HashSet<String>,
+    /// Problems met while processing the last diff, such as files that couldn't be parsed
+    warnings: Vec<String>,
+    /// Whether the rules are chosen for the predominant language of each diff, as none are configured
+    auto_tune: bool,
+    /// Number of files run through the filter rules since this manager was created
+    filtered_file_count: usize,
 }
 
 impl FilterManager {
@@ -21,43 +120,316 @@ impl FilterManager {
     /// * `filters` - List of filter dictionaries with 'file_pattern' and 'context_lines' keys
     pub fn new(filters: &[FilterRule]) -> Self {
         let filters = if filters.is_empty() {
-            vec![FilterRule {
-                file_pattern: "*".to_string(),
-                context_lines: 3,
-                include_method_body: false,
-                include_signatures: false,
-            }]
+            vec![FilterRule::default()]
         } else {
             filters.to_vec()
         };
         
         FilterManager { 
             filters,
-            csharp_parser: CSharpParser::new(),
+            csharp_parser: CSharpParser::new().ok(),
+            razor_parser: RazorParser,
+            python_parser: PythonParser,
+            test_file_pattern: None,
+            test_context_lines: None,
+            extension_language_map: HashMap::new(),
+            collapse_blank_runs: false,
+            detect_moved_methods: false,
+            method_detail: None,
+            dedupe_identical_hunks: false,
+            dedupe_occurrence_counts: false,
+            directory_filters: HashMap::new(),
+            ignore_line_patterns: Vec::new(),
+            method_token_counter: None,
+            method_line_offsets: false,
+            annotate_hunks: false,
+            max_file_lines: None,
+            omitted_files: Vec::new(),
+            opaque_files: HashSet::new(),
+            warnings: Vec::new(),
+            auto_tune: false,
+            filtered_file_count: 0,
+        }
+    }
+
+    /// Initialize the FilterManager from a full configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration holding the filter rules and test file settings
+    ///
+    /// # Errors
+    ///
+    /// Returns a `ConfigError` if an `ignore_line_patterns` entry isn't a valid regular expression
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut filter_manager = Self::new(&config.filters);
+        filter_manager.auto_tune = config.auto_tune_filters && config.filters.is_empty();
+        filter_manager.test_file_pattern = config.test_file_pattern.clone();
+        filter_manager.test_context_lines = config.test_context_lines;
+        filter_manager.extension_language_map = config.extension_language_map.clone();
+        filter_manager.collapse_blank_runs = config.collapse_blank_runs;
+        filter_manager.detect_moved_methods = config.detect_moved_methods;
+        filter_manager.dedupe_identical_hunks = config.dedupe_identical_hunks;
+        filter_manager.dedupe_occurrence_counts = config.dedupe_occurrence_counts;
+        filter_manager.ignore_line_patterns = config.ignore_line_patterns.iter()
+            .map(|pattern| Regex::new(pattern).map_err(|e| {
+                RepoDiffError::ConfigError(format!("Invalid ignore_line_patterns entry '{}': {}", pattern, e))
+            }))
+            .collect::<Result<_>>()?;
+        if config.annotate_method_tokens {
+            filter_manager.method_token_counter = TokenCounter::new(&config.tiktoken_model).ok();
+        }
+        filter_manager.method_line_offsets = config.method_line_offsets;
+        filter_manager.annotate_hunks = config.annotate_hunks;
+        filter_manager.max_file_lines = config.max_file_lines;
+        filter_manager.method_detail = config.method_detail;
+        if let Some(csharp_parser) = filter_manager.csharp_parser.as_mut() {
+            csharp_parser.set_max_depth(config.csharp_max_depth);
+        }
+        Ok(filter_manager)
+    }
+
+    /// Get the number of context lines git diff needs to produce for these filters
+    ///
+    /// C# method-aware rules reconstruct methods from the diff, and change-ratio rules
+    /// may emit the whole file, so they need it from git. Otherwise the largest configured context plus a small buffer is enough.
+    /// Directory config rules count too, so they must be set before git runs. Moved methods
+    /// are found by parsing both sides of each C# file, which also needs the whole file.
+    pub fn required_unified_context(&self) -> usize {
+        if self.method_detail.is_some() || self.detect_moved_methods {
+            return FULL_FILE_CONTEXT;
+        }
+        
+        let include_rules = self.filters.iter()
+            .chain(self.directory_filters.values().flatten())
+            .filter(|rule| !rule.file_pattern.starts_with('!'));
+        if include_rules.clone().any(|rule| {
+            rule.include_method_body || rule.include_signatures || rule.full_file_over_change_ratio.is_some()
+        }) {
+            return FULL_FILE_CONTEXT;
+        }
+        
+        // Unmatched files fall back to the default rule
+        let max_context = include_rules
+            .map(|rule| rule.context_lines)
+            .chain(self.test_context_lines)
+            .chain(std::iter::once(FilterRule::default().context_lines))
+            .max()
+            .unwrap_or_default();
+        max_context + UNIFIED_CONTEXT_BUFFER
+    }
+
+    /// Check whether the rules are chosen for the language of each diff
+    pub fn auto_tunes_filters(&self) -> bool {
+        self.auto_tune
+    }
+
+    /// Choose the rules for the predominant language of a diff, if no rules are configured
+    ///
+    /// The language is the one most of the files are written in, by extension, with
+    /// ties going to the alphabetically first language.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The paths of the changed files
+    ///
+    /// # Returns
+    ///
+    /// The detected language, or None if the rules aren't auto-tuned or no file has a known language
+    pub fn auto_tune_filters<'a>(&mut self, paths: impl IntoIterator<Item = &'a str>) -> Option<String> {
+        if !self.auto_tune {
+            return None;
+        }
+        
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for path in paths {
+            if let Some(language) = DiffParser::language_for_path_with_map(path, &self.extension_language_map) {
+                *counts.entry(language).or_default() += 1;
+            }
+        }
+        let language = counts.into_iter()
+            .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+            .map(|(language, _)| language.to_string());
+        self.filters = Self::language_filters(language.as_deref());
+        language
+    }
+
+    /// Get the default filter rules for a language
+    ///
+    /// Only C# and Razor have parsers that can show whole methods, so other languages
+    /// use the plain default rule.
+    ///
+    /// # Arguments
+    ///
+    /// * `language` - The language, e.g. `csharp`, or None if unknown
+    pub fn language_filters(language: Option<&str>) -> Vec<FilterRule> {
+        match language {
+            Some("csharp" | "razor") => vec![FilterRule {
+                include_method_body: true,
+                ..Default::default()
+            }],
+            _ => vec![FilterRule::default()],
+        }
+    }
+
+    /// Override `include_method_body` and `include_signatures` for all C# files
+    ///
+    /// # Arguments
+    ///
+    /// * `method_detail` - The method detail to use, or None to use the configured rules
+    pub fn set_method_detail(&mut self, method_detail: Option<MethodDetail>) {
+        self.method_detail = method_detail;
+    }
+
+    /// Get the override of the method settings for all C# files, if any
+    pub fn get_method_detail(&self) -> Option<MethodDetail> {
+        self.method_detail
+    }
+
+    /// Check whether a filename matches a glob pattern
+    ///
+    /// Patterns are matched against the whole path. Patterns without a `/` are also
+    /// matched against the file's basename, which lets extensionless files such as
+    /// `Makefile` or `Dockerfile` be targeted by name.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The glob pattern to match against
+    /// * `filename` - The filename to check
+    pub fn matches_pattern(pattern: &str, filename: &str) -> bool {
+        let Ok(regex) = glob_to_regex(pattern) else {
+            return false;
+        };
+        
+        // A pattern without a directory also matches the basename, so `Dockerfile`
+        // matches `docker/Dockerfile`
+        regex.is_match(filename)
+            || (!pattern.contains('/') && filename.rsplit('/').next().is_some_and(|name| regex.is_match(name)))
+    }
+
+    /// Check whether a filename matches the configured test file pattern
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The filename to check
+    pub fn is_test_file(&self, filename: &str) -> bool {
+        self.test_file_pattern
+            .as_deref()
+            .is_some_and(|pattern| Self::matches_pattern(pattern, filename))
+    }
+    
+    /// Check whether a test file pattern is configured, so test files can be told apart
+    pub fn has_test_file_pattern(&self) -> bool {
+        self.test_file_pattern.is_some()
+    }
+    
+    /// Check whether a file is handled by the C# parser
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The filename to check
+    fn is_csharp(&self, filename: &str) -> bool {
+        DiffParser::language_for_path_with_map(filename, &self.extension_language_map) == Some("csharp")
+    }
+    
+    /// Check whether a file is handled by the Razor parser
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The filename to check
+    fn is_razor(&self, filename: &str) -> bool {
+        DiffParser::language_for_path_with_map(filename, &self.extension_language_map) == Some("razor")
+    }
+    
+    /// Use the filter rules of directory configs for the files under those directories
+    ///
+    /// # Arguments
+    ///
+    /// * `directory_filters` - The filter rules of each directory with a config, keyed by its path, e.g. `services/billing`
+    pub fn set_directory_filters(&mut self, directory_filters: HashMap<String, Vec<FilterRule>>) {
+        self.directory_filters = directory_filters;
+    }
+    
+    /// Get the filter rules that apply to a file
+    ///
+    /// The rules of the nearest directory config above the file win over the root rules.
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The path of the file
+    ///
+    /// # Returns
+    ///
+    /// The rules, and the file's path relative to the directory they were configured in
+    fn filters_for<'a>(&'a self, filename: &'a str) -> (&'a [FilterRule], &'a str) {
+        let mut dir = filename;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            dir = parent;
+            if let Some(filters) = self.directory_filters.get(dir) {
+                return (filters, &filename[dir.len() + 1..]);
+            }
         }
+        (&self.filters, filename)
     }
     
-    /// Find the first matching filter rule for a filename
+    /// Check whether a file's content is hidden by an opaque rule
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The path of the file
+    pub fn is_opaque(&self, filename: &str) -> bool {
+        self.find_matching_rule(filename).is_some_and(|rule| rule.opaque)
+    }
+    
+    /// Find the filter rule to apply to a filename
+    ///
+    /// Rules are evaluated in order. A rule whose pattern starts with `!` excludes
+    /// matching files, and the last matching rule decides whether a file is included,
+    /// so a later include can re-include a previously excluded file. For included files,
+    /// the first matching include rule supplies the settings.
     ///
     /// # Arguments
     ///
     /// * `filename` - The filename to match against filter patterns
-    fn find_matching_rule(&self, filename: &str) -> FilterRule {
-        for filter_rule in &self.filters {
-            if let Ok(pattern) = glob_to_regex(&filter_rule.file_pattern) {
-                if pattern.is_match(filename) {
-                    return filter_rule.clone();
+    ///
+    /// # Returns
+    ///
+    /// The rule to apply, or None if the file is excluded
+    fn find_matching_rule(&self, filename: &str) -> Option<FilterRule> {
+        let mut matched_rule = None;
+        let mut included = true;
+        
+        let (filters, relative_path) = self.filters_for(filename);
+        for filter_rule in filters {
+            if let Some(negated) = filter_rule.file_pattern.strip_prefix('!') {
+                if Self::matches_pattern(negated, relative_path) {
+                    included = false;
+                }
+            } else if Self::matches_pattern(&filter_rule.file_pattern, relative_path) {
+                included = true;
+                if matched_rule.is_none() {
+                    matched_rule = Some(filter_rule.clone());
                 }
             }
         }
         
+        if !included {
+            return None;
+        }
+        
         // Default rule
-        FilterRule {
-            file_pattern: "*".to_string(),
-            context_lines: 3,
-            include_method_body: false,
-            include_signatures: false,
+        let mut rule = matched_rule.unwrap_or_default();
+        if self.is_csharp(filename) {
+            match self.method_detail {
+                Some(MethodDetail::Bodies) => rule.include_method_body = true,
+                Some(MethodDetail::SignaturesOnly) => {
+                    rule.include_method_body = false;
+                    rule.include_signatures = true;
+                }
+                None => {}
+            }
         }
+        Some(rule)
     }
     
     /// Adjust the context lines in hunks to match the specified number
@@ -100,10 +472,13 @@ impl FilterManager {
                 }
             }
             
-            // Keep lines in their original order
+            // Keep lines in their original order, along with their line numbers
+            let line_numbers = hunk.source_line_numbers();
+            let mut filtered_numbers = Vec::new();
             for (i, line) in lines.iter().enumerate() {
                 if lines_to_keep.contains(&i) {
                     filtered_lines.push(line.clone());
+                    filtered_numbers.push(line_numbers[i]);
                 }
             }
             
@@ -111,6 +486,7 @@ impl FilterManager {
                 // Create a new hunk with all metadata preserved
                 let mut new_hunk = hunk.clone();
                 new_hunk.lines = filtered_lines;
+                new_hunk.line_numbers = filtered_numbers;
                 filtered_hunks.push(new_hunk);
             }
         }
@@ -125,23 +501,351 @@ impl FilterManager {
     /// * `hunks` - List of hunk dictionaries containing diff information
     /// * `rule` - The filter rule to apply
     /// * `code` - The full C# file content
-    fn process_csharp_file(&mut self, hunks: &[Hunk], rule: &FilterRule, code: &str) -> Vec<Hunk> {
+    /// * `side` - The side of the diff the content comes from
+    fn process_csharp_file(&mut self, hunks: &[Hunk], rule: &FilterRule, code: &str, side: DiffSide) -> Result<Vec<Hunk>> {
         if !rule.include_method_body && !rule.include_signatures {
-            return self.apply_context_filter(hunks, rule.context_lines);
+            return Ok(self.apply_context_filter(hunks, rule.context_lines));
         }
 
-        let file_info = self.csharp_parser.parse_file(code, hunks);
+        let csharp_parser = self.csharp_parser.as_mut()
+            .ok_or_else(|| RepoDiffError::ParseError("C# grammar is not available".to_string()))?;
+        let mut file_info = csharp_parser.parse_file_side(code, hunks, side)?;
+        Self::apply_accessor_granularity(&mut file_info.methods, rule.accessor_granularity);
+        Ok(self.apply_method_filter(hunks, rule, &file_info, side))
+    }
+
+    /// Get the signatures of the methods changed in a C# file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `hunks` - The file's hunks as parsed from git, ideally with full-file context
+    ///
+    /// # Returns
+    ///
+    /// The signature line of each changed method in file order, or nothing for
+    /// files that aren't C# or can't be parsed
+    pub fn changed_method_signatures(&mut self, file_path: &str, hunks: &[Hunk]) -> Vec<String> {
+        if !self.is_csharp(file_path) {
+            return Vec::new();
+        }
+        
+        let side = Self::content_side(hunks);
+        let code = self.reconstruct_file_content(hunks, side);
+        let Some(file_info) = self.csharp_parser.as_mut().and_then(|parser| parser.parse_file_side(&code, hunks, side).ok()) else {
+            return Vec::new();
+        };
+        
+        let lines: Vec<&str> = code.lines().collect();
+        file_info.methods.iter()
+            .filter(|method| method.has_changes)
+            .filter_map(|method| lines.get(method.signature_line.checked_sub(1)?))
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    /// Get the names of the top-level symbols changed in a file, whatever its language
+    ///
+    /// C# methods, Razor code blocks and top-level Python functions and classes are found
+    /// by the parser for the file's language.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `hunks` - The file's hunks as parsed from git, ideally with full-file context
+    ///
+    /// # Returns
+    ///
+    /// The name of each changed symbol in file order, or nothing for languages
+    /// without a parser and files that can't be parsed
+    pub fn changed_symbols(&mut self, file_path: &str, hunks: &[Hunk]) -> Vec<String> {
+        let Some(side) = self.language_parser(file_path).map(|parser| parser.parse_side(Self::content_side(hunks))) else {
+            return Vec::new();
+        };
+        let code = self.reconstruct_file_content(hunks, side);
+        self.language_parser(file_path)
+            .and_then(|parser| parser.changed_symbols(&code, hunks, side).ok())
+            .unwrap_or_default()
+    }
+
+    /// Get the parser of a file's language, if it has one
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    fn language_parser(&mut self, file_path: &str) -> Option<&mut dyn LanguageParser> {
+        match DiffParser::language_for_path_with_map(file_path, &self.extension_language_map) {
+            Some("csharp") => self.csharp_parser.as_mut().map(|parser| parser as &mut dyn LanguageParser),
+            Some("razor") => Some(&mut self.razor_parser),
+            Some("python") => Some(&mut self.python_parser),
+            _ => None,
+        }
+    }
+
+    /// Split the processed hunks of a file by the C# method each of their lines belongs to
+    ///
+    /// Lines are placed by the line numbers kept through processing. A changed line
+    /// belongs to the innermost method enclosing it, and context lines outside any
+    /// method, and lines added by processing, stay with the lines before them.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `original_hunks` - The file's hunks as parsed from git, ideally with full-file context
+    /// * `hunks` - The file's processed hunks
+    ///
+    /// # Returns
+    ///
+    /// Pieces of the processed hunks in order, each with the name of the method it
+    /// changes, or None for changes outside any method and for files that aren't C#
+    /// or can't be parsed
+    pub fn split_hunks_by_symbol(&mut self, file_path: &str, original_hunks: &[Hunk], hunks: &[Hunk]) -> Vec<(Option<String>, Hunk)> {
+        let Some((side, methods)) = self.parse_methods(file_path, original_hunks) else {
+            return hunks.iter().map(|hunk| (None, hunk.clone())).collect();
+        };
+        
+        let mut pieces: Vec<(Option<String>, Hunk)> = Vec::new();
+        for hunk in hunks {
+            let mut current: Option<(Option<String>, Hunk)> = None;
+            for (line, numbers) in hunk.lines.iter().zip(hunk.source_line_numbers()) {
+                let is_change = line.starts_with('+') || line.starts_with('-');
+                let symbol = numbers.and_then(|numbers| Self::innermost_method(&methods, side.line_number(numbers)));
+                
+                // Context outside any method, and lines that can't be placed, stay in the current piece
+                let starts_piece = match &current {
+                    Some((current_symbol, _)) => (is_change || symbol.is_some()) && symbol != *current_symbol,
+                    None => true,
+                };
+                if starts_piece {
+                    pieces.extend(current.take());
+                    let mut piece = hunk.clone();
+                    piece.lines.clear();
+                    piece.line_numbers.clear();
+                    current = Some((symbol, piece));
+                }
+                if let Some((_, piece)) = current.as_mut() {
+                    piece.lines.push(line.clone());
+                    piece.line_numbers.push(numbers);
+                }
+            }
+            pieces.extend(current);
+        }
+        pieces
+    }
+
+    /// Parse the methods of a C# file from its hunks as parsed from git
+    ///
+    /// # Returns
+    ///
+    /// The side the file was parsed from and its methods, or None for files that
+    /// aren't C# or can't be parsed
+    fn parse_methods(&mut self, file_path: &str, original_hunks: &[Hunk]) -> Option<(DiffSide, Vec<CSharpMethod>)> {
+        if !self.is_csharp(file_path) {
+            return None;
+        }
+        
+        let side = Self::content_side(original_hunks);
+        let code = self.reconstruct_file_content(original_hunks, side);
+        let file_info = self.csharp_parser.as_mut()?.parse_file_side(&code, original_hunks, side).ok()?;
+        Some((side, file_info.methods))
+    }
+
+    /// Get the name of the innermost method enclosing a line
+    ///
+    /// # Arguments
+    ///
+    /// * `methods` - The methods of the file
+    /// * `line_number` - The line number, on the side the methods were parsed from
+    fn innermost_method(methods: &[CSharpMethod], line_number: usize) -> Option<String> {
+        methods.iter()
+            .filter(|method| method.start_line <= line_number && line_number <= method.end_line)
+            .min_by_key(|method| method.end_line - method.start_line)
+            .map(|method| method.name.clone())
+    }
+
+    /// Classify each changed method of a C# file by whether its signature changed
+    ///
+    /// Both sides of the file are parsed and methods are matched by name. A changed
+    /// method whose signature line is unchanged has a body-only change; otherwise it's
+    /// paired with a same-named old method whose signature no longer exists. Properties
+    /// are compared as a whole rather than per accessor.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `hunks` - The file's hunks as parsed from git, ideally with full-file context
+    ///
+    /// # Returns
+    ///
+    /// The changes in new-side file order followed by removed methods, or nothing for
+    /// files that aren't C# or can't be parsed
+    pub fn classify_method_changes(&mut self, file_path: &str, hunks: &[Hunk]) -> Vec<MethodChange> {
+        if !self.is_csharp(file_path) {
+            return Vec::new();
+        }
+        
+        let old_code = self.reconstruct_file_content(hunks, DiffSide::Old);
+        let new_code = self.reconstruct_file_content(hunks, DiffSide::New);
+        let Some(parser) = self.csharp_parser.as_mut() else {
+            return Vec::new();
+        };
+        let (Ok(old_file), Ok(new_file)) = (
+            parser.parse_file_side(&old_code, hunks, DiffSide::Old),
+            parser.parse_file_side(&new_code, hunks, DiffSide::New),
+        ) else {
+            return Vec::new();
+        };
+        
+        // (name, whitespace-normalized signature line, has changes) of each method
+        let signatures = |mut methods: Vec<CSharpMethod>, code: &str| {
+            Self::apply_accessor_granularity(&mut methods, AccessorGranularity::Property);
+            let lines: Vec<&str> = code.lines().collect();
+            methods.into_iter()
+                .map(|method| {
+                    let line = method.signature_line.checked_sub(1).and_then(|i| lines.get(i)).unwrap_or(&"");
+                    let signature = line.split_whitespace().collect::<Vec<_>>().join(" ");
+                    (method.name, signature, method.has_changes)
+                })
+                .collect::<Vec<_>>()
+        };
+        let old_methods = signatures(old_file.methods, &old_code);
+        let new_methods = signatures(new_file.methods, &new_code);
+        
+        let mut matched = vec![false; old_methods.len()];
+        let mut changes = Vec::new();
+        for (name, signature, has_changes) in &new_methods {
+            if !has_changes {
+                continue;
+            }
+            let same_name = |i: &usize| !matched[*i] && old_methods[*i].0 == *name;
+            let unchanged = (0..old_methods.len()).filter(same_name).find(|&i| old_methods[i].1 == *signature);
+            // An old signature that no new method has any more was changed
+            let replaced = (0..old_methods.len()).filter(same_name).find(|&i| {
+                !new_methods.iter().any(|(n, s, _)| n == name && *s == old_methods[i].1)
+            });
+            match (unchanged, replaced) {
+                (Some(i), _) => {
+                    matched[i] = true;
+                    changes.push(MethodChange::BodyOnly { name: name.clone() });
+                }
+                (None, Some(i)) => {
+                    matched[i] = true;
+                    changes.push(MethodChange::SignatureChanged {
+                        name: name.clone(),
+                        old_signature: old_methods[i].1.clone(),
+                        new_signature: signature.clone(),
+                    });
+                }
+                (None, None) => changes.push(MethodChange::Added { name: name.clone(), signature: signature.clone() }),
+            }
+        }
+        
+        for (i, (name, signature, has_changes)) in old_methods.iter().enumerate() {
+            if *has_changes && !matched[i] && !new_methods.iter().any(|(n, s, _)| n == name && s == signature) {
+                changes.push(MethodChange::Removed { name: name.clone(), signature: signature.clone() });
+            }
+        }
+        
+        changes
+    }
+
+    /// Get the full diff of the C# methods with the given name, leaving out the rest of the file
+    ///
+    /// Every line of each matching method is kept, so the hunks should come from a diff
+    /// with full-file context. Overloads sharing the name are all included.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `hunks` - The file's hunks as parsed from git
+    /// * `method_name` - The name of the method, e.g. `Save` or `Name.get` for an accessor
+    ///
+    /// # Returns
+    ///
+    /// The hunks restricted to the named methods, or an error if the file isn't C# or has no such method
+    pub fn method_diff(&mut self, file_path: &str, hunks: &[Hunk], method_name: &str) -> Result<Vec<Hunk>> {
+        if !self.is_csharp(file_path) {
+            return Err(RepoDiffError::ParseError(format!("'{}' is not a C# file", file_path)));
+        }
+        
+        // Opaque files show none of their lines, not even the method's
+        if self.is_opaque(file_path) {
+            return Ok(Vec::new());
+        }
+        
+        let side = Self::content_side(hunks);
+        let code = self.reconstruct_file_content(hunks, side);
+        let csharp_parser = self.csharp_parser.as_mut()
+            .ok_or_else(|| RepoDiffError::ParseError("C# grammar is not available".to_string()))?;
+        let file_info = csharp_parser.parse_file_side(&code, hunks, side)?;
+        let spans: Vec<(usize, usize)> = file_info.methods.iter()
+            .filter(|method| method.name == method_name)
+            .map(|method| (method.start_line, method.end_line))
+            .collect();
+        if spans.is_empty() {
+            return Err(RepoDiffError::GeneralError(format!("No method named '{}' in {}", method_name, file_path)));
+        }
+        
+        let mut method_hunks = Vec::new();
+        for hunk in hunks {
+            let mut line_counter = side.start(hunk);
+            let mut lines = Vec::new();
+            let mut line_numbers = Vec::new();
+            for (line, numbers) in hunk.lines.iter().zip(hunk.source_line_numbers()) {
+                if spans.iter().any(|&(start, end)| (start..=end).contains(&line_counter)) {
+                    lines.push(line.clone());
+                    line_numbers.push(numbers);
+                }
+                if side.contains(line) {
+                    line_counter += 1;
+                }
+            }
+            
+            if !lines.is_empty() {
+                let mut method_hunk = hunk.clone();
+                method_hunk.new_count = lines.iter().filter(|l| !l.starts_with('-')).count();
+                method_hunk.old_count = lines.iter().filter(|l| !l.starts_with('+')).count();
+                method_hunk.lines = lines;
+                method_hunk.line_numbers = line_numbers;
+                method_hunks.push(method_hunk);
+            }
+        }
+        
+        Ok(method_hunks)
+    }
+
+    /// Process a Razor file, treating its C# code blocks like methods
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - List of hunk dictionaries containing diff information
+    /// * `rule` - The filter rule to apply
+    /// * `code` - The full Razor file content
+    fn process_razor_file(&self, hunks: &[Hunk], rule: &FilterRule, code: &str) -> Vec<Hunk> {
+        let file_info = RazorParser::parse_file(code, hunks);
+        self.apply_method_filter(hunks, rule, &file_info, DiffSide::New)
+    }
+
+    /// Filter hunks so that changed methods are expanded according to the rule
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - List of hunk dictionaries containing diff information
+    /// * `rule` - The filter rule to apply
+    /// * `file_info` - The methods and declarations found in the file
+    /// * `side` - The side of the diff the file was parsed from
+    fn apply_method_filter(&self, hunks: &[Hunk], rule: &FilterRule, file_info: &CSharpFile, side: DiffSide) -> Vec<Hunk> {
         let mut processed_hunks = Vec::new();
 
         for hunk in hunks {
             let mut new_hunk = hunk.clone();
             let mut new_lines = Vec::new();
-            let mut last_included_line = hunk.new_start - 1;
+            let mut last_included_line = side.start(hunk).saturating_sub(1);
 
             // Step 1: Compute context_lines_set and identify changed lines
             let mut context_lines_set = std::collections::HashSet::new();
             let mut change_locations = Vec::new();
-            let mut temp_line = hunk.new_start;
+            let mut temp_line = side.start(hunk);
             for line in &hunk.lines {
                 if line.starts_with('+') || line.starts_with('-') {
                     change_locations.push(temp_line);
@@ -151,7 +855,7 @@ impl FilterManager {
                         context_lines_set.insert(i);
                     }
                 }
-                if !line.starts_with('-') {
+                if side.contains(line) {
                     temp_line += 1;
                 }
             }
@@ -173,9 +877,25 @@ impl FilterManager {
                 Vec::new()
             };
 
-            // Step 3: Process each line
-            let mut line_counter = hunk.new_start;
-            for line in &hunk.lines {
+            // Fields referred to by expanded methods are shown wherever they are declared
+            let field_lines = if rule.include_method_body && rule.include_referenced_fields {
+                let focused: Vec<&CSharpMethod> = changed_methods.iter()
+                    .copied()
+                    .filter(|m| Self::is_focused_method(rule, m))
+                    .collect();
+                Self::referenced_field_lines(&focused, file_info)
+            } else {
+                std::collections::HashSet::new()
+            };
+
+            // Step 3: Process each line, noting where each changed method's lines end up
+            // (start line -> index of the signature line, indices of all lines)
+            let mut method_output: HashMap<usize, (Option<usize>, Vec<usize>)> = HashMap::new();
+            // (index, offset from the signature) of each body line of a fully expanded method
+            let mut body_offsets = Vec::new();
+            let mut new_numbers = Vec::new();
+            let mut line_counter = side.start(hunk);
+            for (line, numbers) in hunk.lines.iter().zip(hunk.source_line_numbers()) {
                 let is_changed_line = line.starts_with('+') || line.starts_with('-');
                 let is_context_line = context_lines_set.contains(&line_counter);
 
@@ -191,7 +911,13 @@ impl FilterManager {
 
                 if let Some(method) = in_changed_method {
                     // Changed method logic - preserve existing behavior
-                    if rule.include_method_body {
+                    if !Self::is_focused_method(rule, method) {
+                        // Methods outside the name filter keep only their signature, changes included
+                        should_include = line_counter == method.signature_line && side.contains(line);
+                        if !should_include && !new_lines.last().is_some_and(|l: &String| l.ends_with("⋮----")) {
+                            should_add_placeholder = true;
+                        }
+                    } else if rule.include_method_body {
                         should_include = true;
                     } else if line_counter == method.signature_line {
                         should_include = true;
@@ -205,7 +931,7 @@ impl FilterManager {
                         // For body lines, only include if within context range
                         should_include = is_context_line;
                         // Add placeholder if we're skipping lines
-                        if !should_include && !new_lines.last().map_or(false, |l: &String| l.ends_with("⋮----")) {
+                        if !should_include && !new_lines.last().is_some_and(|l: &String| l.ends_with("⋮----")) {
                             should_add_placeholder = true;
                         }
                     }
@@ -221,25 +947,71 @@ impl FilterManager {
                         }
                         found
                     };
-                    should_include = is_context_line || (in_enclosing_declaration && rule.include_signatures);
+                    // Method bodies only bring their declarations along when asked to, so context_lines is
+                    // otherwise respected for them
+                    let show_declarations = rule.include_signatures
+                        || (rule.include_method_body && rule.include_enclosing_declarations);
+                    should_include = is_context_line
+                        || (in_enclosing_declaration && show_declarations)
+                        || field_lines.contains(&line_counter);
                 }
 
                 // Include the line or placeholder
                 if should_include {
+                    if let Some(method) = in_changed_method {
+                        let (signature_index, indices) = method_output.entry(method.start_line).or_default();
+                        if line_counter == method.signature_line && side.contains(line) {
+                            *signature_index = Some(new_lines.len());
+                        }
+                        indices.push(new_lines.len());
+                        
+                        if self.method_line_offsets
+                            && rule.include_method_body
+                            && line_counter > method.signature_line
+                            && Self::is_focused_method(rule, method)
+                        {
+                            body_offsets.push((new_lines.len(), line_counter - method.signature_line));
+                        }
+                    }
                     new_lines.push(line.clone());
+                    new_numbers.push(numbers);
                     last_included_line = line_counter;
                 } else if should_add_placeholder && line_counter > last_included_line + 1 {
                     new_lines.push(" ⋮----".to_string());
+                    new_numbers.push(None);
                     last_included_line = line_counter;
                 }
 
-                if !line.starts_with('-') {
+                if side.contains(line) {
                     line_counter += 1;
                 }
             }
 
+            // Annotate each fully emitted method's signature with the method's token count
+            if rule.include_method_body
+                && let Some(token_counter) = &self.method_token_counter
+            {
+                for (signature_index, indices) in method_output.values() {
+                    let Some(signature_index) = *signature_index else {
+                        continue;
+                    };
+                    let text: Vec<&str> = indices.iter().map(|&i| new_lines[i].as_str()).collect();
+                    let tokens = token_counter.count_tokens(&text.join("\n"));
+                    new_lines[signature_index].push_str(&format!(" // {} tokens", tokens));
+                }
+            }
+
+            // Prefix body lines with their offset, after the diff marker so the line is still a valid diff line
+            for (index, offset) in body_offsets {
+                let line = &new_lines[index];
+                let (marker, content) = line.split_at(line.chars().next().map_or(0, char::len_utf8));
+                let marker = if marker.is_empty() { " " } else { marker };
+                new_lines[index] = format!("{}+{}:{}", marker, offset, content);
+            }
+
             // Update hunk with filtered lines
             new_hunk.lines = new_lines;
+            new_hunk.line_numbers = new_numbers;
             new_hunk.new_count = new_hunk.lines.iter().filter(|l| !l.starts_with('-')).count();
             new_hunk.old_count = new_hunk.lines.iter().filter(|l| !l.starts_with('+')).count();
 
@@ -251,44 +1023,611 @@ impl FilterManager {
         processed_hunks
     }
 
+    /// Get the lines of the field declarations that methods refer to by name
+    ///
+    /// Only fields declared in the innermost class enclosing a method are considered.
+    ///
+    /// # Arguments
+    ///
+    /// * `methods` - The methods whose bodies are scanned for identifiers
+    /// * `file_info` - The fields and class declarations found in the file
+    fn referenced_field_lines(methods: &[&CSharpMethod], file_info: &CSharpFile) -> std::collections::HashSet<usize> {
+        let mut lines = std::collections::HashSet::new();
+        for method in methods {
+            let Some(&(class_start, class_end)) = file_info.class_declarations.iter()
+                .filter(|&&(start, end)| start <= method.start_line && method.end_line <= end)
+                .max_by_key(|&&(start, _)| start)
+            else {
+                continue;
+            };
+            let identifiers: std::collections::HashSet<&str> = method.text
+                .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .filter(|word| !word.is_empty())
+                .collect();
+            for field in &file_info.fields {
+                if field.start_line >= class_start
+                    && field.end_line <= class_end
+                    && field.names.iter().any(|name| identifiers.contains(name.as_str()))
+                {
+                    lines.extend(field.start_line..=field.end_line);
+                }
+            }
+        }
+        lines
+    }
+
+    /// Check whether a changed method matches the rule's method name filter, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `rule` - The filter rule being applied
+    /// * `method` - The changed method
+    fn is_focused_method(rule: &FilterRule, method: &CSharpMethod) -> bool {
+        rule.method_name_filter
+            .as_deref()
+            .is_none_or(|pattern| Self::matches_pattern(pattern, &method.name))
+    }
+
+    /// Keep either the properties or their accessors, so a property isn't expanded twice
+    ///
+    /// # Arguments
+    ///
+    /// * `methods` - The methods found in the file, including properties and their accessors
+    /// * `granularity` - Whether to keep whole properties or their individual accessors
+    fn apply_accessor_granularity(methods: &mut Vec<CSharpMethod>, granularity: AccessorGranularity) {
+        // Accessors are named after their property and lie within its lines
+        let is_accessor_of = |accessor: &CSharpMethod, property: &CSharpMethod| {
+            accessor.name.starts_with(&format!("{}.", property.name))
+                && accessor.start_line >= property.start_line
+                && accessor.end_line <= property.end_line
+        };
+        let keep: Vec<bool> = methods.iter()
+            .map(|method| match granularity {
+                AccessorGranularity::Property => !methods.iter().any(|other| is_accessor_of(method, other)),
+                AccessorGranularity::Accessor => !methods.iter().any(|other| is_accessor_of(other, method)),
+            })
+            .collect();
+        let mut keep = keep.into_iter();
+        methods.retain(|_| keep.next().unwrap_or(true));
+    }
+
     /// Post-process files according to their matching filter rules
     ///
     /// # Arguments
     ///
     /// * `patch_dict` - Dictionary mapping filenames to lists of hunks
     pub fn post_process_files(&mut self, patch_dict: &HashMap<String, Vec<Hunk>>) -> HashMap<String, Vec<Hunk>> {
+        self.begin_post_processing();
         let mut result = HashMap::new();
-        
         for (file_path, hunks) in patch_dict {
-            let rule = self.find_matching_rule(file_path);
-            
-            // Special handling for C# files
-            if file_path.ends_with(".cs") && (rule.include_method_body || rule.include_signatures) {
-                // TODO: Get the full file content from Git
-                // For now, we'll reconstruct it from the hunks
-                let code = self.reconstruct_file_content(hunks);
-                result.insert(file_path.clone(), self.process_csharp_file(hunks, &rule, &code));
-            } else {
-                result.insert(file_path.clone(), self.apply_context_filter(hunks, rule.context_lines));
+            if let Some(processed_hunks) = self.filter_file(file_path, hunks) {
+                result.insert(file_path.clone(), processed_hunks);
             }
         }
         
+        if self.dedupe_identical_hunks {
+            Self::dedupe_hunks(&mut result, self.dedupe_occurrence_counts);
+        }
+        
+        // Skip files that are still too large after filtering
+        let omitted_files = &mut self.omitted_files;
+        let max_file_lines = self.max_file_lines;
+        result.retain(|file_path, hunks| {
+            let too_large = Self::exceeds_max_file_lines(max_file_lines, hunks);
+            if too_large {
+                omitted_files.push((file_path.clone(), OmitReason::OverSize));
+            }
+            !too_large
+        });
+        
+        self.finish_post_processing();
         result
     }
 
+    /// Start post-processing a diff one file at a time, clearing what the last diff left behind
+    ///
+    /// Files are then passed to `post_process_file` as they're parsed, and
+    /// `finish_post_processing` is called after the last one.
+    pub fn begin_post_processing(&mut self) {
+        self.omitted_files.clear();
+        self.opaque_files.clear();
+        self.warnings.clear();
+    }
+
+    /// Post-process a single file according to its matching filter rule
+    ///
+    /// Identical hunks are never deduplicated here, since that needs every file of the diff.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `hunks` - The file's hunks
+    ///
+    /// # Returns
+    ///
+    /// The processed hunks, or None if the file is left out of the output
+    pub fn post_process_file(&mut self, file_path: &str, hunks: &[Hunk]) -> Option<Vec<Hunk>> {
+        let processed_hunks = self.filter_file(file_path, hunks)?;
+        if Self::exceeds_max_file_lines(self.max_file_lines, &processed_hunks) {
+            self.omitted_files.push((file_path.to_string(), OmitReason::OverSize));
+            return None;
+        }
+        Some(processed_hunks)
+    }
+
+    /// Finish post-processing a diff, putting the omitted files and warnings in path order
+    pub fn finish_post_processing(&mut self) {
+        self.omitted_files.sort();
+        self.warnings.sort();
+    }
+
+    /// Restore the results of post-processing a diff earlier, instead of filtering it again
+    ///
+    /// # Arguments
+    ///
+    /// * `omitted_files` - The files left out of the diff, with the reason for each
+    /// * `opaque_files` - The files of the diff whose content is hidden
+    /// * `warnings` - The warnings raised while processing the diff
+    pub fn restore_post_processing(
+        &mut self,
+        omitted_files: Vec<(String, OmitReason)>,
+        opaque_files: HashSet<String>,
+        warnings: Vec<String>,
+    ) {
+        self.omitted_files = omitted_files;
+        self.opaque_files = opaque_files;
+        self.warnings = warnings;
+    }
+
+    /// Get the number of files run through the filter rules since this manager was created
+    pub fn get_filtered_file_count(&self) -> usize {
+        self.filtered_file_count
+    }
+
+    /// Check whether identical hunks are deduplicated, which needs every file of a diff at once
+    pub fn dedupes_identical_hunks(&self) -> bool {
+        self.dedupe_identical_hunks
+    }
+
+    /// Apply a file's filter rule to its hunks
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `hunks` - The file's hunks
+    ///
+    /// # Returns
+    ///
+    /// The processed hunks, or None if the file is excluded or only its ignored lines changed
+    fn filter_file(&mut self, file_path: &str, hunks: &[Hunk]) -> Option<Vec<Hunk>> {
+        self.filtered_file_count += 1;
+        
+        // Skip files excluded by a negated pattern
+        let Some(mut rule) = self.find_matching_rule(file_path) else {
+            self.omitted_files.push((file_path.to_string(), OmitReason::Excluded));
+            return None;
+        };
+        
+        // Opaque files keep their place in the output but none of their lines
+        if rule.opaque {
+            self.opaque_files.insert(file_path.to_string());
+            return Some(Vec::new());
+        }
+        
+        // Test files may use their own (typically smaller) context
+        if let Some(test_context_lines) = self.test_context_lines
+            && self.is_test_file(file_path)
+        {
+            rule.context_lines = test_context_lines;
+        }
+        
+        // Files whose only changes are ignored lines are unchanged
+        let ignored_hunks;
+        let hunks = if self.ignore_line_patterns.is_empty() {
+            hunks
+        } else {
+            ignored_hunks = self.ignore_matching_lines(hunks);
+            if ignored_hunks.is_empty() {
+                return None;
+            }
+            &ignored_hunks
+        };
+        
+        // Mostly rewritten files read better in full than trimmed
+        let rewritten = rule.full_file_over_change_ratio
+            .is_some_and(|ratio| Self::change_ratio(hunks) > ratio);
+        
+        // Special handling for C# files
+        let mut processed_hunks = if rewritten {
+            hunks.to_vec()
+        } else if self.is_csharp(file_path) && (rule.include_method_body || rule.include_signatures) {
+            // TODO: Get the full file content from Git
+            // For now, we'll reconstruct it from the hunks
+            let side = Self::content_side(hunks);
+            let code = self.reconstruct_file_content(hunks, side);
+            
+            // Fall back to plain context filtering if the file can't be parsed
+            match self.process_csharp_file(hunks, &rule, &code, side) {
+                Ok(processed_hunks) => processed_hunks,
+                Err(e) => {
+                    self.warnings.push(format!("{}: {}; showing plain context instead of whole methods", file_path, e));
+                    self.apply_context_filter(hunks, rule.context_lines)
+                }
+            }
+        } else if self.is_razor(file_path)
+            && (rule.include_method_body || rule.include_signatures)
+        {
+            let code = self.reconstruct_file_content(hunks, DiffSide::New);
+            self.process_razor_file(hunks, &rule, &code)
+        } else {
+            self.apply_context_filter(hunks, rule.context_lines)
+        };
+        
+        // Collapse moved methods, keeping the hunks as they are if either side can't be parsed
+        if self.detect_moved_methods
+            && self.is_csharp(file_path)
+            && let Some(csharp_parser) = self.csharp_parser.as_mut()
+        {
+            processed_hunks = match MovedMethodDetector::collapse(csharp_parser, hunks, processed_hunks.clone()) {
+                Ok(collapsed) => collapsed,
+                Err(_) => processed_hunks,
+            };
+        }
+        
+        if self.collapse_blank_runs {
+            processed_hunks = self.collapse_blank_context_runs(processed_hunks);
+        }
+        
+        if let Some(max_hunks) = rule.max_hunks {
+            processed_hunks = self.merge_hunks(processed_hunks, max_hunks);
+        }
+        
+        if self.annotate_hunks {
+            processed_hunks = self.annotate_hunk_changes(file_path, hunks, processed_hunks);
+        }
+        
+        Some(processed_hunks)
+    }
+
+    /// Check whether a file has more lines than allowed after filtering
+    ///
+    /// # Arguments
+    ///
+    /// * `max_file_lines` - The most lines a file may have, if limited
+    /// * `hunks` - The file's processed hunks
+    fn exceeds_max_file_lines(max_file_lines: Option<usize>, hunks: &[Hunk]) -> bool {
+        max_file_lines.is_some_and(|max_file_lines| hunks.iter().map(|h| h.lines.len()).sum::<usize>() > max_file_lines)
+    }
+
+    /// Get the files left out of the last processed diff, with the reason for each
+    pub fn get_omitted_files(&self) -> &[(String, OmitReason)] {
+        &self.omitted_files
+    }
+
+    /// Get the files of the last processed diff whose content is hidden by an opaque rule
+    pub fn get_opaque_files(&self) -> &HashSet<String> {
+        &self.opaque_files
+    }
+
+    /// Get the warnings raised while processing the last diff, such as C# files that couldn't be parsed
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Record a file left out of the output after filtering, e.g. to fit a token budget
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the omitted file
+    /// * `reason` - Why the file was omitted
+    pub fn record_omitted(&mut self, file_path: &str, reason: OmitReason) {
+        self.omitted_files.push((file_path.to_string(), reason));
+        self.omitted_files.sort();
+    }
+
+    /// Summarize the files left out of the last processed diff
+    ///
+    /// # Returns
+    ///
+    /// A line such as `(3 files omitted: 2 over size, 1 excluded)`, or None if no files were omitted
+    pub fn get_omitted_summary(&self) -> Option<String> {
+        if self.omitted_files.is_empty() {
+            return None;
+        }
+        
+        let mut counts: HashMap<OmitReason, usize> = HashMap::new();
+        for (_, reason) in &self.omitted_files {
+            *counts.entry(*reason).or_default() += 1;
+        }
+        
+        // Most common reasons first
+        let mut counts: Vec<(OmitReason, usize)> = counts.into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let reasons: Vec<String> = counts.iter()
+            .map(|(reason, count)| format!("{} {}", count, reason))
+            .collect();
+        
+        let total = self.omitted_files.len();
+        Some(format!(
+            "({} file{} omitted: {})",
+            total,
+            if total == 1 { "" } else { "s" },
+            reasons.join(", ")
+        ))
+    }
+
+    /// Get the fraction of a file's lines that changed
+    ///
+    /// Measured against whichever side of the file is longer, so a file that is
+    /// replaced wholesale scores 1.0 whether it grew or shrank.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The file's hunks, including all context git provided
+    fn change_ratio(hunks: &[Hunk]) -> f32 {
+        let lines = hunks.iter().flat_map(|h| &h.lines);
+        let added = lines.clone().filter(|l| l.starts_with('+')).count();
+        let deleted = lines.clone().filter(|l| l.starts_with('-')).count();
+        let context = lines.count() - added - deleted;
+        
+        let total = context + added.max(deleted);
+        if total == 0 {
+            return 0.0;
+        }
+        added.max(deleted) as f32 / total as f32
+    }
+
+    /// Replace hunks that repeat an earlier hunk's lines with a reference to the earlier file
+    ///
+    /// Files are visited in output order, so the first occurrence is the one that's kept.
+    /// Single-line hunks are left alone since the reference would be longer.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The processed hunks of each file
+    /// * `occurrence_counts` - Whether to precede the first occurrence with a line naming every file that shares it
+    fn dedupe_hunks(files: &mut HashMap<String, Vec<Hunk>>, occurrence_counts: bool) {
+        let mut file_paths: Vec<String> = files.keys().cloned().collect();
+        file_paths.sort();
+        
+        // The files sharing each hunk, in output order
+        let mut occurrences: HashMap<Vec<String>, Vec<String>> = HashMap::new();
+        for file_path in &file_paths {
+            for hunk in &files[file_path] {
+                if hunk.lines.len() < 2 {
+                    continue;
+                }
+                let sharing_files = occurrences.entry(hunk.lines.clone()).or_default();
+                if !sharing_files.contains(file_path) {
+                    sharing_files.push(file_path.clone());
+                }
+            }
+        }
+        
+        let mut summarized = HashSet::new();
+        for file_path in &file_paths {
+            for hunk in files.get_mut(file_path).into_iter().flatten() {
+                let Some(sharing_files) = occurrences.get(&hunk.lines) else {
+                    continue;
+                };
+                if sharing_files[0] != *file_path {
+                    hunk.lines = vec![format!(" (identical change as in {})", sharing_files[0])];
+                    hunk.line_numbers = vec![None];
+                    hunk.annotation = None;
+                } else if occurrence_counts && sharing_files.len() > 1 && summarized.insert(hunk.lines.clone()) {
+                    hunk.line_numbers = hunk.source_line_numbers();
+                    hunk.lines.insert(0, Self::occurrence_summary(sharing_files));
+                    hunk.line_numbers.insert(0, None);
+                }
+            }
+        }
+    }
+
+    /// Describe the files sharing a deduplicated hunk
+    ///
+    /// # Arguments
+    ///
+    /// * `sharing_files` - The files containing the hunk, in output order
+    ///
+    /// # Returns
+    ///
+    /// A line such as ` (identical change applied to 3 files: A.cs, B.cs, C.cs)`, naming
+    /// at most ten files and counting the rest
+    fn occurrence_summary(sharing_files: &[String]) -> String {
+        let listed = sharing_files.iter()
+            .take(DEDUPE_LISTED_FILES)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let unlisted = sharing_files.len().saturating_sub(DEDUPE_LISTED_FILES);
+        if unlisted > 0 {
+            format!(" (identical change applied to {} files: {} and {} more)", sharing_files.len(), listed, unlisted)
+        } else {
+            format!(" (identical change applied to {} files: {})", sharing_files.len(), listed)
+        }
+    }
+
+    /// Merge hunks so that a file has at most `max_hunks` of them
+    ///
+    /// Consecutive hunks are combined into evenly sized groups, with a `⋮----`
+    /// placeholder marking the omitted lines between the original hunks.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - List of hunks for a single file, in file order
+    /// * `max_hunks` - Maximum number of hunks to keep
+    fn merge_hunks(&self, hunks: Vec<Hunk>, max_hunks: usize) -> Vec<Hunk> {
+        let max_hunks = max_hunks.max(1);
+        if hunks.len() <= max_hunks {
+            return hunks;
+        }
+        
+        let group_size = hunks.len().div_ceil(max_hunks);
+        hunks.chunks(group_size)
+            .map(|group| {
+                let first = &group[0];
+                let last = &group[group.len() - 1];
+                
+                let mut merged = first.clone();
+                merged.lines.clear();
+                merged.line_numbers.clear();
+                for (i, hunk) in group.iter().enumerate() {
+                    if i > 0 {
+                        merged.lines.push(" ⋮----".to_string());
+                        merged.line_numbers.push(None);
+                    }
+                    merged.lines.extend(hunk.lines.iter().cloned());
+                    merged.line_numbers.extend(hunk.source_line_numbers());
+                }
+                
+                // The merged hunk spans from the start of the first hunk to the end of the last
+                merged.old_count = last.old_start + last.old_count - first.old_start;
+                merged.new_count = last.new_start + last.new_count - first.new_start;
+                merged.header = format!(
+                    "@@ -{},{} +{},{} @@",
+                    merged.old_start, merged.old_count, merged.new_start, merged.new_count
+                );
+                merged
+            })
+            .collect()
+    }
+
+    /// Treat changed lines matching an ignore pattern as unchanged
+    ///
+    /// Matching removed lines are dropped and matching added lines become context,
+    /// so the hunk shows the new content. Hunks left without changes are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks to remove ignored changes from
+    fn ignore_matching_lines(&self, hunks: &[Hunk]) -> Vec<Hunk> {
+        hunks.iter()
+            .filter_map(|hunk| {
+                let mut new_hunk = hunk.clone();
+                (new_hunk.lines, new_hunk.line_numbers) = hunk.lines.iter()
+                    .zip(hunk.source_line_numbers())
+                    .filter_map(|(line, numbers)| match line.split_at_checked(1) {
+                        Some((marker @ ("+" | "-"), content))
+                            if self.ignore_line_patterns.iter().any(|re| re.is_match(content)) =>
+                        {
+                            (marker == "+").then(|| (format!(" {}", content), numbers))
+                        }
+                        _ => Some((line.clone(), numbers)),
+                    })
+                    .unzip();
+                new_hunk.lines.iter()
+                    .any(|line| line.starts_with('+') || line.starts_with('-'))
+                    .then_some(new_hunk)
+            })
+            .collect()
+    }
+
+    /// Annotate each processed hunk with a count of its changes, e.g.
+    /// `3 additions, 1 deletion in method Foo`, which is rendered as a line of its own
+    /// before the hunk
+    ///
+    /// The methods are those of a C# file enclosing the hunk's changed lines; other
+    /// files only get the counts.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path of the file
+    /// * `original_hunks` - The file's hunks as parsed from git
+    /// * `hunks` - The file's processed hunks
+    fn annotate_hunk_changes(&mut self, file_path: &str, original_hunks: &[Hunk], mut hunks: Vec<Hunk>) -> Vec<Hunk> {
+        let plural = |count: usize, noun: &str| format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" });
+        
+        let parsed = self.parse_methods(file_path, original_hunks);
+        for hunk in &mut hunks {
+            // The methods enclosing the hunk's changed lines, in order
+            let mut methods: Vec<String> = Vec::new();
+            if let Some((side, file_methods)) = &parsed {
+                for (line, numbers) in hunk.lines.iter().zip(hunk.source_line_numbers()) {
+                    if let Some(numbers) = numbers
+                        && (line.starts_with('+') || line.starts_with('-'))
+                        && let Some(method) = Self::innermost_method(file_methods, side.line_number(numbers))
+                        && !methods.contains(&method)
+                    {
+                        methods.push(method);
+                    }
+                }
+            }
+            
+            let additions = hunk.lines.iter().filter(|line| line.starts_with('+')).count();
+            let deletions = hunk.lines.iter().filter(|line| line.starts_with('-')).count();
+            let mut annotation = format!("{}, {}", plural(additions, "addition"), plural(deletions, "deletion"));
+            match methods.len() {
+                0 => {}
+                1 => annotation.push_str(&format!(" in method {}", methods[0])),
+                _ => annotation.push_str(&format!(" in methods {}", methods.join(", "))),
+            }
+            hunk.annotation = Some(annotation);
+        }
+        hunks
+    }
+
+    /// Replace each run of three or more blank context lines with a single blank line
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks to collapse blank runs in
+    fn collapse_blank_context_runs(&self, mut hunks: Vec<Hunk>) -> Vec<Hunk> {
+        let is_blank_context = |line: &str| line.strip_prefix(' ').unwrap_or(line).trim().is_empty();
+        
+        for hunk in &mut hunks {
+            let line_numbers = hunk.source_line_numbers();
+            let mut collapsed = Vec::with_capacity(hunk.lines.len());
+            let mut collapsed_numbers = Vec::with_capacity(hunk.lines.len());
+            let mut i = 0;
+            while i < hunk.lines.len() {
+                let run = hunk.lines[i..].iter().take_while(|l| is_blank_context(l)).count();
+                if run >= 3 {
+                    collapsed.push(" ".to_string());
+                    collapsed_numbers.push(line_numbers[i]);
+                    i += run;
+                } else {
+                    collapsed.push(hunk.lines[i].clone());
+                    collapsed_numbers.push(line_numbers[i]);
+                    i += 1;
+                }
+            }
+            hunk.lines = collapsed;
+            hunk.line_numbers = collapsed_numbers;
+        }
+        
+        hunks
+    }
+
+    /// Get the side of the diff whose content a file should be parsed from
+    ///
+    /// Methods that were removed only exist on the old side, so files whose changes
+    /// are mostly deletions are parsed from the old side and everything else from the new side.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The file's hunks
+    fn content_side(hunks: &[Hunk]) -> DiffSide {
+        let lines = hunks.iter().flat_map(|h| &h.lines);
+        let added = lines.clone().filter(|l| l.starts_with('+')).count();
+        let deleted = lines.filter(|l| l.starts_with('-')).count();
+        if deleted > added { DiffSide::Old } else { DiffSide::New }
+    }
+
     /// Reconstruct file content from hunks (temporary solution)
     ///
     /// # Arguments
     ///
     /// * `hunks` - List of hunks containing the file changes
-    fn reconstruct_file_content(&self, hunks: &[Hunk]) -> String {
+    /// * `side` - The side of the diff to reconstruct
+    fn reconstruct_file_content(&self, hunks: &[Hunk], side: DiffSide) -> String {
         let mut content = String::new();
         for line in hunks.iter().flat_map(|h| &h.lines) {
-            if line.starts_with('-') {
+            if !side.contains(line) {
                 continue;
             }
-            if line.starts_with('+') {
-                content.push_str(&line[1..]);
+            // Indentation matters to some languages, so the context marker is dropped too
+            if let Some(stripped) = line.strip_prefix(['+', '-', ' ']) {
+                content.push_str(stripped);
             } else {
                 content.push_str(line);
             }
@@ -309,6 +1648,16 @@ impl FilterManager {
         Some(result)
     }
 
+    /// Get the root filter rules, as auto-tuned for the last diff
+    pub fn get_filters(&self) -> &[FilterRule] {
+        &self.filters
+    }
+
+    /// Get the filter rules of the directory configs found for the last diff, keyed by directory
+    pub fn get_directory_filters(&self) -> &HashMap<String, Vec<FilterRule>> {
+        &self.directory_filters
+    }
+
     /// Get the filters as a JSON string
     /// 
     /// Returns None if there are no filter rules
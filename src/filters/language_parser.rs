@@ -0,0 +1,32 @@
+use crate::error::Result;
+use crate::utils::diff_parser::{DiffSide, Hunk};
+
+/// A parser that finds the top-level symbols, such as methods or functions, of one language
+///
+/// Each language with symbol support implements this, so the changed symbols of a
+/// file can be found without dispatching on its language at every call site.
+pub trait LanguageParser {
+    /// Get the side of the diff to parse, given the side holding most of the changes
+    ///
+    /// Parsers that can only parse one side override this.
+    ///
+    /// # Arguments
+    ///
+    /// * `preferred` - The side the file would be parsed from by default
+    fn parse_side(&self, preferred: DiffSide) -> DiffSide {
+        preferred
+    }
+
+    /// Find the names of the changed symbols in one side of a file
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The file content of the given side
+    /// * `hunks` - The diff hunks to identify changed symbols
+    /// * `side` - The side of the diff the code comes from
+    ///
+    /// # Returns
+    ///
+    /// The name of each changed symbol in file order
+    fn changed_symbols(&mut self, code: &str, hunks: &[Hunk], side: DiffSide) -> Result<Vec<String>>;
+}
@@ -1,2 +1,6 @@
 pub mod csharp_parser;
-pub mod filter_manager; 
\ No newline at end of file
+pub mod filter_manager;
+pub mod language_parser;
+pub mod moved_methods;
+pub mod python_parser;
+pub mod razor_parser; 
\ No newline at end of file
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use crate::error::Result;
+use crate::filters::csharp_parser::CSharpParser;
+use crate::utils::diff_parser::Hunk;
+
+/// Most lines either version of a moved method may have between its unchanged start and
+/// end for a line-by-line diff; longer stretches are shown as a plain delete and add
+const MAX_LINE_DIFF_LINES: usize = 1000;
+
+/// A method that was deleted in one place and added in another
+struct MovedMethod {
+    /// Name of the method
+    name: String,
+    /// Lines of the method at its old location
+    old_lines: Vec<String>,
+    /// Lines of the method at its new location
+    new_lines: Vec<String>,
+}
+
+/// Detects C# methods that were moved within a file and collapses them
+///
+/// Git shows a moved method as an unrelated delete and add. When a method with the
+/// same name is deleted as a whole in one place and added as a whole in another,
+/// the deleted copy is replaced by a one-line marker, and the added copy by a marker
+/// followed by a line diff of the method when it was also modified.
+pub struct MovedMethodDetector;
+
+impl MovedMethodDetector {
+    /// Collapse moved methods in a file's processed hunks
+    ///
+    /// # Arguments
+    ///
+    /// * `parser` - The C# parser used to find methods on both sides of the diff
+    /// * `original` - The file's hunks as parsed from git, used to find methods
+    /// * `processed` - The file's hunks after filtering, in which moved methods are collapsed
+    pub fn collapse(parser: &mut CSharpParser, original: &[Hunk], processed: Vec<Hunk>) -> Result<Vec<Hunk>> {
+        let deleted = Self::whole_methods(parser, original, '-', &processed)?;
+        let added = Self::whole_methods(parser, original, '+', &processed)?;
+        
+        let mut hunks = processed;
+        for moved in Self::find_moved(deleted, added) {
+            let deleted: Vec<String> = moved.old_lines.iter().map(|l| format!("-{}", l)).collect();
+            let added: Vec<String> = moved.new_lines.iter().map(|l| format!("+{}", l)).collect();
+            
+            let unchanged = moved.old_lines.iter().map(|l| l.trim()).eq(moved.new_lines.iter().map(|l| l.trim()));
+            let mut replacement = vec![format!(
+                "+[moved] {}: moved here, {}",
+                moved.name,
+                if unchanged { "unchanged" } else { "with changes:" }
+            )];
+            if !unchanged {
+                replacement.extend(Self::line_diff(&moved.old_lines, &moved.new_lines));
+            }
+            
+            Self::replace_run(&mut hunks, &deleted, vec![format!("-[moved] {}: moved elsewhere in this file", moved.name)]);
+            Self::replace_run(&mut hunks, &added, replacement);
+        }
+        
+        Ok(hunks)
+    }
+    
+    /// Find the methods that were deleted or added as a whole
+    ///
+    /// Each run of consecutive deleted or added lines is parsed on its own, wrapped in a
+    /// class, so methods are found in whichever hunk they are, however far apart the
+    /// hunks are. Only methods still present as a whole in the processed hunks are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `parser` - The C# parser used to find the methods in each run
+    /// * `original` - The file's hunks as parsed from git
+    /// * `marker` - `-` to find deleted methods, `+` to find added ones
+    /// * `processed` - The file's hunks after filtering
+    ///
+    /// # Returns
+    ///
+    /// The source lines of each method, by name
+    fn whole_methods(
+        parser: &mut CSharpParser,
+        original: &[Hunk],
+        marker: char,
+        processed: &[Hunk],
+    ) -> Result<HashMap<String, Vec<Vec<String>>>> {
+        let mut methods: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+        for hunk in original {
+            for run in hunk.lines.chunk_by(|a, b| a.starts_with(marker) == b.starts_with(marker)) {
+                if !run[0].starts_with(marker) {
+                    continue;
+                }
+                let lines: Vec<String> = run.iter().map(|l| l[1..].to_string()).collect();
+                let code = format!("class Moved\n{{\n{}\n}}\n", lines.join("\n"));
+                
+                // Accessors move with their property, so only whole members are considered
+                for method in parser.parse_file(&code, &[])?.methods.iter().filter(|m| !m.name.contains('.')) {
+                    let (Some(start), Some(end)) = (method.start_line.checked_sub(3), method.end_line.checked_sub(3)) else {
+                        continue;
+                    };
+                    let Some(method_run) = run.get(start..=end) else {
+                        continue;
+                    };
+                    if Self::find_run(processed, method_run).is_some() {
+                        methods.entry(method.name.clone()).or_default().push(lines[start..=end].to_vec());
+                    }
+                }
+            }
+        }
+        Ok(methods)
+    }
+    
+    /// Pair methods deleted as a whole and added as a whole under the same name
+    ///
+    /// Names that are deleted or added more than once, such as overloads, are
+    /// skipped because the pairing would be ambiguous.
+    fn find_moved(
+        deleted: HashMap<String, Vec<Vec<String>>>,
+        mut added: HashMap<String, Vec<Vec<String>>>,
+    ) -> Vec<MovedMethod> {
+        let mut moved: Vec<MovedMethod> = deleted.into_iter()
+            .filter(|(_, runs)| runs.len() == 1)
+            .filter_map(|(name, mut old_runs)| {
+                let new_runs = added.remove(&name).filter(|runs| runs.len() == 1)?;
+                Some(MovedMethod {
+                    name,
+                    old_lines: old_runs.remove(0),
+                    new_lines: new_runs.into_iter().next()?,
+                })
+            })
+            .collect();
+        moved.sort_by(|a, b| a.name.cmp(&b.name));
+        moved
+    }
+    
+    /// Find a contiguous run of lines in the hunks
+    ///
+    /// # Returns
+    ///
+    /// The index of the hunk and of the run's first line in it
+    fn find_run(hunks: &[Hunk], run: &[String]) -> Option<(usize, usize)> {
+        if run.is_empty() {
+            return None;
+        }
+        hunks.iter().enumerate().find_map(|(hunk_index, hunk)| {
+            hunk.lines.windows(run.len())
+                .position(|window| window == run)
+                .map(|line_index| (hunk_index, line_index))
+        })
+    }
+    
+    /// Replace a contiguous run of lines in the hunks
+    fn replace_run(hunks: &mut [Hunk], run: &[String], replacement: Vec<String>) {
+        if let Some((hunk_index, line_index)) = Self::find_run(hunks, run) {
+            let hunk = &mut hunks[hunk_index];
+            let range = line_index..line_index + run.len();
+            hunk.line_numbers = hunk.source_line_numbers();
+            hunk.line_numbers.splice(range.clone(), replacement.iter().map(|_| None));
+            hunk.lines.splice(range, replacement);
+        }
+    }
+    
+    /// Compute a line diff between two versions of a method
+    ///
+    /// Lines shared at the start and end are kept as context, and the longest common
+    /// subsequence of the lines between them is found, which is cheap for method-sized
+    /// inputs. Its table grows with the product of both sides' lengths, so when either
+    /// side has more than `MAX_LINE_DIFF_LINES` changed lines they're shown as a plain
+    /// delete and add instead.
+    ///
+    /// # Returns
+    ///
+    /// The diff as unified diff lines with ` `, `-` and `+` prefixes
+    fn line_diff(old: &[String], new: &[String]) -> Vec<String> {
+        let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let (old_middle, new_middle) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+        
+        let mut diff: Vec<String> = old[..prefix].iter().map(|line| format!(" {}", line)).collect();
+        if old_middle.len() > MAX_LINE_DIFF_LINES || new_middle.len() > MAX_LINE_DIFF_LINES {
+            diff.extend(old_middle.iter().map(|line| format!("-{}", line)));
+            diff.extend(new_middle.iter().map(|line| format!("+{}", line)));
+        } else {
+            diff.extend(Self::lcs_diff(old_middle, new_middle));
+        }
+        diff.extend(old[old.len() - suffix..].iter().map(|line| format!(" {}", line)));
+        diff
+    }
+    
+    /// Compute a line diff from the longest common subsequence of lines
+    ///
+    /// # Returns
+    ///
+    /// The diff as unified diff lines with ` `, `-` and `+` prefixes
+    fn lcs_diff(old: &[String], new: &[String]) -> Vec<String> {
+        let (n, m) = (old.len(), new.len());
+        
+        // lcs[i][j] is the LCS length of old[i..] and new[j..]
+        let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old[i] == new[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        
+        let mut diff = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old[i] == new[j] {
+                diff.push(format!(" {}", old[i]));
+                i += 1;
+                j += 1;
+            } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+                diff.push(format!("+{}", new[j]));
+                j += 1;
+            } else {
+                diff.push(format!("-{}", old[i]));
+                i += 1;
+            }
+        }
+        diff
+    }
+}
@@ -0,0 +1,109 @@
+use crate::error::Result;
+use crate::filters::csharp_parser::CSharpParser;
+use crate::filters::language_parser::LanguageParser;
+use crate::utils::diff_parser::{DiffSide, Hunk};
+
+/// Keywords that open a top-level function or class
+const SYMBOL_KEYWORDS: &[&str] = &["def ", "async def ", "class "];
+
+/// Represents a top-level Python function or class
+#[derive(Debug, PartialEq)]
+pub struct PythonSymbol {
+    /// Name of the function or class
+    pub name: String,
+    /// Start line of the symbol, including its decorators (1-indexed)
+    pub start_line: usize,
+    /// End line of the symbol, excluding trailing blank lines and unindented comments (1-indexed)
+    pub end_line: usize,
+    /// Whether this symbol contains changes
+    pub has_changes: bool,
+}
+
+/// Scanner for Python files that finds top-level functions and classes
+///
+/// This is not a full Python parser: a symbol starts at an unindented `def`,
+/// `async def` or `class` line, or at the decorators right above it, and runs
+/// until the next unindented line of code. Comments and closing brackets at the
+/// start of a line don't end a symbol, but unindented lines inside a multi-line
+/// string do.
+pub struct PythonParser;
+
+impl PythonParser {
+    /// Find the top-level functions and classes in one side of a Python file
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Python file content
+    /// * `hunks` - The diff hunks to identify changed symbols
+    /// * `side` - The side of the diff the code comes from
+    pub fn parse_file_side(code: &str, hunks: &[Hunk], side: DiffSide) -> Vec<PythonSymbol> {
+        let lines: Vec<&str> = code.lines().collect();
+        let boundaries: Vec<usize> = (0..lines.len())
+            .filter(|&i| Self::is_top_level_code(lines[i]))
+            .collect();
+        
+        let mut symbols = Vec::new();
+        for (index, &line) in boundaries.iter().enumerate() {
+            let Some(name) = Self::symbol_name(lines[line]) else {
+                continue;
+            };
+            
+            // Decorators belong to the symbol they precede
+            let mut start = line;
+            for &previous in boundaries[..index].iter().rev() {
+                if previous + 1 != start || !lines[previous].starts_with('@') {
+                    break;
+                }
+                start = previous;
+            }
+            
+            let mut end = boundaries.get(index + 1).map_or(lines.len(), |&next| next) - 1;
+            while end > line && (lines[end].trim().is_empty() || lines[end].starts_with('#')) {
+                end -= 1;
+            }
+            
+            symbols.push(PythonSymbol {
+                name,
+                start_line: start + 1,
+                end_line: end + 1,
+                has_changes: CSharpParser::lines_contain_changes_on_side(start + 1, end + 1, hunks, side),
+            });
+        }
+        symbols
+    }
+    
+    /// Check whether a line is unindented code, which ends any symbol before it
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line to check
+    fn is_top_level_code(line: &str) -> bool {
+        match line.chars().next() {
+            Some(c) => !c.is_whitespace() && !matches!(c, '#' | ')' | ']' | '}'),
+            None => false,
+        }
+    }
+    
+    /// Get the name of the function or class declared on a line, if any
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The unindented line to check
+    fn symbol_name(line: &str) -> Option<String> {
+        let rest = SYMBOL_KEYWORDS.iter().find_map(|keyword| line.strip_prefix(keyword))?;
+        let name: String = rest.trim_start()
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect();
+        (!name.is_empty()).then_some(name)
+    }
+}
+
+impl LanguageParser for PythonParser {
+    fn changed_symbols(&mut self, code: &str, hunks: &[Hunk], side: DiffSide) -> Result<Vec<String>> {
+        Ok(Self::parse_file_side(code, hunks, side).into_iter()
+            .filter(|symbol| symbol.has_changes)
+            .map(|symbol| symbol.name)
+            .collect())
+    }
+}
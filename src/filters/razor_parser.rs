@@ -0,0 +1,150 @@
+use crate::error::Result;
+use crate::filters::csharp_parser::{CSharpFile, CSharpMethod, CSharpParser};
+use crate::filters::language_parser::LanguageParser;
+use crate::utils::diff_parser::{DiffSide, Hunk};
+
+/// Directives that open a block of C# code in a Razor file
+const CODE_BLOCK_DIRECTIVES: &[&str] = &["@code", "@functions", "@{"];
+
+/// Scanner for Razor (`.cshtml`/`.razor`) files that finds blocks of C# code
+///
+/// This is not a full Razor parser: it looks for `@code`, `@functions` and `@{`
+/// directives and follows braces to the end of each block, skipping braces inside
+/// string literals and line comments. The HTML between blocks is treated as context.
+pub struct RazorParser;
+
+impl RazorParser {
+    /// Find the C# code blocks in a Razor file
+    ///
+    /// Each block is reported as a method named after its directive, so the
+    /// method-aware filtering used for C# files can be applied to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - The Razor file content
+    /// * `hunks` - The diff hunks to identify changed blocks
+    pub fn parse_file(code: &str, hunks: &[Hunk]) -> CSharpFile {
+        let lines: Vec<&str> = code.lines().collect();
+        let mut methods = Vec::new();
+        
+        let mut i = 0;
+        while i < lines.len() {
+            let Some((directive, column)) = Self::find_directive(lines[i]) else {
+                i += 1;
+                continue;
+            };
+            
+            // Scan from just after the directive name, so the brace of `@{` is counted
+            let offset = column + directive.trim_end_matches('{').len();
+            match Self::find_block_end(&lines, i, offset) {
+                Some(end) => {
+                    let start_line = i + 1;
+                    let end_line = end + 1;
+                    methods.push(CSharpMethod {
+                        name: directive.to_string(),
+                        start_line,
+                        end_line,
+                        signature_line: start_line,
+                        text: lines[i..=end].join("\n"),
+                        has_changes: CSharpParser::lines_contain_changes(start_line, end_line, hunks),
+                    });
+                    i = end + 1;
+                }
+                // An unterminated block is left as plain context
+                None => i += 1,
+            }
+        }
+        
+        CSharpFile {
+            methods,
+            using_statements: Vec::new(),
+            class_declarations: Vec::new(),
+            namespace_declarations: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+    
+    /// Find the first code block directive on a line
+    ///
+    /// # Returns
+    ///
+    /// The directive and its byte offset in the line
+    fn find_directive(line: &str) -> Option<(&'static str, usize)> {
+        CODE_BLOCK_DIRECTIVES.iter()
+            .filter_map(|directive| {
+                line.match_indices(directive)
+                    // `@code` must not be the start of a longer identifier such as `@codeBehind`
+                    .find(|(idx, _)| {
+                        let next = line[idx + directive.len()..].chars().next();
+                        directive.ends_with('{') || !next.is_some_and(|c| c.is_alphanumeric() || c == '_')
+                    })
+                    .map(|(idx, _)| (*directive, idx))
+            })
+            .min_by_key(|(_, idx)| *idx)
+    }
+    
+    /// Find the line on which a code block closes
+    ///
+    /// # Arguments
+    ///
+    /// * `lines` - The lines of the file
+    /// * `start` - Index of the line containing the directive
+    /// * `offset` - Byte offset in the start line just after the directive
+    ///
+    /// # Returns
+    ///
+    /// The index of the line containing the closing brace, or None if the block never closes
+    fn find_block_end(lines: &[&str], start: usize, offset: usize) -> Option<usize> {
+        let mut depth = 0usize;
+        let mut opened = false;
+        
+        for (index, line) in lines.iter().enumerate().skip(start) {
+            let text = if index == start { &line[offset..] } else { line };
+            let mut chars = text.chars();
+            let mut in_string = false;
+            
+            while let Some(c) = chars.next() {
+                if in_string {
+                    match c {
+                        '\\' => { chars.next(); }
+                        '"' => in_string = false,
+                        _ => {}
+                    }
+                    continue;
+                }
+                
+                match c {
+                    '"' => in_string = true,
+                    '/' if chars.clone().next() == Some('/') => break,
+                    '{' => {
+                        depth += 1;
+                        opened = true;
+                    }
+                    '}' if opened => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(index);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        
+        None
+    }
+}
+
+impl LanguageParser for RazorParser {
+    /// Razor blocks are found on the new side only
+    fn parse_side(&self, _preferred: DiffSide) -> DiffSide {
+        DiffSide::New
+    }
+
+    fn changed_symbols(&mut self, code: &str, hunks: &[Hunk], _side: DiffSide) -> Result<Vec<String>> {
+        Ok(Self::parse_file(code, hunks).methods.into_iter()
+            .filter(|method| method.has_changes)
+            .map(|method| method.name)
+            .collect())
+    }
+}
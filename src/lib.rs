@@ -2,13 +2,19 @@
 pub mod utils {
     pub mod config_manager;
     pub mod diff_parser;
+    pub mod diff_result;
     pub mod token_counter;
     pub mod git_operations;
+    pub mod processed_cache;
 }
 
 pub mod filters {
     pub mod filter_manager;
     pub mod csharp_parser;
+    pub mod razor_parser;
+    pub mod moved_methods;
+    pub mod python_parser;
+    pub mod language_parser;
 }
 
 pub mod error;
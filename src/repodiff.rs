@@ -1,12 +1,27 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::Path;
+use std::io::{BufRead, BufWriter, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
 
-use crate::error::Result;
-use crate::utils::config_manager::ConfigManager;
-use crate::utils::git_operations::GitOperations;
-use crate::utils::diff_parser::DiffParser;
+use crate::error::{RepoDiffError, Result};
+use crate::utils::config_manager::{Config, ConfigManager, DirectoryConfigs, LineEnding, MethodDetail};
+use crate::utils::git_operations::{BlameLine, GitOperations, WhitespaceError, EMPTY_TREE_HASH, FULL_FILE_CONTEXT};
+use crate::utils::diff_parser::{DiffParser, FileHeaders, Hunk, HunklessChange, OutputFormat, OutputOptions, PathPrefixes, REFORMAT_WARNING_MIN_LINES, REFORMAT_WARNING_RATIO};
+use crate::utils::diff_result::{ChangeKind, Chunk, DiffResult, FileSummary};
+use crate::utils::processed_cache::{ProcessedFiles, ProcessedFilesCache};
 use crate::utils::token_counter::TokenCounter;
-use crate::filters::filter_manager::FilterManager;
+use crate::filters::filter_manager::{FilterManager, MethodChange, OmitReason};
+
+/// Name of the manifest written alongside the per-file diffs in an output directory
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Name of the path mapping written into an output directory when paths are anonymized
+pub const PATH_MAP_FILE_NAME: &str = "paths.json";
+
+/// Name of the resolved configuration written beside the output for audits
+pub const RESOLVED_CONFIG_FILE_NAME: &str = "config.resolved.json";
 
 /// Main class for the RepoDiff tool that handles the core functionality
 pub struct RepoDiff {
@@ -16,6 +31,128 @@ pub struct RepoDiff {
     filter_manager: FilterManager,
     /// Git operations
     git_operations: GitOperations,
+    /// Options controlling how the output is rendered
+    output_options: OutputOptions,
+    /// Number of context lines to request from git diff
+    git_unified_context: usize,
+    /// Line ending used when writing output files
+    output_line_ending: LineEnding,
+    /// Maximum number of tokens the output may use
+    max_tokens: Option<usize>,
+    /// Tokens reserved for the rest of the prompt, subtracted from the maximum
+    reserved_tokens: usize,
+    /// Whether to bypass all filtering and emit the parsed diff as-is
+    raw: bool,
+    /// Whether to prepend git's diff --stat output
+    git_stat: bool,
+    /// Whether to append the names of unchanged files that sit alongside changed ones
+    include_unchanged_files: bool,
+    /// Whether to append the whitespace errors git diff --check finds in the changed files
+    check_whitespace: bool,
+    /// Whether working tree comparisons also include untracked files as additions
+    include_untracked: bool,
+    /// Only include files changed by commits from authors matching this pattern
+    author: Option<String>,
+    /// If set, only files matching this glob pattern are kept from the parsed diff
+    only_pattern: Option<String>,
+    /// If set, only files of this language, e.g. `csharp`, are kept from the parsed diff
+    only_language: Option<String>,
+    /// If set, only files with exactly these paths are kept from the parsed diff
+    only_paths: Option<HashSet<String>>,
+    /// Whether to append an index of the changed files and methods after the diff
+    trailing_index: bool,
+    /// Whether to append a summary of the C# methods whose signatures changed
+    signature_changes: bool,
+    /// Whether to label files whose permissions or symlink target changed
+    show_mode_changes: bool,
+    /// Whether to group hunks under the C# method they change instead of by file
+    group_by_symbol: bool,
+    /// The prefixes of the paths in diffs given as text, or None to detect them
+    path_prefixes: Option<PathPrefixes>,
+    /// Config files in subdirectories that override the filter rules for files under them
+    directory_configs: Option<DirectoryConfigs>,
+    /// If set, the output is truncated to at most this many bytes at a file boundary
+    max_output_bytes: Option<usize>,
+    /// If set, up to this many removed lines are annotated with git blame
+    blame_limit: Option<usize>,
+    /// Commit whose version of the files is blamed, set while processing a commit range
+    blame_commit: Option<String>,
+    /// Whitespace-only and total changed lines counted by git, set while processing a commit range
+    whitespace_change_counts: Option<(usize, usize)>,
+    /// Whether git counts a commit range's changed lines for the reformatting warning even when nothing else needs them
+    count_whitespace_changes: bool,
+    /// If set, only the full diff of this (file, method name) is emitted
+    method_query: Option<(String, String)>,
+    /// If set, only the hunks of this file overlapping these new-file lines (first, last) are kept
+    range_in_file: Option<(String, usize, usize)>,
+    /// If set, only these files are kept from the parsed diff
+    file_allowlist: Option<HashSet<String>>,
+    /// Paths of the files changed in the last processed diff
+    changed_files: Vec<String>,
+    /// Warnings raised while processing the last diff
+    warnings: Vec<String>,
+    /// If set, the output is split into part files of at most this many tokens
+    chunk_tokens: Option<usize>,
+    /// Part files written for the last processed diff
+    chunks: Vec<Chunk>,
+    /// Whether the output path is a directory receiving one file per changed file
+    split_into_dir: bool,
+    /// If set, the instructions preamble is written to this file instead of the output
+    instructions_file: Option<String>,
+    /// Whether file paths are replaced with anonymized names in the output
+    anonymize_paths: bool,
+    /// Mapping from each original path to its anonymized name, for the last processed diff
+    path_map: BTreeMap<String, String>,
+    /// The configuration this instance was created from, with any command-line overrides applied
+    config: Config,
+    /// Whether the resolved configuration is written beside the output
+    write_config: bool,
+    /// Files of the last processed diff, as they were rendered, unless it was streamed
+    processed_files: HashMap<String, Vec<Hunk>>,
+    /// Summaries of the files of the last processed diff, in output order, if it was streamed
+    streamed_files: Option<Vec<FileSummary>>,
+    /// Number of tokens in the last processed diff
+    token_count: usize,
+    /// Hex-encoded SHA-256 hash of the last processed diff
+    output_hash: String,
+    /// Number of tokens in the instructions preamble of the last processed diff
+    preamble_token_count: usize,
+    /// Number of tokens in the last processed diff, excluding the instructions preamble
+    content_token_count: usize,
+    /// Price in dollars per 1K input tokens of the token counting model, if configured
+    model_price: Option<f64>,
+    /// If set, filtered files are cached in this directory, keyed by the diff and configuration
+    cache_dir: Option<String>,
+}
+
+/// An output being written one file at a time, as the diff's files are parsed
+struct StreamedOutput {
+    /// The output file
+    writer: BufWriter<fs::File>,
+    /// Hash of the bytes written so far
+    hasher: Sha256,
+    /// Tokens written so far, summed over each piece of text as it's written
+    token_count: usize,
+    /// Options for rendering a single file's section
+    options: OutputOptions,
+    /// The instructions preamble, until it's written before the first file
+    preamble: Option<String>,
+    /// Number of files written so far
+    file_count: usize,
+    /// Top-level directory of the last file written, for the directory headings
+    current_dir: Option<String>,
+    /// Added and removed lines of the selected files, as the diff shows them
+    shown_changed_lines: usize,
+    /// Whitespace-only and total changed lines of the selected files, if counted from the diff
+    whitespace_change_counts: Option<(usize, usize)>,
+    /// Whether each file's directory rules are loaded with it, when the changed files aren't known up front
+    loads_directory_filters: bool,
+    /// The first error writing the output, after which the remaining files are skipped
+    error: Option<RepoDiffError>,
+    /// Path of the last file parsed, to check that the diff is in path order
+    last_path: Option<String>,
+    /// Whether a file came out of path order, after which the diff must be collected instead
+    out_of_order: bool,
 }
 
 impl RepoDiff {
@@ -26,17 +163,110 @@ impl RepoDiff {
     /// * `config_file_name` - The name of the configuration file to load
     pub fn new(config_file_name: &str) -> Result<Self> {
         let config_manager = ConfigManager::new(config_file_name)?;
-        let token_counter = TokenCounter::new(config_manager.get_tiktoken_model())?;
-        let filter_manager = FilterManager::new(config_manager.get_filters());
-        let git_operations = GitOperations::new();
+        let mut repodiff = Self::from_config(config_manager.get_config())?;
+        repodiff.set_directory_configs(Some(config_manager.get_directory_configs().clone()));
+        Ok(repodiff)
+    }
+    
+    /// Initialize the RepoDiff tool from an already loaded configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration to use, with any command-line overrides applied
+    pub fn from_config(config: &Config) -> Result<Self> {
+        ConfigManager::check_filter_flag_conflicts(config)?;
+        ConfigManager::check_token_budget(config.max_tokens, config.reserved_tokens)?;
+        let token_counter = TokenCounter::new(&config.tiktoken_model)?;
+        let filter_manager = FilterManager::from_config(config)?;
+        let git_operations = GitOperations::with_retry_attempts(config.git_retry_attempts);
+        // The filters need at least their own context, and method-aware filtering the whole
+        // file, even if less context is configured
+        let git_unified_context = config.git_unified_context
+            .unwrap_or_default()
+            .max(filter_manager.required_unified_context());
+        let output_options = OutputOptions {
+            test_file_pattern: config.test_file_pattern.clone(),
+            wrap_width: config.wrap_width,
+            max_line_length: config.max_line_length,
+            show_trailing_whitespace: config.show_trailing_whitespace,
+            extension_language_map: config.extension_language_map.clone(),
+            preserve_original_headers: config.preserve_original_headers,
+            plain_context: config.plain_context,
+            verbose_markers: config.verbose_markers,
+            legend: config.legend,
+            compact_renames: config.compact_renames,
+            preamble_sections: config.preamble_sections.clone(),
+            file_separator: config.file_separator.clone(),
+            format: config.format,
+            ..OutputOptions::default()
+        };
         
         Ok(RepoDiff {
             token_counter,
             filter_manager,
             git_operations,
+            output_options,
+            git_unified_context,
+            output_line_ending: config.output_line_ending,
+            max_tokens: config.max_tokens,
+            reserved_tokens: config.reserved_tokens,
+            raw: false,
+            git_stat: false,
+            include_unchanged_files: false,
+            check_whitespace: false,
+            include_untracked: false,
+            author: None,
+            only_pattern: config.only_pattern.clone(),
+            only_language: config.only_language.clone(),
+            only_paths: None,
+            method_query: None,
+            range_in_file: None,
+            trailing_index: false,
+            signature_changes: false,
+            show_mode_changes: false,
+            path_prefixes: None,
+            group_by_symbol: false,
+            directory_configs: None,
+            max_output_bytes: config.max_output_bytes,
+            blame_limit: None,
+            blame_commit: None,
+            whitespace_change_counts: None,
+            count_whitespace_changes: config.count_whitespace_changes,
+            file_allowlist: None,
+            changed_files: Vec::new(),
+            warnings: Vec::new(),
+            chunk_tokens: None,
+            chunks: Vec::new(),
+            split_into_dir: false,
+            instructions_file: None,
+            anonymize_paths: false,
+            path_map: BTreeMap::new(),
+            config: config.clone(),
+            write_config: false,
+            processed_files: HashMap::new(),
+            streamed_files: None,
+            token_count: 0,
+            output_hash: String::new(),
+            preamble_token_count: 0,
+            content_token_count: 0,
+            model_price: config.model_pricing.get(&config.tiktoken_model).copied(),
+            cache_dir: None,
         })
     }
     
+    /// Enable or disable raw mode
+    ///
+    /// In raw mode the parsed diff is reconstructed without any context trimming,
+    /// method awareness or instructions preamble, which is useful as a baseline
+    /// to compare against git's native output.
+    ///
+    /// # Arguments
+    ///
+    /// * `raw` - Whether to bypass all filtering
+    pub fn set_raw(&mut self, raw: bool) {
+        self.raw = raw;
+    }
+    
     /// Process the diff between two commits and write the result to a file
     ///
     /// # Arguments
@@ -49,40 +279,2433 @@ impl RepoDiff {
     ///
     /// The number of tokens in the processed diff
     pub fn process_diff(&mut self, commit1: &str, commit2: &str, output_file: &str) -> Result<usize> {
-        // Get the raw diff output
-        let raw_diff = self.git_operations.run_git_diff(commit1, commit2)?;
+        if self.output_options.format == OutputFormat::PatchSeries {
+            return self.process_ranges(&[(commit1.to_string(), commit2.to_string())], output_file);
+        }
+        if self.can_stream() {
+            return self.stream_commit_range(commit1, Some(commit2), output_file);
+        }
+        let final_output = self.process_commit_range(commit1, Some(commit2))?;
+        self.write_output(&final_output, output_file)
+    }
+    
+    /// Process the diff between a commit and the working tree and write the result to a file
+    ///
+    /// Both changes committed since the commit and uncommitted changes to tracked files are included.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit` - The commit to compare the working tree with
+    /// * `output_file` - The file to write the processed diff to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the processed diff
+    pub fn process_diff_against_working_tree(&mut self, commit: &str, output_file: &str) -> Result<usize> {
+        if self.output_options.format == OutputFormat::PatchSeries {
+            return Err(RepoDiffError::GeneralError("The patch-series format needs a commit range, not the working tree".to_string()));
+        }
+        // Untracked files are added after git's diff, so they'd be out of path order
+        if self.can_stream() && !self.include_untracked {
+            return self.stream_commit_range(commit, None, output_file);
+        }
+        let final_output = self.process_commit_range(commit, None)?;
+        self.write_output(&final_output, output_file)
+    }
+    
+    /// Process several commit ranges into one combined output
+    ///
+    /// Each range is processed on its own and its output is preceded by a
+    /// `# Range: A..B` separator. With the patch-series format, each commit in a range
+    /// is processed on its own instead, preceded by a `# Commit: <hash> <subject>`
+    /// separator. The instructions preamble appears once, at the top.
+    ///
+    /// # Arguments
+    ///
+    /// * `ranges` - The (first, second) commit pairs to compare, in output order
+    /// * `output_file` - The file to write the combined diff to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the combined output
+    pub fn process_ranges(&mut self, ranges: &[(String, String)], output_file: &str) -> Result<usize> {
+        // Each range would be rendered as a separate document, labeled outside of it
+        if self.output_options.format == OutputFormat::Html {
+            return Err(RepoDiffError::GeneralError("Ranges can't be used with the html format".to_string()));
+        }
         
-        // Parse and process the diff
-        let patch_dict = DiffParser::parse_unified_diff(&raw_diff)?;
-        let processed_dict = self.filter_manager.post_process_files(&patch_dict);
+        // (label, first commit, second commit) of each section, in output order
+        let mut labeled_ranges = Vec::new();
+        for (commit1, commit2) in ranges {
+            if self.output_options.format != OutputFormat::PatchSeries {
+                labeled_ranges.push((format!("# Range: {}..{}", commit1, commit2), commit1.clone(), commit2.clone()));
+                continue;
+            }
+            // Paths would be numbered differently in each commit's section
+            if self.anonymize_paths {
+                return Err(RepoDiffError::GeneralError("Anonymized paths can't be used with the patch-series format".to_string()));
+            }
+            for commit in self.git_operations.list_commits(&format!("{}..{}", commit1, commit2))? {
+                let parent = self.git_operations.get_previous_commit(&commit.hash)?;
+                let label = format!("# Commit: {} {}", &commit.hash[..12.min(commit.hash.len())], commit.subject);
+                labeled_ranges.push((label, parent, commit.hash));
+            }
+        }
+        // An output directory has one file per changed file, which would merge the sections' changes to it
+        if self.split_into_dir && labeled_ranges.len() > 1 {
+            return Err(RepoDiffError::GeneralError(
+                "Several ranges or commits can't be written to an output directory, as their changes to a file would be merged".to_string()
+            ));
+        }
         
-        // Get filters as JSON if available
-        let filters_json = self.filter_manager.get_filters_json();
+        let include_instructions = self.output_options.include_instructions;
+        self.output_options.include_instructions = false;
+        
+        let mut sections = Vec::new();
+        let mut warnings = Vec::new();
+        // The files of every range are kept, so the legend and summary cover all of them
+        let mut processed_files: HashMap<String, Vec<Hunk>> = HashMap::new();
+        let result: Result<()> = labeled_ranges.iter().try_for_each(|(label, commit1, commit2)| {
+            let output = self.process_commit_range(commit1, Some(commit2))?;
+            warnings.append(&mut self.warnings);
+            for (path, hunks) in std::mem::take(&mut self.processed_files) {
+                processed_files.entry(path).or_default().extend(hunks);
+            }
+            sections.push(format!("{}\n{}", label, output));
+            Ok(())
+        });
+        self.output_options.include_instructions = include_instructions;
+        result?;
+        self.warnings = warnings;
+        self.processed_files = processed_files;
+        
+        // The preamble is shared by all ranges
+        self.preamble_token_count = 0;
+        if include_instructions && !self.raw {
+            let filters_json = self.filter_manager.get_filters_json();
+            let preamble = DiffParser::preamble(&self.processed_files, filters_json.as_deref(), &self.output_options).join("\n");
+            self.preamble_token_count = self.token_counter.count_tokens(&preamble);
+            sections.insert(0, preamble);
+        }
+        
+        self.write_output(&sections.join("\n\n"), output_file)
+    }
+    
+    /// Run git diff for a commit range and process it, including the optional stat and unchanged-files sections
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    fn process_commit_range(&mut self, commit1: &str, commit2: Option<&str>) -> Result<String> {
+        let tree_commit = commit2.unwrap_or("HEAD");
+        let (changed_lines, unified) = self.prepare_commit_range(commit1, commit2, false)?;
+        
+        // Git's output is parsed as it streams in, and files that aren't selected are
+        // dropped right away; the selected files are all kept for filtering
+        let mut patch_dict = HashMap::new();
+        let mut headers = self.git_operations.stream_git_diff(commit1, commit2, unified, |path, hunks, _| {
+            if self.is_selected_path(&path) {
+                patch_dict.insert(path, hunks);
+            }
+        })?;
+        
+        // The lines git counted beyond those the diff shows only change whitespace; without
+        // git's count, they're counted from the parsed hunks when the files are processed
+        let mut counts = changed_lines.map(|changed_lines| {
+            let changed: usize = changed_lines.values().sum();
+            let shown = patch_dict.values()
+                .flatten()
+                .flat_map(|hunk: &Hunk| &hunk.lines)
+                .filter(|line| line.starts_with(['+', '-']))
+                .count();
+            (changed.saturating_sub(shown), changed)
+        });
+        
+        // Git diff never shows untracked files, so they're added as new files
+        if commit2.is_none() && self.include_untracked {
+            let untracked_diff = self.untracked_files_diff()?;
+            let untracked = DiffParser::parse_unified_diff(&untracked_diff)?;
+            let untracked_headers = DiffParser::parse_file_headers(&untracked_diff, None)?;
+            headers.hunkless_changes.extend(untracked_headers.hunkless_changes);
+            headers.mode_changes.extend(untracked_headers.mode_changes);
+            if let Some((whitespace_only, changed)) = &mut counts {
+                let (untracked_whitespace_only, untracked_changed) = DiffParser::count_whitespace_only_changes(&untracked);
+                *whitespace_only += untracked_whitespace_only;
+                *changed += untracked_changed;
+            }
+            patch_dict.extend(untracked);
+        }
+        self.whitespace_change_counts = counts;
+        
+        // Removed lines are blamed at the first commit, where they still exist
+        self.blame_commit = self.blame_limit.map(|_| commit1.to_string());
+        let result = self.process_patch_dict(patch_dict, headers);
+        self.blame_commit = None;
+        let mut final_output = result?;
+        
+        // Prepend git's own change histogram if requested, or append it to keep the
+        // static content at the front in cache-friendly mode
+        if self.git_stat {
+            let stat = self.git_operations.run_git_diff_stat(commit1, commit2)?;
+            final_output = if self.output_options.cache_friendly {
+                format!("{}\n\n{}", final_output, stat.trim_end())
+            } else {
+                format!("{}\n{}", stat.trim_end(), final_output)
+            };
+        }
+        
+        for section in self.commit_range_appendix(commit1, commit2, tree_commit)? {
+            final_output = format!("{}\n\n{}", final_output, section);
+        }
+        
+        Ok(final_output)
+    }
+    
+    /// Get ready to diff a commit range: restrict it to the author's files, count git's
+    /// changed lines if needed and settle the filters that depend on which files changed
+    ///
+    /// Git's count takes a full extra diff, so it's only run when the filters must be settled
+    /// before the diff, or when `count_whitespace_changes` asks for it.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `streaming` - Whether the files are written as they're parsed, so the filters
+    ///   can't be settled from the parsed files afterwards
+    ///
+    /// # Returns
+    ///
+    /// The changed lines git counted for each selected file, or None if they weren't counted,
+    /// and the context git must give
+    fn prepare_commit_range(
+        &mut self,
+        commit1: &str,
+        commit2: Option<&str>,
+        streaming: bool,
+    ) -> Result<(Option<HashMap<String, usize>>, usize)> {
+        // The working tree has no commits of its own, so history and trees are read from HEAD
+        let tree_commit = commit2.unwrap_or("HEAD");
+        
+        // Restrict the output to the files touched by the author's commits
+        if let Some(author) = &self.author {
+            // The empty tree a root commit is diffed against isn't a commit, so it can't bound a range
+            let range = if commit1 == EMPTY_TREE_HASH {
+                tree_commit.to_string()
+            } else {
+                format!("{}..{}", commit1, tree_commit)
+            };
+            let files = self.git_operations.files_changed_by_author(&range, author)?;
+            self.file_allowlist = Some(files.into_iter().collect());
+        }
+        
+        // Raw mode must show git's full output regardless of the filters, and signature
+        // maps and symbol indexes need whole files to find the changed symbols
+        let whole_files = self.raw || matches!(self.output_options.format, OutputFormat::SignatureMap | OutputFormat::SymbolIndex);
+        
+        // Auto-tuned rules depend on the diff's language and directory configs on where the
+        // changed files are, and both decide how much context git must give, unless git
+        // already gives the whole file and the rules can wait for the parsed files
+        let settles_filters = (self.filter_manager.auto_tunes_filters() || self.directory_configs.is_some())
+            && !whole_files
+            && (streaming || self.git_unified_context < FULL_FILE_CONTEXT);
+        if !settles_filters && !self.count_whitespace_changes {
+            let unified = if whole_files { FULL_FILE_CONTEXT } else { self.git_unified_context };
+            return Ok((None, unified));
+        }
+        
+        // Git's line counts include the lines that only change whitespace, which the diff below
+        // ignores, and also list the changed files
+        let changed_lines: HashMap<String, usize> = self.git_operations.count_changed_lines(commit1, commit2, false)?
+            .into_iter()
+            .filter(|(path, _)| self.is_selected_path(path))
+            .collect();
+        
+        if settles_filters {
+            self.filter_manager.auto_tune_filters(changed_lines.keys().map(String::as_str));
+            if let Some(directory_configs) = &self.directory_configs {
+                let directory_filters = directory_configs.load_filters(changed_lines.keys().map(String::as_str))?;
+                self.filter_manager.set_directory_filters(directory_filters);
+            }
+            self.git_unified_context = self.git_unified_context.max(self.filter_manager.required_unified_context());
+        }
+        let unified = if whole_files { FULL_FILE_CONTEXT } else { self.git_unified_context };
+        
+        Ok((Some(changed_lines), unified))
+    }
+    
+    /// Get the sections appended after a commit range's files: the names of unchanged
+    /// sibling files for structural context, and the whitespace errors git finds
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `tree_commit` - The commit whose tree the unchanged files are listed from
+    fn commit_range_appendix(&mut self, commit1: &str, commit2: Option<&str>, tree_commit: &str) -> Result<Vec<String>> {
+        let mut sections = Vec::new();
+        if self.include_unchanged_files {
+            let unchanged = self.list_unchanged_files(tree_commit)?;
+            if !unchanged.is_empty() {
+                sections.push(unchanged);
+            }
+        }
+        
+        if self.check_whitespace {
+            let errors = self.git_operations.check_whitespace_errors(commit1, commit2)?;
+            if let Some(section) = self.whitespace_errors_section(&errors) {
+                sections.push(section);
+            }
+        }
+        Ok(sections)
+    }
+    
+    /// Run git diff for a commit range and write each file to the output as soon as git's
+    /// output for it is parsed, including the optional stat and appended sections
+    ///
+    /// Only used when `can_stream` allows it. Files are written in git's order, which is path order.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `output_file` - The file to write the processed diff to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the processed diff
+    fn stream_commit_range(&mut self, commit1: &str, commit2: Option<&str>, output_file: &str) -> Result<usize> {
+        let tree_commit = commit2.unwrap_or("HEAD");
+        let (changed_lines, unified) = self.prepare_commit_range(commit1, commit2, true)?;
+        
+        let mut output = self.begin_streamed_output(output_file)?;
+        // Without git's count, whitespace-only changes are counted from each file's hunks
+        if changed_lines.is_none() {
+            output.whitespace_change_counts = Some((0, 0));
+        }
+        if self.git_stat {
+            let stat = self.git_operations.run_git_diff_stat(commit1, commit2)?;
+            self.write_streamed_text(&mut output, &format!("{}\n", stat.trim_end()))?;
+        }
+        
+        // The callback needs all of self, so git is run from the moved-out operations
+        let git_operations = std::mem::take(&mut self.git_operations);
+        let result = git_operations.stream_git_diff(commit1, commit2, unified, |path, hunks, headers| {
+            self.stream_file(&mut output, path, hunks, headers);
+        });
+        self.git_operations = git_operations;
+        let headers = result?;
+        // Git lists files in path order unless told otherwise, e.g. by diff.orderFile
+        if output.out_of_order {
+            drop(output);
+            let final_output = self.process_commit_range(commit1, commit2)?;
+            return self.write_output(&final_output, output_file);
+        }
+        
+        // The lines git counted beyond those the diff shows only change whitespace
+        let (whitespace_only, changed) = match &changed_lines {
+            Some(changed_lines) => {
+                let changed: usize = changed_lines.values().sum();
+                (changed.saturating_sub(output.shown_changed_lines), changed)
+            }
+            None => output.whitespace_change_counts.unwrap_or_default(),
+        };
+        self.finish_streamed_files(&mut output, headers, whitespace_only, changed)?;
+        
+        let appendix = self.commit_range_appendix(commit1, commit2, tree_commit)?;
+        self.close_streamed_output(output, &appendix, output_file)
+    }
+    
+    /// Check whether the output can be written one file at a time while the diff is parsed
+    ///
+    /// Anything that needs every file before the first is written, such as a token budget,
+    /// the legend, a file tree or deduplicated hunks, makes the diff be collected first. So
+    /// does the processed-files cache, whose key is computed from the whole diff.
+    fn can_stream(&self) -> bool {
+        let options = &self.output_options;
+        matches!(options.format, OutputFormat::Text | OutputFormat::Markdown)
+            && !self.raw
+            && !options.legend
+            && !options.cache_friendly
+            && !options.file_tree
+            && self.get_effective_budget().is_none()
+            && self.max_output_bytes.is_none()
+            && self.chunk_tokens.is_none()
+            && !self.split_into_dir
+            && !self.anonymize_paths
+            && self.method_query.is_none()
+            && self.range_in_file.is_none()
+            && self.blame_limit.is_none()
+            && !self.group_by_symbol
+            && !self.signature_changes
+            && !self.trailing_index
+            && !self.filter_manager.dedupes_identical_hunks()
+            && self.cache_dir.is_none()
+    }
+    
+    /// Open the output for writing a diff one file at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file` - The file to write the processed diff to
+    fn begin_streamed_output(&mut self, output_file: &str) -> Result<StreamedOutput> {
+        if let Some(parent) = Path::new(output_file).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let writer = BufWriter::new(fs::File::create(output_file)?);
+        
+        self.filter_manager.begin_post_processing();
+        self.warnings.clear();
+        self.changed_files.clear();
+        self.processed_files.clear();
+        self.streamed_files = Some(Vec::new());
+        self.path_map.clear();
+        self.chunks.clear();
+        self.preamble_token_count = 0;
+        self.output_options.mode_changes.clear();
+        self.output_options.hunkless_changes.clear();
+        self.output_options.opaque_files.clear();
+        
+        // Without the legend, the preamble doesn't depend on the files
+        let preamble = self.output_options.include_instructions.then(|| {
+            let filters_json = self.filter_manager.get_filters_json();
+            DiffParser::preamble(&HashMap::new(), filters_json.as_deref(), &self.output_options).join("\n")
+        });
+        
+        Ok(StreamedOutput {
+            writer,
+            hasher: Sha256::new(),
+            token_count: 0,
+            options: self.section_options(),
+            preamble,
+            file_count: 0,
+            current_dir: None,
+            shown_changed_lines: 0,
+            whitespace_change_counts: None,
+            loads_directory_filters: false,
+            error: None,
+            last_path: None,
+            out_of_order: false,
+        })
+    }
+    
+    /// Filter a file of a streamed diff and write it to the output
+    ///
+    /// Once writing fails, the remaining files are skipped and the error is kept for
+    /// `finish_streamed_files` to return. Collected diffs are written in path order, so
+    /// once a file comes out of that order the remaining files are skipped too, and the
+    /// caller collects the diff instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output being written
+    /// * `path` - The path of the file
+    /// * `hunks` - The file's hunks as parsed from the diff
+    /// * `headers` - What the headers of the files parsed so far say that their hunks don't
+    fn stream_file(&mut self, output: &mut StreamedOutput, path: String, hunks: Vec<Hunk>, headers: &FileHeaders) {
+        if output.error.is_some() || output.out_of_order {
+            return;
+        }
+        if output.last_path.as_ref().is_some_and(|last_path| path <= *last_path) {
+            output.out_of_order = true;
+            return;
+        }
+        output.last_path = Some(path.clone());
+        if !self.is_selected_path(&path) {
+            return;
+        }
+        
+        // A file only follows the configs of the directories above it
+        if output.loads_directory_filters
+            && let Some(directory_configs) = &self.directory_configs
+        {
+            match directory_configs.load_filters([path.as_str()]) {
+                Ok(directory_filters) => self.filter_manager.set_directory_filters(directory_filters),
+                Err(e) => {
+                    output.error = Some(e);
+                    return;
+                }
+            }
+        }
+        
+        self.changed_files.push(path.clone());
+        self.warnings.extend(DiffParser::validate_file_hunks(&path, &hunks));
+        output.shown_changed_lines += hunks.iter()
+            .flat_map(|hunk| &hunk.lines)
+            .filter(|line| line.starts_with(['+', '-']))
+            .count();
+        if let Some((whitespace_only, changed)) = &mut output.whitespace_change_counts {
+            let (file_whitespace_only, file_changed) = DiffParser::count_file_whitespace_only_changes(&hunks);
+            *whitespace_only += file_whitespace_only;
+            *changed += file_changed;
+        }
+        
+        let Some(processed_hunks) = self.filter_manager.post_process_file(&path, &hunks) else {
+            return;
+        };
+        
+        let result = self.write_streamed_file(output, &path, &processed_hunks, headers);
+        if let Err(e) = result {
+            output.error = Some(e);
+        }
+    }
+    
+    /// Write a filtered file of a streamed diff to the output, after the preamble or the previous file
+    ///
+    /// Only the file's summary is kept afterwards, not its hunks, so memory doesn't grow
+    /// with the output.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output being written
+    /// * `path` - The path of the file
+    /// * `processed_hunks` - The file's filtered hunks
+    /// * `headers` - What the headers of the files parsed so far say that their hunks don't
+    fn write_streamed_file(
+        &mut self,
+        output: &mut StreamedOutput,
+        path: &str,
+        processed_hunks: &[Hunk],
+        headers: &FileHeaders,
+    ) -> Result<()> {
+        let mut text = String::new();
+        if output.file_count == 0 {
+            if let Some(preamble) = output.preamble.take() {
+                self.preamble_token_count = self.write_streamed_text(output, &preamble)?;
+                text.push('\n');
+            }
+        } else {
+            text.push('\n');
+            if let Some(separator) = &output.options.file_separator {
+                text.push_str(separator);
+                text.push('\n');
+            }
+        }
+        let dir = DiffParser::top_level_dir(path).to_string();
+        if self.output_options.group_by_dir && output.current_dir.as_ref() != Some(&dir) {
+            text.push_str(&format!("# Directory: {}\n", dir));
+            output.current_dir = Some(dir);
+        }
+        self.write_streamed_text(output, &text)?;
+        
+        // Only this file's labels are passed on, so rendering it doesn't copy everyone's
+        if self.show_mode_changes && let Some(mode_change) = headers.mode_changes.get(path) {
+            output.options.mode_changes.insert(path.to_string(), mode_change.clone());
+        }
+        if let Some(hunkless_change) = headers.hunkless_changes.get(path) {
+            output.options.hunkless_changes.insert(path.to_string(), hunkless_change.clone());
+        }
+        if self.filter_manager.is_opaque(path) {
+            output.options.opaque_files.insert(path.to_string());
+        }
+        let section = Self::render_section(&output.options, path, processed_hunks);
+        output.options.mode_changes.clear();
+        output.options.hunkless_changes.clear();
+        output.options.opaque_files.clear();
+        output.file_count += 1;
+        
+        // Flushing puts each file on disk before the next one is parsed
+        let tokens = self.write_streamed_text(output, &section)?;
+        output.writer.flush()?;
+        let summary = self.file_summary(path, processed_hunks, tokens);
+        self.streamed_files.get_or_insert_with(Vec::new).push(summary);
+        Ok(())
+    }
+    
+    /// Write text to a streamed output, adding it to the output's hash and token count
+    ///
+    /// Each piece is tokenized on its own, so the output's token count is a sum that can
+    /// differ by a few tokens from counting the whole output at once, where tokens may
+    /// merge across the pieces' boundaries.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output being written
+    /// * `text` - The text to write
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the text
+    fn write_streamed_text(&self, output: &mut StreamedOutput, text: &str) -> Result<usize> {
+        let written = self.output_line_ending.normalize(text);
+        output.writer.write_all(written.as_bytes())?;
+        output.hasher.update(written.as_bytes());
+        let tokens = self.token_counter.count_tokens(text);
+        output.token_count += tokens;
+        Ok(tokens)
+    }
+    
+    /// Wrap up the files of a streamed diff, keeping what the rest of the run reports
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output being written
+    /// * `headers` - What the diff's file headers say that its hunks don't
+    /// * `whitespace_only` - The number of changed lines that only change whitespace
+    /// * `changed` - The number of changed lines
+    fn finish_streamed_files(
+        &mut self,
+        output: &mut StreamedOutput,
+        headers: FileHeaders,
+        whitespace_only: usize,
+        changed: usize,
+    ) -> Result<()> {
+        if let Some(e) = output.error.take() {
+            return Err(e);
+        }
+        self.filter_manager.finish_post_processing();
+        self.warn_if_reformatting(whitespace_only, changed);
+        self.warnings.extend(self.filter_manager.get_warnings().iter().cloned());
+        self.output_options.mode_changes = if self.show_mode_changes { headers.mode_changes } else { HashMap::new() };
+        self.output_options.hunkless_changes = headers.hunkless_changes;
+        self.output_options.opaque_files = self.filter_manager.get_opaque_files().clone();
+        Ok(())
+    }
+    
+    /// Write the sections that follow the files of a streamed diff and close the output
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output being written
+    /// * `appendix` - The sections to append after the files
+    /// * `output_file` - The file the processed diff was written to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the processed diff
+    fn close_streamed_output(&mut self, mut output: StreamedOutput, appendix: &[String], output_file: &str) -> Result<usize> {
+        for section in appendix {
+            self.write_streamed_text(&mut output, &format!("\n\n{}", section))?;
+        }
+        output.writer.flush()?;
+        
+        self.output_hash = Self::hex_digest(output.hasher);
+        self.token_count = output.token_count;
+        self.content_token_count = output.token_count.saturating_sub(self.preamble_token_count);
+        self.write_sidecar_files(output_file)?;
+        Ok(output.token_count)
+    }
+    
+    /// Process an existing unified diff, such as one read from a file or stdin, and write the result to a file
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_diff` - The unified diff to process
+    /// * `output_file` - The file to write the processed diff to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the processed diff
+    pub fn process_diff_text(&mut self, raw_diff: &str, output_file: &str) -> Result<usize> {
+        let final_output = self.process_raw_diff(raw_diff)?;
+        self.write_output(&final_output, output_file)
+    }
+    
+    /// Process a unified diff read from a reader, such as stdin, and write the result to a file
+    ///
+    /// When `can_stream` allows it, each file is written as soon as it's parsed, so the
+    /// diff is never held in memory as a whole. Otherwise, and when the rules are
+    /// auto-tuned to the language of all the changed files, the diff is read in full first.
+    /// Files are always written in path order, so a diff whose files aren't in that order
+    /// is read again from the start and collected, which is why the reader must be seekable.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the unified diff
+    /// * `output_file` - The file to write the processed diff to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the processed diff
+    pub fn process_diff_reader<R: BufRead + Seek>(&mut self, mut reader: R, output_file: &str) -> Result<usize> {
+        if !self.can_stream() || self.filter_manager.auto_tunes_filters() {
+            let mut raw_diff = String::new();
+            reader.read_to_string(&mut raw_diff)?;
+            return self.process_diff_text(&raw_diff, output_file);
+        }
+        
+        let mut output = self.begin_streamed_output(output_file)?;
+        output.whitespace_change_counts = Some((0, 0));
+        output.loads_directory_filters = true;
+        let path_prefixes = self.path_prefixes.clone();
+        let headers = DiffParser::parse_unified_diff_reader(&mut reader, path_prefixes.as_ref(), |path, hunks, headers| {
+            self.stream_file(&mut output, path, hunks, headers);
+        })?;
+        if output.out_of_order {
+            drop(output);
+            reader.rewind()?;
+            let mut raw_diff = String::new();
+            reader.read_to_string(&mut raw_diff)?;
+            return self.process_diff_text(&raw_diff, output_file);
+        }
+        let (whitespace_only, changed) = output.whitespace_change_counts.unwrap_or_default();
+        self.finish_streamed_files(&mut output, headers, whitespace_only, changed)?;
+        self.close_streamed_output(output, &[], output_file)
+    }
+    
+    /// Build a unified diff adding each untracked file, as if diffed against /dev/null
+    ///
+    /// Like git, links aren't followed but added with their target as content, so links
+    /// to directories or to missing files are diffed too, and executable files get their
+    /// own mode. Line endings and a missing final newline are kept as they are in the
+    /// file. Files that aren't valid UTF-8 are reported as binary.
+    fn untracked_files_diff(&self) -> Result<String> {
+        let root = self.git_operations.get_repo_root()?;
         
-        let final_output = DiffParser::reconstruct_patch(
-            &processed_dict,
-            filters_json.as_deref()
-        );
+        let mut sections = Vec::new();
+        for path in self.git_operations.list_untracked_files()? {
+            let full_path = Path::new(&root).join(&path);
+            let metadata = fs::symlink_metadata(&full_path)?;
+            let (mode, content) = if metadata.file_type().is_symlink() {
+                ("120000", fs::read_link(&full_path)?.to_string_lossy().into_owned().into_bytes())
+            } else if Self::is_executable(&metadata) {
+                ("100755", fs::read(&full_path)?)
+            } else {
+                ("100644", fs::read(&full_path)?)
+            };
+            
+            let mut section = vec![
+                format!("diff --git a/{} b/{}", path, path),
+                format!("new file mode {}", mode),
+            ];
+            match String::from_utf8(content) {
+                Ok(content) => {
+                    section.push("--- /dev/null".to_string());
+                    section.push(format!("+++ b/{}", path));
+                    if !content.is_empty() {
+                        // Only the newline is split off, so a carriage return stays on its line
+                        let lines: Vec<&str> = content.strip_suffix('\n').unwrap_or(&content).split('\n').collect();
+                        section.push(format!("@@ -0,0 +1,{} @@", lines.len()));
+                        section.extend(lines.iter().map(|line| format!("+{}", line)));
+                        if !content.ends_with('\n') {
+                            section.push("\\ No newline at end of file".to_string());
+                        }
+                    }
+                }
+                Err(_) => section.push(format!("Binary files /dev/null and b/{} differ", path)),
+            }
+            sections.push(section.join("\n"));
+        }
         
+        Ok(sections.join("\n"))
+    }
+    
+    /// Check whether a file is executable by its owner, as git records with mode 100755
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The file's metadata
+    #[cfg(unix)]
+    fn is_executable(metadata: &fs::Metadata) -> bool {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o100 != 0
+    }
+    
+    /// Check whether a file is executable, which file permissions can't tell outside Unix
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The file's metadata
+    #[cfg(not(unix))]
+    fn is_executable(_metadata: &fs::Metadata) -> bool {
+        false
+    }
+    
+    /// Write the processed diff to a file and count its tokens
+    ///
+    /// # Arguments
+    ///
+    /// * `final_output` - The processed diff
+    /// * `output_file` - The file to write the processed diff to
+    ///
+    /// # Returns
+    ///
+    /// The number of tokens in the processed diff
+    fn write_output(&mut self, final_output: &str, output_file: &str) -> Result<usize> {
         // Create output directory if it doesn't exist
         if let Some(parent) = Path::new(output_file).parent() {
             fs::create_dir_all(parent)?;
         }
         
-        // Write the processed diff to the output file
-        fs::write(output_file, &final_output)?;
+        // The limit is on a single output file, so it can't be kept when writing several
+        if self.max_output_bytes.is_some() && (self.split_into_dir || self.chunk_tokens.is_some()) {
+            return Err(RepoDiffError::GeneralError(
+                "max_output_bytes can't be used with an output directory or chunks".to_string()
+            ));
+        }
+        
+        // The limit applies to the bytes written, after the line endings are converted, and
+        // anything added after the files may still push the output over it
+        let mut written = self.output_line_ending.normalize(final_output);
+        let capped;
+        let final_output = match self.max_output_bytes {
+            Some(max_bytes) if written.len() > max_bytes => {
+                written = Self::cap_output(&written, max_bytes);
+                capped = LineEnding::Lf.normalize(&written);
+                capped.as_str()
+            }
+            _ => final_output,
+        };
+        
+        // Write the processed diff to the output file, to part files when chunking,
+        // or to one file per changed file when writing a directory
+        self.chunks.clear();
+        let mut hasher = Sha256::new();
+        if self.split_into_dir {
+            self.write_files_to_dir(output_file, &mut hasher)?;
+        } else if let Some(chunk_tokens) = self.chunk_tokens {
+            self.chunks = self.write_chunks(output_file, chunk_tokens, &mut hasher)?;
+        } else {
+            fs::write(output_file, &written)?;
+            hasher.update(written.as_bytes());
+        }
+        self.output_hash = Self::hex_digest(hasher);
         
         // Calculate token count
-        let token_count = self.token_counter.count_tokens(&final_output);
+        let token_count = self.token_counter.count_tokens(final_output);
+        self.token_count = token_count;
+        self.content_token_count = token_count.saturating_sub(self.preamble_token_count);
+        
+        // The manifest carries the token counts, so it's written last
+        if self.split_into_dir {
+            let manifest = Path::new(output_file).join(MANIFEST_FILE_NAME);
+            self.get_diff_result().write_json(&manifest.to_string_lossy())?;
+        }
         
+        self.write_sidecar_files(output_file)?;
         Ok(token_count)
     }
     
-    /// Get the default output file path in the temporary directory
-    pub fn get_default_output_file() -> String {
+    /// Write the files that go beside the output: the instructions file, the map of
+    /// anonymized paths and the resolved configuration, each if enabled
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file` - The output file or directory
+    fn write_sidecar_files(&self, output_file: &str) -> Result<()> {
+        // The preamble is the same every run, so it can be sent once, e.g. as a system prompt
+        if let Some(instructions_file) = &self.instructions_file {
+            let filters_json = self.filter_manager.get_filters_json();
+            let preamble = DiffParser::preamble(&self.processed_files, filters_json.as_deref(), &self.output_options).join("\n");
+            if let Some(parent) = Path::new(instructions_file).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(instructions_file, self.output_line_ending.normalize(&preamble))?;
+        }
+        
+        // The mapping back to the real paths is kept beside the output for internal reference
+        if self.anonymize_paths {
+            fs::write(Self::path_map_file_name(output_file, self.split_into_dir), serde_json::to_string_pretty(&self.path_map)?)?;
+        }
+        
+        if self.write_config {
+            fs::write(Self::resolved_config_file_name(output_file, self.split_into_dir), serde_json::to_string_pretty(&self.resolved_config()?)?)?;
+        }
+        Ok(())
+    }
+    
+    /// Split the processed files into part files of at most `chunk_tokens` tokens each
+    ///
+    /// Files are never split across parts, so a single file larger than the limit
+    /// makes its part exceed it. The instructions preamble is only included in the first part.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file` - The output file the part file names are derived from
+    /// * `chunk_tokens` - The maximum number of tokens per part
+    /// * `hasher` - The hash of the output, which each part is added to as it's written
+    fn write_chunks(&mut self, output_file: &str, chunk_tokens: usize, hasher: &mut Sha256) -> Result<Vec<Chunk>> {
+        let mut paths: Vec<&String> = self.processed_files.keys().collect();
+        paths.sort();
+        
+        let files: Vec<(&str, &[Hunk])> = paths.iter()
+            .map(|path| (path.as_str(), self.processed_files[*path].as_slice()))
+            .collect();
+        let token_counts = self.count_files_tokens(&files);
+        
+        // Group files greedily in output order, starting a new part when the next file doesn't fit
+        let mut groups: Vec<Vec<&String>> = vec![Vec::new()];
+        let mut current_tokens = self.preamble_token_count;
+        for (path, tokens) in paths.into_iter().zip(token_counts) {
+            if groups.last().is_some_and(|group| !group.is_empty()) && current_tokens + tokens > chunk_tokens {
+                groups.push(Vec::new());
+                current_tokens = 0;
+            }
+            if let Some(group) = groups.last_mut() {
+                group.push(path);
+            }
+            current_tokens += tokens;
+        }
+        
+        let mut chunks = Vec::new();
+        for (index, group) in groups.iter().enumerate() {
+            let files: HashMap<String, Vec<Hunk>> = group.iter()
+                .map(|path| ((*path).clone(), self.processed_files[*path].clone()))
+                .collect();
+            let content = self.render_files(&files, index == 0);
+            let path = Self::part_file_name(output_file, index + 1);
+            let name = Path::new(&path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            Self::write_hashed(hasher, Path::new(&path), &name, &self.output_line_ending.normalize(&content))?;
+            
+            chunks.push(Chunk {
+                path,
+                tokens: self.token_counter.count_tokens(&content),
+                files: group.iter().map(|path| (*path).clone()).collect(),
+            });
+        }
+        
+        Ok(chunks)
+    }
+    
+    /// Write each processed file to its own file under a directory, mirroring the repository layout
+    ///
+    /// `src/foo/Bar.cs` is written to `<dir>/src/foo/Bar.cs.diff`. The instructions
+    /// preamble, if enabled, is written to `<dir>/instructions.txt`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory to write the files to
+    /// * `hasher` - The hash of the output, which each file is added to as it's written
+    fn write_files_to_dir(&self, dir: &str, hasher: &mut Sha256) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        
+        if self.preamble_token_count > 0 {
+            let filters_json = self.filter_manager.get_filters_json();
+            let preamble = DiffParser::preamble(&self.processed_files, filters_json.as_deref(), &self.output_options).join("\n");
+            let instructions = "instructions.txt";
+            Self::write_hashed(hasher, &Path::new(dir).join(instructions), instructions, &self.output_line_ending.normalize(&preamble))?;
+        }
+        
+        // Files are written in path order so the hash doesn't depend on the map's order
+        let mut paths: Vec<&String> = self.processed_files.keys().collect();
+        paths.sort();
+        for path in paths {
+            let file_path = Self::dir_output_path(dir, path);
+            if let Some(parent) = file_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let files = HashMap::from([(path.clone(), self.processed_files[path].clone())]);
+            let content = self.render_files(&files, false);
+            let name = file_path.strip_prefix(dir).unwrap_or(&file_path).to_string_lossy().replace('\\', "/");
+            Self::write_hashed(hasher, &file_path, &name, &self.output_line_ending.normalize(&content))?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Write one of several output files and add it to the hash of the output
+    ///
+    /// The file's name relative to the output is hashed before its content, each followed
+    /// by a NUL byte, so moving content between files changes the hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hash of the output
+    /// * `path` - The path to write the file to
+    /// * `name` - The file's name relative to the output
+    /// * `content` - The content to write
+    fn write_hashed(hasher: &mut Sha256, path: &Path, name: &str, content: &str) -> Result<()> {
+        fs::write(path, content)?;
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update(content.as_bytes());
+        hasher.update([0]);
+        Ok(())
+    }
+    
+    /// Get the path a file's diff is written to under an output directory
+    ///
+    /// Only the normal components of the path are kept, so a path can't escape the directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The output directory
+    /// * `path` - The path of the changed file
+    fn dir_output_path(dir: &str, path: &str) -> PathBuf {
+        let mut file_path = PathBuf::from(dir);
+        for component in Path::new(path).components() {
+            if let Component::Normal(part) = component {
+                file_path.push(part);
+            }
+        }
+        file_path.set_file_name(format!(
+            "{}.diff",
+            file_path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default()
+        ));
+        file_path
+    }
+    
+    /// Get the path of the sidecar file mapping original paths to anonymized names
+    ///
+    /// This is `output.txt.paths.json` for `output.txt`, or `paths.json` inside an output directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file` - The output file or directory
+    /// * `split_into_dir` - Whether the output is a directory
+    pub fn path_map_file_name(output_file: &str, split_into_dir: bool) -> String {
+        if split_into_dir {
+            Path::new(output_file).join(PATH_MAP_FILE_NAME).to_string_lossy().to_string()
+        } else {
+            format!("{}.{}", output_file, PATH_MAP_FILE_NAME)
+        }
+    }
+    
+    /// Get the path of the sidecar file holding the resolved configuration
+    ///
+    /// This is `output.txt.config.resolved.json` for `output.txt`, or `config.resolved.json` inside an output directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file` - The output file or directory
+    /// * `split_into_dir` - Whether the output is a directory
+    pub fn resolved_config_file_name(output_file: &str, split_into_dir: bool) -> String {
+        if split_into_dir {
+            Path::new(output_file).join(RESOLVED_CONFIG_FILE_NAME).to_string_lossy().to_string()
+        } else {
+            format!("{}.{}", output_file, RESOLVED_CONFIG_FILE_NAME)
+        }
+    }
+    
+    /// Render a set of processed files with the options used for the main output
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The files to render
+    /// * `include_instructions` - Whether to include the instructions preamble, if enabled
+    fn render_files(&self, files: &HashMap<String, Vec<Hunk>>, include_instructions: bool) -> String {
+        if self.raw {
+            let options = OutputOptions {
+                include_instructions: false,
+                ..OutputOptions::default()
+            };
+            return DiffParser::reconstruct_patch_with_options(files, None, &options);
+        }
+        
+        let options = OutputOptions {
+            include_instructions: include_instructions && self.output_options.include_instructions,
+            ..self.output_options.clone()
+        };
+        let filters_json = self.filter_manager.get_filters_json();
+        DiffParser::reconstruct_patch_with_options(files, filters_json.as_deref(), &options)
+    }
+    
+    /// Get the name of a part file, e.g. `output.part2.txt` for `output.txt`
+    ///
+    /// # Arguments
+    ///
+    /// * `output_file` - The output file the part belongs to
+    /// * `part` - The 1-based part number
+    fn part_file_name(output_file: &str, part: usize) -> String {
+        let path = Path::new(output_file);
+        let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+        let file_name = match path.extension() {
+            Some(extension) => format!("{}.part{}.{}", stem, part, extension.to_string_lossy()),
+            None => format!("{}.part{}", stem, part),
+        };
+        path.with_file_name(file_name).to_string_lossy().to_string()
+    }
+    
+    /// Split the output into part files of at most this many tokens
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_tokens` - The maximum number of tokens per part, or None to write a single file
+    pub fn set_chunk_tokens(&mut self, chunk_tokens: Option<usize>) {
+        self.chunk_tokens = chunk_tokens;
+    }
+    
+    /// Get the part files written for the last processed diff
+    pub fn get_chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+    
+    /// Enable or disable prepending git's diff --stat histogram to the output
+    ///
+    /// # Arguments
+    ///
+    /// * `git_stat` - Whether to include the stat output
+    pub fn set_git_stat(&mut self, git_stat: bool) {
+        self.git_stat = git_stat;
+    }
+    
+    /// Enable or disable listing unchanged files in the directories of changed files
+    ///
+    /// # Arguments
+    ///
+    /// * `include_unchanged_files` - Whether to list the unchanged sibling file names
+    pub fn set_include_unchanged_files(&mut self, include_unchanged_files: bool) {
+        self.include_unchanged_files = include_unchanged_files;
+    }
+    
+    /// Enable or disable appending the whitespace errors introduced by a commit range
+    ///
+    /// # Arguments
+    ///
+    /// * `check_whitespace` - Whether to run git diff --check and append the errors it reports
+    pub fn set_check_whitespace(&mut self, check_whitespace: bool) {
+        self.check_whitespace = check_whitespace;
+    }
+    
+    /// Warn if most of the changed lines of a diff only change whitespace
+    ///
+    /// Mostly reformatted diffs are flagged so the reviewer knows before reading them.
+    ///
+    /// # Arguments
+    ///
+    /// * `whitespace_only` - The number of changed lines that only change whitespace
+    /// * `changed` - The number of changed lines
+    fn warn_if_reformatting(&mut self, whitespace_only: usize, changed: usize) {
+        if changed >= REFORMAT_WARNING_MIN_LINES && whitespace_only as f64 > changed as f64 * REFORMAT_WARNING_RATIO {
+            self.warnings.push(format!(
+                "This diff appears to be mostly reformatting: {} of {} changed lines only change whitespace",
+                whitespace_only, changed
+            ));
+        }
+    }
+    
+    /// Build the section listing the whitespace errors in the files of the output, and
+    /// warn about them
+    ///
+    /// # Arguments
+    ///
+    /// * `errors` - The errors reported by git diff --check
+    ///
+    /// # Returns
+    ///
+    /// The section, or None if none of the errors are in the output's files
+    fn whitespace_errors_section(&mut self, errors: &[WhitespaceError]) -> Option<String> {
+        let lines: Vec<String> = errors.iter()
+            .filter_map(|error| {
+                let path = self.path_map.get(&error.path).unwrap_or(&error.path);
+                self.is_output_file(path)
+                    .then(|| format!("{}:{}: {}", path, error.line, error.message))
+            })
+            .collect();
+        if lines.is_empty() {
+            return None;
+        }
+        
+        self.warnings.push(format!(
+            "git diff --check found {} whitespace error{}",
+            lines.len(),
+            if lines.len() == 1 { "" } else { "s" }
+        ));
+        let mut section = vec!["# Whitespace errors".to_string()];
+        section.extend(lines);
+        Some(section.join("\n"))
+    }
+    
+    /// List the unchanged files in each directory containing a changed file
+    ///
+    /// Only file names are listed, not content, to keep the token cost low.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit` - The commit to list the directories at
+    ///
+    /// # Returns
+    ///
+    /// A section with one line per directory, or an empty string if there are no unchanged siblings
+    fn list_unchanged_files(&self, commit: &str) -> Result<String> {
+        // Group the changed file names by directory
+        let mut changed_by_dir: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for path in &self.changed_files {
+            let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+            changed_by_dir.entry(dir).or_default().push(name);
+        }
+        
+        let mut lines = Vec::new();
+        for (dir, changed) in changed_by_dir {
+            let unchanged: Vec<String> = self.git_operations.list_dir_files(commit, dir)?
+                .into_iter()
+                .filter(|name| !changed.contains(&name.as_str()))
+                .collect();
+            if !unchanged.is_empty() {
+                let dir = if dir.is_empty() { "." } else { dir };
+                lines.push(format!("{}/: {}", dir, unchanged.join(", ")));
+            }
+        }
+        
+        if lines.is_empty() {
+            return Ok(String::new());
+        }
+        
+        lines.insert(0, "# Unchanged files in changed directories:".to_string());
+        Ok(lines.join("\n"))
+    }
+    
+    /// Only include files changed by commits from a matching author
+    ///
+    /// # Arguments
+    ///
+    /// * `author` - Pattern matched against commit authors, as for `git log --author`
+    pub fn set_author(&mut self, author: Option<String>) {
+        self.author = author;
+    }
+    
+    /// Only include files matching a glob pattern
+    ///
+    /// # Arguments
+    ///
+    /// * `only_pattern` - The glob pattern files must match, e.g. `src/migrations/*.sql`
+    pub fn set_only_pattern(&mut self, only_pattern: Option<String>) {
+        self.only_pattern = only_pattern;
+    }
+    
+    /// Only include files of a language
+    ///
+    /// Languages are looked up from file extensions, with `extension_language_map`
+    /// taking precedence over the built-in mapping.
+    ///
+    /// # Arguments
+    ///
+    /// * `only_language` - The language files must be written in, e.g. `csharp`
+    pub fn set_only_language(&mut self, only_language: Option<String>) {
+        self.only_language = only_language;
+    }
+    
+    /// Include untracked files as additions when comparing against the working tree
+    ///
+    /// # Arguments
+    ///
+    /// * `include_untracked` - Whether to include untracked files
+    pub fn set_include_untracked(&mut self, include_untracked: bool) {
+        self.include_untracked = include_untracked;
+    }
+    
+    /// Only include files whose paths are in a list
+    ///
+    /// # Arguments
+    ///
+    /// * `only_paths` - The exact repository-relative paths to keep, or None to keep all files
+    pub fn set_only_paths(&mut self, only_paths: Option<HashSet<String>>) {
+        self.only_paths = only_paths;
+    }
+    
+    /// Cache the filtered files of each diff on disk, so a later run on an identical diff skips the filters
+    ///
+    /// Entries are keyed by a hash of the diff's files and the configuration they were
+    /// filtered with, so any change to either filters the diff again. The diff is still
+    /// parsed to compute the key; only filtering is skipped. Caching needs the whole diff,
+    /// so the output is no longer written while the diff is parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache_dir` - The directory holding the cache, or None to always run the filters
+    pub fn set_cache_dir(&mut self, cache_dir: Option<String>) {
+        self.cache_dir = cache_dir;
+    }
+    
+    /// Get the number of files run through the filter rules by this instance
+    ///
+    /// Files whose filtered diff was reused from the cache aren't counted.
+    pub fn get_filtered_file_count(&self) -> usize {
+        self.filter_manager.get_filtered_file_count()
+    }
+    
+    /// Get the default directory of the processed-files cache, in the temporary directory
+    pub fn get_default_cache_dir() -> String {
+        Self::default_output_path("cache")
+    }
+    
+    /// Override the method settings of the filter rules for all C# files
+    ///
+    /// Method-aware filtering needs the whole file, so git is asked for full context.
+    ///
+    /// # Arguments
+    ///
+    /// * `method_detail` - The method detail to use, or None to use the configured rules
+    pub fn set_method_detail(&mut self, method_detail: Option<MethodDetail>) {
+        self.filter_manager.set_method_detail(method_detail);
+        self.git_unified_context = self.git_unified_context.max(self.filter_manager.required_unified_context());
+    }
+    
+    /// Write the instructions preamble to a separate file instead of the output
+    ///
+    /// Without a file, whether the output includes the preamble is left as it was, unless
+    /// it was only left out because of an earlier instructions file.
+    ///
+    /// # Arguments
+    ///
+    /// * `instructions_file` - The file to write the preamble to, or None to keep it in the output
+    pub fn set_instructions_file(&mut self, instructions_file: Option<String>) {
+        if instructions_file.is_some() {
+            self.output_options.include_instructions = false;
+        } else if self.instructions_file.is_some() {
+            self.output_options.include_instructions = true;
+        }
+        self.instructions_file = instructions_file;
+    }
+    
+    /// Replace file paths with stable anonymized names such as `file_1.cs`
+    ///
+    /// The mapping back to the original paths is written next to the output.
+    ///
+    /// # Arguments
+    ///
+    /// * `anonymize_paths` - Whether to anonymize file paths
+    pub fn set_anonymize_paths(&mut self, anonymize_paths: bool) {
+        self.anonymize_paths = anonymize_paths;
+    }
+    
+    /// Write the resolved configuration beside the output, so the output can be traced to its settings
+    ///
+    /// The configuration is the one this instance was created from, as printed by `--print-config`.
+    ///
+    /// # Arguments
+    ///
+    /// * `write_config` - Whether to write the resolved configuration
+    pub fn set_write_config(&mut self, write_config: bool) {
+        self.write_config = write_config;
+    }
+    
+    /// Get the mapping from each original path to its anonymized name for the last processed diff
+    ///
+    /// The mapping is empty unless paths are anonymized.
+    pub fn get_path_map(&self) -> &BTreeMap<String, String> {
+        &self.path_map
+    }
+    
+    /// Limit the diff to a single file, e.g. to see how it changed since a commit
+    ///
+    /// The path is passed to git as a pathspec, so it applies to the diff and the stat.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The repository-relative path of the file, or None for the whole repository
+    pub fn set_file_path(&mut self, file_path: Option<String>) {
+        self.git_operations.set_pathspecs(file_path.into_iter().collect());
+    }
+    
+    /// Append an index of the changed files and their changed methods after the diff
+    ///
+    /// Git is asked for full-file context so the changed methods can be found.
+    ///
+    /// # Arguments
+    ///
+    /// * `trailing_index` - Whether to append the index
+    pub fn set_trailing_index(&mut self, trailing_index: bool) {
+        if trailing_index {
+            self.git_unified_context = FULL_FILE_CONTEXT;
+        }
+        self.trailing_index = trailing_index;
+    }
+    
+    /// Append a summary of the C# methods whose signatures changed after the diff
+    ///
+    /// Git is asked for full-file context so both sides of each method can be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `signature_changes` - Whether to append the summary
+    pub fn set_signature_changes(&mut self, signature_changes: bool) {
+        if signature_changes {
+            self.git_unified_context = FULL_FILE_CONTEXT;
+        }
+        self.signature_changes = signature_changes;
+    }
+    
+    /// Label files whose permissions or symlink target changed, which their hunks don't show
+    ///
+    /// # Arguments
+    ///
+    /// * `show_mode_changes` - Whether to add a line such as `symlink <path> now points to <target>` to each such file's section
+    pub fn set_show_mode_changes(&mut self, show_mode_changes: bool) {
+        self.show_mode_changes = show_mode_changes;
+    }
+    
+    /// Set the prefixes of the paths in diffs given as text, such as with `--src-prefix`/`--dst-prefix`
+    ///
+    /// # Arguments
+    ///
+    /// * `path_prefixes` - The prefixes, or None to detect them for each file
+    pub fn set_path_prefixes(&mut self, path_prefixes: Option<PathPrefixes>) {
+        self.path_prefixes = path_prefixes;
+    }
+    
+    /// Group hunks under the C# method they change instead of by file
+    ///
+    /// Experimental. Changes to a method of the same name in several files, such as
+    /// a renamed method and its call sites, end up together under one heading. Git is
+    /// asked for full-file context so the methods can be found.
+    ///
+    /// # Arguments
+    ///
+    /// * `group_by_symbol` - Whether to group the output by changed method
+    pub fn set_group_by_symbol(&mut self, group_by_symbol: bool) {
+        if group_by_symbol {
+            self.git_unified_context = FULL_FILE_CONTEXT;
+        }
+        self.group_by_symbol = group_by_symbol;
+    }
+    
+    /// Apply config files found in subdirectories to the files under them
+    ///
+    /// # Arguments
+    ///
+    /// * `directory_configs` - The finder for directory configs, or None to use the root filter rules for every file
+    pub fn set_directory_configs(&mut self, directory_configs: Option<DirectoryConfigs>) {
+        self.directory_configs = directory_configs;
+    }
+    
+    /// Limit the size of the written output
+    ///
+    /// Files that don't fit are left out whole and a truncation notice is appended.
+    /// Anything added after the files, such as the stat or the index, is cut at a line
+    /// boundary if it would still exceed the limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_output_bytes` - The maximum number of bytes to write, or None for no limit
+    pub fn set_max_output_bytes(&mut self, max_output_bytes: Option<usize>) {
+        self.max_output_bytes = max_output_bytes;
+    }
+    
+    /// Annotate removed lines with the commit and author that last changed them, using git blame
+    ///
+    /// This runs git blame for every changed file, so it's limited to a number of lines.
+    /// Only diffs of commit ranges are blamed.
+    ///
+    /// # Arguments
+    ///
+    /// * `blame_limit` - The maximum number of lines to blame, or None to disable blaming
+    pub fn set_blame(&mut self, blame_limit: Option<usize>) {
+        self.blame_limit = blame_limit;
+    }
+    
+    /// Emit only the full diff of one method instead of the filtered files
+    ///
+    /// Git is asked for full-file context so every line of the method is available.
+    ///
+    /// # Arguments
+    ///
+    /// * `method_query` - The path of the C# file and the name of the method, or None to process all files
+    pub fn set_method_query(&mut self, method_query: Option<(String, String)>) {
+        if method_query.is_some() {
+            self.git_unified_context = FULL_FILE_CONTEXT;
+        }
+        self.method_query = method_query;
+    }
+    
+    /// Keep only the changes of one file within a range of its lines
+    ///
+    /// The range refers to line numbers in the new version of the file, and each changed line
+    /// is checked against it: added lines outside it become context and removed lines outside
+    /// it are dropped, so hunks left without changes disappear. Other files are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `range_in_file` - The path of the file and its first and last line, or None to keep every change
+    pub fn set_range_in_file(&mut self, range_in_file: Option<(String, usize, usize)>) {
+        self.range_in_file = range_in_file;
+    }
+    
+    /// Write one file per changed file under the output path, which is treated as a directory
+    ///
+    /// # Arguments
+    ///
+    /// * `split_into_dir` - Whether to write a directory instead of a single output file
+    pub fn set_split_into_dir(&mut self, split_into_dir: bool) {
+        self.split_into_dir = split_into_dir;
+    }
+    
+    /// Set the maximum number of tokens the output may use
+    ///
+    /// # Arguments
+    ///
+    /// * `max_tokens` - The token limit, or None for no limit
+    pub fn set_max_tokens(&mut self, max_tokens: Option<usize>) {
+        self.max_tokens = max_tokens;
+    }
+    
+    /// Set the number of tokens reserved for the rest of the prompt
+    ///
+    /// # Arguments
+    ///
+    /// * `reserved_tokens` - Tokens used by the system prompt and instructions sent alongside the diff
+    pub fn set_reserved_tokens(&mut self, reserved_tokens: usize) {
+        self.reserved_tokens = reserved_tokens;
+    }
+    
+    /// Get the number of tokens available to the output
+    ///
+    /// # Returns
+    ///
+    /// The maximum number of tokens minus the reserved tokens, or None if there is no limit
+    pub fn get_effective_budget(&self) -> Option<usize> {
+        self.max_tokens.map(|max_tokens| max_tokens.saturating_sub(self.reserved_tokens))
+    }
+    
+    /// Get the summary of files left out of the last processed diff
+    ///
+    /// Returns None if no files were omitted, including in raw mode.
+    pub fn get_omitted_summary(&self) -> Option<String> {
+        if self.raw {
+            return None;
+        }
+        self.filter_manager.get_omitted_summary()
+    }
+    
+    /// Get the warnings raised while processing the last diff
+    pub fn get_warnings(&self) -> &[String] {
+        &self.warnings
+    }
+    
+    /// Get the number of tokens in the last processed diff, excluding the instructions preamble
+    ///
+    /// This is the number to budget with when the preamble is only sent once.
+    pub fn get_content_token_count(&self) -> usize {
+        self.content_token_count
+    }
+    
+    /// Get the estimated cost in dollars of the last processed diff, if the model has a configured price
+    pub fn get_estimated_cost(&self) -> Option<f64> {
+        self.model_price.map(|price| TokenCounter::estimate_cost(self.token_count, price))
+    }
+    
+    /// Get mutable access to the output options
+    pub fn output_options_mut(&mut self) -> &mut OutputOptions {
+        &mut self.output_options
+    }
+    
+    /// Parse, filter and reconstruct a raw unified diff
+    ///
+    /// # Arguments
+    ///
+    /// * `raw_diff` - The raw output of git diff
+    ///
+    /// # Returns
+    ///
+    /// The processed diff as a string
+    pub fn process_raw_diff(&mut self, raw_diff: &str) -> Result<String> {
+        let patch_dict = DiffParser::parse_unified_diff_with_prefixes(raw_diff, self.path_prefixes.as_ref())?;
+        let headers = DiffParser::parse_file_headers(raw_diff, self.path_prefixes.as_ref())?;
+        self.process_patch_dict(patch_dict, headers)
+    }
+    
+    /// Check whether a file passes the author, path, pattern and language restrictions
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file in the diff
+    fn is_selected_path(&self, path: &str) -> bool {
+        self.file_allowlist.as_ref().is_none_or(|allowlist| allowlist.contains(path))
+            && self.only_paths.as_ref().is_none_or(|paths| paths.contains(path))
+            && self.only_pattern.as_ref().is_none_or(|pattern| FilterManager::matches_pattern(pattern, path))
+            && self.only_language.as_ref().is_none_or(|language| {
+                DiffParser::language_for_path_with_map(path, &self.output_options.extension_language_map)
+                    .is_some_and(|file_language| file_language.eq_ignore_ascii_case(language))
+            })
+    }
+    
+    /// Process a parsed diff
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - The files of the diff and their hunks
+    /// * `headers` - What the diff's file headers say that its hunks don't, such as mode changes
+    ///
+    /// # Returns
+    ///
+    /// The processed diff as a string
+    fn process_patch_dict(
+        &mut self,
+        mut patch_dict: HashMap<String, Vec<Hunk>>,
+        headers: FileHeaders,
+    ) -> Result<String> {
+        ConfigManager::check_token_budget(self.max_tokens, self.reserved_tokens)?;
+        self.streamed_files = None;
+        patch_dict.retain(|path, _| self.is_selected_path(path));
+        self.filter_manager.auto_tune_filters(patch_dict.keys().map(String::as_str));
+        if let Some(directory_configs) = &self.directory_configs {
+            let directory_filters = directory_configs.load_filters(patch_dict.keys().map(String::as_str))?;
+            self.filter_manager.set_directory_filters(directory_filters);
+        }
+        self.output_options.mode_changes = if self.show_mode_changes { headers.mode_changes } else { HashMap::new() };
+        self.output_options.hunkless_changes = headers.hunkless_changes;
+        self.warnings = DiffParser::validate_hunks(&patch_dict);
+        
+        // Changes outside the requested lines are shown as unchanged, so the filters only
+        // expand the ones inside, however much context git gave
+        if let Some((path, first, last)) = &self.range_in_file {
+            match patch_dict.get(path) {
+                Some(hunks) => {
+                    let hunks = Self::keep_changes_in_range(hunks, *first, *last);
+                    if hunks.is_empty() {
+                        self.warnings.push(format!("{} has no changes in lines {}-{}", path, first, last));
+                        patch_dict.remove(path);
+                    } else {
+                        patch_dict.insert(path.clone(), hunks);
+                    }
+                }
+                None => self.warnings.push(format!("{} is not changed in this diff", path)),
+            }
+        }
+        
+        let (whitespace_only, changed) = self.whitespace_change_counts.take()
+            .unwrap_or_else(|| DiffParser::count_whitespace_only_changes(&patch_dict));
+        self.warn_if_reformatting(whitespace_only, changed);
+        self.preamble_token_count = 0;
+        self.changed_files = patch_dict.keys().cloned().collect();
+        self.path_map.clear();
+        
+        if self.raw {
+            // Raw mode ignores the filters, except that opaque files stay hidden
+            let opaque_files: HashSet<String> = patch_dict.keys()
+                .filter(|path| self.filter_manager.is_opaque(path))
+                .cloned()
+                .collect();
+            for path in &opaque_files {
+                patch_dict.insert(path.clone(), Vec::new());
+            }
+            let patch_dict = self.anonymize(patch_dict);
+            let options = OutputOptions {
+                include_instructions: false,
+                opaque_files: opaque_files.into_iter()
+                    .map(|path| self.path_map.get(&path).cloned().unwrap_or(path))
+                    .collect(),
+                ..OutputOptions::default()
+            };
+            let output = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+            self.processed_files = patch_dict;
+            return Ok(output);
+        }
+        
+        // A method query replaces the filtered files with the full diff of the one method
+        let mut processed_dict = match &self.method_query {
+            Some((path, name)) => {
+                let hunks = patch_dict.get(path)
+                    .ok_or_else(|| RepoDiffError::GeneralError(format!("{} is not changed in this diff", path)))?;
+                HashMap::from([(path.clone(), self.filter_manager.method_diff(path, hunks, name)?)])
+            }
+            None => {
+                let processed_dict = self.filter_files(&patch_dict)?;
+                self.warnings.extend(self.filter_manager.get_warnings().iter().cloned());
+                processed_dict
+            }
+        };
+        self.output_options.opaque_files = match &self.method_query {
+            Some((path, _)) if self.filter_manager.is_opaque(path) => HashSet::from([path.clone()]),
+            Some(_) => HashSet::new(),
+            None => self.filter_manager.get_opaque_files().clone(),
+        };
+        // Nothing derived from the original hunks, such as signatures or the index, may reveal hidden content
+        for path in &self.output_options.opaque_files {
+            patch_dict.insert(path.clone(), Vec::new());
+        }
+        
+        // A signature map lists the changed methods of the included files instead of their diffs,
+        // and a symbol index the changed symbols of any language
+        if matches!(self.output_options.format, OutputFormat::SignatureMap | OutputFormat::SymbolIndex) {
+            let symbol_index = self.output_options.format == OutputFormat::SymbolIndex;
+            let mut signatures: BTreeMap<String, Vec<String>> = processed_dict.keys()
+                .map(|path| {
+                    let symbols = if symbol_index {
+                        self.filter_manager.changed_symbols(path, &patch_dict[path])
+                    } else {
+                        self.filter_manager.changed_method_signatures(path, &patch_dict[path])
+                    };
+                    (path.clone(), symbols)
+                })
+                .collect();
+            self.processed_files = self.anonymize(processed_dict);
+            if self.anonymize_paths {
+                signatures = signatures.into_iter().map(|(path, methods)| (self.path_map[&path].clone(), methods)).collect();
+            }
+            return Ok(DiffParser::render_signature_map(&signatures));
+        }
+        
+        if let (Some(limit), Some(commit)) = (self.blame_limit, &self.blame_commit) {
+            self.annotate_blame(&mut processed_dict, &patch_dict, commit, limit)?;
+        }
+        
+        // Get filters as JSON if available
+        let filters_json = self.filter_manager.get_filters_json();
+        
+        // Count the preamble separately so it can be excluded from the content token count
+        let mut preamble_bytes = 0;
+        if self.output_options.include_instructions
+            && self.output_options.format != OutputFormat::Html
+            && !processed_dict.is_empty()
+        {
+            let preamble = DiffParser::preamble(&processed_dict, filters_json.as_deref(), &self.output_options).join("\n");
+            self.preamble_token_count = self.token_counter.count_tokens(&preamble);
+            preamble_bytes = self.output_line_ending.normalize(&preamble).len();
+        }
+        
+        if let Some(budget) = self.get_effective_budget() {
+            processed_dict = self.apply_token_budget(processed_dict, budget, filters_json.as_deref());
+            if processed_dict.is_empty() {
+                self.preamble_token_count = 0;
+            }
+        }
+        
+        let mut truncated_files = 0;
+        if let Some(max_bytes) = self.max_output_bytes {
+            let file_count = processed_dict.len();
+            processed_dict = self.apply_output_limit(processed_dict, max_bytes, preamble_bytes);
+            truncated_files = file_count - processed_dict.len();
+            if processed_dict.is_empty() {
+                self.preamble_token_count = 0;
+            }
+        }
+        
+        // The index is built from the real paths, which the parser needs, and renamed afterwards
+        let mut index_paths: Vec<String> = processed_dict.keys().cloned().collect();
+        index_paths.sort();
+        
+        // Paths are only anonymized now, as the filters match against the real paths
+        let processed_dict = self.anonymize(processed_dict);
+        if self.anonymize_paths {
+            let mode_changes = std::mem::take(&mut self.output_options.mode_changes);
+            self.output_options.mode_changes = mode_changes.into_iter()
+                .filter_map(|(path, mode_change)| Some((self.path_map.get(&path)?.clone(), mode_change)))
+                .collect();
+            let opaque_files = std::mem::take(&mut self.output_options.opaque_files);
+            self.output_options.opaque_files = opaque_files.into_iter()
+                .filter_map(|path| self.path_map.get(&path).cloned())
+                .collect();
+        }
+        // The report is a complete HTML document, so nothing is appended to it
+        if self.output_options.format == OutputFormat::Html {
+            let output = DiffParser::render_html(&processed_dict, &self.output_options);
+            self.processed_files = processed_dict;
+            return Ok(output);
+        }
+        let mut output = if self.group_by_symbol {
+            self.render_by_symbol(&processed_dict, &patch_dict, filters_json.as_deref())
+        } else {
+            DiffParser::reconstruct_patch_with_options(
+                &processed_dict,
+                filters_json.as_deref(),
+                &self.output_options,
+            )
+        };
+        if self.signature_changes && !index_paths.is_empty() {
+            output = format!("{}\n\n{}", output, self.signature_changes_summary(&index_paths, &patch_dict));
+        }
+        if self.trailing_index && !index_paths.is_empty() {
+            output = format!("{}\n\n{}", output, self.changes_index(&index_paths, &patch_dict));
+        }
+        if let Some(max_bytes) = self.max_output_bytes
+            && truncated_files > 0
+        {
+            let notice = Self::truncation_notice(max_bytes, Some(truncated_files));
+            output = if output.is_empty() { notice } else { format!("{}\n\n{}", output, notice) };
+        }
+        self.processed_files = processed_dict;
+        Ok(output)
+    }
+    
+    /// Render the processed files with their hunks grouped under the C# method they change
+    ///
+    /// Each group starts with a `# Symbol: <method>` heading, in name order, and holds
+    /// the matching parts of the hunks of every file. Changes outside any method and
+    /// files that aren't C# follow under `# Other changes`.
+    ///
+    /// # Arguments
+    ///
+    /// * `processed_dict` - The processed files, with anonymized paths if enabled
+    /// * `patch_dict` - The files as parsed from git, keyed by their real paths
+    /// * `filters_json` - The filters as JSON, for the instructions preamble
+    fn render_by_symbol(
+        &mut self,
+        processed_dict: &HashMap<String, Vec<Hunk>>,
+        patch_dict: &HashMap<String, Vec<Hunk>>,
+        filters_json: Option<&str>,
+    ) -> String {
+        let mut paths: Vec<&String> = processed_dict.keys().collect();
+        paths.sort();
+        
+        let mut symbol_groups: BTreeMap<String, HashMap<String, Vec<Hunk>>> = BTreeMap::new();
+        let mut other_changes: HashMap<String, Vec<Hunk>> = HashMap::new();
+        for path in paths {
+            let hunks = &processed_dict[path];
+            let original_path = self.original_path(path).to_string();
+            let pieces = self.filter_manager.split_hunks_by_symbol(&original_path, &patch_dict[&original_path], hunks);
+            
+            // Files without hunks, such as binary files, still get a section
+            if hunks.is_empty() {
+                other_changes.insert(path.clone(), Vec::new());
+            }
+            for (symbol, piece) in pieces {
+                let group = match symbol {
+                    Some(symbol) => symbol_groups.entry(symbol).or_default(),
+                    None => &mut other_changes,
+                };
+                group.entry(path.clone()).or_default().push(piece);
+            }
+        }
+        
+        let mut output = Vec::new();
+        if self.output_options.include_instructions && !processed_dict.is_empty() {
+            output.extend(DiffParser::preamble(processed_dict, filters_json, &self.output_options));
+        }
+        if self.output_options.file_tree && !processed_dict.is_empty() {
+            output.push(DiffParser::render_file_tree(processed_dict));
+            output.push(String::new());
+        }
+        let options = OutputOptions {
+            include_instructions: false,
+            file_tree: false,
+            ..self.output_options.clone()
+        };
+        for (symbol, files) in &symbol_groups {
+            output.push(format!("# Symbol: {}", symbol));
+            output.push(DiffParser::reconstruct_patch_with_options(files, None, &options));
+        }
+        if !other_changes.is_empty() {
+            output.push("# Other changes".to_string());
+            output.push(DiffParser::reconstruct_patch_with_options(&other_changes, None, &options));
+        }
+        output.join("\n")
+    }
+    
+    /// Build the index appended after the diff: each file with its change label, line
+    /// counts and, for C# files, the signatures of its changed methods
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The real paths of the files in the output, in output order
+    /// * `patch_dict` - The files as parsed from git
+    fn changes_index(&mut self, paths: &[String], patch_dict: &HashMap<String, Vec<Hunk>>) -> String {
+        let mut index = vec!["# Changes index".to_string()];
+        for path in paths {
+            let hunks = &patch_dict[path];
+            let lines = hunks.iter().flat_map(|hunk| &hunk.lines);
+            let added = lines.clone().filter(|line| line.starts_with('+')).count();
+            let removed = lines.filter(|line| line.starts_with('-')).count();
+            let name = self.path_map.get(path).unwrap_or(path);
+            let label = DiffParser::change_label(hunks, self.output_options.hunkless_changes.get(name));
+            index.push(format!("{} {} +{} -{}", name, label, added, removed));
+            
+            let signatures = self.filter_manager.changed_method_signatures(path, hunks);
+            index.extend(signatures.iter().map(|signature| format!("  {}", signature)));
+        }
+        index.join("\n")
+    }
+    
+    /// Build the summary of signature changes appended after the diff: each C# file with
+    /// changed, added or removed method signatures, and a count of body-only changes
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The real paths of the files in the output, in output order
+    /// * `patch_dict` - The files as parsed from git
+    fn signature_changes_summary(&mut self, paths: &[String], patch_dict: &HashMap<String, Vec<Hunk>>) -> String {
+        let mut summary = vec!["# Signature changes".to_string()];
+        let mut body_only = 0;
+        for path in paths {
+            let mut lines = Vec::new();
+            for change in self.filter_manager.classify_method_changes(path, &patch_dict[path]) {
+                match change {
+                    MethodChange::SignatureChanged { old_signature, new_signature, .. } => {
+                        lines.push(format!("  - {}", old_signature));
+                        lines.push(format!("  + {}", new_signature));
+                    }
+                    MethodChange::Added { signature, .. } => lines.push(format!("  + {} [added]", signature)),
+                    MethodChange::Removed { signature, .. } => lines.push(format!("  - {} [removed]", signature)),
+                    MethodChange::BodyOnly { .. } => body_only += 1,
+                }
+            }
+            if !lines.is_empty() {
+                summary.push(self.path_map.get(path).unwrap_or(path).clone());
+                summary.extend(lines);
+            }
+        }
+        if summary.len() == 1 {
+            summary.push("(none)".to_string());
+        }
+        summary.push(format!("({} changed method{} with body-only changes)", body_only, if body_only == 1 { "" } else { "s" }));
+        summary.join("\n")
+    }
+    
+    /// Append the commit and author that last changed each removed line, e.g. `[blame 1a2b3c4 Alice]`
+    ///
+    /// Files are visited in output order, and blaming stops once `limit` lines have been blamed.
+    ///
+    /// # Arguments
+    ///
+    /// * `processed_dict` - The processed files, whose removed lines are annotated
+    /// * `patch_dict` - The files as parsed from git, giving renamed files' old paths
+    /// * `commit` - The commit at which the removed lines are blamed
+    /// * `limit` - The maximum number of lines to blame
+    fn annotate_blame(
+        &self,
+        processed_dict: &mut HashMap<String, Vec<Hunk>>,
+        patch_dict: &HashMap<String, Vec<Hunk>>,
+        commit: &str,
+        limit: usize,
+    ) -> Result<()> {
+        let mut paths: Vec<String> = processed_dict.keys().cloned().collect();
+        paths.sort();
+        
+        let mut remaining = limit;
+        for path in paths {
+            if remaining == 0 {
+                break;
+            }
+            
+            // Processed hunks keep each line's old line number, so removed lines are blamed by
+            // position even if the same text was removed more than once
+            let mut removed = Vec::new();
+            for (hunk_idx, hunk) in processed_dict[&path].iter().enumerate() {
+                for (line_idx, numbers) in hunk.source_line_numbers().into_iter().enumerate() {
+                    if let Some((old_line, _)) = numbers
+                        && hunk.lines[line_idx].starts_with('-')
+                        && removed.len() < remaining
+                    {
+                        removed.push((hunk_idx, line_idx, old_line));
+                    }
+                }
+            }
+            if removed.is_empty() {
+                continue;
+            }
+            remaining -= removed.len();
+            
+            let old_path = patch_dict[&path].iter().find_map(|hunk| hunk.rename_from.as_deref()).unwrap_or(&path);
+            let mut old_lines: Vec<usize> = removed.iter().map(|(_, _, old_line)| *old_line).collect();
+            old_lines.sort_unstable();
+            old_lines.dedup();
+            let ranges = Self::line_ranges(old_lines.into_iter());
+            let blame: HashMap<usize, BlameLine> = self.git_operations.blame_lines(commit, old_path, &ranges)?
+                .into_iter()
+                .map(|blame| (blame.line, blame))
+                .collect();
+            
+            let hunks = processed_dict.get_mut(&path).expect("path is a key of the processed files");
+            for (hunk_idx, line_idx, old_line) in removed {
+                if let Some(blame) = blame.get(&old_line) {
+                    hunks[hunk_idx].lines[line_idx].push_str(&format!("  [blame {} {}]", blame.commit, blame.author));
+                }
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Treat the changes outside a range of new lines as unchanged
+    ///
+    /// Removed lines outside the range are dropped and added lines become context, as with
+    /// ignored lines. A pure deletion has no new lines, so it is placed at the line it follows.
+    /// Hunks left without changes are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks of the file
+    /// * `first` - The first line of the range in the new file
+    /// * `last` - The last line of the range in the new file
+    fn keep_changes_in_range(hunks: &[Hunk], first: usize, last: usize) -> Vec<Hunk> {
+        hunks.iter()
+            .filter_map(|hunk| {
+                let mut new_hunk = hunk.clone();
+                new_hunk.lines.clear();
+                new_hunk.line_numbers.clear();
+                let mut new_line = hunk.new_start;
+                let mut lines = hunk.lines.iter().zip(hunk.source_line_numbers()).peekable();
+                while let Some((line, numbers)) = lines.next() {
+                    if !line.starts_with(['+', '-']) {
+                        new_hunk.lines.push(line.clone());
+                        new_hunk.line_numbers.push(numbers);
+                        if !line.starts_with('\\') {
+                            new_line += 1;
+                        }
+                        continue;
+                    }
+                    
+                    let mut run = vec![(line, numbers)];
+                    run.extend(std::iter::from_fn(|| lines.next_if(|(l, _)| l.starts_with(['+', '-']))));
+                    let added = run.iter().filter(|(l, _)| l.starts_with('+')).count();
+                    let (run_first, run_last) = match added {
+                        0 => (new_line.saturating_sub(1), new_line.saturating_sub(1)),
+                        _ => (new_line, new_line + added - 1),
+                    };
+                    for (line, numbers) in run {
+                        if run_first <= last && run_last >= first {
+                            new_hunk.lines.push(line.clone());
+                        } else if let Some(content) = line.strip_prefix('+') {
+                            new_hunk.lines.push(format!(" {}", content));
+                        } else {
+                            continue;
+                        }
+                        new_hunk.line_numbers.push(numbers);
+                    }
+                    new_line += added;
+                }
+                new_hunk.lines.iter()
+                    .any(|line| line.starts_with('+') || line.starts_with('-'))
+                    .then_some(new_hunk)
+            })
+            .collect()
+    }
+    
+    /// Run a diff's files through the filters, reusing the cached result for an identical diff and configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - The diff's selected files
+    ///
+    /// # Returns
+    ///
+    /// The filtered files, with the filter manager's omitted files, opaque files and warnings set as if it filtered them
+    fn filter_files(&mut self, patch_dict: &HashMap<String, Vec<Hunk>>) -> Result<HashMap<String, Vec<Hunk>>> {
+        let Some(cache_dir) = &self.cache_dir else {
+            return Ok(self.filter_manager.post_process_files(patch_dict));
+        };
+        
+        let cache = ProcessedFilesCache::new(cache_dir);
+        let key = ProcessedFilesCache::key(&self.resolved_config()?, patch_dict)?;
+        if let Some(cached) = cache.load(&key) {
+            self.filter_manager.restore_post_processing(cached.omitted_files, cached.opaque_files, cached.warnings);
+            return Ok(cached.files);
+        }
+        
+        let processed = ProcessedFiles {
+            files: self.filter_manager.post_process_files(patch_dict),
+            omitted_files: self.filter_manager.get_omitted_files().to_vec(),
+            opaque_files: self.filter_manager.get_opaque_files().clone(),
+            warnings: self.filter_manager.get_warnings().to_vec(),
+        };
+        // A cache that can't be written only costs the next run its reuse
+        if let Err(e) = cache.store(&key, &processed) {
+            self.warnings.push(format!("Could not write the processed-files cache: {}", e));
+        }
+        Ok(processed.files)
+    }
+    
+    /// Get the configuration the last diff was processed with, for the resolved config sidecar
+    ///
+    /// The filters are those actually applied, after auto-tuning, and settings changed since
+    /// the config was loaded are included. Settings of the run that the config file can't
+    /// hold, such as the file selection, are added under their own keys.
+    fn resolved_config(&self) -> Result<serde_json::Value> {
+        let mut config = self.config.clone();
+        config.filters = self.filter_manager.get_filters().to_vec();
+        config.auto_tune_filters = false;
+        config.method_detail = self.filter_manager.get_method_detail();
+        config.format = self.output_options.format;
+        config.only_pattern = self.only_pattern.clone();
+        config.only_language = self.only_language.clone();
+        config.max_output_bytes = self.max_output_bytes;
+        config.git_unified_context = Some(self.git_unified_context);
+        
+        let mut resolved = serde_json::to_value(&config)?;
+        let mut only_paths: Vec<&String> = self.only_paths.iter().flatten().collect();
+        only_paths.sort();
+        resolved["directory_filters"] = serde_json::to_value(
+            self.filter_manager.get_directory_filters().iter().collect::<BTreeMap<_, _>>()
+        )?;
+        resolved["file_paths"] = serde_json::to_value(self.git_operations.get_pathspecs())?;
+        resolved["only_paths"] = serde_json::to_value(self.only_paths.as_ref().map(|_| only_paths))?;
+        resolved["author"] = serde_json::to_value(&self.author)?;
+        resolved["range_in_file"] = serde_json::to_value(self.range_in_file.as_ref().map(|(path, first, last)| {
+            format!("{}:{}-{}", path, first, last)
+        }))?;
+        Ok(resolved)
+    }
+    
+    /// Merge ascending line numbers into (first, last) ranges of consecutive lines
+    ///
+    /// # Arguments
+    ///
+    /// * `lines` - The line numbers, in ascending order
+    fn line_ranges(lines: impl Iterator<Item = usize>) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = Vec::new();
+        for line in lines {
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == line => *end = line,
+                _ => ranges.push((line, line)),
+            }
+        }
+        ranges
+    }
+    
+    /// Anonymize the paths of the processed files if enabled, recording the mapping
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The processed files
+    fn anonymize(&mut self, files: HashMap<String, Vec<Hunk>>) -> HashMap<String, Vec<Hunk>> {
+        if !self.anonymize_paths {
+            return files;
+        }
+        let (files, path_map) = DiffParser::anonymize_paths(files, &self.output_options.hunkless_changes);
+        self.path_map = path_map;
+        
+        // Files without hunks are labeled with their header's change, which names renames' old paths
+        let anonymize = |path: &String| self.path_map.get(path).cloned();
+        self.output_options.hunkless_changes = std::mem::take(&mut self.output_options.hunkless_changes)
+            .into_iter()
+            .filter_map(|(path, change)| {
+                let change = match change {
+                    HunklessChange::Renamed { from } => HunklessChange::Renamed { from: anonymize(&from)? },
+                    HunklessChange::Copied { from } => HunklessChange::Copied { from: anonymize(&from)? },
+                    change => change,
+                };
+                Some((anonymize(&path)?, change))
+            })
+            .collect();
+        files
+    }
+    
+    /// Keep files, in output order, while they fit in the token budget
+    ///
+    /// Each file's section is rendered and tokenized in parallel as it's expected to
+    /// appear, after the separator and directory heading the previous file leaves for it
+    /// and with the newline joining it to the next section, since tokens merge across
+    /// those. A section is only tokenized again if dropping a file before it changes its
+    /// separator or heading, or if it misses the budget by just the joining newline, which
+    /// the last section doesn't have. The kept files are then rendered together and counted
+    /// once; if tokens merging between sections still puts that over the budget, the last
+    /// files are dropped until their sizes cover the excess.
+    ///
+    /// # Arguments
+    ///
+    /// * `processed_dict` - The processed files
+    /// * `budget` - The number of tokens available, including the preamble
+    /// * `filters_json` - The filters shown in the preamble, if any
+    fn apply_token_budget(
+        &mut self,
+        mut processed_dict: HashMap<String, Vec<Hunk>>,
+        budget: usize,
+        filters_json: Option<&str>,
+    ) -> HashMap<String, Vec<Hunk>> {
+        let mut paths: Vec<String> = processed_dict.keys().cloned().collect();
+        paths.sort();
+        
+        let options = self.section_options();
+        let token_counter = &self.token_counter;
+        let expected: Vec<(String, String, usize)> = paths.par_iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let previous_path = index.checked_sub(1).map(|previous| paths[previous].as_str());
+                let prefix = self.section_prefix(path, previous_path);
+                let section = Self::render_section(&options, path, &processed_dict[path]);
+                let tokens = token_counter.count_tokens(&format!("{}{}\n", prefix, section));
+                (prefix, section, tokens)
+            })
+            .collect();
+        
+        // The preamble is joined to the first section by a newline too
+        let mut used = 0;
+        if self.preamble_token_count > 0 {
+            let preamble = DiffParser::preamble(&processed_dict, filters_json, &self.output_options).join("\n");
+            used = self.token_counter.count_tokens(&(preamble + "\n"));
+        }
+        
+        let mut kept = HashMap::new();
+        let mut kept_paths: Vec<(String, usize)> = Vec::new();
+        for (path, (expected_prefix, section, expected_tokens)) in paths.into_iter().zip(expected) {
+            let hunks = processed_dict.remove(&path).unwrap_or_default();
+            let prefix = self.section_prefix(&path, kept_paths.last().map(|(kept_path, _)| kept_path.as_str()));
+            let mut tokens = if prefix == expected_prefix {
+                expected_tokens
+            } else {
+                self.token_counter.count_tokens(&format!("{}{}\n", prefix, section))
+            };
+            // The last section has no joining newline, so a file that only misses the
+            // budget by that newline still fits as the last one
+            if used + tokens == budget + 1 {
+                tokens = self.token_counter.count_tokens(&format!("{}{}", prefix, section));
+            }
+            if used + tokens <= budget {
+                used += tokens;
+                kept.insert(path.clone(), hunks);
+                kept_paths.push((path, tokens));
+            } else {
+                self.filter_manager.record_omitted(&path, OmitReason::OverBudget);
+            }
+        }
+        
+        let output = DiffParser::reconstruct_patch_with_options(&kept, filters_json, &self.output_options);
+        let mut excess = self.token_counter.count_tokens(&output).saturating_sub(budget);
+        while excess > 0 {
+            let Some((path, tokens)) = kept_paths.pop() else {
+                break;
+            };
+            kept.remove(&path);
+            self.filter_manager.record_omitted(&path, OmitReason::OverBudget);
+            excess = excess.saturating_sub(tokens);
+        }
+        kept
+    }
+    
+    /// Get the text the output puts before a file's section: the file separator and directory heading
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    /// * `previous_path` - The path of the file whose section precedes it, or None if it's the first
+    fn section_prefix(&self, path: &str, previous_path: Option<&str>) -> String {
+        let mut prefix = String::new();
+        if previous_path.is_some()
+            && let Some(separator) = &self.output_options.file_separator
+        {
+            prefix.push_str(separator);
+            prefix.push('\n');
+        }
+        let dir = DiffParser::top_level_dir(path);
+        if self.output_options.group_by_dir && previous_path.map(DiffParser::top_level_dir) != Some(dir) {
+            prefix.push_str(&format!("# Directory: {}\n", dir));
+        }
+        prefix
+    }
+    
+    /// Keep files, in output order, while their sections fit in the output size limit
+    ///
+    /// # Arguments
+    ///
+    /// * `processed_dict` - The processed files
+    /// * `max_bytes` - The maximum size of the output in bytes
+    /// * `preamble_bytes` - The size of the instructions preamble, if included
+    fn apply_output_limit(
+        &mut self,
+        mut processed_dict: HashMap<String, Vec<Hunk>>,
+        max_bytes: usize,
+        preamble_bytes: usize,
+    ) -> HashMap<String, Vec<Hunk>> {
+        let mut paths: Vec<String> = processed_dict.keys().cloned().collect();
+        paths.sort();
+        
+        // Room is kept for the notice and the blank line before it, which can't be longer than with every file omitted
+        let notice_bytes = self.output_line_ending.normalize(&format!("\n\n{}", Self::truncation_notice(max_bytes, Some(paths.len())))).len();
+        let mut used = preamble_bytes + notice_bytes;
+        let mut kept = HashMap::new();
+        let mut full = false;
+        for path in paths {
+            let hunks = processed_dict.remove(&path).unwrap_or_default();
+            // Sections are joined by a newline, and measured as written
+            let section = self.render_file_section(&path, &hunks) + "\n";
+            let bytes = self.output_line_ending.normalize(&section).len();
+            if !full && used + bytes <= max_bytes {
+                used += bytes;
+                kept.insert(path, hunks);
+            } else {
+                // Later files are left out too, so the output stops at a file boundary
+                full = true;
+                self.filter_manager.record_omitted(&path, OmitReason::OverOutputLimit);
+            }
+        }
+        kept
+    }
+    
+    /// Build the notice appended to output truncated by the output size limit
+    ///
+    /// # Arguments
+    ///
+    /// * `max_bytes` - The maximum size of the output in bytes
+    /// * `omitted_files` - The number of files left out, if known
+    fn truncation_notice(max_bytes: usize, omitted_files: Option<usize>) -> String {
+        match omitted_files {
+            Some(count) => format!(
+                "[Output truncated at {} bytes: {} more file{} omitted]",
+                max_bytes,
+                count,
+                if count == 1 { "" } else { "s" }
+            ),
+            None => format!("[Output truncated at {} bytes]", max_bytes),
+        }
+    }
+    
+    /// Cut output that is still over the size limit at a line boundary, ending it with a notice
+    ///
+    /// The lines kept end with their own line endings, so the output's line endings are
+    /// kept. If even the notice doesn't fit, it is cut to the limit.
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The output to cut, with its line endings as written
+    /// * `max_bytes` - The maximum size of the output in bytes
+    fn cap_output(output: &str, max_bytes: usize) -> String {
+        let mut notice = Self::truncation_notice(max_bytes, None);
+        if notice.len() > max_bytes {
+            notice.truncate(max_bytes);
+            return notice;
+        }
+        let keep = max_bytes - notice.len();
+        match output.as_bytes()[..keep.min(output.len())].iter().rposition(|&byte| byte == b'\n') {
+            Some(end) => format!("{}{}", &output[..=end], notice),
+            None => notice,
+        }
+    }
+    
+    /// Count the tokens in the sections of several files of the output
+    ///
+    /// Sections are independent, so they're rendered and encoded in parallel with a
+    /// single shared encoder. Each count is the same as encoding the section on its
+    /// own, so the results don't depend on the number of threads.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The paths and processed hunks of the files
+    ///
+    /// # Returns
+    ///
+    /// The token count of each file's section, in the order given
+    fn count_files_tokens(&self, files: &[(&str, &[Hunk])]) -> Vec<usize> {
+        let options = self.section_options();
+        let token_counter = &self.token_counter;
+        files.par_iter()
+            .map(|(path, hunks)| token_counter.count_tokens(&Self::render_section(&options, path, hunks)))
+            .collect()
+    }
+    
+    /// Render a single file's section of the output
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    /// * `hunks` - The file's processed hunks
+    fn render_file_section(&self, path: &str, hunks: &[Hunk]) -> String {
+        Self::render_section(&self.section_options(), path, hunks)
+    }
+    
+    /// Get the output options for rendering a file's section on its own, without the preamble or directory heading
+    fn section_options(&self) -> OutputOptions {
+        OutputOptions {
+            include_instructions: false,
+            group_by_dir: false,
+            file_tree: false,
+            ..self.output_options.clone()
+        }
+    }
+    
+    /// Render a single file's section with the given output options
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - The options for rendering a section on its own
+    /// * `path` - The path of the file
+    /// * `hunks` - The file's processed hunks
+    fn render_section(options: &OutputOptions, path: &str, hunks: &[Hunk]) -> String {
+        let single_file = HashMap::from([(path.to_string(), hunks.to_vec())]);
+        DiffParser::reconstruct_patch_with_options(&single_file, None, options)
+    }
+    
+    /// Summarize the last processed diff
+    ///
+    /// Each file's token count is that of its own section of the output, so the
+    /// per-file counts don't include the instructions preamble.
+    pub fn get_diff_result(&self) -> DiffResult {
+        let files = match &self.streamed_files {
+            Some(files) => files.clone(),
+            None => {
+                let mut paths: Vec<&String> = self.processed_files.keys().collect();
+                paths.sort();
+                let sections: Vec<(&str, &[Hunk])> = paths.iter()
+                    .map(|path| (path.as_str(), self.processed_files[*path].as_slice()))
+                    .collect();
+                let token_counts = self.count_files_tokens(&sections);
+                sections.into_iter()
+                    .zip(token_counts)
+                    .map(|((path, hunks), tokens)| self.file_summary(path, hunks, tokens))
+                    .collect()
+            }
+        };
+        
+        let mut result = DiffResult::new(self.token_counter.get_model(), self.token_count, self.content_token_count, files);
+        
+        // Without a test file pattern every file would count as production code
+        if !self.filter_manager.has_test_file_pattern() {
+            result.change_scope = None;
+        }
+        result.estimated_cost = self.get_estimated_cost();
+        result
+    }
+    
+    /// Summarize a file of the last processed diff
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file, as shown in the output
+    /// * `hunks` - The file's processed hunks
+    /// * `tokens` - The number of tokens in the file's section of the output
+    fn file_summary(&self, path: &str, hunks: &[Hunk], tokens: usize) -> FileSummary {
+        FileSummary {
+            id: FileSummary::file_id(path),
+            path: path.to_string(),
+            change_kind: ChangeKind::classify(hunks),
+            tokens,
+            is_test: self.filter_manager.is_test_file(self.original_path(path)),
+        }
+    }
+    
+    /// Check whether a file is in the output of the last processed diff
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file, as shown in the output
+    fn is_output_file(&self, path: &str) -> bool {
+        match &self.streamed_files {
+            // Streamed files are written in path order
+            Some(files) => files.binary_search_by(|file| file.path.as_str().cmp(path)).is_ok(),
+            None => self.processed_files.contains_key(path),
+        }
+    }
+    
+    /// Get the path a file had before anonymization, or the path itself if it wasn't anonymized
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path as it appears in the output
+    fn original_path<'a>(&'a self, path: &'a str) -> &'a str {
+        self.path_map.iter()
+            .find(|(_, name)| name.as_str() == path)
+            .map_or(path, |(original, _)| original.as_str())
+    }
+    
+    /// Get the SHA-256 hash of the last processed diff, as lowercase hex
+    ///
+    /// The hash is of the output as written, with its configured line endings. When the output
+    /// is split into part files or an output directory, each file is hashed as it's written,
+    /// its name then its content: the parts in order, or `instructions.txt` followed by the
+    /// files' diffs in path order. The output is deterministic, so identical input gives an
+    /// identical hash, which can be used to skip re-sending an unchanged diff.
+    pub fn get_output_hash(&self) -> &str {
+        &self.output_hash
+    }
+    
+    /// Get the hex encoding of a SHA-256 hash
+    ///
+    /// # Arguments
+    ///
+    /// * `hasher` - The hash of the output
+    fn hex_digest(hasher: Sha256) -> String {
+        hasher.finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+    
+    /// Get the default output file path in the temporary directory
+    pub fn get_default_output_file() -> String {
+        Self::default_output_path("repodiff_output.txt")
+    }
+    
+    /// Get the default output file path when comparing against a branch
+    ///
+    /// The branch name is sanitized for use in a file name, e.g. `feature/x`
+    /// gives `repodiff_vs_feature-x.txt`.
+    ///
+    /// # Arguments
+    ///
+    /// * `branch` - The name of the branch being compared against
+    pub fn get_default_output_file_for_branch(branch: &str) -> String {
+        let sanitized: String = branch
+            .chars()
+            .map(|c| if c.is_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '-' })
+            .collect();
+        Self::default_output_path(&format!("repodiff_vs_{}.txt", sanitized))
+    }
+    
+    /// Get the path of a file in the repodiff folder of the temporary directory
+    fn default_output_path(file_name: &str) -> String {
         let temp_dir = std::env::temp_dir();
         let output_dir = temp_dir.join("repodiff");
-        let output_file = output_dir.join("repodiff_output.txt");
+        let output_file = output_dir.join(file_name);
         
         output_file.to_string_lossy().to_string()
     }
@@ -1,14 +1,20 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use serde::{Deserialize, Serialize};
-use crate::error::Result;
+use fnmatch_regex::glob_to_regex;
+use regex::Regex;
+use crate::error::{RepoDiffError, Result};
+use crate::utils::diff_parser::{OutputFormat, PREAMBLE_SECTIONS};
+use crate::utils::git_operations::GitOperations;
 
 /// Filter rule for controlling context lines in git diffs
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FilterRule {
-    /// File pattern to match (glob pattern)
+    /// File pattern to match (glob pattern); a leading `!` excludes matching files
     pub file_pattern: String,
     /// Number of context lines to keep around changes
+    #[serde(default = "default_context_lines")]
     pub context_lines: usize,
     /// Whether to include the full method body for changed methods (C# only)
     #[serde(default)]
@@ -16,34 +22,452 @@ pub struct FilterRule {
     /// Whether to include method signatures within context range (C# only)
     #[serde(default)]
     pub include_signatures: bool,
+    /// Whether include_method_body also shows the declarations enclosing a changed method beyond the context range (C# only)
+    #[serde(default)]
+    pub include_enclosing_declarations: bool,
+    /// Whether include_method_body also shows the declarations of the class fields a changed method refers to (C# only)
+    #[serde(default)]
+    pub include_referenced_fields: bool,
+    /// Whether a C# property with accessors is expanded as a whole or per accessor
+    #[serde(default)]
+    pub accessor_granularity: AccessorGranularity,
+    /// Maximum number of hunks to emit for a file; additional hunks are merged
+    #[serde(default)]
+    pub max_hunks: Option<usize>,
+    /// Emit the whole file when more than this fraction (0.0 to 1.0) of its lines changed
+    #[serde(default)]
+    pub full_file_over_change_ratio: Option<f32>,
+    /// Glob a changed method's name must match to be shown; others are collapsed (C# only)
+    #[serde(default)]
+    pub method_name_filter: Option<String>,
+    /// Whether matching files are only noted as changed, never emitting their content, e.g. for secrets
+    #[serde(default)]
+    pub opaque: bool,
+}
+
+impl Default for FilterRule {
+    fn default() -> Self {
+        FilterRule {
+            file_pattern: "*".to_string(),
+            context_lines: default_context_lines(),
+            include_method_body: false,
+            include_signatures: false,
+            include_enclosing_declarations: false,
+            include_referenced_fields: false,
+            accessor_granularity: AccessorGranularity::default(),
+            max_hunks: None,
+            full_file_over_change_ratio: None,
+            method_name_filter: None,
+            opaque: false,
+        }
+    }
+}
+
+impl FilterRule {
+    /// Describe the flags of this rule that contradict each other or can't take effect
+    ///
+    /// Each conflict is resolved by a fixed precedence: `opaque` wins over every option
+    /// shaping the content, and options refining method-aware filtering are ignored
+    /// without the method-aware option they depend on. `include_method_body` and
+    /// `include_signatures` combine rather than conflict: changed methods are emitted in
+    /// full and unchanged methods near a change get their signature.
+    pub fn flag_conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        if self.opaque {
+            let content_flags = [
+                ("include_method_body", self.include_method_body),
+                ("include_signatures", self.include_signatures),
+                ("max_hunks", self.max_hunks.is_some()),
+                ("full_file_over_change_ratio", self.full_file_over_change_ratio.is_some()),
+                ("method_name_filter", self.method_name_filter.is_some()),
+            ];
+            for (flag, _) in content_flags.into_iter().filter(|(_, set)| *set) {
+                conflicts.push(format!("opaque hides the content, so {} has no effect", flag));
+            }
+        }
+        if self.include_enclosing_declarations && !self.include_method_body {
+            conflicts.push("include_enclosing_declarations has no effect without include_method_body".to_string());
+        }
+        if self.include_referenced_fields && !self.include_method_body {
+            conflicts.push("include_referenced_fields has no effect without include_method_body".to_string());
+        }
+        if self.method_name_filter.is_some() && !self.include_method_body && !self.include_signatures {
+            conflicts.push("method_name_filter has no effect without include_method_body or include_signatures".to_string());
+        }
+        conflicts
+    }
+}
+
+/// Describe the flag conflicts of each filter rule, prefixed with the rule's `file_pattern`
+fn describe_flag_conflicts(rules: &[FilterRule]) -> Vec<String> {
+    rules.iter()
+        .flat_map(|rule| rule.flag_conflicts().into_iter().map(move |conflict| format!("'{}': {}", rule.file_pattern, conflict)))
+        .collect()
+}
+
+/// What to do with filter rules whose flags conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlagConflictPolicy {
+    /// Apply the documented precedence and carry on
+    #[default]
+    Resolve,
+    /// Reject the config, listing the conflicts
+    Reject,
+}
+
+/// How a C# property with `get`/`set` accessors is treated by method-aware filtering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessorGranularity {
+    /// The property is one unit, expanded in full when any accessor changes
+    #[default]
+    Property,
+    /// Each accessor is its own unit, so only the changed accessors are expanded
+    Accessor,
+}
+
+/// Override of how much of each changed C# method to show, for all C# files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodDetail {
+    /// Include the full body of every changed method
+    Bodies,
+    /// Include method signatures but not full bodies
+    SignaturesOnly,
+}
+
+/// Line ending used when writing the output file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// Unix line endings (`\n`)
+    #[default]
+    Lf,
+    /// Windows line endings (`\r\n`)
+    Crlf,
+    /// The platform's own line ending
+    Native,
+}
+
+impl LineEnding {
+    /// Rewrite every line ending in the text to this line ending
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to normalize, possibly with mixed line endings
+    pub fn normalize(self, text: &str) -> String {
+        let lf = text.replace("\r\n", "\n");
+        let crlf = match self {
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+            LineEnding::Native => cfg!(windows),
+        };
+        if crlf { lf.replace('\n', "\r\n") } else { lf }
+    }
+}
+
+/// Default number of context lines for a filter rule
+fn default_context_lines() -> usize {
+    3
+}
+
+/// Filter rules are auto-tuned unless disabled
+fn default_auto_tune_filters() -> bool {
+    true
+}
+
+/// Default number of attempts for each git command
+fn default_git_retry_attempts() -> usize {
+    1
+}
+
+/// Default maximum depth of the C# syntax tree searched for methods
+pub const DEFAULT_CSHARP_MAX_DEPTH: usize = 1000;
+
+/// Default maximum depth of the C# syntax tree searched for methods
+fn default_csharp_max_depth() -> usize {
+    DEFAULT_CSHARP_MAX_DEPTH
 }
 
 /// Configuration for the RepoDiff tool
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// The tiktoken model to use for token counting
     pub tiktoken_model: String,
-    /// List of filter rules
+    /// List of filter rules; if empty, rules are chosen for the diff's language when `auto_tune_filters` is set
+    #[serde(default)]
     pub filters: Vec<FilterRule>,
+    /// Whether to pick filter rules for the predominant language of the diff when no filters are configured
+    #[serde(default = "default_auto_tune_filters")]
+    pub auto_tune_filters: bool,
+    /// Whether filter rules with conflicting flags are resolved by precedence or rejected
+    #[serde(default)]
+    pub filter_flag_conflicts: FlagConflictPolicy,
+    /// Maps file extensions to languages, e.g. `"cake": "csharp"`, overriding the built-in mapping;
+    /// keys are stored in lowercase without a leading dot when the config is loaded
+    #[serde(default)]
+    pub extension_language_map: HashMap<String, String>,
+    /// Glob pattern identifying test files (e.g. "*Test*.cs")
+    #[serde(default)]
+    pub test_file_pattern: Option<String>,
+    /// Number of context lines to keep for test files, overriding the matched rule
+    #[serde(default)]
+    pub test_context_lines: Option<usize>,
+    /// Maximum width of output content lines; longer lines are soft-wrapped
+    #[serde(default)]
+    pub wrap_width: Option<usize>,
+    /// Maximum number of characters of an output line; longer lines are truncated
+    #[serde(default)]
+    pub max_line_length: Option<usize>,
+    /// Whether to render trailing spaces and tabs of output lines as visible `·` and `→` markers
+    #[serde(default)]
+    pub show_trailing_whitespace: bool,
+    /// Sections of the instructions preamble to include, in order; all of them if unset
+    #[serde(default)]
+    pub preamble_sections: Option<Vec<String>>,
+    /// Text inserted between consecutive file sections of the output
+    #[serde(default)]
+    pub file_separator: Option<String>,
+    /// Whether to keep git's original hunk headers in the output
+    #[serde(default)]
+    pub preserve_original_headers: bool,
+    /// Whether to emit context lines without their leading space marker
+    #[serde(default)]
+    pub plain_context: bool,
+    /// Whether to mark changed lines with `[+] `/`[-] ` instead of `+`/`-`, for models that don't follow diff syntax
+    #[serde(default)]
+    pub verbose_markers: bool,
+    /// Whether to replace the instructions preamble with a short legend of the markers used in the output
+    #[serde(default)]
+    pub legend: bool,
+    /// Whether to show renames as a one-line `# renamed: old → new` instead of git's rename header block
+    #[serde(default)]
+    pub compact_renames: bool,
+    /// Whether to collapse runs of three or more blank context lines into one
+    #[serde(default)]
+    pub collapse_blank_runs: bool,
+    /// Whether to collapse C# methods that were moved within a file into short markers
+    #[serde(default)]
+    pub detect_moved_methods: bool,
+    /// Whether to annotate each fully emitted C# method's signature with its token count
+    #[serde(default)]
+    pub annotate_method_tokens: bool,
+    /// Whether to prefix each body line of a fully emitted C# method with its offset from the signature, e.g. `+5:`
+    #[serde(default)]
+    pub method_line_offsets: bool,
+    /// Whether to start each hunk with a comment counting its additions and deletions and naming the C# methods it changes
+    #[serde(default)]
+    pub annotate_hunks: bool,
+    /// Whether to replace hunks repeated across files with a reference to the first file
+    #[serde(default)]
+    pub dedupe_identical_hunks: bool,
+    /// Whether the first occurrence of a deduplicated hunk names every file sharing it
+    #[serde(default)]
+    pub dedupe_occurrence_counts: bool,
+    /// Regexes matched against the content of changed lines; matching lines are treated as unchanged
+    #[serde(default)]
+    pub ignore_line_patterns: Vec<String>,
+    /// Omit files whose processed diff has more lines than this
+    #[serde(default)]
+    pub max_file_lines: Option<usize>,
+    /// Maximum number of tokens the output may use; files that don't fit are omitted
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// Price in dollars per 1K input tokens, by model; the output's cost is estimated when the model is listed
+    #[serde(default)]
+    pub model_pricing: HashMap<String, f64>,
+    /// Tokens reserved for the rest of the prompt, subtracted from `max_tokens`
+    #[serde(default)]
+    pub reserved_tokens: usize,
+    /// Line ending used in the output file: `lf`, `crlf` or `native`
+    #[serde(default)]
+    pub output_line_ending: LineEnding,
+    /// Number of context lines to request from git diff; computed from the filters if unset
+    #[serde(default)]
+    pub git_unified_context: Option<usize>,
+    /// Branch to compare against when `--branch` is given without a value, e.g. `develop`
+    #[serde(default)]
+    pub default_base_branch: Option<String>,
+    /// Number of times to attempt each git command before giving up; 1 means no retries
+    #[serde(default = "default_git_retry_attempts")]
+    pub git_retry_attempts: usize,
+    /// Count a commit range's changed lines with git even when nothing else needs the count, so the
+    /// reformatting warning also sees the whitespace-only changes that git's diff hides
+    #[serde(default)]
+    pub count_whitespace_changes: bool,
+    /// Maximum depth of the C# syntax tree searched for methods; deeper declarations are not found
+    #[serde(default = "default_csharp_max_depth")]
+    pub csharp_max_depth: usize,
+    /// Show the full bodies or only the signatures of every changed C# method, overriding the filter rules
+    #[serde(default)]
+    pub method_detail: Option<MethodDetail>,
+    /// Truncate the output at a file boundary so it's at most this many bytes
+    #[serde(default)]
+    pub max_output_bytes: Option<usize>,
+    /// Only include files matching this glob pattern, e.g. `src/**/*.sql`
+    #[serde(default)]
+    pub only_pattern: Option<String>,
+    /// Only include files of this language, e.g. `csharp`, as mapped from their extensions
+    #[serde(default)]
+    pub only_language: Option<String>,
+    /// Output format: `text`, `markdown`, `signature-map`, `patch-series`, `symbol-index` or `html`
+    #[serde(default)]
+    pub format: OutputFormat,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             tiktoken_model: "gpt-4o".to_string(),
-            filters: vec![FilterRule {
-                file_pattern: "*".to_string(),
-                context_lines: 3,
-                include_method_body: false,
-                include_signatures: false,
-            }],
+            filters: Vec::new(),
+            auto_tune_filters: default_auto_tune_filters(),
+            filter_flag_conflicts: FlagConflictPolicy::default(),
+            extension_language_map: HashMap::new(),
+            test_file_pattern: None,
+            test_context_lines: None,
+            wrap_width: None,
+            max_line_length: None,
+            show_trailing_whitespace: false,
+            preamble_sections: None,
+            file_separator: None,
+            preserve_original_headers: false,
+            plain_context: false,
+            verbose_markers: false,
+            legend: false,
+            compact_renames: false,
+            collapse_blank_runs: false,
+            detect_moved_methods: false,
+            dedupe_identical_hunks: false,
+            dedupe_occurrence_counts: false,
+            ignore_line_patterns: Vec::new(),
+            annotate_method_tokens: false,
+            method_line_offsets: false,
+            annotate_hunks: false,
+            max_file_lines: None,
+            max_tokens: None,
+            model_pricing: HashMap::new(),
+            reserved_tokens: 0,
+            output_line_ending: LineEnding::default(),
+            git_unified_context: None,
+            default_base_branch: None,
+            git_retry_attempts: default_git_retry_attempts(),
+            count_whitespace_changes: false,
+            csharp_max_depth: default_csharp_max_depth(),
+            method_detail: None,
+            max_output_bytes: None,
+            only_pattern: None,
+            only_language: None,
+            format: OutputFormat::default(),
         }
     }
 }
 
+/// The part of a config file in a subdirectory that overrides the root config
+#[derive(Debug, Deserialize)]
+struct DirectoryConfigFile {
+    /// Filter rules for the files under the directory, or None if the file doesn't override them
+    #[serde(default)]
+    filters: Option<Vec<FilterRule>>,
+}
+
+/// Finds config files in subdirectories, whose filter rules override the root config for files under them
+///
+/// As with `.editorconfig`, the config in the nearest directory above a file wins, and
+/// its patterns are matched against the file's path relative to that directory.
+#[derive(Debug, Clone)]
+pub struct DirectoryConfigs {
+    /// Directory that the changed files' paths are relative to
+    root: PathBuf,
+    /// Name of the config files to look for
+    config_file_name: String,
+    /// Whether the rules of directory configs with conflicting flags are resolved or rejected
+    flag_conflicts: FlagConflictPolicy,
+}
+
+impl DirectoryConfigs {
+    /// Look for directory configs below a root directory
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The directory that the changed files' paths are relative to, usually the repository root
+    /// * `config_file_name` - The name of the config files to look for
+    pub fn new(root: impl Into<PathBuf>, config_file_name: &str) -> Self {
+        DirectoryConfigs {
+            root: root.into(),
+            config_file_name: config_file_name.to_string(),
+            flag_conflicts: FlagConflictPolicy::default(),
+        }
+    }
+
+    /// Set what to do with directory config rules whose flags conflict, as for the root config
+    ///
+    /// # Arguments
+    ///
+    /// * `flag_conflicts` - The policy of the root config
+    pub fn with_flag_conflicts(mut self, flag_conflicts: FlagConflictPolicy) -> Self {
+        self.flag_conflicts = flag_conflicts;
+        self
+    }
+
+    /// Load the filter rules of the config files in the directories above the given files
+    ///
+    /// The root directory itself is skipped, since its config is the root config.
+    ///
+    /// # Arguments
+    ///
+    /// * `paths` - The changed files, relative to the root directory
+    ///
+    /// # Returns
+    ///
+    /// The filter rules of each directory with a config that overrides them, keyed by
+    /// the directory's path relative to the root, e.g. `services/billing`
+    pub fn load_filters<'a>(&self, paths: impl IntoIterator<Item = &'a str>) -> Result<HashMap<String, Vec<FilterRule>>> {
+        let mut checked = HashSet::new();
+        let mut filters = HashMap::new();
+        for path in paths {
+            let mut dir = path;
+            while let Some((parent, _)) = dir.rsplit_once('/') {
+                dir = parent;
+                if !checked.insert(dir) {
+                    break;
+                }
+                
+                let config_path = self.root.join(dir).join(&self.config_file_name);
+                if !config_path.is_file() {
+                    continue;
+                }
+                let config_str = fs::read_to_string(&config_path)?;
+                let config: DirectoryConfigFile = serde_json::from_str(&config_str).map_err(|e| {
+                    RepoDiffError::ConfigError(format!("Invalid directory config {}: {}", config_path.display(), e))
+                })?;
+                if let Some(dir_filters) = config.filters {
+                    if self.flag_conflicts == FlagConflictPolicy::Reject {
+                        let conflicts = describe_flag_conflicts(&dir_filters);
+                        if !conflicts.is_empty() {
+                            return Err(RepoDiffError::ConfigError(format!(
+                                "Conflicting filter flags in directory config {}: {}",
+                                config_path.display(),
+                                conflicts.join("; ")
+                            )));
+                        }
+                    }
+                    filters.insert(dir.to_string(), dir_filters);
+                }
+            }
+        }
+        
+        Ok(filters)
+    }
+}
+
 /// Manages configuration loading and access for the RepoDiff tool
 pub struct ConfigManager {
     config: Config,
+    /// Path the configuration was loaded from, or None if defaults are used
+    config_path: Option<PathBuf>,
+    /// Config files in subdirectories of the directory the config is looked up in
+    directory_configs: DirectoryConfigs,
 }
 
 impl ConfigManager {
@@ -53,27 +477,74 @@ impl ConfigManager {
     ///
     /// * `config_file_name` - The name of the configuration file to load
     pub fn new(config_file_name: &str) -> Result<Self> {
-        let config = Self::load_config(config_file_name)?;
-        Ok(ConfigManager { config })
+        let config_path = Self::find_config_path(config_file_name)?;
+        
+        // Changed files' paths are relative to the repository root, so directory configs are
+        // found below it, or below the current directory outside a repository
+        let root = match GitOperations::new().get_repo_root() {
+            Ok(repo_root) => PathBuf::from(repo_root),
+            Err(_) => std::env::current_dir()?,
+        };
+        let config_name = Path::new(config_file_name)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let directory_configs = DirectoryConfigs::new(root, &config_name);
+        
+        // Use the default config if the file doesn't exist
+        if !config_path.exists() {
+            return Ok(ConfigManager {
+                config: Config::default(),
+                config_path: None,
+                directory_configs,
+            });
+        }
+        
+        let config = Self::load_config(&config_path)?;
+        Ok(ConfigManager {
+            directory_configs: directory_configs.with_flag_conflicts(config.filter_flag_conflicts),
+            config,
+            config_path: Some(config_path),
+        })
     }
 
     /// Load configuration from the config file
     ///
     /// # Arguments
     ///
-    /// * `config_file_name` - The name of the configuration file to load
-    fn load_config(config_file_name: &str) -> Result<Config> {
-        let config_path = Self::find_config_path(config_file_name)?;
+    /// * `config_path` - The path of the configuration file to load
+    fn load_config(config_path: &Path) -> Result<Config> {
+        let config_str = fs::read_to_string(config_path)?;
+        let mut config: Config = serde_json::from_str(&config_str)?;
+        config.extension_language_map = Self::normalize_extension_language_map(&config.extension_language_map)?;
         
-        // Return default config if file doesn't exist
-        if !config_path.exists() {
-            return Ok(Config::default());
+        Ok(config)
+    }
+
+    /// Store each extension of `extension_language_map` in lowercase and without its leading dot
+    ///
+    /// # Arguments
+    ///
+    /// * `extension_language_map` - The extension to language map as written in the config
+    ///
+    /// # Returns
+    ///
+    /// The normalized map, or an error if two keys name the same extension
+    fn normalize_extension_language_map(
+        extension_language_map: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let mut normalized = HashMap::new();
+        for (key, language) in extension_language_map {
+            let extension = key.trim_start_matches('.').to_lowercase();
+            if normalized.insert(extension.clone(), language.clone()).is_some() {
+                return Err(RepoDiffError::ConfigError(format!(
+                    "extension_language_map maps the extension '{}' more than once",
+                    extension
+                )));
+            }
         }
         
-        let config_str = fs::read_to_string(&config_path)?;
-        let config: Config = serde_json::from_str(&config_str)?;
-        
-        Ok(config)
+        Ok(normalized)
     }
 
     /// Find the path to the config file
@@ -91,12 +562,12 @@ impl ConfigManager {
         }
         
         // Then try the executable directory
-        if let Ok(exe_path) = std::env::current_exe() {
-            if let Some(exe_dir) = exe_path.parent() {
-                let config_path = exe_dir.join(config_file_name);
-                if config_path.exists() {
-                    return Ok(config_path);
-                }
+        if let Ok(exe_path) = std::env::current_exe()
+            && let Some(exe_dir) = exe_path.parent()
+        {
+            let config_path = exe_dir.join(config_file_name);
+            if config_path.exists() {
+                return Ok(config_path);
             }
         }
         
@@ -113,4 +584,105 @@ impl ConfigManager {
     pub fn get_filters(&self) -> &[FilterRule] {
         &self.config.filters
     }
+
+    /// Get the full configuration
+    pub fn get_config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Get the finder for config files in subdirectories, which override the filter rules for files under them
+    pub fn get_directory_configs(&self) -> &DirectoryConfigs {
+        &self.directory_configs
+    }
+
+    /// Get the path the configuration was loaded from
+    ///
+    /// Returns None if no config file was found and the defaults are used
+    pub fn get_config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
+    /// Validate the loaded configuration
+    ///
+    /// Checks that a tiktoken model is set and that every filter pattern is a valid glob.
+    pub fn validate(&self) -> Result<()> {
+        if self.config.tiktoken_model.trim().is_empty() {
+            return Err(RepoDiffError::ConfigError("tiktoken_model must not be empty".to_string()));
+        }
+        
+        for rule in &self.config.filters {
+            let pattern = rule.file_pattern.strip_prefix('!').unwrap_or(&rule.file_pattern);
+            glob_to_regex(pattern).map_err(|e| {
+                RepoDiffError::ConfigError(format!("Invalid file_pattern '{}': {}", rule.file_pattern, e))
+            })?;
+        }
+        
+        Self::check_filter_flag_conflicts(&self.config)?;
+        Self::check_token_budget(self.config.max_tokens, self.config.reserved_tokens)?;
+        
+        for section in self.config.preamble_sections.iter().flatten() {
+            if !PREAMBLE_SECTIONS.contains(&section.as_str()) {
+                return Err(RepoDiffError::ConfigError(format!(
+                    "Unknown preamble section '{}', expected one of: {}",
+                    section,
+                    PREAMBLE_SECTIONS.join(", ")
+                )));
+            }
+        }
+        
+        for (model, price) in &self.config.model_pricing {
+            if !price.is_finite() || *price < 0.0 {
+                return Err(RepoDiffError::ConfigError(format!(
+                    "Invalid model_pricing entry for '{}': {} is not a non-negative price",
+                    model, price
+                )));
+            }
+        }
+        
+        for pattern in &self.config.ignore_line_patterns {
+            Regex::new(pattern).map_err(|e| {
+                RepoDiffError::ConfigError(format!("Invalid ignore_line_patterns entry '{}': {}", pattern, e))
+            })?;
+        }
+        
+        Ok(())
+    }
+
+    /// Reject a configuration whose filter rules have conflicting flags, if its policy says so
+    ///
+    /// With the default policy conflicts are resolved by precedence, as described by
+    /// `FilterRule::flag_conflicts`, and this always succeeds.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The configuration to check
+    pub fn check_filter_flag_conflicts(config: &Config) -> Result<()> {
+        if config.filter_flag_conflicts == FlagConflictPolicy::Resolve {
+            return Ok(());
+        }
+        
+        let conflicts = describe_flag_conflicts(&config.filters);
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        Err(RepoDiffError::ConfigError(format!("Conflicting filter flags: {}", conflicts.join("; "))))
+    }
+
+    /// Check that the reserved tokens leave room in the token budget
+    ///
+    /// Reserving all of the maximum, or more, would leave no budget, omitting every file.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_tokens` - The maximum number of tokens the output may use, if limited
+    /// * `reserved_tokens` - The tokens reserved for the rest of the prompt
+    pub fn check_token_budget(max_tokens: Option<usize>, reserved_tokens: usize) -> Result<()> {
+        match max_tokens {
+            Some(max_tokens) if reserved_tokens >= max_tokens => Err(RepoDiffError::ConfigError(format!(
+                "reserved_tokens ({}) must be less than max_tokens ({})",
+                reserved_tokens, max_tokens
+            ))),
+            _ => Ok(()),
+        }
+    }
 } 
\ No newline at end of file
@@ -1,9 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::BufRead;
 use regex::Regex;
-use crate::error::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use crate::error::{RepoDiffError, Result};
+use crate::filters::filter_manager::FilterManager;
+use crate::utils::diff_result::{ChangeKind, FileSummary};
+
+/// Fraction of changed lines that must be whitespace-only for a diff to be reported as mostly reformatting
+pub const REFORMAT_WARNING_RATIO: f64 = 0.8;
+
+/// Minimum number of changed lines for a diff to be reported as mostly reformatting
+pub const REFORMAT_WARNING_MIN_LINES: usize = 20;
+
+/// Names of the sections of the instructions preamble, in their default order
+pub const PREAMBLE_SECTIONS: &[&str] = &["introduction", "basic_structure", "special_handling", "usage_guidelines"];
 
 /// Represents a hunk in a git diff
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Hunk {
     /// The hunk header
     pub header: String,
@@ -25,6 +39,553 @@ pub struct Hunk {
     pub rename_to: Option<String>,
     /// The similarity index (for renames)
     pub similarity_index: Option<String>,
+    /// The old and new line numbers of each line in `lines`, kept through processing
+    ///
+    /// Lines of one side only are numbered on the other side at the position where
+    /// they would be, and lines added by processing, such as placeholders, have none.
+    /// Empty for hunks as parsed from git, whose line numbers follow from the header.
+    pub line_numbers: Vec<Option<(usize, usize)>>,
+    /// A summary of the hunk's changes, rendered on its own line before the hunk's lines
+    pub annotation: Option<String>,
+}
+
+impl Hunk {
+    /// Get the old and new line numbers of each line
+    ///
+    /// # Returns
+    ///
+    /// The numbers kept through processing, or the numbers counted from the header
+    /// for hunks as parsed from git
+    pub fn source_line_numbers(&self) -> Vec<Option<(usize, usize)>> {
+        if self.line_numbers.len() == self.lines.len() && !self.lines.is_empty() {
+            return self.line_numbers.clone();
+        }
+        
+        let (mut old_line, mut new_line) = (self.old_start, self.new_start);
+        self.lines.iter()
+            .map(|line| {
+                let numbers = Some((old_line, new_line));
+                if DiffSide::Old.contains(line) && !line.starts_with('\\') {
+                    old_line += 1;
+                }
+                if DiffSide::New.contains(line) && !line.starts_with('\\') {
+                    new_line += 1;
+                }
+                numbers
+            })
+            .collect()
+    }
+}
+
+/// Side of a diff whose file content and line numbers are used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffSide {
+    /// The file before the change, numbered from each hunk's `old_start`
+    Old,
+    /// The file after the change, numbered from each hunk's `new_start`
+    #[default]
+    New,
+}
+
+impl DiffSide {
+    /// Get the first line number of a hunk on this side
+    pub fn start(self, hunk: &Hunk) -> usize {
+        match self {
+            DiffSide::Old => hunk.old_start,
+            DiffSide::New => hunk.new_start,
+        }
+    }
+
+    /// Check whether a hunk line belongs to this side, i.e. advances its line number
+    pub fn contains(self, line: &str) -> bool {
+        match self {
+            DiffSide::Old => !line.starts_with('+'),
+            DiffSide::New => !line.starts_with('-'),
+        }
+    }
+
+    /// Get this side's number from a line's old and new line numbers
+    pub fn line_number(self, (old_line, new_line): (usize, usize)) -> usize {
+        match self {
+            DiffSide::Old => old_line,
+            DiffSide::New => new_line,
+        }
+    }
+}
+
+/// Format of the rendered output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Plain unified diff text
+    #[default]
+    Text,
+    /// Each file's diff in a fenced code block tagged with the file's language
+    Markdown,
+    /// Only the changed files and, for C# files, the signatures of their changed methods
+    SignatureMap,
+    /// One text diff section per commit in the range, labeled with the commit's subject
+    PatchSeries,
+    /// Only the changed files and the names of their changed top-level symbols, in any supported language
+    SymbolIndex,
+    /// A self-contained HTML report with a collapsible section per file
+    Html,
+}
+
+/// Options controlling how a processed patch is rendered
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    /// Whether to prepend the instructions preamble to the output
+    pub include_instructions: bool,
+    /// Glob pattern identifying test files, whose sections are tagged with a `// test file` marker
+    pub test_file_pattern: Option<String>,
+    /// Whether to group files under a heading per top-level directory
+    pub group_by_dir: bool,
+    /// Maximum width of content lines; longer lines are wrapped with a `↪` continuation marker
+    pub wrap_width: Option<usize>,
+    /// Whether to emit git's original hunk headers verbatim, including any section text
+    pub preserve_original_headers: bool,
+    /// Format of the rendered output
+    pub format: OutputFormat,
+    /// Whether to strip the leading space marker from context lines
+    pub plain_context: bool,
+    /// Whether to replace the `+`/`-` markers with `[+] `/`[-] ` and the context marker with four spaces
+    pub verbose_markers: bool,
+    /// Whether the preamble is a short legend of the markers in use instead of the instructions
+    pub legend: bool,
+    /// Whether to replace the rename header block with a single `# renamed: old → new` line
+    pub compact_renames: bool,
+    /// Maps file extensions to languages, taking precedence over the built-in mapping
+    pub extension_language_map: HashMap<String, String>,
+    /// Maximum number of characters of a line's content; longer lines are cut with a `…[truncated N chars]` marker
+    pub max_line_length: Option<usize>,
+    /// Whether to precede each file's section with a label such as `[Added]` or `[Renamed from X]`
+    pub label_changes: bool,
+    /// Whether to render trailing spaces as `·` and trailing tabs as `→` so whitespace changes are visible
+    pub show_trailing_whitespace: bool,
+    /// Directory joined with the paths in file headers in place of git's `a/` and `b/` prefixes, making them absolute
+    pub path_root: Option<String>,
+    /// Whether to list the changed files after the preamble, keeping the static content at the front for prompt caching
+    pub cache_friendly: bool,
+    /// Sections of the instructions preamble to include, in order, or None for all of them
+    pub preamble_sections: Option<Vec<String>>,
+    /// Text inserted between consecutive file sections, e.g. `---`
+    pub file_separator: Option<String>,
+    /// Whether to precede each file's section with a `// FILE-ID: <id>` comment holding its stable identifier
+    pub file_ids: bool,
+    /// Whether to show a tree of the changed files, with change markers, before the diffs
+    pub file_tree: bool,
+    /// Mode and symlink changes labeled in the sections of their files, e.g. `symlink <path> now points to <target>`
+    pub mode_changes: HashMap<String, ModeChange>,
+    /// How each file without hunks changed, for its change label
+    pub hunkless_changes: HashMap<String, HunklessChange>,
+    /// Files shown only as `<path> changed (content hidden)`, without headers or lines
+    pub opaque_files: HashSet<String>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            include_instructions: true,
+            test_file_pattern: None,
+            group_by_dir: false,
+            wrap_width: None,
+            preserve_original_headers: false,
+            format: OutputFormat::Text,
+            plain_context: false,
+            verbose_markers: false,
+            legend: false,
+            compact_renames: false,
+            label_changes: false,
+            show_trailing_whitespace: false,
+            path_root: None,
+            cache_friendly: false,
+            preamble_sections: None,
+            file_separator: None,
+            file_ids: false,
+            file_tree: false,
+            mode_changes: HashMap::new(),
+            hunkless_changes: HashMap::new(),
+            opaque_files: HashSet::new(),
+            max_line_length: None,
+            extension_language_map: HashMap::new(),
+        }
+    }
+}
+
+/// Counts describing the structure of a parsed diff
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiffStructure {
+    /// Number of files in the diff
+    pub files: usize,
+    /// Number of hunks across all files
+    pub hunks: usize,
+    /// Number of renamed files
+    pub renames: usize,
+}
+
+impl fmt::Display for DiffStructure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files, {} hunks, {} renames", self.files, self.hunks, self.renames)
+    }
+}
+
+/// Git's file mode for symbolic links
+const SYMLINK_MODE: &str = "120000";
+
+/// A change to a file that its text hunks don't show, such as new permissions or a new symlink target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeChange {
+    /// The file's mode changed, e.g. from `100644` to `100755` when it was made executable
+    Permissions {
+        /// The mode before the change
+        old_mode: String,
+        /// The mode after the change
+        new_mode: String,
+    },
+    /// A symlink was created, retargeted or removed
+    Symlink {
+        /// The path the symlink now points to, or None if it was removed
+        target: Option<String>,
+    },
+}
+
+impl ModeChange {
+    /// Get the label describing this change to a file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the changed file
+    pub fn label(&self, path: &str) -> String {
+        match self {
+            ModeChange::Permissions { old_mode, new_mode } => {
+                format!("mode {} changed from {} to {}", path, old_mode, new_mode)
+            }
+            ModeChange::Symlink { target: Some(target) } => format!("symlink {} now points to {}", path, target),
+            ModeChange::Symlink { target: None } => format!("symlink {} removed", path),
+        }
+    }
+}
+
+/// How a file whose diff has no hunks changed, as its header tells
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HunklessChange {
+    /// Git found the file binary and only reported that it differs
+    Binary,
+    /// The file was renamed without changing its content
+    Renamed { from: String },
+    /// The file was copied without changing its content
+    Copied { from: String },
+    /// Only the file's mode or symlink target changed
+    Mode,
+    /// An empty file was added or deleted
+    Empty,
+}
+
+impl HunklessChange {
+    /// Get the label describing the change, e.g. `[Binary]`
+    pub fn label(&self) -> String {
+        match self {
+            HunklessChange::Binary => "[Binary]".to_string(),
+            HunklessChange::Renamed { from } => format!("[Renamed from {}]", from),
+            HunklessChange::Copied { from } => format!("[Copied from {}]", from),
+            HunklessChange::Mode => "[Mode]".to_string(),
+            HunklessChange::Empty => "[Empty]".to_string(),
+        }
+    }
+}
+
+/// What the file headers of a diff say that its hunks don't
+#[derive(Debug, Clone, Default)]
+pub struct FileHeaders {
+    /// The files whose mode or symlink target changed
+    pub mode_changes: HashMap<String, ModeChange>,
+    /// How each file without hunks changed
+    pub hunkless_changes: HashMap<String, HunklessChange>,
+}
+
+/// The prefixes a diff puts before its old and new paths, as set with git's `--src-prefix` and `--dst-prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPrefixes {
+    /// The prefix of old paths, `a/` by default
+    pub src: String,
+    /// The prefix of new paths, `b/` by default
+    pub dst: String,
+}
+
+impl Default for PathPrefixes {
+    fn default() -> Self {
+        PathPrefixes {
+            src: "a/".to_string(),
+            dst: "b/".to_string(),
+        }
+    }
+}
+
+impl PathPrefixes {
+    /// The prefixes of a diff made with `--no-prefix`
+    pub fn none() -> Self {
+        PathPrefixes {
+            src: String::new(),
+            dst: String::new(),
+        }
+    }
+}
+
+/// Incremental parser for unified diff output, fed one line at a time
+///
+/// Each file is returned once the next file's `diff --git` line (or the end of
+/// the input) shows that it is complete.
+pub struct DiffStreamParser {
+    hunk_header_re: Regex,
+    current_file: Option<String>,
+    current_hunks: Vec<Hunk>,
+    is_rename: bool,
+    rename_from: Option<String>,
+    rename_to: Option<String>,
+    similarity_index: Option<String>,
+    old_mode: Option<String>,
+    new_mode: Option<String>,
+    copy_from: Option<String>,
+    // Whether the header says the file is binary, or that it was added or deleted
+    is_binary: bool,
+    is_added_or_deleted: bool,
+    // Whether we're between a diff --git line and the file's first hunk
+    in_file_header: bool,
+    headers: FileHeaders,
+    // The prefixes of the diff's paths, or None to detect them for each file
+    path_prefixes: Option<PathPrefixes>,
+}
+
+impl DiffStreamParser {
+    /// Create a parser positioned before the first file of a diff
+    pub fn new() -> Result<Self> {
+        Ok(DiffStreamParser {
+            hunk_header_re: Regex::new(r"@@ -(\d+),?(\d+)? \+(\d+),?(\d+)? @@")?,
+            current_file: None,
+            current_hunks: Vec::new(),
+            is_rename: false,
+            rename_from: None,
+            rename_to: None,
+            similarity_index: None,
+            old_mode: None,
+            new_mode: None,
+            copy_from: None,
+            is_binary: false,
+            is_added_or_deleted: false,
+            in_file_header: false,
+            headers: FileHeaders::default(),
+            path_prefixes: None,
+        })
+    }
+
+    /// Set the prefixes of the diff's paths instead of detecting them
+    ///
+    /// Prefixes can't always be detected: with equal custom prefixes, `x/path x/path`
+    /// reads the same as `x/path` diffed with `--no-prefix`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_prefixes` - The prefixes, or None to detect them
+    pub fn with_path_prefixes(mut self, path_prefixes: Option<PathPrefixes>) -> Self {
+        self.path_prefixes = path_prefixes;
+        self
+    }
+
+    /// Parse the next line of the diff
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line, without its line ending
+    ///
+    /// # Returns
+    ///
+    /// The path and hunks of the previous file if this line starts a new one, or a parse
+    /// error if the line is a hunk header whose line numbers don't fit in a `usize`
+    pub fn push_line(&mut self, line: &str) -> Result<Option<(String, Vec<Hunk>)>> {
+        if let Some(header_paths) = line.strip_prefix("diff --git ") {
+            let completed = self.take_file();
+            
+            self.rename_from = None;
+            self.rename_to = None;
+            self.similarity_index = None;
+            self.is_rename = false;
+            self.old_mode = None;
+            self.new_mode = None;
+            self.copy_from = None;
+            self.is_binary = false;
+            self.is_added_or_deleted = false;
+            self.in_file_header = true;
+            self.current_file = DiffParser::parse_git_header_path(header_paths, self.path_prefixes.as_ref());
+            return Ok(completed);
+        }
+        
+        if self.in_file_header {
+            if line.starts_with("similarity index ") {
+                self.similarity_index = Some(line.to_string());
+                return Ok(None);
+            } else if let Some(from) = line.strip_prefix("rename from ") {
+                self.rename_from = Some(from.to_string());
+                return Ok(None);
+            } else if let Some(to) = line.strip_prefix("rename to ") {
+                self.rename_to = Some(to.to_string());
+                // Copies also have a similarity index, so only both rename lines make a
+                // rename, and renames name the new path explicitly
+                if self.rename_from.is_some() {
+                    self.is_rename = true;
+                    self.current_file = self.rename_to.clone();
+                }
+                return Ok(None);
+            } else if let Some(to) = line.strip_prefix("copy to ") {
+                // Copies name the new path explicitly too, but keep the hunks' rename fields unset
+                self.current_file = Some(to.to_string());
+                return Ok(None);
+            } else if let Some(from) = line.strip_prefix("copy from ") {
+                self.copy_from = Some(from.to_string());
+                return Ok(None);
+            } else if let Some(mode) = line.strip_prefix("old mode ") {
+                self.old_mode = Some(mode.to_string());
+                return Ok(None);
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                self.new_mode = Some(mode.to_string());
+                return Ok(None);
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                self.old_mode = Some(mode.to_string());
+                self.is_added_or_deleted = true;
+                return Ok(None);
+            } else if let Some(mode) = line.strip_prefix("new file mode ") {
+                self.new_mode = Some(mode.to_string());
+                self.is_added_or_deleted = true;
+                return Ok(None);
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                self.is_binary = true;
+                return Ok(None);
+            } else if let Some(index) = line.strip_prefix("index ") {
+                // The index line names the mode when it's the same on both sides
+                if let Some(mode) = index.split_whitespace().nth(1) {
+                    self.old_mode.get_or_insert_with(|| mode.to_string());
+                    self.new_mode.get_or_insert_with(|| mode.to_string());
+                }
+                return Ok(None);
+            } else if line.starts_with("--- ") || line.starts_with("+++ ") {
+                // The file path was already taken from the diff --git line, which
+                // works regardless of the prefixes git used
+                return Ok(None);
+            }
+        }
+        
+        if line.starts_with("@@") {
+            self.in_file_header = false;
+            
+            // Parse hunk header
+            if let Some(caps) = self.hunk_header_re.captures(line) {
+                // The regex only matches digits, so a number can only fail to parse by overflowing
+                let number = |index: usize, default: usize| {
+                    caps.get(index).map_or(Ok(default), |m| m.as_str().parse::<usize>()).map_err(|_| {
+                        RepoDiffError::ParseError(format!("hunk header '{}' has a line number too large to handle", line))
+                    })
+                };
+                let old_start = number(1, 0)?;
+                let old_count = number(2, 1)?;
+                let new_start = number(3, 0)?;
+                let new_count = number(4, 1)?;
+                
+                self.current_hunks.push(Hunk {
+                    header: line.to_string(),
+                    old_start,
+                    old_count,
+                    new_start,
+                    new_count,
+                    lines: Vec::new(),
+                    is_rename: self.is_rename,
+                    rename_from: self.rename_from.clone(),
+                    rename_to: self.rename_to.clone(),
+                    similarity_index: self.similarity_index.clone(),
+                    line_numbers: Vec::new(),
+                    annotation: None,
+                });
+            }
+        } else if self.current_file.is_some() && let Some(hunk) = self.current_hunks.last_mut() {
+            hunk.lines.push(line.to_string());
+        }
+        
+        Ok(None)
+    }
+
+    /// Finish parsing at the end of the diff
+    ///
+    /// # Returns
+    ///
+    /// The path and hunks of the last file, if any
+    pub fn finish(&mut self) -> Option<(String, Vec<Hunk>)> {
+        self.take_file()
+    }
+
+    /// Take what the headers of the files parsed so far say that their hunks don't
+    ///
+    /// These are reported separately because mode-only changes, binary files and
+    /// pure renames have no hunks.
+    pub fn take_file_headers(&mut self) -> FileHeaders {
+        std::mem::take(&mut self.headers)
+    }
+
+    /// Take the file parsed so far, leaving the parser without a current file
+    fn take_file(&mut self) -> Option<(String, Vec<Hunk>)> {
+        let hunks = std::mem::take(&mut self.current_hunks);
+        let file = self.current_file.take()?;
+        let mode_change = self.mode_change(&hunks);
+        if hunks.is_empty() {
+            let hunkless_change = if self.is_binary {
+                Some(HunklessChange::Binary)
+            } else if let (true, Some(from)) = (self.is_rename, &self.rename_from) {
+                Some(HunklessChange::Renamed { from: from.clone() })
+            } else if let Some(from) = &self.copy_from {
+                Some(HunklessChange::Copied { from: from.clone() })
+            } else if mode_change.is_some() {
+                Some(HunklessChange::Mode)
+            } else if self.is_added_or_deleted {
+                Some(HunklessChange::Empty)
+            } else {
+                None
+            };
+            if let Some(hunkless_change) = hunkless_change {
+                self.headers.hunkless_changes.insert(file.clone(), hunkless_change);
+            }
+        }
+        if let Some(mode_change) = mode_change {
+            self.headers.mode_changes.insert(file.clone(), mode_change);
+        }
+        Some((file, hunks))
+    }
+
+    /// Work out the mode or symlink change of the current file from its header and hunks
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks of the current file
+    fn mode_change(&self, hunks: &[Hunk]) -> Option<ModeChange> {
+        let old_mode = self.old_mode.as_deref();
+        let new_mode = self.new_mode.as_deref();
+        
+        // Git diffs a symlink's target as its content
+        if new_mode == Some(SYMLINK_MODE) {
+            let target = hunks.iter()
+                .flat_map(|hunk| &hunk.lines)
+                .find_map(|line| line.strip_prefix('+'))?;
+            return Some(ModeChange::Symlink { target: Some(target.to_string()) });
+        }
+        if old_mode == Some(SYMLINK_MODE) && new_mode.is_none() {
+            return Some(ModeChange::Symlink { target: None });
+        }
+        
+        match (old_mode, new_mode) {
+            (Some(old_mode), Some(new_mode)) if old_mode != new_mode => Some(ModeChange::Permissions {
+                old_mode: old_mode.to_string(),
+                new_mode: new_mode.to_string(),
+            }),
+            _ => None,
+        }
+    }
 }
 
 /// Parser for git diff output that converts it to a structured format
@@ -37,107 +598,396 @@ impl DiffParser {
     ///
     /// * `diff_output` - The raw output from git diff command
     pub fn parse_unified_diff(diff_output: &str) -> Result<HashMap<String, Vec<Hunk>>> {
+        Self::parse_unified_diff_with_prefixes(diff_output, None)
+    }
+    
+    /// Parse the unified diff output, given the prefixes of its paths
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_output` - The raw output from git diff command
+    /// * `path_prefixes` - The prefixes of the diff's paths, or None to detect them
+    pub fn parse_unified_diff_with_prefixes(
+        diff_output: &str,
+        path_prefixes: Option<&PathPrefixes>,
+    ) -> Result<HashMap<String, Vec<Hunk>>> {
         let mut files = HashMap::new();
-        let mut current_file = None;
-        let mut current_hunks = Vec::new();
-        let mut is_rename = false;
-        let mut rename_from = None;
-        let mut rename_to = None;
-        let mut similarity_index = None;
+        let mut parser = DiffStreamParser::new()?.with_path_prefixes(path_prefixes.cloned());
+        for line in diff_output.lines() {
+            if let Some((file, hunks)) = parser.push_line(line)? {
+                files.insert(file, hunks);
+            }
+        }
+        if let Some((file, hunks)) = parser.finish() {
+            files.insert(file, hunks);
+        }
         
-        let hunk_header_re = Regex::new(r"@@ -(\d+),?(\d+)? \+(\d+),?(\d+)? @@")?;
+        Ok(files)
+    }
+    
+    /// Find the files of a unified diff whose mode or symlink target changed
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_output` - The raw output from git diff command
+    pub fn parse_mode_changes(diff_output: &str) -> Result<HashMap<String, ModeChange>> {
+        Ok(Self::parse_file_headers(diff_output, None)?.mode_changes)
+    }
+    
+    /// Find what the file headers of a unified diff say that its hunks don't, such as mode changes and binary files
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_output` - The raw output from git diff command
+    /// * `path_prefixes` - The prefixes of the diff's paths, or None to detect them
+    pub fn parse_file_headers(diff_output: &str, path_prefixes: Option<&PathPrefixes>) -> Result<FileHeaders> {
+        let mut parser = DiffStreamParser::new()?.with_path_prefixes(path_prefixes.cloned());
+        for line in diff_output.lines() {
+            parser.push_line(line)?;
+        }
+        parser.finish();
         
-        let lines: Vec<&str> = diff_output.lines().collect();
-        let mut i = 0;
+        Ok(parser.take_file_headers())
+    }
+    
+    /// Parse a unified diff line by line from a reader, handing each file to a callback as soon as it's complete
+    ///
+    /// Only the file being parsed is held in memory, so very large diffs can be read
+    /// straight from git's output without buffering all of it first.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The source of the raw diff, such as git's stdout
+    /// * `path_prefixes` - The prefixes of the diff's paths, or None to detect them
+    /// * `on_file` - Called with the path and hunks of each file, in diff order, and what
+    ///   the headers of the files parsed so far, including this one, say that their hunks don't
+    ///
+    /// # Returns
+    ///
+    /// What the diff's file headers say that its hunks don't, such as mode changes
+    pub fn parse_unified_diff_reader<R: BufRead>(
+        mut reader: R,
+        path_prefixes: Option<&PathPrefixes>,
+        mut on_file: impl FnMut(String, Vec<Hunk>, &FileHeaders),
+    ) -> Result<FileHeaders> {
+        let mut parser = DiffStreamParser::new()?.with_path_prefixes(path_prefixes.cloned());
+        let mut buffer = Vec::new();
+        loop {
+            buffer.clear();
+            if reader.read_until(b'\n', &mut buffer)? == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            if let Some((file, hunks)) = parser.push_line(line)? {
+                on_file(file, hunks, &parser.headers);
+            }
+        }
+        if let Some((file, hunks)) = parser.finish() {
+            on_file(file, hunks, &parser.headers);
+        }
+        
+        Ok(parser.take_file_headers())
+    }
+    
+    /// Extract the file path from the paths on a `diff --git` line
+    ///
+    /// The line holds the old and new paths, each with the prefix git was told to use
+    /// for its side (`a/` and `b/` by default, none with `--no-prefix`, or custom ones
+    /// via `--src-prefix`/`--dst-prefix`). Paths may contain spaces, so every split point
+    /// is tried, preferring one where both sides name the same file. The new path is
+    /// returned; renames and copies are corrected by their `rename to`/`copy to` lines.
+    ///
+    /// Without configured prefixes, `a/` and `b/` are tried first, then paths without
+    /// a prefix, then a prefix of one directory on each side such as git's mnemonic
+    /// `c/` and `w/`.
+    ///
+    /// # Arguments
+    ///
+    /// * `header_paths` - The part of the line after `diff --git `
+    /// * `path_prefixes` - The prefixes of the diff's paths, or None to detect them
+    fn parse_git_header_path(header_paths: &str, path_prefixes: Option<&PathPrefixes>) -> Option<String> {
+        let splits: Vec<(&str, &str)> = header_paths.match_indices(' ')
+            .map(|(idx, _)| (&header_paths[..idx], &header_paths[idx + 1..]))
+            .collect();
+        
+        // Strip each side's prefix on its own, preferring a split where both sides match
+        let strip = |src: &str, dst: &str, same_file: bool| {
+            splits.iter()
+                .filter_map(|(old_path, new_path)| {
+                    Some((old_path.strip_prefix(src)?, new_path.strip_prefix(dst)?))
+                })
+                .find(|(old_path, new_path)| {
+                    !old_path.is_empty() && !new_path.is_empty() && (!same_file || old_path == new_path)
+                })
+                .map(|(_, new_path)| new_path.to_string())
+        };
+        let strip_any = |src: &str, dst: &str| strip(src, dst, true).or_else(|| strip(src, dst, false));
+        
+        if let Some(prefixes) = path_prefixes {
+            return strip_any(&prefixes.src, &prefixes.dst);
+        }
+        
+        let default = PathPrefixes::default();
+        strip_any(&default.src, &default.dst)
+            .or_else(|| strip("", "", true))
+            .or_else(|| {
+                splits.iter()
+                    .filter_map(|(old_path, new_path)| {
+                        Some((old_path.split_once('/')?.1, new_path.split_once('/')?.1))
+                    })
+                    .find(|(old_path, new_path)| !new_path.is_empty() && old_path == new_path)
+                    .map(|(_, new_path)| new_path.to_string())
+            })
+            .or_else(|| strip("", "", false))
+    }
+
+    /// Check that each hunk contains as many lines as its header declares
+    ///
+    /// A mismatch usually means the git output was cut off, for example because the
+    /// process was killed, so the parsed result is incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of parsed hunks
+    ///
+    /// # Returns
+    ///
+    /// A warning message for each hunk whose line counts don't match its header
+    pub fn validate_hunks(patch_dict: &HashMap<String, Vec<Hunk>>) -> Vec<String> {
+        let mut filenames: Vec<&String> = patch_dict.keys().collect();
+        filenames.sort();
         
-        while i < lines.len() {
-            let line = lines[i];
+        filenames.into_iter()
+            .flat_map(|filename| Self::validate_file_hunks(filename, &patch_dict[filename]))
+            .collect()
+    }
+
+    /// Check that the line counts declared in a file's hunk headers match the lines each hunk holds
+    ///
+    /// # Arguments
+    ///
+    /// * `filename` - The path of the file
+    /// * `hunks` - The file's parsed hunks
+    ///
+    /// # Returns
+    ///
+    /// A warning for each mismatched hunk
+    pub fn validate_file_hunks(filename: &str, hunks: &[Hunk]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for hunk in hunks {
+            // Lines like "\ No newline at end of file" don't count towards either side
+            let content_lines = hunk.lines.iter().filter(|l| !l.starts_with('\\'));
+            let (old_count, new_count) = content_lines.fold((0, 0), |(old, new), line| {
+                if line.starts_with('+') {
+                    (old, new + 1)
+                } else if line.starts_with('-') {
+                    (old + 1, new)
+                } else {
+                    (old + 1, new + 1)
+                }
+            });
             
-            if line.starts_with("diff --git") {
-                // Save previous file data if exists
-                if let Some(file) = current_file.take() {
-                    files.insert(file, current_hunks);
-                    current_hunks = Vec::new();
+            if old_count != hunk.old_count || new_count != hunk.new_count {
+                warnings.push(format!(
+                    "Hunk '{}' in {} has {} old and {} new lines but declares {} and {}; the git output may be truncated",
+                    hunk.header, filename, old_count, new_count, hunk.old_count, hunk.new_count
+                ));
+            }
+        }
+        
+        warnings
+    }
+
+    /// Count the changed lines that only change whitespace
+    ///
+    /// Within each run of consecutive removed and added lines, a line is whitespace-only
+    /// when a line on the other side of the run has the same content ignoring whitespace.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of parsed hunks
+    ///
+    /// # Returns
+    ///
+    /// The number of whitespace-only changed lines and the total number of changed lines
+    pub fn count_whitespace_only_changes(patch_dict: &HashMap<String, Vec<Hunk>>) -> (usize, usize) {
+        patch_dict.values()
+            .map(|hunks| Self::count_file_whitespace_only_changes(hunks))
+            .fold((0, 0), |(whitespace_only, total), (file_whitespace_only, file_total)| {
+                (whitespace_only + file_whitespace_only, total + file_total)
+            })
+    }
+
+    /// Count the changed lines of a single file that only change whitespace
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The file's parsed hunks
+    ///
+    /// # Returns
+    ///
+    /// The number of whitespace-only changed lines and the total number of changed lines
+    pub fn count_file_whitespace_only_changes(hunks: &[Hunk]) -> (usize, usize) {
+        let strip = |line: &str| line[1..].chars().filter(|c| !c.is_whitespace()).collect::<String>();
+        let (mut whitespace_only, mut total) = (0, 0);
+        for hunk in hunks {
+            let mut lines = hunk.lines.iter().peekable();
+            while lines.peek().is_some() {
+                let run: Vec<&String> = std::iter::from_fn(|| lines.next_if(|l| l.starts_with('-') || l.starts_with('+')))
+                    .collect();
+                if run.is_empty() {
+                    lines.next();
+                    continue;
                 }
                 
-                is_rename = false;
-                rename_from = None;
-                rename_to = None;
-                similarity_index = None;
-                
-                // Check for rename by looking ahead
-                let mut j = i + 1;
-                while j < lines.len() && !lines[j].starts_with("diff --git") {
-                    if lines[j].starts_with("similarity index ") {
-                        similarity_index = Some(lines[j].to_string());
-                        is_rename = true;
-                    } else if lines[j].starts_with("rename from ") {
-                        rename_from = Some(lines[j][12..].to_string());
-                    } else if lines[j].starts_with("rename to ") {
-                        rename_to = Some(lines[j][10..].to_string());
+                let mut removed: Vec<String> = run.iter().filter(|l| l.starts_with('-')).map(|l| strip(l)).collect();
+                for added in run.iter().filter(|l| l.starts_with('+')).map(|l| strip(l)) {
+                    if let Some(index) = removed.iter().position(|r| *r == added) {
+                        removed.swap_remove(index);
+                        whitespace_only += 2;
                     }
-                    j += 1;
                 }
-            } else if line.starts_with("--- a/") {
-                // For renames, we need to handle this differently
-                if !is_rename {
-                    i += 1;
-                    continue;
-                }
-            } else if line.starts_with("+++ b/") {
-                if is_rename && rename_from.is_some() && rename_to.is_some() {
-                    current_file = rename_to.clone();
-                } else {
-                    current_file = Some(line[6..].to_string());
-                }
-            } else if line.starts_with("@@") {
-                // Parse hunk header
-                if let Some(caps) = hunk_header_re.captures(line) {
-                    let old_start = caps.get(1).unwrap().as_str().parse::<usize>().unwrap();
-                    let old_count = caps.get(2)
-                        .map_or(1, |m| m.as_str().parse::<usize>().unwrap_or(1));
-                    let new_start = caps.get(3).unwrap().as_str().parse::<usize>().unwrap();
-                    let new_count = caps.get(4)
-                        .map_or(1, |m| m.as_str().parse::<usize>().unwrap_or(1));
-                    
-                    current_hunks.push(Hunk {
-                        header: line.to_string(),
-                        old_start,
-                        old_count,
-                        new_start,
-                        new_count,
-                        lines: Vec::new(),
-                        is_rename,
-                        rename_from: rename_from.clone(),
-                        rename_to: rename_to.clone(),
-                        similarity_index: similarity_index.clone(),
-                    });
-                }
-            } else if current_file.is_some() && !current_hunks.is_empty() {
-                current_hunks.last_mut().unwrap().lines.push(line.to_string());
+                total += run.len();
             }
-            
-            i += 1;
         }
+        (whitespace_only, total)
+    }
+
+    /// Render a signature map: each file followed by the indented signatures of its changed methods
+    ///
+    /// # Arguments
+    ///
+    /// * `signatures` - The changed method signatures of each file, in output order
+    pub fn render_signature_map(signatures: &BTreeMap<String, Vec<String>>) -> String {
+        let mut output = Vec::new();
+        for (path, file_signatures) in signatures {
+            output.push(path.clone());
+            output.extend(file_signatures.iter().map(|signature| format!("  {}", signature)));
+        }
+        output.join("\n")
+    }
+
+    /// Replace every file path with a stable anonymized name such as `file_1.cs`
+    ///
+    /// Names are numbered in path order and keep the original extension so language
+    /// detection still works. Both sides of a rename or copy are anonymized, also for files
+    /// without hunks, and a path always gets the same name wherever it appears.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of hunks
+    /// * `hunkless_changes` - How the files without hunks changed, naming the old paths of their renames and copies
+    ///
+    /// # Returns
+    ///
+    /// The anonymized dictionary and the mapping from each original path to its anonymized name
+    pub fn anonymize_paths(
+        patch_dict: HashMap<String, Vec<Hunk>>,
+        hunkless_changes: &HashMap<String, HunklessChange>,
+    ) -> (HashMap<String, Vec<Hunk>>, BTreeMap<String, String>) {
+        let hunkless_sources = hunkless_changes.iter()
+            .filter(|(path, _)| patch_dict.contains_key(*path))
+            .filter_map(|(_, change)| match change {
+                HunklessChange::Renamed { from } | HunklessChange::Copied { from } => Some(from),
+                _ => None,
+            });
+        let mut paths: Vec<&String> = patch_dict.keys()
+            .chain(patch_dict.values().flatten().filter_map(|hunk| hunk.rename_from.as_ref()))
+            .chain(hunkless_sources)
+            .collect();
+        paths.sort();
+        paths.dedup();
+        
+        let mapping: BTreeMap<String, String> = paths.into_iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let file_name = path.rsplit('/').next().unwrap_or(path);
+                let name = match file_name.rsplit_once('.') {
+                    Some((stem, extension)) if !stem.is_empty() => format!("file_{}.{}", index + 1, extension),
+                    _ => format!("file_{}", index + 1),
+                };
+                (path.clone(), name)
+            })
+            .collect();
         
-        // Save the last file
-        if let Some(file) = current_file {
-            files.insert(file, current_hunks);
+        let anonymize = |path: &mut Option<String>| {
+            if let Some(p) = path.as_mut()
+                && let Some(name) = mapping.get(p.as_str())
+            {
+                *p = name.clone();
+            }
+        };
+        let anonymized = patch_dict.into_iter()
+            .map(|(path, mut hunks)| {
+                for hunk in &mut hunks {
+                    anonymize(&mut hunk.rename_from);
+                    anonymize(&mut hunk.rename_to);
+                }
+                (mapping[&path].clone(), hunks)
+            })
+            .collect();
+        
+        (anonymized, mapping)
+    }
+
+    /// Parse a diff and check it for problems without producing any output
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_output` - The unified diff to check
+    ///
+    /// # Returns
+    ///
+    /// The structure of the diff, or a parse error describing the first problem found
+    pub fn validate_diff(diff_output: &str) -> Result<DiffStructure> {
+        let patch_dict = Self::parse_unified_diff(diff_output)?;
+        if patch_dict.is_empty() && !diff_output.trim().is_empty() {
+            return Err(RepoDiffError::ParseError("no 'diff --git' file headers found".to_string()));
+        }
+        if let Some(warning) = Self::validate_hunks(&patch_dict).into_iter().next() {
+            return Err(RepoDiffError::ParseError(warning));
         }
         
-        Ok(files)
+        Ok(DiffStructure {
+            files: patch_dict.len(),
+            hunks: patch_dict.values().map(|hunks| hunks.len()).sum(),
+            renames: diff_output.lines().filter(|line| line.starts_with("rename from ")).count(),
+        })
     }
-    
+
     /// Get the instructions for interpreting git diff output
     ///
     /// # Arguments
     ///
     /// * `filters_json` - JSON string containing the file filters configuration
     pub fn get_diff_instructions(filters_json: Option<&str>) -> Vec<String> {
-        let mut instructions = String::from("This file provides a guide to understanding the diff output generated by RepoDiff, a simplified and context-aware unified diff designed for code reviews.
+        Self::get_diff_instructions_for_sections(filters_json, &OutputOptions::default())
+    }
+
+    /// Get the instructions for interpreting git diff output, made of the sections chosen
+    /// by `preamble_sections`, or all of them if it's unset
+    ///
+    /// The closing "Diff Output" banner is always included, and markers are only described
+    /// when the options can produce them.
+    ///
+    /// # Arguments
+    ///
+    /// * `filters_json` - JSON string containing the file filters configuration
+    /// * `options` - Options controlling how the output is rendered
+    pub fn get_diff_instructions_for_sections(filters_json: Option<&str>, options: &OutputOptions) -> Vec<String> {
+        let mut instructions = String::new();
+        let all_sections: Vec<String> = PREAMBLE_SECTIONS.iter().map(|s| s.to_string()).collect();
+        for name in options.preamble_sections.as_deref().unwrap_or(&all_sections) {
+            match name.as_str() {
+                "introduction" => instructions.push_str("This file provides a guide to understanding the diff output generated by RepoDiff, a simplified and context-aware unified diff designed for code reviews.
 RepoDiff processes a single `git diff` output and applies user-defined rules to tailor the content, with special handling for C# files.
 
-# 1. Basic Structure:
+"),
+                "basic_structure" => {
+                    let (added, removed, unchanged) = Self::marker_names(options);
+                    instructions.push_str("# 1. Basic Structure:
 
 A Git diff file describes the *differences* between two versions of a file. It's structured into *hunks*, which represent contiguous regions of change.
 
@@ -149,63 +999,88 @@ A Git diff file describes the *differences* between two versions of a file. It's
     *   `+<start_line_new>,<num_lines_new>`: Indicates the starting line number and number of lines in the *new* version of the file that this hunk represents.  If only one line is affected, `,<num_lines_new>` will be omitted.
     *   `<section_header>`:  (Optional) This is often a function or method name, providing context for the change.
 *   Hunk Content:  Lines within a hunk are marked with a prefix:
-    *   ` ` (space):  Unchanged line (context).
-    *   `-`: Line removed from the old version.
-    *   `+`: Line added to the new version.
-
+");
+                    instructions.push_str(&format!("    *   {}:  Unchanged line (context).
+    *   {}: Line removed from the old version.
+    *   {}: Line added to the new version.
+", unchanged, removed, added));
+                    
+                    // The example is rendered with the markers the output uses
+                    let example: Vec<String> = [
+                        " // Some code",
+                        " string oldValue = \"old\";",
+                        "-// Removed line",
+                        "+string newValue = \"new\";",
+                        " // More code",
+                    ].into_iter().map(|line| Self::render_markers(line.to_string(), options)).collect();
+                    instructions.push_str(&format!("
 ## Simplified Example:
 
 ```diff
 diff --git a/MyFile.cs b/MyFile.cs
 --- a/MyFile.cs
 +++ b/MyFile.cs 
- // Some code
- string oldValue = \"old\";
--// Removed line
-+string newValue = \"new\";
- // More code
+{}
 ```
 
 **Explanation of the Example:**
 *   The file being changed is `MyFile.cs`.
-*   `\" string oldValue = \"old\";\"`:  This line is present in both versions.
-*   `-// Removed line`: This line was removed from the old version.
-*   `+string newValue = \"new\";`: This line was added to the new version.
-*   `\" // More code\"`: This line is present in both versions.
+*   `\"{}\"`:  This line is present in both versions.
+*   `{}`: This line was removed from the old version.
+*   `{}`: This line was added to the new version.
+*   `\"{}\"`: This line is present in both versions.
 
-# 2. Special Handling in RepoDiff
+", example.join("\n"), example[1], example[2], example[3], example[4]));
+                }
+                "special_handling" => {
+                    instructions.push_str("# 2. Special Handling in RepoDiff
 
 RepoDiff customizes the diff output using user-defined filters, with enhanced control for C# files (*.cs).
 
 The following JSON filters are applied to the diff output:
 
 ");
-        
-        if let Some(filters) = filters_json {
-            instructions.push_str(filters);
-        }
-        
-        instructions.push_str("
+                    if let Some(filters) = filters_json {
+                        instructions.push_str(filters);
+                    }
+                    instructions.push_str("
 
 Each filter defines:
 
-*   *`file_pattern`*: A glob pattern matching file names (e.g., \"*.cs\" for C# files).
+*   *`file_pattern`*: A glob pattern matching file names (e.g., \"*.cs\" for C# files). A leading `!` (e.g., \"!*Generated.cs\") excludes matching files from the output.
 *   *`context_lines`*: Number of unchanged lines shown before and after each change or hunk.
 *   **For C# files only:**
     *   *`include_method_body`*: If true, includes the entire body of methods with changes.
     *   *`include_signatures`*: If true, includes signatures of methods within the context range of changes, with partial or full bodies based on size. It will always include namespace/class declarations enclosing changed methods. The placeholder `⋮----`* is used to omit code inside the hunk that is outside of the context range
 
-# 4. Usage Guidelines
+");
+                }
+                "usage_guidelines" => {
+                    let (added, removed, unchanged) = Self::marker_names(options);
+                    instructions.push_str(&format!("# 4. Usage Guidelines
 
-*   Focus on Content: Lines with  ` `, `-`, or `+` show the actual changes.
-*   Use Context: Unchanged lines provide purpose and structure.
+*   Focus on Content: Lines with  {}, {}, or {} show the actual changes.
+", unchanged, removed, added));
+                    instructions.push_str("*   Use Context: Unchanged lines provide purpose and structure.
 *   Interpret Placeholders: `⋮----` signals omitted code; infer its presence simplifies analysis. Consider the context around the placeholder to understand what might have been omitted (e.g., method body, part of a method, etc.).
 *   File Paths: Track `a/<path>` and `b/<path>` to identify modified files.
 *   C# Specifics: Note method bodies and signatures in *.cs files are tailored by filters.
-
+");
+                    if options.test_file_pattern.is_some() {
+                        instructions.push_str("*   Test Files: Sections preceded by a `// test file` marker contain test code rather than production code.
+");
+                    }
+                    instructions.push_str("
 By focusing on these key elements, you can effectively extract meaningful information from Git diff output and summarize the changes made in a software project.
 
-================================================================
+");
+                }
+                // Unknown names are reported by config validation
+                _ => {}
+            }
+        }
+        
+        instructions.push_str("================================================================
 Diff Output
 ================================================================
 
@@ -214,6 +1089,101 @@ Diff Output
         instructions.lines().map(|s| s.to_string()).collect()
     }
 
+    /// Get the preamble placed before the diffs: the legend of the markers in use if
+    /// `legend` is set, otherwise the instructions
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of processed hunks
+    /// * `filters_json` - JSON string containing the file filters configuration
+    /// * `options` - Options controlling how the output is rendered
+    pub fn preamble(patch_dict: &HashMap<String, Vec<Hunk>>, filters_json: Option<&str>, options: &OutputOptions) -> Vec<String> {
+        if options.legend {
+            Self::marker_legend(patch_dict, options)
+        } else {
+            Self::get_diff_instructions_for_sections(filters_json, options)
+        }
+    }
+
+    /// Build a short legend of the markers used in the output
+    ///
+    /// Markers added while rendering are listed when their options are enabled, and
+    /// markers added while filtering when they appear in the processed hunks.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of processed hunks
+    /// * `options` - Options controlling how the output is rendered
+    pub fn marker_legend(patch_dict: &HashMap<String, Vec<Hunk>>, options: &OutputOptions) -> Vec<String> {
+        let lines = || patch_dict.values().flatten().flat_map(|hunk| &hunk.lines);
+        let offset_pattern = Regex::new(r"^[-+ ]\+\d+:").unwrap();
+        
+        let mut legend = vec!["# Legend".to_string()];
+        legend.push(match (options.verbose_markers, options.plain_context) {
+            (true, true) => "* `[+] ` added line, `[-] ` removed line; unchanged lines have no marker".to_string(),
+            (true, false) => "* `[+] ` added line, `[-] ` removed line, four spaces: unchanged line".to_string(),
+            (false, true) => "* `+` added line, `-` removed line; unchanged lines have no marker".to_string(),
+            (false, false) => "* `+` added line, `-` removed line, ` ` unchanged line".to_string(),
+        });
+        if lines().any(|line| line.ends_with("⋮----")) {
+            legend.push("* `⋮----` unchanged code left out".to_string());
+        }
+        if lines().any(|line| offset_pattern.is_match(line)) {
+            legend.push("* `+N:` after the marker: the line's offset from its method's signature".to_string());
+        }
+        if patch_dict.values().flatten().any(|hunk| hunk.annotation.is_some()) {
+            legend.push("* `# hunk: ...` counts the changed lines of the hunk it starts".to_string());
+        }
+        if lines().any(|line| line.starts_with(" (identical change ")) {
+            legend.push("* `(identical change ...)` the same change is made in the files it names".to_string());
+        }
+        if lines().any(|line| line.starts_with("+[moved] ") || line.starts_with("-[moved] ")) {
+            legend.push("* `[moved] <method>` a method moved within its file, shown once where it now is".to_string());
+        }
+        if lines().any(|line| line.contains("  [blame ")) {
+            legend.push("* `[blame <commit> <author>]` the commit that last changed a removed line".to_string());
+        }
+        if options.show_trailing_whitespace {
+            legend.push("* `·` trailing space, `→` trailing tab".to_string());
+        }
+        if options.wrap_width.is_some() {
+            legend.push("* `↪` after the marker: continuation of the line above".to_string());
+        }
+        if options.max_line_length.is_some() {
+            legend.push("* `…[truncated N chars]` the rest of a long line is left out".to_string());
+        }
+        if options.file_tree {
+            legend.push("* File tree: `[A]` added, `[D]` deleted, `[R]` renamed, `[M]` modified".to_string());
+        }
+        if options.group_by_dir {
+            legend.push("* `# Directory: <dir>` starts the files of a top-level directory".to_string());
+        }
+        if options.file_ids {
+            legend.push("* `// FILE-ID: <id>` stable identifier of the file that follows".to_string());
+        }
+        if options.test_file_pattern.is_some() {
+            legend.push("* `// test file` the file that follows is test code".to_string());
+        }
+        if options.label_changes {
+            legend.push("* `[Added]`, `[Deleted]`, `[Renamed from <path>]`, `[Modified]` how the file changed; files without changed lines are `[Binary]`, `[Renamed from <path>]`, `[Copied from <path>]`, `[Mode]` for mode or symlink changes, or `[Empty]` for added or deleted empty files".to_string());
+        }
+        if options.compact_renames {
+            legend.push("* `# renamed: <old> → <new>` the file was renamed".to_string());
+        }
+        if !options.opaque_files.is_empty() {
+            legend.push("* `<path> changed (content hidden)` the file changed, but its content isn't shown".to_string());
+        }
+        
+        legend.extend([
+            String::new(),
+            "================================================================".to_string(),
+            "Diff Output".to_string(),
+            "================================================================".to_string(),
+            String::new(),
+        ]);
+        legend
+    }
+
     /// Reconstruct a unified diff from the processed patch dictionary
     ///
     /// # Arguments
@@ -221,49 +1191,569 @@ Diff Output
     /// * `patch_dict` - Dictionary mapping filenames to lists of hunks
     /// * `filters_json` - JSON string containing the file filters configuration
     pub fn reconstruct_patch(patch_dict: &HashMap<String, Vec<Hunk>>, filters_json: Option<&str>) -> String {
+        Self::reconstruct_patch_with_options(patch_dict, filters_json, &OutputOptions::default())
+    }
+
+    /// Reconstruct a unified diff from the processed patch dictionary using the given output options
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of hunks
+    /// * `filters_json` - JSON string containing the file filters configuration
+    /// * `options` - Options controlling how the output is rendered
+    pub fn reconstruct_patch_with_options(
+        patch_dict: &HashMap<String, Vec<Hunk>>,
+        filters_json: Option<&str>,
+        options: &OutputOptions,
+    ) -> String {
+        if options.format == OutputFormat::Html {
+            return Self::render_html(patch_dict, options);
+        }
+        
         let mut output = Vec::new();
         
-        // Only add instructions if the patch dictionary is not empty
-        if !patch_dict.is_empty() {
-            output.extend(Self::get_diff_instructions(filters_json));
+        // Only add instructions if requested and the patch dictionary is not empty
+        if options.include_instructions && !patch_dict.is_empty() {
+            output.extend(Self::preamble(patch_dict, filters_json, options));
+        }
+        
+        // Emit files in path order so the output is deterministic
+        let mut filenames: Vec<&String> = patch_dict.keys().collect();
+        filenames.sort();
+        
+        // The file list sits between the preamble and the diffs, so runs over the same
+        // files share everything up to the first diff as a cacheable prefix
+        if options.cache_friendly && !filenames.is_empty() {
+            output.push("# Files".to_string());
+            output.extend(filenames.iter().map(|filename| filename.to_string()));
+            output.push(String::new());
+        }
+        
+        if options.file_tree && !patch_dict.is_empty() {
+            output.push(Self::render_file_tree(patch_dict));
+            output.push(String::new());
         }
         
-        for (filename, hunks) in patch_dict {
-            // Check if any hunks have rename information
-            let is_rename = hunks.iter().any(|hunk| hunk.is_rename);
+        // Absolute paths replace git's a/ and b/ prefixes, like `git diff --no-prefix`
+        let (old_prefix, new_prefix) = match &options.path_root {
+            Some(root) => {
+                let root = format!("{}/", root.trim_end_matches(['/', '\\']));
+                (root.clone(), root)
+            }
+            None => ("a/".to_string(), "b/".to_string()),
+        };
+        
+        let mut current_dir = None;
+        for (index, filename) in filenames.into_iter().enumerate() {
+            let hunks = &patch_dict[filename];
             
-            if is_rename && !hunks.is_empty() {
-                // Get rename information from the first hunk
-                let first_hunk = &hunks[0];
-                let rename_from = first_hunk.rename_from.as_ref();
-                let rename_to = first_hunk.rename_to.as_ref();
-                let similarity_index = first_hunk.similarity_index.as_ref();
-                
+            if index > 0
+                && let Some(separator) = &options.file_separator
+            {
+                output.push(separator.clone());
+            }
+            
+            // Insert a heading whenever the top-level directory changes
+            if options.group_by_dir {
+                let dir = Self::top_level_dir(filename);
+                if current_dir != Some(dir) {
+                    output.push(format!("# Directory: {}", dir));
+                    current_dir = Some(dir);
+                }
+            }
+            
+            if options.file_ids {
+                output.push(format!("// FILE-ID: {}", FileSummary::file_id(filename)));
+            }
+            
+            // Tag test files so they can be weighted accordingly
+            if let Some(pattern) = &options.test_file_pattern
+                && FilterManager::matches_pattern(pattern, filename)
+            {
+                output.push("// test file".to_string());
+            }
+            
+            if options.opaque_files.contains(filename) {
+                output.push(format!("{} changed (content hidden)", filename));
+                continue;
+            }
+            
+            if options.label_changes {
+                output.push(Self::change_label(hunks, options.hunkless_changes.get(filename)));
+            }
+            
+            if let Some(mode_change) = options.mode_changes.get(filename) {
+                output.push(mode_change.label(filename));
+            }
+            
+            // The fence must be longer than any backtick run in the content so it isn't closed early
+            let fence = (options.format == OutputFormat::Markdown).then(|| Self::markdown_fence(hunks));
+            if let Some(fence) = &fence {
+                let language = Self::language_for_path_with_map(filename, &options.extension_language_map);
+                output.push(format!("{}{}", fence, language.unwrap_or("diff")));
+            }
+            
+            // Every hunk of a renamed file carries the rename information, so a single
+            // rename header covers all of them
+            let rename = hunks.iter().find(|hunk| hunk.is_rename);
+            
+            if let Some(hunk) = rename
+                && let (Some(from), Some(to)) = (&hunk.rename_from, &hunk.rename_to)
+                && options.compact_renames
+            {
+                output.push(format!("# renamed: {} → {}", from, to));
+            } else if let Some(hunk) = rename
+                && let (Some(from), Some(to)) = (&hunk.rename_from, &hunk.rename_to)
+            {
                 // Construct the rename diff header
-                if let (Some(from), Some(to)) = (rename_from, rename_to) {
-                    output.push(format!("diff --git a/{} b/{}", from, to));
-                    if let Some(sim_idx) = similarity_index {
-                        output.push(sim_idx.clone());
-                    }
-                    output.push(format!("rename from {}", from));
-                    output.push(format!("rename to {}", to));
-                    output.push(format!("--- a/{}", from));
-                    output.push(format!("+++ b/{}", to));
+                output.push(format!("diff --git {}{} {}{}", old_prefix, from, new_prefix, to));
+                if let Some(sim_idx) = &hunk.similarity_index {
+                    output.push(sim_idx.clone());
                 }
+                output.push(format!("rename from {}", from));
+                output.push(format!("rename to {}", to));
+                output.push(format!("--- {}{}", old_prefix, from));
+                output.push(format!("+++ {}{}", new_prefix, to));
             } else {
                 // Regular file diff
-                output.push(format!("diff --git a/{} b/{}", filename, filename));
-                output.push(format!("--- a/{}", filename));
-                output.push(format!("+++ b/{}", filename));
+                output.push(format!("diff --git {}{} {}{}", old_prefix, filename, new_prefix, filename));
+                output.push(format!("--- {}{}", old_prefix, filename));
+                output.push(format!("+++ {}{}", new_prefix, filename));
+            }
+            
+            for hunk in hunks {
+                // The hunk header is skipped unless exact traceability to git's output is wanted,
+                // in which case it is kept even if its counts no longer match the trimmed lines
+                if options.preserve_original_headers {
+                    output.push(hunk.header.clone());
+                }
+                if let Some(annotation) = &hunk.annotation {
+                    output.push(format!("# hunk: {}", annotation));
+                }
+                for line in &hunk.lines {
+                    // Pathological lines such as minified code are cut before wrapping
+                    let mut line = match options.max_line_length {
+                        Some(max_length) => Self::truncate_line(line, max_length),
+                        None => line.clone(),
+                    };
+                    if options.show_trailing_whitespace {
+                        line = Self::mark_trailing_whitespace(&line);
+                    }
+                    let rendered = match options.wrap_width {
+                        Some(width) => Self::wrap_line(&line, width),
+                        None => vec![line],
+                    };
+                    
+                    output.extend(rendered.into_iter().map(|l| Self::render_markers(l, options)));
+                }
+            }
+            
+            if let Some(fence) = fence {
+                output.push(fence);
+            }
+        }
+        
+        output.join("\n")
+    }
+
+    /// Render the diff marker of a hunk line as the options ask
+    ///
+    /// Plain context drops the space marker so unchanged code reads like normal source,
+    /// and verbose markers replace the single-character markers with explicit ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The hunk line, starting with its ` `, `+` or `-` marker
+    /// * `options` - Options controlling how the output is rendered
+    fn render_markers(line: String, options: &OutputOptions) -> String {
+        if options.plain_context && let Some(stripped) = line.strip_prefix(' ') {
+            stripped.to_string()
+        } else if options.verbose_markers {
+            Self::verbose_marker_line(line)
+        } else {
+            line
+        }
+    }
+
+    /// Describe the markers of added, removed and unchanged lines, as the options render them
+    ///
+    /// # Arguments
+    ///
+    /// * `options` - Options controlling how the output is rendered
+    ///
+    /// # Returns
+    ///
+    /// The descriptions of the added, removed and unchanged line markers
+    fn marker_names(options: &OutputOptions) -> (&'static str, &'static str, &'static str) {
+        let unchanged = match (options.plain_context, options.verbose_markers) {
+            (true, _) => "no marker",
+            (false, true) => "four spaces",
+            (false, false) => "` ` (space)",
+        };
+        if options.verbose_markers {
+            ("`[+] `", "`[-] `", unchanged)
+        } else {
+            ("`+`", "`-`", unchanged)
+        }
+    }
+
+    /// Replace the single-character diff marker of a hunk line with a more explicit one:
+    /// `[+] ` for added lines, `[-] ` for removed lines and four spaces for context
+    ///
+    /// Lines without a diff marker, such as `\ No newline at end of file`, are kept as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The hunk line including its diff prefix
+    fn verbose_marker_line(line: String) -> String {
+        let marker = match line.chars().next() {
+            Some('+') => "[+] ",
+            Some('-') => "[-] ",
+            Some(' ') => "    ",
+            _ => return line,
+        };
+        format!("{}{}", marker, &line[1..])
+    }
+
+    /// Soft-wrap a hunk line to the given width
+    ///
+    /// The diff prefix (` `, `+` or `-`) is repeated on every continuation line,
+    /// followed by a `↪` marker, so the marker column is never split. Widths are
+    /// measured in characters.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The hunk line including its diff prefix
+    /// * `width` - The maximum number of characters per output line
+    fn wrap_line(line: &str, width: usize) -> Vec<String> {
+        // Wrapping needs room for the prefix, the marker and at least one character
+        if width < 3 || line.chars().count() <= width {
+            return vec![line.to_string()];
+        }
+        
+        let mut chars = line.chars();
+        let prefix = chars.next().unwrap_or(' ');
+        let content: Vec<char> = chars.collect();
+        
+        let (first, rest) = content.split_at(width - 1);
+        let mut wrapped = vec![format!("{}{}", prefix, first.iter().collect::<String>())];
+        for chunk in rest.chunks(width - 2) {
+            wrapped.push(format!("{}↪{}", prefix, chunk.iter().collect::<String>()));
+        }
+        wrapped
+    }
+
+    /// Truncate a diff line whose content is longer than a maximum length
+    ///
+    /// The diff prefix is kept, and the removed characters are replaced with a
+    /// `…[truncated N chars]` marker.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The diff line, including its prefix
+    /// * `max_length` - The maximum number of content characters to keep
+    fn truncate_line(line: &str, max_length: usize) -> String {
+        let mut chars = line.chars();
+        let prefix = chars.next().map(String::from).unwrap_or_default();
+        let content = chars.as_str();
+        
+        let length = content.chars().count();
+        if length <= max_length {
+            return line.to_string();
+        }
+        
+        let kept: String = content.chars().take(max_length).collect();
+        format!("{}{}…[truncated {} chars]", prefix, kept, length - max_length)
+    }
+
+    /// Replace the trailing spaces and tabs of a diff line's content with visible markers
+    ///
+    /// Spaces become `·` and tabs become `→`. The diff prefix is never marked.
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The diff line, including its prefix
+    fn mark_trailing_whitespace(line: &str) -> String {
+        let mut chars = line.chars();
+        let prefix = chars.next().map(String::from).unwrap_or_default();
+        let content = chars.as_str();
+        
+        let trimmed = content.trim_end_matches([' ', '\t']);
+        let markers: String = content[trimmed.len()..]
+            .chars()
+            .map(|c| if c == '\t' { '→' } else { '·' })
+            .collect();
+        format!("{}{}{}", prefix, trimmed, markers)
+    }
+
+    /// Get a Markdown code fence that is safe to wrap a file's hunks in
+    ///
+    /// CommonMark closes a fence only with a run of backticks at least as long as
+    /// the opening one, so the fence is made one longer than the longest run in the content.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks to be fenced
+    fn markdown_fence(hunks: &[Hunk]) -> String {
+        let longest_run = hunks.iter()
+            .flat_map(|hunk| &hunk.lines)
+            .flat_map(|line| line.split(|c| c != '`'))
+            .map(|run| run.len())
+            .max()
+            .unwrap_or(0);
+        "`".repeat((longest_run + 1).max(3))
+    }
+
+    /// Get the label describing the kind of change made to a file
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks of the file
+    /// * `hunkless_change` - How the file changed if its diff has no hunks, as its header tells
+    pub fn change_label(hunks: &[Hunk], hunkless_change: Option<&HunklessChange>) -> String {
+        if let Some(hunkless_change) = hunkless_change {
+            return hunkless_change.label();
+        }
+        
+        match ChangeKind::classify(hunks) {
+            ChangeKind::Added => "[Added]".to_string(),
+            ChangeKind::Deleted => "[Deleted]".to_string(),
+            ChangeKind::Renamed => match hunks.iter().find_map(|hunk| hunk.rename_from.as_deref()) {
+                Some(from) => format!("[Renamed from {}]", from),
+                None => "[Renamed]".to_string(),
+            },
+            ChangeKind::Modified => "[Modified]".to_string(),
+        }
+    }
+
+    /// Get a file's language, checking a configured extension map before the built-in mapping
+    ///
+    /// Map keys are extensions in lowercase and without the leading dot, as the config stores them.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    /// * `extension_language_map` - Configured extension to language overrides
+    pub fn language_for_path_with_map<'a>(
+        path: &str,
+        extension_language_map: &'a HashMap<String, String>,
+    ) -> Option<&'a str> {
+        Self::file_extension(path)
+            .and_then(|extension| extension_language_map.get(&extension))
+            .map(String::as_str)
+            .or_else(|| Self::language_for_path(path))
+    }
+
+    /// Get the lowercased extension of a file, taken from its name so dots in directory names are ignored
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    fn file_extension(path: &str) -> Option<String> {
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        file_name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase())
+    }
+
+    /// Get the language tag used for syntax highlighting a file in Markdown output
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    ///
+    /// # Returns
+    ///
+    /// The language tag, or None if the extension is not recognized
+    pub fn language_for_path(path: &str) -> Option<&'static str> {
+        let extension = Self::file_extension(path)?;
+        let language = match extension.as_str() {
+            "cs" => "csharp",
+            "cshtml" | "razor" => "razor",
+            "py" => "python",
+            "rs" => "rust",
+            "js" | "mjs" | "cjs" => "javascript",
+            "jsx" => "jsx",
+            "ts" => "typescript",
+            "tsx" => "tsx",
+            "java" => "java",
+            "kt" => "kotlin",
+            "go" => "go",
+            "rb" => "ruby",
+            "php" => "php",
+            "c" | "h" => "c",
+            "cpp" | "cc" | "cxx" | "hpp" => "cpp",
+            "swift" => "swift",
+            "sh" | "bash" => "bash",
+            "ps1" => "powershell",
+            "sql" => "sql",
+            "html" | "htm" => "html",
+            "css" => "css",
+            "scss" => "scss",
+            "xml" | "csproj" | "props" | "targets" => "xml",
+            "json" => "json",
+            "yml" | "yaml" => "yaml",
+            "toml" => "toml",
+            "md" => "markdown",
+            _ => return None,
+        };
+        Some(language)
+    }
+
+    /// Render the changed files as a tree of their directories, like the `tree` command
+    ///
+    /// Each file is marked with its kind of change: `A` added, `D` deleted, `R`
+    /// renamed or `M` modified. Directories and files are listed in name order.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of hunks
+    pub fn render_file_tree(patch_dict: &HashMap<String, Vec<Hunk>>) -> String {
+        // Directory names keep their trailing slash, so they never clash with a file of the same name
+        enum Node {
+            Dir(BTreeMap<String, Node>),
+            File(char),
+        }
+        
+        fn render(entries: &BTreeMap<String, Node>, prefix: &str, lines: &mut Vec<String>) {
+            for (index, (name, node)) in entries.iter().enumerate() {
+                let last = index + 1 == entries.len();
+                let branch = if last { "└── " } else { "├── " };
+                match node {
+                    Node::Dir(children) => {
+                        lines.push(format!("{}{}{}", prefix, branch, name));
+                        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                        render(children, &child_prefix, lines);
+                    }
+                    Node::File(marker) => lines.push(format!("{}{}{} [{}]", prefix, branch, name, marker)),
+                }
+            }
+        }
+        
+        let mut root = BTreeMap::new();
+        for (path, hunks) in patch_dict {
+            let marker = match ChangeKind::classify(hunks) {
+                ChangeKind::Added => 'A',
+                ChangeKind::Deleted => 'D',
+                ChangeKind::Renamed => 'R',
+                ChangeKind::Modified => 'M',
+            };
+            let mut components: Vec<&str> = path.split('/').collect();
+            let file_name = components.pop().unwrap_or_default();
+            let mut entries = &mut root;
+            for component in components {
+                entries = match entries.entry(format!("{}/", component)).or_insert_with(|| Node::Dir(BTreeMap::new())) {
+                    Node::Dir(children) => children,
+                    Node::File(_) => unreachable!("directory keys end with a slash"),
+                };
+            }
+            entries.insert(file_name.to_string(), Node::File(marker));
+        }
+        
+        let mut lines = vec![".".to_string()];
+        render(&root, "", &mut lines);
+        lines.join("\n")
+    }
+
+    /// Render the files as a self-contained HTML report
+    ///
+    /// Each file gets a collapsible `<details>` section, in path order, with its added
+    /// and removed lines colored by inline CSS. The instructions preamble is left out,
+    /// as the report is meant for people rather than models.
+    ///
+    /// # Arguments
+    ///
+    /// * `patch_dict` - Dictionary mapping filenames to lists of hunks
+    /// * `options` - Options controlling how the output is rendered
+    pub fn render_html(patch_dict: &HashMap<String, Vec<Hunk>>, options: &OutputOptions) -> String {
+        let mut output = vec![
+            "<!DOCTYPE html>".to_string(),
+            "<html>".to_string(),
+            "<head>".to_string(),
+            "<meta charset=\"utf-8\">".to_string(),
+            "<title>RepoDiff report</title>".to_string(),
+            "<style>".to_string(),
+            "body { font-family: sans-serif; margin: 2em; }".to_string(),
+            "details.file { border: 1px solid #d0d7de; border-radius: 6px; margin-bottom: 1em; }".to_string(),
+            "details.file > summary { background: #f6f8fa; padding: 0.5em; cursor: pointer; font-family: monospace; }".to_string(),
+            "pre { margin: 0; padding: 0.5em; overflow-x: auto; }".to_string(),
+            ".add { background: #e6ffec; color: #116329; display: block; }".to_string(),
+            ".del { background: #ffebe9; color: #82071e; display: block; }".to_string(),
+            ".ctx { display: block; }".to_string(),
+            ".hunk { color: #0550ae; display: block; }".to_string(),
+            "</style>".to_string(),
+            "</head>".to_string(),
+            "<body>".to_string(),
+        ];
+        
+        let mut filenames: Vec<&String> = patch_dict.keys().collect();
+        filenames.sort();
+        for filename in filenames {
+            let hunks = &patch_dict[filename];
+            let title = match hunks.iter().find(|hunk| hunk.is_rename) {
+                Some(Hunk { rename_from: Some(from), rename_to: Some(to), .. }) => format!("{} → {}", from, to),
+                _ => filename.clone(),
+            };
+            output.push("<details class=\"file\" open>".to_string());
+            output.push(format!("<summary>{}</summary>", Self::escape_html(&title)));
+            
+            if options.opaque_files.contains(filename) {
+                output.push(format!("<pre>{} changed (content hidden)</pre>", Self::escape_html(filename)));
+                output.push("</details>".to_string());
+                continue;
             }
             
+            output.push("<pre>".to_string());
             for hunk in hunks {
-                // Skip the hunk header as it's not necessary for understanding changes
-                // output.push(hunk.header.clone());
-                output.extend(hunk.lines.clone());
+                if options.preserve_original_headers {
+                    output.push(format!("<span class=\"hunk\">{}</span>", Self::escape_html(&hunk.header)));
+                }
+                if let Some(annotation) = &hunk.annotation {
+                    output.push(format!("<span class=\"hunk\">hunk: {}</span>", Self::escape_html(annotation)));
+                }
+                for line in &hunk.lines {
+                    let class = match line.chars().next() {
+                        Some('+') => "add",
+                        Some('-') => "del",
+                        _ => "ctx",
+                    };
+                    output.push(format!("<span class=\"{}\">{}</span>", class, Self::escape_html(line)));
+                }
             }
+            output.push("</pre>".to_string());
+            output.push("</details>".to_string());
         }
         
+        output.push("</body>".to_string());
+        output.push("</html>".to_string());
         output.join("\n")
     }
+
+    /// Escape the characters of a string that are special in HTML
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The text to escape
+    fn escape_html(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            match c {
+                '&' => escaped.push_str("&amp;"),
+                '<' => escaped.push_str("&lt;"),
+                '>' => escaped.push_str("&gt;"),
+                '"' => escaped.push_str("&quot;"),
+                '\'' => escaped.push_str("&#39;"),
+                _ => escaped.push(c),
+            }
+        }
+        escaped
+    }
+
+    /// Get the top-level directory of a path, with a trailing slash
+    ///
+    /// Files in the repository root are grouped under "./".
+    pub fn top_level_dir(path: &str) -> &str {
+        match path.find('/') {
+            Some(idx) => &path[..=idx],
+            None => "./",
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,209 @@
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::error::Result;
+use crate::utils::diff_parser::Hunk;
+
+/// The kind of change made to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// The file was created
+    Added,
+    /// The file was removed
+    Deleted,
+    /// The file was moved, possibly with changes
+    Renamed,
+    /// The file's content changed in place
+    Modified,
+}
+
+impl ChangeKind {
+    /// Classify the change made to a file from its hunks
+    ///
+    /// A file whose only hunk starts at line 0 on the old side was added, and one
+    /// whose only hunk starts at line 0 on the new side was deleted.
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The parsed hunks of the file
+    pub fn classify(hunks: &[Hunk]) -> Self {
+        if hunks.iter().any(|hunk| hunk.is_rename) {
+            ChangeKind::Renamed
+        } else if let [hunk] = hunks
+            && hunk.old_start == 0
+            && hunk.old_count == 0
+        {
+            ChangeKind::Added
+        } else if let [hunk] = hunks
+            && hunk.new_start == 0
+            && hunk.new_count == 0
+        {
+            ChangeKind::Deleted
+        } else {
+            ChangeKind::Modified
+        }
+    }
+}
+
+/// Whether a change set touches production code, tests, or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum ChangeScope {
+    /// Both production and test files changed
+    #[serde(rename = "production + tests")]
+    ProductionAndTests,
+    /// Only test files changed
+    #[serde(rename = "tests only")]
+    TestsOnly,
+    /// Only production files changed
+    #[serde(rename = "production only")]
+    ProductionOnly,
+}
+
+impl ChangeScope {
+    /// Classify a change set from its files
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The per-file summaries
+    ///
+    /// # Returns
+    ///
+    /// The scope of the change, or None if no files changed
+    pub fn classify(files: &[FileSummary]) -> Option<Self> {
+        let tests = files.iter().any(|file| file.is_test);
+        let production = files.iter().any(|file| !file.is_test);
+        match (production, tests) {
+            (true, true) => Some(ChangeScope::ProductionAndTests),
+            (false, true) => Some(ChangeScope::TestsOnly),
+            (true, false) => Some(ChangeScope::ProductionOnly),
+            (false, false) => None,
+        }
+    }
+}
+
+impl fmt::Display for ChangeScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            ChangeScope::ProductionAndTests => "production + tests",
+            ChangeScope::TestsOnly => "tests only",
+            ChangeScope::ProductionOnly => "production only",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Summary of a single file in the processed diff
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSummary {
+    /// Stable identifier of the file's section, derived from its path
+    pub id: String,
+    /// Path of the file
+    pub path: String,
+    /// The kind of change made to the file
+    pub change_kind: ChangeKind,
+    /// Number of tokens in the file's section of the output
+    pub tokens: usize,
+    /// Whether the file matches the configured test file pattern
+    pub is_test: bool,
+}
+
+impl FileSummary {
+    /// Get the stable identifier of a file, the first 12 hex digits of the SHA-256 hash of its path
+    ///
+    /// The same path always gets the same identifier, so tools can anchor comments to
+    /// a file's section across runs.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file as it appears in the output
+    pub fn file_id(path: &str) -> String {
+        Sha256::digest(path.as_bytes())
+            .iter()
+            .take(6)
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+/// Machine-readable summary of a processed diff
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffResult {
+    /// The tiktoken model used for token counting
+    pub model: String,
+    /// Number of tokens in the whole output
+    pub total_tokens: usize,
+    /// Number of tokens in the output, excluding the instructions preamble
+    pub content_tokens: usize,
+    /// Number of files in the output
+    pub files_changed: usize,
+    /// Number of added files
+    pub files_added: usize,
+    /// Number of deleted files
+    pub files_deleted: usize,
+    /// Number of renamed files
+    pub files_renamed: usize,
+    /// Number of files modified in place
+    pub files_modified: usize,
+    /// Whether production code, tests or both changed; unset without a test file pattern
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_scope: Option<ChangeScope>,
+    /// Estimated cost in dollars of sending the whole output; unset without a price for the model
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_cost: Option<f64>,
+    /// Per-file summaries, in output order
+    pub files: Vec<FileSummary>,
+}
+
+impl DiffResult {
+    /// Create a summary from per-file summaries, computing the change counts
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The tiktoken model used for token counting
+    /// * `total_tokens` - Number of tokens in the whole output
+    /// * `content_tokens` - Number of tokens excluding the instructions preamble
+    /// * `files` - The per-file summaries
+    pub fn new(model: &str, total_tokens: usize, content_tokens: usize, files: Vec<FileSummary>) -> Self {
+        let count = |kind: ChangeKind| files.iter().filter(|f| f.change_kind == kind).count();
+        DiffResult {
+            model: model.to_string(),
+            total_tokens,
+            content_tokens,
+            files_changed: files.len(),
+            files_added: count(ChangeKind::Added),
+            files_deleted: count(ChangeKind::Deleted),
+            files_renamed: count(ChangeKind::Renamed),
+            files_modified: count(ChangeKind::Modified),
+            change_scope: ChangeScope::classify(&files),
+            estimated_cost: None,
+            files,
+        }
+    }
+
+    /// Write the summary to a JSON file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to write the summary to
+    pub fn write_json(&self, path: &str) -> Result<()> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// A part of the output written when splitting it into chunks
+#[derive(Debug, Clone, Serialize)]
+pub struct Chunk {
+    /// Path of the part file
+    pub path: String,
+    /// Number of tokens in the part
+    pub tokens: usize,
+    /// Paths of the files in the part, in output order
+    pub files: Vec<String>,
+}
@@ -1,13 +1,234 @@
-use std::process::Command;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, BufReader, Read};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use regex::Regex;
 use crate::error::{RepoDiffError, Result};
+use crate::utils::diff_parser::{DiffParser, FileHeaders, Hunk, PathPrefixes};
+
+/// Unified context value that makes git diff include the whole file
+pub const FULL_FILE_CONTEXT: usize = 999999;
+
+/// Hash of git's empty tree, which root commits are diffed against as they have no parent
+pub const EMPTY_TREE_HASH: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+/// Delay before the first retry of a failed git command; doubled on each further retry
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Error messages from git, lowercased, of failures that may go away by retrying the command,
+/// such as filesystem hiccups, a lock held by a concurrent git process or a dropped connection
+const TRANSIENT_GIT_ERRORS: &[&str] = &[
+    "input/output error",
+    "stale file handle",
+    "resource temporarily unavailable",
+    "device or resource busy",
+    "interrupted system call",
+    "too many open files",
+    "cannot allocate memory",
+    "index.lock",
+    ".lock': file exists",
+    "could not lock",
+    "connection reset",
+    "connection timed out",
+    "broken pipe",
+    "the remote end hung up unexpectedly",
+    "early eof",
+];
+
+/// Matches the "<path>:<line>: <message>" lines of git diff --check
+static WHITESPACE_ERROR_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(.+):(\d+): (.+)$").unwrap());
+
+/// The commit and author that last changed a line, as reported by git blame
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameLine {
+    /// Line number in the blamed revision of the file (1-indexed)
+    pub line: usize,
+    /// Abbreviated hash of the commit that last changed the line
+    pub commit: String,
+    /// Name of the author of that commit
+    pub author: String,
+}
+
+/// A whitespace error introduced by a diff, as reported by git diff --check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitespaceError {
+    /// Path of the file with the error
+    pub path: String,
+    /// Line number of the offending line in the new version of the file
+    pub line: usize,
+    /// Git's description of the error, e.g. "trailing whitespace."
+    pub message: String,
+}
+
+/// A commit listed from a range, as reported by git log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    /// Full hash of the commit
+    pub hash: String,
+    /// First line of the commit message
+    pub subject: String,
+}
 
 /// Handles git operations for the RepoDiff tool
-pub struct GitOperations;
+pub struct GitOperations {
+    /// Number of times to attempt each git command; 1 means no retries
+    retry_attempts: usize,
+    /// Paths that git diff commands are limited to; empty for the whole repository
+    pathspecs: Vec<String>,
+    /// Number of times a git command was retried after a transient failure
+    retries: AtomicUsize,
+}
+
+impl Default for GitOperations {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl GitOperations {
     /// Create a new GitOperations instance
     pub fn new() -> Self {
-        GitOperations
+        Self::with_retry_attempts(1)
+    }
+
+    /// Create a GitOperations instance that retries transient git failures
+    ///
+    /// # Arguments
+    ///
+    /// * `retry_attempts` - Number of times to attempt each git command; 1 means no retries
+    pub fn with_retry_attempts(retry_attempts: usize) -> Self {
+        GitOperations {
+            retry_attempts: retry_attempts.max(1),
+            pathspecs: Vec::new(),
+            retries: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the number of times a git command was retried after a transient failure
+    pub fn get_retry_count(&self) -> usize {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    /// Limit the diffs to the given paths, as with `git diff A B -- <paths>`
+    ///
+    /// # Arguments
+    ///
+    /// * `pathspecs` - The paths to limit diffs to, or an empty list for the whole repository
+    pub fn set_pathspecs(&mut self, pathspecs: Vec<String>) {
+        self.pathspecs = pathspecs;
+    }
+
+    /// Get the paths diffs are limited to, empty for the whole repository
+    pub fn get_pathspecs(&self) -> &[String] {
+        &self.pathspecs
+    }
+
+    /// Build the arguments of a git diff between two commits, or a commit and the working tree
+    ///
+    /// The configured pathspecs are appended after a `--` separator.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `options` - The options to pass to git diff, e.g. `--stat`
+    fn diff_args(&self, commit1: &str, commit2: Option<&str>, options: &[&str]) -> Vec<String> {
+        let mut args = vec!["diff".to_string(), commit1.to_string()];
+        args.extend(commit2.map(str::to_string));
+        args.extend(options.iter().map(|option| option.to_string()));
+        if !self.pathspecs.is_empty() {
+            args.push("--".to_string());
+            args.extend(self.pathspecs.iter().cloned());
+        }
+        args
+    }
+
+    /// Check whether a failed git command is worth retrying
+    ///
+    /// # Arguments
+    ///
+    /// * `stderr` - The error output of the failed command
+    ///
+    /// # Returns
+    ///
+    /// True only for errors known to come and go, such as I/O errors or a held index.lock;
+    /// anything else, such as an unknown revision, would fail the same way again
+    pub fn is_transient_failure(stderr: &str) -> bool {
+        let stderr = stderr.to_lowercase();
+        TRANSIENT_GIT_ERRORS.iter().any(|message| stderr.contains(message))
+    }
+
+    /// Run git with the given arguments, retrying transient failures with exponential backoff
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments to pass to git
+    fn run_git<S: AsRef<OsStr>>(&self, args: &[S]) -> io::Result<Output> {
+        self.run_git_accepting(args, &[])
+    }
+
+    /// Run git with the given arguments, retrying transient failures with exponential backoff
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments to pass to git
+    /// * `accepted_codes` - Exit codes other than 0 that report a result rather than a failure
+    fn run_git_accepting<S: AsRef<OsStr>>(&self, args: &[S], accepted_codes: &[i32]) -> io::Result<Output> {
+        self.retrying(|| {
+            let result = Command::new("git").args(args).output();
+            let transient = match &result {
+                Ok(output) => !output.status.success()
+                    && !output.status.code().is_some_and(|code| accepted_codes.contains(&code))
+                    && Self::is_transient_failure(&String::from_utf8_lossy(&output.stderr)),
+                Err(e) => e.kind() == io::ErrorKind::Interrupted,
+            };
+            (result, transient)
+        })
+    }
+
+    /// Make attempts at a git command until one doesn't fail transiently or the attempts run out,
+    /// with exponential backoff between them
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - Runs the command once, returning its result and whether it failed transiently
+    fn retrying<T>(&self, mut attempt: impl FnMut() -> (T, bool)) -> T {
+        let mut delay = RETRY_BASE_DELAY;
+        let mut attempts = 1;
+        loop {
+            let (result, transient) = attempt();
+            if !transient || attempts >= self.retry_attempts {
+                return result;
+            }
+
+            thread::sleep(delay);
+            delay *= 2;
+            attempts += 1;
+            self.retries.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Get the version of the installed git executable
+    ///
+    /// # Returns
+    ///
+    /// The output of `git --version`, e.g. "git version 2.43.0"
+    pub fn git_version(&self) -> Result<String> {
+        let output = self.run_git(&["--version"])
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Failed to get git version: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
     }
 
     /// Execute the git diff command and return the result
@@ -21,16 +242,22 @@ impl GitOperations {
     ///
     /// The output of the git diff command as a string
     pub fn run_git_diff(&self, commit1: &str, commit2: &str) -> Result<String> {
-        let output = Command::new("git")
-            .args([
-                "diff",
-                commit1,
-                commit2,
-                "--unified=999999",
-                "--ignore-all-space",
-                "--find-renames",
-            ])
-            .output()
+        self.run_git_diff_with_context(commit1, Some(commit2), FULL_FILE_CONTEXT)
+    }
+
+    /// Execute the git diff command with a given number of context lines
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `unified` - The number of context lines git should include around each change
+    ///
+    /// # Returns
+    ///
+    /// The output of the git diff command as a string
+    pub fn run_git_diff_with_context(&self, commit1: &str, commit2: Option<&str>, unified: usize) -> Result<String> {
+        let output = self.run_git(&self.unified_diff_args(commit1, commit2, unified))
             .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git diff: {}", e)))?;
 
         if !output.status.success() {
@@ -43,11 +270,434 @@ impl GitOperations {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
+    /// Build the arguments of the git diff whose output is parsed, the same whether it's collected or streamed
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `unified` - The number of context lines git should include around each change
+    fn unified_diff_args(&self, commit1: &str, commit2: Option<&str>, unified: usize) -> Vec<String> {
+        let unified = format!("--unified={}", unified);
+        // Explicit prefixes override diff.noPrefix and similar settings in the user's git config
+        self.diff_args(
+            commit1,
+            commit2,
+            &[&unified, "--ignore-all-space", "--find-renames", "--src-prefix=a/", "--dst-prefix=b/"],
+        )
+    }
+
+    /// Execute the git diff command and parse its output while git is still producing it
+    ///
+    /// Git's output is read line by line instead of being collected first, so git's output
+    /// as a whole is never held in memory. Transient failures are only retried if git
+    /// failed before any file was handed to `on_file`.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `unified` - The number of context lines git should include around each change
+    /// * `on_file` - Called with the path and hunks of each file as soon as it's parsed, and
+    ///   what the headers of the files parsed so far say that their hunks don't
+    ///
+    /// # Returns
+    ///
+    /// What the diff's file headers say that its hunks don't, such as mode changes
+    pub fn stream_git_diff(
+        &self,
+        commit1: &str,
+        commit2: Option<&str>,
+        unified: usize,
+        mut on_file: impl FnMut(String, Vec<Hunk>, &FileHeaders),
+    ) -> Result<FileHeaders> {
+        let args = self.unified_diff_args(commit1, commit2, unified);
+        
+        let mut emitted = false;
+        let (headers, status, stderr) = self.retrying(|| {
+            let result = Self::stream_git(&args, |file, hunks, headers| {
+                emitted = true;
+                on_file(file, hunks, headers);
+            });
+            let transient = match &result {
+                Ok((_, status, stderr)) => !status.success() && !emitted && Self::is_transient_failure(stderr),
+                Err(_) => false,
+            };
+            (result, transient)
+        })?;
+        
+        if !status.success() {
+            return Err(RepoDiffError::GitError(format!("Git diff command failed: {}", stderr)));
+        }
+        Ok(headers)
+    }
+
+    /// Run git once, parsing its output as a unified diff while it's produced
+    ///
+    /// # Arguments
+    ///
+    /// * `args` - The arguments to pass to git
+    /// * `on_file` - Called with the path and hunks of each file as soon as it's parsed, and
+    ///   what the headers of the files parsed so far say that their hunks don't
+    ///
+    /// # Returns
+    ///
+    /// What the diff's file headers say that its hunks don't, git's exit status and its error output
+    fn stream_git(
+        args: &[String],
+        on_file: impl FnMut(String, Vec<Hunk>, &FileHeaders),
+    ) -> Result<(FileHeaders, ExitStatus, String)> {
+        let mut child = Command::new("git")
+            .args(args)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git diff: {}", e)))?;
+        let stdout = child.stdout.take().expect("git diff stdout is piped");
+        let mut stderr = child.stderr.take().expect("git diff stderr is piped");
+        
+        // Git blocks once the stderr pipe is full, e.g. with many line ending warnings,
+        // so stderr is drained while stdout is parsed
+        let stderr_reader = thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = stderr.read_to_end(&mut output);
+            String::from_utf8_lossy(&output).to_string()
+        });
+        
+        let parsed = DiffParser::parse_unified_diff_reader(BufReader::new(stdout), Some(&PathPrefixes::default()), on_file);
+        let status = child.wait()
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git diff: {}", e)))?;
+        let stderr = stderr_reader.join().unwrap_or_default();
+        Ok((parsed?, status, stderr))
+    }
+
+    /// Execute git diff --stat and return the change histogram
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    ///
+    /// # Returns
+    ///
+    /// The output of the git diff --stat command as a string
+    pub fn run_git_diff_stat(&self, commit1: &str, commit2: Option<&str>) -> Result<String> {
+        let output = self.run_git(&self.diff_args(commit1, commit2, &["--stat", "--ignore-all-space", "--find-renames"]))
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git diff --stat: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Git diff --stat command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Find the whitespace errors, such as trailing whitespace or a space before a tab in
+    /// the indent, that a diff introduces, using git diff --check
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    ///
+    /// # Returns
+    ///
+    /// The errors in the order git reports them
+    pub fn check_whitespace_errors(&self, commit1: &str, commit2: Option<&str>) -> Result<Vec<WhitespaceError>> {
+        // Git exits with 2 when it finds errors
+        let output = self.run_git_accepting(&self.diff_args(commit1, commit2, &["--check"]), &[2])
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git diff --check: {}", e)))?;
+
+        if !output.status.success() && output.status.code() != Some(2) {
+            return Err(RepoDiffError::GitError(format!(
+                "Git diff --check command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        // Each error is a "<path>:<line>: <message>" line followed by the offending line
+        let errors = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.starts_with(['+', '-', ' ']))
+            .filter_map(|line| {
+                let captures = WHITESPACE_ERROR_PATTERN.captures(line)?;
+                Some(WhitespaceError {
+                    path: captures[1].to_string(),
+                    line: captures[2].parse().ok()?,
+                    message: captures[3].to_string(),
+                })
+            })
+            .collect();
+        Ok(errors)
+    }
+
+    /// List the names of the files directly inside a directory at a given commit
+    ///
+    /// # Arguments
+    ///
+    /// * `commit` - The commit to list the files at
+    /// * `dir` - The directory relative to the repository root, or "" for the root
+    ///
+    /// # Returns
+    ///
+    /// The sorted file names (not paths) in the directory, excluding subdirectories
+    pub fn list_dir_files(&self, commit: &str, dir: &str) -> Result<Vec<String>> {
+        let mut args = vec!["ls-tree".to_string(), "--full-tree".to_string(), commit.to_string()];
+        if !dir.is_empty() {
+            args.push("--".to_string());
+            args.push(format!("{}/", dir.trim_end_matches('/')));
+        }
+
+        let output = self.run_git(&args)
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to list files in '{}': {}", dir, e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Failed to list files in '{}': {}",
+                dir,
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        // Each line is "<mode> <type> <hash>\t<path>"; only blobs are files
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (info, path) = line.split_once('\t')?;
+                if info.split_whitespace().nth(1) != Some("blob") {
+                    return None;
+                }
+                Some(path.rsplit('/').next().unwrap_or(path).to_string())
+            })
+            .collect();
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// List the files changed by an author's commits in a range
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The commit range to search, e.g. "abc123..def456"
+    /// * `author` - Pattern matched against commit authors, as for `git log --author`
+    ///
+    /// # Returns
+    ///
+    /// The sorted, deduplicated paths touched by the author's commits
+    pub fn files_changed_by_author(&self, range: &str, author: &str) -> Result<Vec<String>> {
+        let output = self.run_git(&[
+            "log",
+            &format!("--author={}", author),
+            "--name-only",
+            "--pretty=format:",
+            range,
+        ])
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git log: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Git log command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.to_string())
+            .collect();
+        files.sort();
+        files.dedup();
+
+        Ok(files)
+    }
+
+    /// List the commits in a range, oldest first
+    ///
+    /// # Arguments
+    ///
+    /// * `range` - The commit range to list, e.g. "abc123..def456"
+    ///
+    /// # Returns
+    ///
+    /// The commits reachable from the end of the range but not its start, in the order they were made
+    pub fn list_commits(&self, range: &str) -> Result<Vec<CommitInfo>> {
+        let output = self.run_git(&["log", "--reverse", "--format=%H%x00%s", range])
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git log: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Git log command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        // Each line is a hash and a subject separated by a NUL, which neither can contain
+        let commits = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once('\0'))
+            .map(|(hash, subject)| CommitInfo { hash: hash.to_string(), subject: subject.to_string() })
+            .collect();
+
+        Ok(commits)
+    }
+
+    /// Count the changed lines of each file, using git diff --numstat
+    ///
+    /// Binary files count as no lines, since git doesn't count their lines, so every
+    /// changed file is listed.
+    ///
+    /// # Arguments
+    ///
+    /// * `commit1` - The first commit hash to compare
+    /// * `commit2` - The second commit hash to compare, or None for the working tree
+    /// * `ignore_all_space` - Whether lines that only change whitespace are left out of the counts
+    ///
+    /// # Returns
+    ///
+    /// The number of added plus removed lines of each file, keyed by its new path
+    pub fn count_changed_lines(&self, commit1: &str, commit2: Option<&str>, ignore_all_space: bool) -> Result<HashMap<String, usize>> {
+        let mut options = vec!["--numstat", "-z", "--find-renames"];
+        if ignore_all_space {
+            options.push("--ignore-all-space");
+        }
+        let output = self.run_git(&self.diff_args(commit1, commit2, &options))
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git diff --numstat: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Git diff --numstat command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        // Each entry is "<added>\t<removed>\t<path>", where a rename has an empty path
+        // followed by its old and new paths as separate entries
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut entries = stdout.split('\0');
+        let mut counts = HashMap::new();
+        while let Some(entry) = entries.next() {
+            let mut fields = entry.splitn(3, '\t');
+            let (Some(added), Some(removed), Some(path)) = (fields.next(), fields.next(), fields.next()) else {
+                continue;
+            };
+            let path = if path.is_empty() {
+                entries.next();
+                entries.next().unwrap_or_default()
+            } else {
+                path
+            };
+            let lines = added.parse::<usize>().unwrap_or_default() + removed.parse::<usize>().unwrap_or_default();
+            counts.insert(path.to_string(), lines);
+        }
+        Ok(counts)
+    }
+
+    /// List the untracked files in the working tree, excluding ignored files
+    ///
+    /// # Returns
+    ///
+    /// The sorted paths of the untracked files, relative to the repository root
+    pub fn list_untracked_files(&self) -> Result<Vec<String>> {
+        let output = self.run_git(&["status", "--porcelain", "-z", "--untracked-files=all"])
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git status: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Git status command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        // Entries are NUL-separated, so paths are never quoted
+        let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .split('\0')
+            .filter_map(|entry| entry.strip_prefix("?? "))
+            .map(|path| path.to_string())
+            .collect();
+        files.sort();
+
+        Ok(files)
+    }
+
+    /// Find the commit and author that last changed each line in some ranges of a file
+    ///
+    /// # Arguments
+    ///
+    /// * `commit` - The revision of the file to blame
+    /// * `path` - The path of the file at that revision
+    /// * `ranges` - The (first, last) line ranges to blame, 1-indexed and inclusive
+    ///
+    /// # Returns
+    ///
+    /// One entry per blamed line, in file order
+    pub fn blame_lines(&self, commit: &str, path: &str, ranges: &[(usize, usize)]) -> Result<Vec<BlameLine>> {
+        let mut args = vec!["blame".to_string(), "--line-porcelain".to_string()];
+        for (start, end) in ranges {
+            args.push(format!("-L{},{}", start, end));
+        }
+        args.extend([commit.to_string(), "--".to_string(), path.to_string()]);
+
+        let output = self.run_git(&args)
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to execute git blame: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Git blame command failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        // Each line starts with "<hash> <original line> <final line> [<group size>]",
+        // followed by "key value" headers and finally the line's content after a tab
+        let mut lines = Vec::new();
+        let mut current: Option<BlameLine> = None;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            if line.starts_with('\t') {
+                lines.extend(current.take());
+            } else if let Some(author) = line.strip_prefix("author ") {
+                if let Some(blame) = current.as_mut() {
+                    blame.author = author.to_string();
+                }
+            } else if current.is_none() {
+                let mut fields = line.split(' ');
+                if let (Some(hash), Some(_), Some(final_line)) = (fields.next(), fields.next(), fields.next())
+                    && let Ok(final_line) = final_line.parse()
+                {
+                    current = Some(BlameLine {
+                        line: final_line,
+                        commit: hash.chars().take(7).collect(),
+                        author: String::new(),
+                    });
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Get the absolute path of the repository's top-level directory
+    pub fn get_repo_root(&self) -> Result<String> {
+        let output = self.run_git(&["rev-parse", "--show-toplevel"])
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to get repository root: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Failed to get repository root: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
     /// Get the latest commit hash for the current branch
     pub fn get_latest_commit(&self) -> Result<String> {
-        let output = Command::new("git")
-            .args(["rev-parse", "HEAD"])
-            .output()
+        let output = self.run_git(&["rev-parse", "HEAD"])
             .map_err(|e| RepoDiffError::GitError(format!("Failed to get latest commit: {}", e)))?;
 
         if !output.status.success() {
@@ -66,9 +716,7 @@ impl GitOperations {
     ///
     /// * `branch` - The name of the base branch to compare with
     pub fn get_latest_common_commit_with_branch(&self, branch: &str) -> Result<String> {
-        let output = Command::new("git")
-            .args(["merge-base", "HEAD", branch])
-            .output()
+        let output = self.run_git(&["merge-base", "HEAD", branch])
             .map_err(|e| {
                 RepoDiffError::GitError(format!(
                     "Failed to get latest common commit with '{}': {}",
@@ -87,6 +735,31 @@ impl GitOperations {
         Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
     }
 
+    /// Get the most recent tag reachable from the current commit
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - Optional glob the tag name must match, e.g. `v*`
+    pub fn get_latest_tag(&self, pattern: Option<&str>) -> Result<String> {
+        let mut args = vec!["describe", "--tags", "--abbrev=0"];
+        if let Some(pattern) = pattern {
+            args.extend(["--match", pattern]);
+        }
+        args.push("HEAD");
+        
+        let output = self.run_git(&args)
+            .map_err(|e| RepoDiffError::GitError(format!("Failed to get latest tag: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(RepoDiffError::GitError(format!(
+                "Failed to get latest tag: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
     /// Get the previous commit of a given commit hash
     ///
     /// # Arguments
@@ -95,11 +768,9 @@ impl GitOperations {
     ///
     /// # Returns
     ///
-    /// The hash of the previous commit
+    /// The hash of the first parent, or of the empty tree for a root commit
     pub fn get_previous_commit(&self, commit: &str) -> Result<String> {
-        let output = Command::new("git")
-            .args(["rev-parse", &format!("{}^1", commit)])
-            .output()
+        let output = self.run_git(&["rev-list", "--parents", "-n", "1", commit, "--"])
             .map_err(|e| RepoDiffError::GitError(format!("Failed to get previous commit for '{}': {}", commit, e)))?;
 
         if !output.status.success() {
@@ -110,6 +781,9 @@ impl GitOperations {
             )));
         }
 
-        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        // The commit's hash is followed by the hashes of its parents
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let parent = stdout.split_whitespace().nth(1).unwrap_or(EMPTY_TREE_HASH);
+        Ok(parent.to_string())
     }
 } 
\ No newline at end of file
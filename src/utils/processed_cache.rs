@@ -0,0 +1,154 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::error::Result;
+use crate::filters::filter_manager::OmitReason;
+use crate::utils::diff_parser::Hunk;
+
+/// The filtered files of a diff, with what filtering reported about them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProcessedFiles {
+    /// The files kept by the filters, with their processed hunks
+    pub files: HashMap<String, Vec<Hunk>>,
+    /// Files left out of the output, with the reason for each
+    pub omitted_files: Vec<(String, OmitReason)>,
+    /// Files matched by an opaque rule, whose content is hidden
+    pub opaque_files: HashSet<String>,
+    /// Problems met while filtering, such as files that couldn't be parsed
+    pub warnings: Vec<String>,
+}
+
+/// Number of entries a cache keeps by default before the least recently used are removed
+pub const DEFAULT_MAX_CACHE_ENTRIES: usize = 256;
+
+/// An on-disk cache of filtered diffs, so unchanged diffs aren't run through the filters again
+///
+/// Each entry is a JSON file named by the hash of the diff's files and the settings
+/// the filters ran with, so changing the configuration never reuses an entry. The key
+/// is computed from the parsed diff, so the diff is always parsed; only filtering is
+/// skipped. Once a store takes the cache over its maximum number of entries, the least
+/// recently used entries are removed.
+pub struct ProcessedFilesCache {
+    /// The directory holding the cache entries
+    dir: PathBuf,
+    /// The number of entries kept in the directory
+    max_entries: usize,
+}
+
+impl ProcessedFilesCache {
+    /// Initialize a cache stored in a directory, which is created when the first entry is stored
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding the cache entries
+    pub fn new(dir: &str) -> Self {
+        Self::with_max_entries(dir, DEFAULT_MAX_CACHE_ENTRIES)
+    }
+
+    /// Initialize a cache that keeps at most a number of entries
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - The directory holding the cache entries
+    /// * `max_entries` - The number of entries kept; the least recently used beyond it are removed
+    pub fn with_max_entries(dir: &str, max_entries: usize) -> Self {
+        ProcessedFilesCache { dir: PathBuf::from(dir), max_entries }
+    }
+
+    /// Compute the key of a diff's entry
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The settings the filters run with, e.g. the resolved configuration
+    /// * `patch_dict` - The diff's files, before filtering
+    ///
+    /// # Returns
+    ///
+    /// The hex-encoded SHA-256 hash of the crate version, the settings and each file's path and hunks, in path order
+    pub fn key(settings: &serde_json::Value, patch_dict: &HashMap<String, Vec<Hunk>>) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update([0]);
+        hasher.update(serde_json::to_string(settings)?);
+        hasher.update([0]);
+        
+        let mut paths: Vec<&String> = patch_dict.keys().collect();
+        paths.sort();
+        for path in paths {
+            hasher.update(path);
+            hasher.update([0]);
+            hasher.update(serde_json::to_string(&patch_dict[path])?);
+            hasher.update([0]);
+        }
+        
+        Ok(hasher.finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    /// Load a diff's entry, marking it as recently used
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the entry
+    ///
+    /// # Returns
+    ///
+    /// The stored files, or None if there's no entry or it can't be read
+    pub fn load(&self, key: &str) -> Option<ProcessedFiles> {
+        let path = self.entry_path(key);
+        let content = fs::read_to_string(&path).ok()?;
+        let processed = serde_json::from_str(&content).ok()?;
+        // The modification time records the last use, which decides what is removed first
+        let _ = fs::File::options().write(true).open(&path).and_then(|file| file.set_modified(SystemTime::now()));
+        Some(processed)
+    }
+
+    /// Store a diff's entry, replacing any existing one, and remove the least recently
+    /// used entries beyond the maximum
+    ///
+    /// The entry is written to a temporary file and renamed into place, so a concurrent
+    /// run never reads a partial entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the entry
+    /// * `processed` - The filtered files to store
+    pub fn store(&self, key: &str, processed: &ProcessedFiles) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let temp_path = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        fs::write(&temp_path, serde_json::to_string(processed)?)?;
+        fs::rename(&temp_path, self.entry_path(key))?;
+        self.prune()
+    }
+
+    /// Remove the least recently used entries beyond the maximum
+    fn prune(&self) -> Result<()> {
+        let mut entries: Vec<(SystemTime, PathBuf)> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+            .collect();
+        if entries.len() <= self.max_entries {
+            return Ok(());
+        }
+        
+        entries.sort();
+        let excess = entries.len() - self.max_entries;
+        for (_, path) in entries.into_iter().take(excess) {
+            // A concurrent run may have removed it already
+            let _ = fs::remove_file(path);
+        }
+        Ok(())
+    }
+
+    /// Get the path of an entry's file
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
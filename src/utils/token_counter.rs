@@ -5,6 +5,8 @@ use crate::error::{RepoDiffError, Result};
 pub struct TokenCounter {
     /// The tiktoken encoding
     bpe: CoreBPE,
+    /// The name of the model the encoding is for
+    model: String,
 }
 
 impl TokenCounter {
@@ -16,7 +18,12 @@ impl TokenCounter {
     pub fn new(model: &str) -> Result<Self> {
         let bpe = tiktoken_rs::get_bpe_from_model(model)
             .map_err(|e| RepoDiffError::TiktokenError(format!("Failed to get BPE for model {}: {}", model, e)))?;
-        Ok(Self { bpe })
+        Ok(Self { bpe, model: model.to_string() })
+    }
+
+    /// Get the name of the model used for token counting
+    pub fn get_model(&self) -> &str {
+        &self.model
     }
 
     /// Count the number of tokens in the given text
@@ -27,4 +34,14 @@ impl TokenCounter {
     pub fn count_tokens(&self, text: &str) -> usize {
         self.bpe.encode_ordinary(text).len()
     }
+
+    /// Estimate the cost in dollars of sending a number of input tokens
+    ///
+    /// # Arguments
+    ///
+    /// * `tokens` - The number of input tokens
+    /// * `price_per_1k` - The price in dollars per 1K input tokens
+    pub fn estimate_cost(tokens: usize, price_per_1k: f64) -> f64 {
+        tokens as f64 / 1000.0 * price_per_1k
+    }
 } 
\ No newline at end of file
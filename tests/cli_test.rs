@@ -0,0 +1,118 @@
+use clap::Parser;
+
+// Import the module to test
+use repodiff::cli::{base_branch, effective_config, Args};
+use repodiff::repodiff::RepoDiff;
+use repodiff::utils::config_manager::Config;
+
+#[test]
+fn test_effective_config_applies_cli_overrides() {
+    let config = Config {
+        max_tokens: Some(1000),
+        reserved_tokens: 100,
+        ..Default::default()
+    };
+    let args = Args::parse_from(["repodiff", "--max-tokens", "500", "--print-config"]);
+    
+    let effective = effective_config(&args, &config);
+    let printed = serde_json::to_value(&effective).unwrap();
+    
+    // The CLI value wins, and settings without an override come from the config
+    assert_eq!(printed["max_tokens"], 500);
+    assert_eq!(printed["reserved_tokens"], 100);
+}
+
+#[test]
+fn test_effective_config_includes_output_flags() {
+    let args = Args::parse_from([
+        "repodiff", "--signatures-only", "--max-output-bytes", "2048",
+        "--only", "src/*.cs", "--lang", "csharp", "--format", "signature-map",
+    ]);
+    
+    let printed = serde_json::to_value(effective_config(&args, &Config::default())).unwrap();
+    
+    assert_eq!(printed["method_detail"], "signatures_only");
+    assert_eq!(printed["max_output_bytes"], 2048);
+    assert_eq!(printed["only_pattern"], "src/*.cs");
+    assert_eq!(printed["only_language"], "csharp");
+    assert_eq!(printed["format"], "signature-map");
+    
+    // Without the flags, the config's settings are kept
+    let printed = serde_json::to_value(effective_config(&Args::parse_from(["repodiff"]), &Config::default())).unwrap();
+    assert_eq!(printed["method_detail"], serde_json::Value::Null);
+    assert_eq!(printed["format"], "text");
+    
+    // Formats are named as in the config
+    assert!(Args::try_parse_from(["repodiff", "--format", "signature_map"]).is_err());
+}
+
+#[test]
+fn test_method_query_splits_on_last_separator() {
+    let args = Args::parse_from(["repodiff", "--method", "src/Orders.cs::Save"]);
+    assert_eq!(args.method, Some(("src/Orders.cs".to_string(), "Save".to_string())));
+    
+    assert!(Args::try_parse_from(["repodiff", "--method", "Save"]).is_err());
+}
+
+#[test]
+fn test_ranges_conflict_with_output_dir() {
+    let args = ["repodiff", "--range", "a..b", "--range", "c..d", "--output-dir", "out"];
+    assert!(Args::try_parse_from(args).is_err());
+    assert!(Args::try_parse_from(&args[..5]).is_ok());
+}
+
+#[test]
+fn test_branch_without_value_uses_default_base_branch() {
+    let config = Config {
+        default_base_branch: Some("develop".to_string()),
+        ..Default::default()
+    };
+    
+    let args = Args::parse_from(["repodiff", "--branch"]);
+    assert_eq!(base_branch(&args, &config).unwrap(), Some("develop".to_string()));
+    
+    // An explicit branch wins over the default, and no --branch means no branch comparison
+    let args = Args::parse_from(["repodiff", "-b", "main"]);
+    assert_eq!(base_branch(&args, &config).unwrap(), Some("main".to_string()));
+    let args = Args::parse_from(["repodiff"]);
+    assert_eq!(base_branch(&args, &config).unwrap(), None);
+    
+    // Without a configured default, a bare --branch is an error
+    let args = Args::parse_from(["repodiff", "--branch"]);
+    assert!(base_branch(&args, &Config::default()).is_err());
+}
+
+#[test]
+fn test_write_config_records_effective_config() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        max_tokens: Some(1000),
+        reserved_tokens: 100,
+        ..Default::default()
+    };
+    let args = Args::parse_from([
+        "repodiff", "--max-tokens", "5000", "--annotate-hunks", "--write-config",
+        "--format", "markdown", "--signatures-only", "--range-in-file", "a.txt:1-3",
+    ]);
+    let effective = effective_config(&args, &config);
+    
+    let mut repodiff = RepoDiff::from_config(&effective).unwrap();
+    repodiff.set_write_config(args.write_config);
+    repodiff.set_range_in_file(args.range_in_file.clone());
+    let output_file = temp_dir.path().join("output.txt");
+    let output_file = output_file.to_str().unwrap();
+    repodiff.process_diff_text("diff --git a/a.txt b/a.txt\n--- a/a.txt\n+++ b/a.txt\n@@ -1 +1 @@\n-old\n+new\n", output_file).unwrap();
+    
+    // The sidecar holds the settings after the CLI overrides, as --print-config shows them
+    let written = std::fs::read_to_string(RepoDiff::resolved_config_file_name(output_file, false)).unwrap();
+    let written: serde_json::Value = serde_json::from_str(&written).unwrap();
+    assert_eq!(written["max_tokens"], 5000);
+    assert_eq!(written["annotate_hunks"], true);
+    assert_eq!(written["reserved_tokens"], 100);
+    assert_eq!(written["format"], "markdown");
+    assert_eq!(written["method_detail"], "signatures_only");
+    assert_eq!(written["range_in_file"], "a.txt:1-3");
+    // The filters are the ones applied, including the default rule used for unmatched files
+    assert_eq!(written["filters"][0]["file_pattern"], "*");
+    assert_eq!(written["filters"][0]["context_lines"], 3);
+}
@@ -3,7 +3,8 @@ use tempfile::tempdir;
 use serde_json::json;
 
 // Import the module to test
-use repodiff::utils::config_manager::ConfigManager;
+use repodiff::utils::config_manager::{ConfigManager, DirectoryConfigs, FlagConflictPolicy};
+use repodiff::error::RepoDiffError;
 
 #[test]
 fn test_load_config_success() {
@@ -68,6 +69,26 @@ fn test_get_filters_default() {
     assert_eq!(config_manager.get_filters().len(), 0);
 }
 
+#[test]
+fn test_load_config_negated_filter_without_context_lines() {
+    // Create a temporary directory
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    // Negated rules don't need context_lines
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [{"file_pattern": "*"}, {"file_pattern": "!*Generated.cs"}]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    assert_eq!(config_manager.get_filters().len(), 2);
+    assert_eq!(config_manager.get_filters()[1].file_pattern, "!*Generated.cs");
+    assert_eq!(config_manager.get_filters()[1].context_lines, 3);
+}
+
 #[test]
 fn test_load_config_file_not_found() {
     // Try to create a ConfigManager with a non-existent file
@@ -75,10 +96,47 @@ fn test_load_config_file_not_found() {
     let config_manager = ConfigManager::new(non_existent_path).unwrap();
     
     // Verify that we get the default configuration
+    assert!(config_manager.get_config_path().is_none());
     assert_eq!(config_manager.get_tiktoken_model(), "gpt-4o");
-    assert_eq!(config_manager.get_filters().len(), 1);
-    assert_eq!(config_manager.get_filters()[0].file_pattern, "*");
-    assert_eq!(config_manager.get_filters()[0].context_lines, 3);
+    // No filters are configured, so they're chosen for each diff's language
+    assert!(config_manager.get_filters().is_empty());
+    assert!(config_manager.get_config().auto_tune_filters);
+}
+
+#[test]
+fn test_validate_config() {
+    // Create a temporary directory
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [{"file_pattern": "*.cs", "context_lines": 5}, {"file_pattern": "!*Generated.cs"}]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    assert!(config_manager.validate().is_ok());
+    assert_eq!(config_manager.get_config_path(), Some(config_path.as_path()));
+}
+
+#[test]
+fn test_validate_config_empty_model() {
+    // Create a temporary directory
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "",
+        "filters": []
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    let error = config_manager.validate().unwrap_err();
+    assert!(matches!(error, RepoDiffError::ConfigError(_)));
 }
 
 #[test]
@@ -93,4 +151,180 @@ fn test_load_config_invalid_json() {
     
     // Try to create a ConfigManager with the invalid file
     let _ = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
-} 
\ No newline at end of file
+}
+
+#[test]
+fn test_validate_config_invalid_ignore_line_pattern() {
+    // Create a temporary directory
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [],
+        "ignore_line_patterns": ["Version: ("]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    let error = config_manager.validate().unwrap_err();
+    assert!(matches!(error, RepoDiffError::ConfigError(_)));
+}
+
+#[test]
+fn test_validate_config_unknown_preamble_section() {
+    // Create a temporary directory
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [],
+        "preamble_sections": ["basic_structure", "application"]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    let error = config_manager.validate().unwrap_err();
+    assert!(error.to_string().contains("Unknown preamble section 'application'"));
+}
+
+#[test]
+fn test_validate_config_negative_model_price() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [],
+        "model_pricing": {"gpt-4o": -0.01}
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    let error = config_manager.validate().unwrap_err();
+    assert!(error.to_string().contains("Invalid model_pricing entry for 'gpt-4o'"));
+}
+
+#[test]
+fn test_validate_config_reserved_tokens_over_max_tokens() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [],
+        "max_tokens": 1000,
+        "reserved_tokens": 1500
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    
+    let error = config_manager.validate().unwrap_err();
+    assert_eq!(error.to_string(), "Config error: reserved_tokens (1500) must be less than max_tokens (1000)");
+}
+
+#[test]
+fn test_directory_configs_load_nearest_filters() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir_all(temp_dir.path().join("web/app")).unwrap();
+    fs::create_dir_all(temp_dir.path().join("docs")).unwrap();
+    fs::write(
+        temp_dir.path().join("web/config.json"),
+        json!({"filters": [{"file_pattern": "*.ts", "context_lines": 2}]}).to_string(),
+    ).unwrap();
+    // A config without filters doesn't override them
+    fs::write(temp_dir.path().join("web/app/config.json"), json!({"name": "app"}).to_string()).unwrap();
+    
+    let directory_configs = DirectoryConfigs::new(temp_dir.path(), "config.json");
+    let filters = directory_configs.load_filters(["web/app/main.ts", "web/index.ts", "README.md"]).unwrap();
+    assert_eq!(filters.len(), 1);
+    assert_eq!(filters["web"][0].file_pattern, "*.ts");
+    assert_eq!(filters["web"][0].context_lines, 2);
+    
+    // Invalid directory configs are reported with their path
+    fs::write(temp_dir.path().join("docs/config.json"), "{ invalid").unwrap();
+    let error = directory_configs.load_filters(["docs/guide.md"]).unwrap_err();
+    assert!(matches!(error, RepoDiffError::ConfigError(_)));
+    assert!(error.to_string().contains("docs"));
+}
+
+#[test]
+fn test_validate_config_conflicting_filter_flags() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    let filters = json!([
+        {"file_pattern": "secrets/*", "opaque": true, "include_method_body": true},
+        {"file_pattern": "*.cs", "include_referenced_fields": true}
+    ]);
+    
+    // By default the conflicts are resolved by precedence
+    let config_content = json!({"tiktoken_model": "gpt-4o", "filters": filters});
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    assert!(config_manager.validate().is_ok());
+    assert_eq!(
+        config_manager.get_filters()[0].flag_conflicts(),
+        vec!["opaque hides the content, so include_method_body has no effect"]
+    );
+    
+    // Rejecting them lists every conflict
+    let config_content = json!({"tiktoken_model": "gpt-4o", "filters": filters, "filter_flag_conflicts": "reject"});
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    let error = config_manager.validate().unwrap_err().to_string();
+    assert!(error.contains("'secrets/*': opaque hides the content, so include_method_body has no effect"));
+    assert!(error.contains("'*.cs': include_referenced_fields has no effect without include_method_body"));
+}
+
+#[test]
+fn test_directory_configs_reject_conflicting_filter_flags() {
+    let temp_dir = tempdir().unwrap();
+    fs::create_dir_all(temp_dir.path().join("secrets")).unwrap();
+    fs::write(
+        temp_dir.path().join("secrets/config.json"),
+        json!({"filters": [{"file_pattern": "*", "opaque": true, "max_hunks": 2}]}).to_string(),
+    ).unwrap();
+    
+    // Resolved by default, as for the root config
+    let directory_configs = DirectoryConfigs::new(temp_dir.path(), "config.json");
+    assert!(directory_configs.load_filters(["secrets/key.pem"]).is_ok());
+    
+    let error = directory_configs.with_flag_conflicts(FlagConflictPolicy::Reject)
+        .load_filters(["secrets/key.pem"])
+        .unwrap_err();
+    assert!(matches!(error, RepoDiffError::ConfigError(_)));
+    assert!(error.to_string().contains("secrets"));
+    assert!(error.to_string().contains("'*': opaque hides the content, so max_hunks has no effect"));
+}
+
+#[test]
+fn test_extension_language_map_keys_are_normalized() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "extension_language_map": {".cake": "csharp", "TSX": "typescript"}
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let config_manager = ConfigManager::new(config_path.to_str().unwrap()).unwrap();
+    let map = &config_manager.get_config().extension_language_map;
+    assert_eq!(map.get("cake").map(String::as_str), Some("csharp"));
+    assert_eq!(map.get("tsx").map(String::as_str), Some("typescript"));
+    assert_eq!(map.len(), 2);
+    
+    // Keys that name the same extension are ambiguous
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "extension_language_map": {"cake": "csharp", ".cake": "python"}
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let error = ConfigManager::new(config_path.to_str().unwrap()).err().unwrap();
+    assert!(matches!(error, RepoDiffError::ConfigError(_)));
+    assert!(error.to_string().contains("'cake'"));
+}
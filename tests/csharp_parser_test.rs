@@ -0,0 +1,142 @@
+use repodiff::filters::csharp_parser::CSharpParser;
+use repodiff::utils::diff_parser::Hunk;
+
+// Helper function to create a hunk covering the whole file with one changed line
+fn create_hunk(lines: Vec<&str>) -> Hunk {
+    Hunk {
+        header: format!("@@ -1,{} +1,{} @@", lines.len(), lines.len()),
+        old_start: 1,
+        old_count: lines.len(),
+        new_start: 1,
+        new_count: lines.len(),
+        lines: lines.into_iter().map(|l| l.to_string()).collect(),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    }
+}
+
+#[test]
+fn test_parse_file_extracts_method_names_and_spans() {
+    let code = "namespace Test {
+    public class MyClass {
+        [Obsolete]
+        public void MyMethod() {
+            int x = 1;
+        }
+
+        public int MyProperty
+        {
+            get { return 1; }
+            set { }
+        }
+    }
+}
+";
+    let mut parser = CSharpParser::new().unwrap();
+    let file = parser.parse_file(code, &[]).unwrap();
+    
+    let names: Vec<&str> = file.methods.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, vec!["MyMethod", "MyProperty", "MyProperty.get", "MyProperty.set"]);
+    
+    // The method span includes its attribute, but the signature is the name's line
+    let method = &file.methods[0];
+    assert_eq!(method.start_line, 3);
+    assert_eq!(method.signature_line, 4);
+    assert_eq!(method.end_line, 6);
+}
+
+#[test]
+fn test_parse_file_extracts_fields() {
+    let code = "public class Counter {
+    private int count, limit = 10;
+    [NonSerialized]
+    private readonly string name;
+
+    public void Add() {
+        int local = 1;
+        count += local;
+    }
+}
+";
+    let mut parser = CSharpParser::new().unwrap();
+    let file = parser.parse_file(code, &[]).unwrap();
+    
+    // Locals aren't fields, and a field's span includes its attribute
+    let fields: Vec<(Vec<&str>, usize, usize)> = file.fields.iter()
+        .map(|f| (f.names.iter().map(|n| n.as_str()).collect(), f.start_line, f.end_line))
+        .collect();
+    assert_eq!(fields, vec![(vec!["count", "limit"], 2, 2), (vec!["name"], 3, 4)]);
+}
+
+#[test]
+fn test_parse_file_with_non_ascii_identifiers() {
+    let code = "namespace Prüfung {
+    public class Größe {
+        // Berechnung für Ärger
+        public int BerechneÄrger(int wert) {
+            return wert * 2;
+        }
+
+        public int Höhe => 42;
+    }
+}
+";
+    let hunk = create_hunk(vec![
+        " namespace Prüfung {",
+        "     public class Größe {",
+        "         // Berechnung für Ärger",
+        "         public int BerechneÄrger(int wert) {",
+        "-            return wert;",
+        "+            return wert * 2;",
+        "         }",
+        "",
+        "         public int Höhe => 42;",
+        "     }",
+        " }",
+    ]);
+    
+    let mut parser = CSharpParser::new().unwrap();
+    let file = parser.parse_file(code, &[hunk]).unwrap();
+    
+    assert_eq!(file.methods.len(), 2);
+    
+    let method = &file.methods[0];
+    assert_eq!(method.name, "BerechneÄrger");
+    assert_eq!(method.start_line, 4);
+    assert_eq!(method.signature_line, 4);
+    assert_eq!(method.end_line, 6);
+    assert!(method.has_changes);
+    assert!(method.text.starts_with("public int BerechneÄrger(int wert)"));
+    
+    let property = &file.methods[1];
+    assert_eq!(property.name, "Höhe");
+    assert_eq!(property.start_line, 8);
+    assert!(!property.has_changes);
+}
+
+#[test]
+fn test_parse_deeply_nested_code_without_stack_overflow() {
+    // Thousands of nested lambdas, far deeper than the default maximum depth
+    let depth = 3000;
+    let mut code = String::from("public class Deep {\n    public void Outer() {\n        Action a = ");
+    code.push_str(&"() => { Action b = ".repeat(depth));
+    code.push_str("null;");
+    code.push_str(&" };".repeat(depth));
+    code.push_str("\n    }\n\n    public int After() { return 1; }\n}\n");
+    
+    let mut parser = CSharpParser::new().unwrap();
+    let file = parser.parse_file(&code, &[]).unwrap();
+    let names: Vec<&str> = file.methods.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, vec!["Outer", "After"]);
+    assert_eq!(file.class_declarations, vec![(1, 7)]);
+    
+    // A lower limit stops the search before the methods are reached
+    parser.set_max_depth(1);
+    let file = parser.parse_file(&code, &[]).unwrap();
+    assert!(file.methods.is_empty());
+    assert_eq!(file.class_declarations.len(), 1);
+}
@@ -1,5 +1,5 @@
 // Import the module to test
-use repodiff::utils::diff_parser::DiffParser;
+use repodiff::utils::diff_parser::{DiffParser, HunklessChange, ModeChange, OutputOptions, PathPrefixes};
 
 #[test]
 fn test_parse_unified_diff_empty() {
@@ -129,6 +129,279 @@ rename to new_file.txt
     assert_eq!(hunk.similarity_index.as_ref().unwrap(), "similarity index 90%");
 }
 
+#[test]
+fn test_renamed_file_with_multiple_hunks_round_trips() {
+    let diff_output = "diff --git a/old_file.txt b/new_file.txt
+similarity index 85%
+rename from old_file.txt
+rename to new_file.txt
+--- a/old_file.txt
++++ b/new_file.txt
+@@ -1,3 +1,3 @@
+ line1
+-line2
++line2_modified
+ line3
+@@ -20,3 +20,3 @@
+ line20
+-line21
++line21_modified
+ line22";
+    
+    let result = DiffParser::parse_unified_diff(diff_output).unwrap();
+    assert_eq!(result["new_file.txt"].len(), 2);
+    assert!(result["new_file.txt"].iter().all(|hunk| hunk.is_rename));
+    
+    // Both hunks appear, in order, under a single rename header
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        preserve_original_headers: true,
+        ..Default::default()
+    };
+    let output = DiffParser::reconstruct_patch_with_options(&result, None, &options);
+    assert_eq!(output, diff_output);
+    assert_eq!(output.matches("diff --git").count(), 1);
+    
+    // Reparsing the output gives the same hunks
+    let reparsed = DiffParser::parse_unified_diff(&output).unwrap();
+    let lines = |files: &std::collections::HashMap<String, Vec<repodiff::utils::diff_parser::Hunk>>| -> Vec<Vec<String>> {
+        files["new_file.txt"].iter().map(|hunk| hunk.lines.clone()).collect()
+    };
+    assert_eq!(lines(&reparsed), lines(&result));
+}
+
+#[test]
+fn test_reconstruct_patch_with_compact_renames() {
+    let diff_output = "diff --git a/old_file.txt b/new_file.txt
+similarity index 85%
+rename from old_file.txt
+rename to new_file.txt
+--- a/old_file.txt
++++ b/new_file.txt
+@@ -1,3 +1,3 @@
+ line1
+-line2
++line2_modified
+ line3";
+    
+    let result = DiffParser::parse_unified_diff(diff_output).unwrap();
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        compact_renames: true,
+        ..Default::default()
+    };
+    let output = DiffParser::reconstruct_patch_with_options(&result, None, &options);
+    
+    // The one-line form replaces the whole rename header block
+    assert_eq!(output, "# renamed: old_file.txt → new_file.txt\n line1\n-line2\n+line2_modified\n line3");
+}
+
+#[test]
+fn test_copied_file_is_not_treated_as_rename() {
+    let diff_output = "diff --git a/file.txt b/file.txt
+similarity index 90%
+copy from template.txt
+copy to file.txt
+--- a/file.txt
++++ b/file.txt
+@@ -1,1 +1,1 @@
+-old
++new";
+    
+    let result = DiffParser::parse_unified_diff(diff_output).unwrap();
+    assert!(!result["file.txt"][0].is_rename);
+    
+    // The file keeps a regular header instead of losing it
+    let output = DiffParser::reconstruct_patch(&result, None);
+    assert!(output.contains("diff --git a/file.txt b/file.txt\n--- a/file.txt\n+++ b/file.txt\n-old\n+new"));
+}
+
+#[test]
+fn test_parse_unified_diff_without_prefix() {
+    let diff = "diff --git file1.txt file1.txt
+index 1234567..89abcdef 100644
+--- file1.txt
++++ file1.txt
+@@ -1,2 +1,2 @@
+ line1
+-line2
++modified line2";
+
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result.len(), 1);
+    assert!(result.contains_key("file1.txt"));
+}
+
+#[test]
+fn test_parse_unified_diff_with_custom_prefix() {
+    let diff = "diff --git old/src/my file.txt new/src/my file.txt
+index 1234567..89abcdef 100644
+--- old/src/my file.txt
++++ new/src/my file.txt
+@@ -1,2 +1,2 @@
+ line1
+-line2
++modified line2";
+
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result.len(), 1);
+    assert!(result.contains_key("src/my file.txt"));
+}
+
+#[test]
+fn test_parse_unified_diff_with_equal_custom_prefixes() {
+    let diff = "diff --git x/src/lib.rs x/src/lib.rs
+index 1234567..89abcdef 100644
+--- x/src/lib.rs
++++ x/src/lib.rs
+@@ -1 +1 @@
+-before
++after";
+    
+    // Equal prefixes read the same as no prefix, so they have to be given
+    let prefixes = PathPrefixes { src: "x/".to_string(), dst: "x/".to_string() };
+    let result = DiffParser::parse_unified_diff_with_prefixes(diff, Some(&prefixes)).unwrap();
+    assert_eq!(result.keys().collect::<Vec<_>>(), vec!["src/lib.rs"]);
+    
+    let result = DiffParser::parse_unified_diff_with_prefixes(diff, Some(&PathPrefixes::none())).unwrap();
+    assert_eq!(result.keys().collect::<Vec<_>>(), vec!["x/src/lib.rs"]);
+}
+
+#[test]
+fn test_parse_unified_diff_strips_each_prefix_on_its_own() {
+    // Prefixes without a trailing '/' must not take the first directory with them
+    let diff = "diff --git old_src/lib.rs new_src/lib.rs
+--- old_src/lib.rs
++++ new_src/lib.rs
+@@ -1 +1 @@
+-before
++after";
+    let prefixes = PathPrefixes { src: "old_".to_string(), dst: "new_".to_string() };
+    let result = DiffParser::parse_unified_diff_with_prefixes(diff, Some(&prefixes)).unwrap();
+    assert_eq!(result.keys().collect::<Vec<_>>(), vec!["src/lib.rs"]);
+    
+    // Git's mnemonic prefixes are detected
+    let diff = "diff --git c/src/my file.rs w/src/my file.rs
+--- c/src/my file.rs
++++ w/src/my file.rs
+@@ -1 +1 @@
+-before
++after";
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result.keys().collect::<Vec<_>>(), vec!["src/my file.rs"]);
+}
+
+#[test]
+fn test_parse_unified_diff_copy_uses_destination_path() {
+    let diff = "diff --git a/src/templates/base.txt b/src/pages/base.txt
+similarity index 90%
+copy from src/templates/base.txt
+copy to src/pages/base.txt
+--- a/src/templates/base.txt
++++ b/src/pages/base.txt
+@@ -1 +1 @@
+-old
++new";
+    
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result.keys().collect::<Vec<_>>(), vec!["src/pages/base.txt"]);
+    assert!(!result["src/pages/base.txt"][0].is_rename);
+    
+    // Custom prefixes don't change which path is used
+    let prefixed = diff.replace("a/src", "old/src").replace("b/src", "new/src");
+    let prefixes = PathPrefixes { src: "old/".to_string(), dst: "new/".to_string() };
+    let result = DiffParser::parse_unified_diff_with_prefixes(&prefixed, Some(&prefixes)).unwrap();
+    assert_eq!(result.keys().collect::<Vec<_>>(), vec!["src/pages/base.txt"]);
+}
+
+#[test]
+fn test_parse_unified_diff_deleted_file_does_not_leak_hunks() {
+    let diff = "diff --git a/removed.txt b/removed.txt
+deleted file mode 100644
+index 1234567..0000000
+--- a/removed.txt
++++ /dev/null
+@@ -1,2 +0,0 @@
+-old1
+--- old2
+diff --git a/kept.txt b/kept.txt
+index 1234567..89abcdef 100644
+--- a/kept.txt
++++ b/kept.txt
+@@ -1 +1 @@
+-before
++after";
+
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result.len(), 2);
+    
+    // A removed line starting with "-- " must stay part of the hunk
+    let removed = &result["removed.txt"];
+    assert_eq!(removed.len(), 1);
+    assert_eq!(removed[0].lines, vec!["-old1", "--- old2"]);
+    
+    let kept = &result["kept.txt"];
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].lines, vec!["-before", "+after"]);
+}
+
+#[test]
+fn test_parse_unified_diff_without_file_header_lines() {
+    // Mode-only and binary changes have no ---/+++ lines and no hunks
+    let diff = "diff --git a/script.sh b/script.sh
+old mode 100644
+new mode 100755
+diff --git a/image.png b/image.png
+index 1234567..89abcdef 100644
+Binary files a/image.png and b/image.png differ
+diff --git a/file1.txt b/file1.txt
+--- a/file1.txt
++++ b/file1.txt
+@@ -1 +1 @@
+-before
++after";
+
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result.len(), 3);
+    assert!(result["script.sh"].is_empty());
+    assert!(result["image.png"].is_empty());
+    assert_eq!(result["file1.txt"][0].lines, vec!["-before", "+after"]);
+}
+
+#[test]
+fn test_validate_hunks_complete_diff() {
+    let diff_output = "diff --git a/file1.txt b/file1.txt
+--- a/file1.txt
++++ b/file1.txt
+@@ -1,3 +1,3 @@
+ line1
+-line2
++line2_modified
+ line3";
+    
+    let result = DiffParser::parse_unified_diff(diff_output).unwrap();
+    
+    assert!(DiffParser::validate_hunks(&result).is_empty());
+}
+
+#[test]
+fn test_validate_hunks_truncated_diff() {
+    // The last hunk declares 5 lines but the output stops after 2
+    let diff_output = "diff --git a/file1.txt b/file1.txt
+--- a/file1.txt
++++ b/file1.txt
+@@ -1,5 +1,5 @@
+ line1
+-line2";
+    
+    let result = DiffParser::parse_unified_diff(diff_output).unwrap();
+    let warnings = DiffParser::validate_hunks(&result);
+    
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].contains("file1.txt"));
+    assert!(warnings[0].contains("truncated"));
+}
+
 #[test]
 fn test_reconstruct_patch_empty() {
     // Test reconstructing an empty patch
@@ -163,6 +436,8 @@ fn test_filter_hunk_context_lines() {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
     };
     
     // Create a vector of hunks
@@ -175,6 +450,7 @@ fn test_filter_hunk_context_lines() {
             context_lines: 2,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         }
     ];
     let mut filter_manager = repodiff::filters::filter_manager::FilterManager::new(&filter_rules);
@@ -227,4 +503,890 @@ fn test_get_diff_instructions() {
     assert!(result_str.contains("*.xml"));
     assert!(result_str.contains("include_method_body"));
     assert!(result_str.contains("include_signatures"));
-} 
\ No newline at end of file
+} 
+
+#[test]
+fn test_get_diff_instructions_for_sections() {
+    let options = OutputOptions {
+        preamble_sections: Some(vec!["usage_guidelines".to_string(), "basic_structure".to_string()]),
+        ..OutputOptions::default()
+    };
+    let result = DiffParser::get_diff_instructions_for_sections(Some("[]"), &options).join("\n");
+    
+    // Only the chosen sections appear, in the chosen order, followed by the banner
+    let usage = result.find("# 4. Usage Guidelines").unwrap();
+    let basic = result.find("# 1. Basic Structure:").unwrap();
+    assert!(usage < basic);
+    assert!(!result.contains("This file provides a guide"));
+    assert!(!result.contains("# 2. Special Handling in RepoDiff"));
+    assert!(!result.contains("The following JSON filters"));
+    assert!(result.trim_end().ends_with("Diff Output\n================================================================"));
+    
+    // No sections leaves just the banner
+    let options = OutputOptions { preamble_sections: Some(Vec::new()), ..OutputOptions::default() };
+    let banner = DiffParser::get_diff_instructions_for_sections(None, &options).join("\n");
+    assert!(banner.starts_with("================================================================\nDiff Output"));
+}
+
+#[test]
+fn test_diff_instructions_describe_the_markers_in_use() {
+    let default = DiffParser::get_diff_instructions_for_sections(None, &OutputOptions::default()).join("\n");
+    assert!(default.contains("*   ` ` (space):  Unchanged line (context)."));
+    assert!(default.contains("\n-// Removed line\n+string newValue"));
+    
+    let options = OutputOptions { verbose_markers: true, ..OutputOptions::default() };
+    let verbose = DiffParser::get_diff_instructions_for_sections(None, &options).join("\n");
+    assert!(verbose.contains("*   four spaces:  Unchanged line (context)."));
+    assert!(verbose.contains("*   `[-] `: Line removed from the old version."));
+    assert!(verbose.contains("*   `[+] `: Line added to the new version."));
+    assert!(verbose.contains("\n    // Some code\n"));
+    assert!(verbose.contains("\n[-] // Removed line\n[+] string newValue"));
+    assert!(!verbose.contains("*   `+`:"));
+    
+    let options = OutputOptions { plain_context: true, ..OutputOptions::default() };
+    let plain = DiffParser::get_diff_instructions_for_sections(None, &options).join("\n");
+    assert!(plain.contains("*   no marker:  Unchanged line (context)."));
+    assert!(plain.contains("+++ b/MyFile.cs \n// Some code\n"));
+    assert!(!plain.contains("` ` (space)"));
+}
+
+#[test]
+fn test_diff_instructions_mention_test_files_only_with_a_test_pattern() {
+    let marker = "Sections preceded by a `// test file` marker";
+    let without_pattern = DiffParser::get_diff_instructions_for_sections(None, &OutputOptions::default()).join("\n");
+    assert!(!without_pattern.contains(marker));
+    
+    let options = OutputOptions { test_file_pattern: Some("*Tests.cs".to_string()), ..OutputOptions::default() };
+    let with_pattern = DiffParser::get_diff_instructions_for_sections(None, &options).join("\n");
+    assert!(with_pattern.contains(marker));
+}
+
+#[test]
+fn test_reconstruct_patch_marks_test_files() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,2 +1,2 @@".to_string(),
+        old_start: 1,
+        old_count: 2,
+        new_start: 1,
+        new_count: 2,
+        lines: vec![" line1".to_string(), "-line2".to_string(), "+line2_modified".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([
+        ("src/FooTest.cs".to_string(), vec![hunk.clone()]),
+        ("src/Foo.cs".to_string(), vec![hunk]),
+    ]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        test_file_pattern: Some("*Test*.cs".to_string()),
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().collect();
+    
+    // The marker should directly precede the test file's section only
+    let test_header = lines.iter().position(|l| *l == "diff --git a/src/FooTest.cs b/src/FooTest.cs").unwrap();
+    assert_eq!(lines[test_header - 1], "// test file");
+    let prod_header = lines.iter().position(|l| *l == "diff --git a/src/Foo.cs b/src/Foo.cs").unwrap();
+    assert_ne!(lines[prod_header - 1], "// test file");
+    assert_eq!(lines.iter().filter(|l| **l == "// test file").count(), 1);
+}
+
+#[test]
+fn test_reconstruct_patch_groups_by_directory() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,1 +1,1 @@".to_string(),
+        old_start: 1,
+        old_count: 1,
+        new_start: 1,
+        new_count: 1,
+        lines: vec!["-old".to_string(), "+new".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([
+        ("tests/a_test.rs".to_string(), vec![hunk.clone()]),
+        ("src/b.rs".to_string(), vec![hunk.clone()]),
+        ("src/a.rs".to_string(), vec![hunk.clone()]),
+        ("README.md".to_string(), vec![hunk]),
+    ]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        group_by_dir: true,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let headers: Vec<&str> = result.lines()
+        .filter(|l| l.starts_with("# Directory:") || l.starts_with("diff --git"))
+        .collect();
+    
+    assert_eq!(headers, vec![
+        "# Directory: ./",
+        "diff --git a/README.md b/README.md",
+        "# Directory: src/",
+        "diff --git a/src/a.rs b/src/a.rs",
+        "diff --git a/src/b.rs b/src/b.rs",
+        "# Directory: tests/",
+        "diff --git a/tests/a_test.rs b/tests/a_test.rs",
+    ]);
+}
+
+#[test]
+fn test_reconstruct_patch_wraps_long_lines() {
+    let long_line = format!("+{}", "x".repeat(200));
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,1 +1,2 @@".to_string(),
+        old_start: 1,
+        old_count: 1,
+        new_start: 1,
+        new_count: 2,
+        lines: vec![" short".to_string(), long_line],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        wrap_width: Some(80),
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().skip(3).collect();
+    
+    // 200 chars split as 79 + 78 + 43, each continuation keeping the prefix and marker
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], " short");
+    assert_eq!(lines[1].chars().count(), 80);
+    assert!(lines[2].starts_with("+↪"));
+    assert_eq!(lines[2].chars().count(), 80);
+    assert!(lines[3].starts_with("+↪"));
+    assert!(lines.iter().all(|l| l.chars().count() <= 80));
+    assert_eq!(lines.iter().map(|l| l.matches('x').count()).sum::<usize>(), 200);
+}
+
+#[test]
+fn test_reconstruct_patch_preserves_original_headers() {
+    let header = "@@ -10,7 +10,7 @@ fn process_diff(&self)";
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: header.to_string(),
+        old_start: 10,
+        old_count: 7,
+        new_start: 10,
+        new_count: 7,
+        lines: vec!["-old".to_string(), "+new".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    
+    // Headers are omitted by default
+    let result = DiffParser::reconstruct_patch(&patch_dict, None);
+    assert!(!result.contains("@@ -10,7"));
+    
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        preserve_original_headers: true,
+        ..Default::default()
+    };
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().collect();
+    assert_eq!(lines[3], header);
+    assert_eq!(lines[4], "-old");
+}
+
+#[test]
+fn test_reconstruct_patch_renders_hunk_annotation() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,1 +1,1 @@".to_string(),
+        old_start: 1,
+        old_count: 1,
+        new_start: 1,
+        new_count: 1,
+        lines: vec!["-old".to_string(), "+new".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: Some("1 addition, 1 deletion".to_string()),
+    };
+    let patch_dict = std::collections::HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        preserve_original_headers: true,
+        ..Default::default()
+    };
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().collect();
+    
+    // The annotation follows the hunk header rather than posing as a context line
+    assert_eq!(lines[3..6], ["@@ -1,1 +1,1 @@", "# hunk: 1 addition, 1 deletion", "-old"]);
+}
+
+#[test]
+fn test_reconstruct_patch_markdown_language_fences() {
+    let create_hunk = || repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,1 +1,1 @@".to_string(),
+        old_start: 1,
+        old_count: 1,
+        new_start: 1,
+        new_count: 1,
+        lines: vec!["-old".to_string(), "+new".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([
+        ("src/Program.cs".to_string(), vec![create_hunk()]),
+        ("notes.unknown".to_string(), vec![create_hunk()]),
+    ]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        format: repodiff::utils::diff_parser::OutputFormat::Markdown,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().collect();
+    
+    // Known extensions get their language, others fall back to diff
+    assert_eq!(lines, vec![
+        "```diff",
+        "diff --git a/notes.unknown b/notes.unknown",
+        "--- a/notes.unknown",
+        "+++ b/notes.unknown",
+        "-old",
+        "+new",
+        "```",
+        "```csharp",
+        "diff --git a/src/Program.cs b/src/Program.cs",
+        "--- a/src/Program.cs",
+        "+++ b/src/Program.cs",
+        "-old",
+        "+new",
+        "```",
+    ]);
+}
+
+#[test]
+fn test_reconstruct_patch_plain_context() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,3 +1,3 @@".to_string(),
+        old_start: 1,
+        old_count: 3,
+        new_start: 1,
+        new_count: 3,
+        lines: vec![
+            "     indented();".to_string(),
+            "-old();".to_string(),
+            "+new();".to_string(),
+            " ".to_string(),
+        ],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        plain_context: true,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.split('\n').skip(3).collect();
+    
+    // Only the marker is stripped; the code's own indentation is kept
+    assert_eq!(lines, vec!["    indented();", "-old();", "+new();", ""]);
+}
+
+#[test]
+fn test_reconstruct_patch_verbose_markers() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,3 +1,3 @@".to_string(),
+        old_start: 1,
+        old_count: 3,
+        new_start: 1,
+        new_count: 3,
+        lines: vec![
+            "     indented();".to_string(),
+            "-old();".to_string(),
+            "+new();".to_string(),
+            "\\ No newline at end of file".to_string(),
+        ],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        verbose_markers: true,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.split('\n').skip(3).collect();
+    
+    // The file headers keep git's syntax; only the line markers are replaced
+    assert_eq!(lines, vec!["        indented();", "[-] old();", "[+] new();", "\\ No newline at end of file"]);
+}
+
+#[test]
+fn test_reconstruct_patch_markdown_fence_longer_than_content() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,3 +1,3 @@".to_string(),
+        old_start: 1,
+        old_count: 3,
+        new_start: 1,
+        new_count: 3,
+        lines: vec![" ```rust".to_string(), "-old();".to_string(), "+new();".to_string(), " ```".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("README.md".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        format: repodiff::utils::diff_parser::OutputFormat::Markdown,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().collect();
+    
+    // The outer fence is longer than the triple backticks in the content
+    assert_eq!(lines.first(), Some(&"````markdown"));
+    assert_eq!(lines.last(), Some(&"````"));
+}
+
+#[test]
+fn test_reconstruct_patch_labels_changes() {
+    let diff = "diff --git a/new.txt b/new.txt
+new file mode 100644
+index 0000000..1234567
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++first
++second
+diff --git a/old_name.txt b/renamed.txt
+similarity index 90%
+rename from old_name.txt
+rename to renamed.txt
+--- a/old_name.txt
++++ b/renamed.txt
+@@ -1,2 +1,2 @@
+ kept
+-old
++new
+diff --git a/edited.txt b/edited.txt
+index 1234567..89abcde 100644
+--- a/edited.txt
++++ b/edited.txt
+@@ -1,2 +1,2 @@
+ kept
+-old
++new
+";
+    let patch_dict = DiffParser::parse_unified_diff(diff).unwrap();
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        label_changes: true,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    
+    // Each label directly precedes its file's diff header
+    assert!(result.contains("[Modified]\ndiff --git a/edited.txt b/edited.txt"));
+    assert!(result.contains("[Added]\ndiff --git a/new.txt b/new.txt"));
+    assert!(result.contains("[Renamed from old_name.txt]\ndiff --git a/old_name.txt b/renamed.txt"));
+}
+
+#[test]
+fn test_reconstruct_patch_labels_files_without_hunks() {
+    let diff = "diff --git a/bin.dat b/bin.dat
+index 88768ef..3e3315e 100644
+Binary files a/bin.dat and b/bin.dat differ
+diff --git a/empty.txt b/empty.txt
+new file mode 100644
+index 0000000..e69de29
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+index e69de29..0000000
+diff --git a/mode.sh b/mode.sh
+old mode 100644
+new mode 100755
+diff --git a/old.txt b/new.txt
+similarity index 100%
+rename from old.txt
+rename to new.txt
+diff --git a/template.txt b/copy.txt
+similarity index 100%
+copy from template.txt
+copy to copy.txt
+";
+    let patch_dict = DiffParser::parse_unified_diff(diff).unwrap();
+    let headers = DiffParser::parse_file_headers(diff, None).unwrap();
+    assert_eq!(headers.hunkless_changes["bin.dat"], HunklessChange::Binary);
+    assert_eq!(headers.hunkless_changes["mode.sh"], HunklessChange::Mode);
+    assert_eq!(headers.hunkless_changes["new.txt"], HunklessChange::Renamed { from: "old.txt".to_string() });
+    
+    let options = OutputOptions {
+        include_instructions: false,
+        label_changes: true,
+        hunkless_changes: headers.hunkless_changes,
+        ..Default::default()
+    };
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    
+    // Each file is labeled with what its header says, not all as binary
+    assert!(result.contains("[Binary]\ndiff --git a/bin.dat b/bin.dat"));
+    assert!(result.contains("[Empty]\ndiff --git a/empty.txt b/empty.txt"));
+    assert!(result.contains("[Empty]\ndiff --git a/gone.txt b/gone.txt"));
+    assert!(result.contains("[Mode]\ndiff --git a/mode.sh b/mode.sh"));
+    assert!(result.contains("[Renamed from old.txt]\ndiff --git a/new.txt b/new.txt"));
+    assert!(result.contains("[Copied from template.txt]\ndiff --git a/copy.txt b/copy.txt"));
+    assert_eq!(result.matches("[Binary]").count(), 1);
+}
+
+#[test]
+fn test_reconstruct_patch_truncates_long_lines() {
+    let long_line = format!("+{}", "x".repeat(10_000));
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,2 +1,2 @@".to_string(),
+        old_start: 1,
+        old_count: 2,
+        new_start: 1,
+        new_count: 2,
+        lines: vec![" short".to_string(), "-old".to_string(), long_line],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("bundle.min.js".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        max_line_length: Some(200),
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().skip(3).collect();
+    
+    // The prefix and the first 200 characters are kept; short lines are untouched
+    assert_eq!(lines[0], " short");
+    assert_eq!(lines[2], format!("+{}…[truncated 9800 chars]", "x".repeat(200)));
+}
+
+#[test]
+fn test_reconstruct_patch_shows_trailing_whitespace() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,3 +1,3 @@".to_string(),
+        old_start: 1,
+        old_count: 3,
+        new_start: 1,
+        new_count: 3,
+        lines: vec![
+            " key: value".to_string(),
+            "-name: app  ".to_string(),
+            "+name: app\t ".to_string(),
+            " ".to_string(),
+        ],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("config.yml".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        show_trailing_whitespace: true,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().skip(3).collect();
+    
+    // Only trailing whitespace is marked, and the context marker of a blank line is left alone
+    assert_eq!(lines, vec![" key: value", "-name: app··", "+name: app→·", " "]);
+}
+
+#[test]
+fn test_reconstruct_patch_with_absolute_paths() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,1 +1,1 @@".to_string(),
+        old_start: 1,
+        old_count: 1,
+        new_start: 1,
+        new_count: 1,
+        lines: vec!["-a".to_string(), "+b".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("src/file.cs".to_string(), vec![hunk])]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        path_root: Some("/home/user/repo/".to_string()),
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    let lines: Vec<&str> = result.lines().take(3).collect();
+    
+    // The root replaces git's a/ and b/ prefixes
+    assert_eq!(lines, vec![
+        "diff --git /home/user/repo/src/file.cs /home/user/repo/src/file.cs",
+        "--- /home/user/repo/src/file.cs",
+        "+++ /home/user/repo/src/file.cs",
+    ]);
+}
+
+#[test]
+fn test_reconstruct_patch_with_file_separator() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,1 +1,1 @@".to_string(),
+        old_start: 1,
+        old_count: 1,
+        new_start: 1,
+        new_count: 1,
+        lines: vec!["-a".to_string(), "+b".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([
+        ("one.txt".to_string(), vec![hunk.clone()]),
+        ("two.txt".to_string(), vec![hunk]),
+    ]);
+    let options = repodiff::utils::diff_parser::OutputOptions {
+        include_instructions: false,
+        file_separator: Some("---".to_string()),
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    
+    // The separator sits between the files only, not before the first or after the last
+    assert!(result.starts_with("diff --git a/one.txt b/one.txt"));
+    assert!(result.contains("+b\n---\ndiff --git a/two.txt b/two.txt"));
+    assert_eq!(result.matches("\n---\n").count(), 1);
+}
+
+#[test]
+fn test_validate_diff_reports_structure_or_first_problem() {
+    let valid = "diff --git a/old.txt b/new.txt
+similarity index 90%
+rename from old.txt
+rename to new.txt
+--- a/old.txt
++++ b/new.txt
+@@ -1,2 +1,2 @@
+ kept
+-old
++new
+diff --git a/edited.txt b/edited.txt
+--- a/edited.txt
++++ b/edited.txt
+@@ -1,1 +1,1 @@
+-a
++b
+@@ -10,1 +10,1 @@
+-c
++d
+";
+    let structure = DiffParser::validate_diff(valid).unwrap();
+    assert_eq!(structure.to_string(), "2 files, 3 hunks, 1 renames");
+    
+    // The second hunk is cut off after one of its three lines
+    let truncated = "diff --git a/edited.txt b/edited.txt
+--- a/edited.txt
++++ b/edited.txt
+@@ -1,1 +1,1 @@
+-a
++b
+@@ -10,2 +10,2 @@
+ c
+";
+    let error = DiffParser::validate_diff(truncated).unwrap_err().to_string();
+    assert!(error.starts_with("Parse error: Hunk '@@ -10,2 +10,2 @@' in edited.txt"));
+    
+    let error = DiffParser::validate_diff("not a diff\n").unwrap_err().to_string();
+    assert_eq!(error, "Parse error: no 'diff --git' file headers found");
+}
+
+#[test]
+fn test_overflowing_hunk_header_is_a_parse_error() {
+    let diff = "diff --git a/big.txt b/big.txt
+--- a/big.txt
++++ b/big.txt
+@@ -99999999999999999999999 +1 @@
+-a
++b
+";
+    let expected = "Parse error: hunk header '@@ -99999999999999999999999 +1 @@' has a line number too large to handle";
+    assert_eq!(DiffParser::validate_diff(diff).unwrap_err().to_string(), expected);
+    let error = DiffParser::parse_unified_diff_reader(diff.as_bytes(), None, |_, _, _| {}).unwrap_err();
+    assert_eq!(error.to_string(), expected);
+    
+    let count_overflow = diff.replace("@@ -99999999999999999999999 +1 @@", "@@ -1 +1,99999999999999999999999 @@");
+    assert!(DiffParser::parse_unified_diff(&count_overflow).is_err());
+}
+
+#[test]
+fn test_parse_unified_diff_reader_streams_large_diff() {
+    // A large synthetic diff with a rename in the middle and CRLF line endings
+    let mut diff = String::new();
+    for i in 0..2000 {
+        if i == 1000 {
+            diff.push_str("diff --git a/old.cs b/new.cs\r\nsimilarity index 90%\r\nrename from old.cs\r\nrename to new.cs\r\n");
+            diff.push_str("--- a/old.cs\r\n+++ b/new.cs\r\n@@ -1,2 +1,2 @@\r\n-old\r\n+new\r\n context\r\n");
+        }
+        diff.push_str(&format!(
+            "diff --git a/dir/file{i}.txt b/dir/file{i}.txt\r\n--- a/dir/file{i}.txt\r\n+++ b/dir/file{i}.txt\r\n@@ -1,3 +1,3 @@\r\n line1\r\n-line{i}\r\n+changed{i}\r\n line3\r\n"
+        ));
+    }
+    
+    let mut streamed = Vec::new();
+    DiffParser::parse_unified_diff_reader(std::io::Cursor::new(diff.as_bytes()), None, |path, hunks, _| {
+        streamed.push((path, hunks));
+    }).unwrap();
+    
+    // Files are handed over one by one, in diff order
+    assert_eq!(streamed.len(), 2001);
+    assert_eq!(streamed[0].0, "dir/file0.txt");
+    assert_eq!(streamed[1000].0, "new.cs");
+    assert_eq!(streamed[2000].0, "dir/file1999.txt");
+    
+    // The streaming path gives the same result as parsing the whole diff at once
+    let parsed = DiffParser::parse_unified_diff(&diff).unwrap();
+    assert_eq!(parsed.len(), streamed.len());
+    for (path, hunks) in &streamed {
+        let expected = &parsed[path];
+        assert_eq!(hunks.len(), expected.len());
+        for (hunk, expected) in hunks.iter().zip(expected) {
+            assert_eq!(hunk.header, expected.header);
+            assert_eq!(hunk.lines, expected.lines);
+            assert_eq!(hunk.is_rename, expected.is_rename);
+            assert_eq!(hunk.rename_from, expected.rename_from);
+        }
+    }
+    
+    let rename = &streamed[1000].1[0];
+    assert!(rename.is_rename);
+    assert_eq!(rename.rename_from.as_deref(), Some("old.cs"));
+    assert_eq!(rename.similarity_index.as_deref(), Some("similarity index 90%"));
+    assert_eq!(streamed[5].1[0].lines, vec![" line1", "-line5", "+changed5", " line3"]);
+}
+
+#[test]
+fn test_parse_mode_changes() {
+    let diff = "diff --git a/link b/link
+new file mode 120000
+index 0000000..1234567
+--- /dev/null
++++ b/link
+@@ -0,0 +1 @@
++target/file
+\\ No newline at end of file
+diff --git a/old_link b/old_link
+deleted file mode 120000
+index 1234567..0000000
+--- a/old_link
++++ /dev/null
+@@ -1 +0,0 @@
+-somewhere
+\\ No newline at end of file
+diff --git a/run.sh b/run.sh
+old mode 100755
+new mode 100644
+diff --git a/plain.txt b/plain.txt
+index 1234567..89abcde 100644
+--- a/plain.txt
++++ b/plain.txt
+@@ -1 +1 @@
+-a
++b
+";
+    let mode_changes = DiffParser::parse_mode_changes(diff).unwrap();
+    assert_eq!(mode_changes.len(), 3);
+    assert_eq!(mode_changes["link"], ModeChange::Symlink { target: Some("target/file".to_string()) });
+    assert_eq!(mode_changes["old_link"], ModeChange::Symlink { target: None });
+    assert_eq!(mode_changes["run.sh"].label("run.sh"), "mode run.sh changed from 100755 to 100644");
+    assert_eq!(mode_changes["link"].label("link"), "symlink link now points to target/file");
+    
+    // The hunks themselves are parsed as before
+    let result = DiffParser::parse_unified_diff(diff).unwrap();
+    assert_eq!(result["link"][0].lines, vec!["+target/file", "\\ No newline at end of file"]);
+    assert!(result["run.sh"].is_empty());
+}
+
+#[test]
+fn test_render_file_tree() {
+    let diff = "diff --git a/src/app/Main.cs b/src/app/Main.cs
+index 1234567..89abcde 100644
+--- a/src/app/Main.cs
++++ b/src/app/Main.cs
+@@ -1 +1 @@
+-a
++b
+diff --git a/src/Util.cs b/src/Util.cs
+new file mode 100644
+index 0000000..1234567
+--- /dev/null
++++ b/src/Util.cs
+@@ -0,0 +1 @@
++c
+";
+    let patch_dict = DiffParser::parse_unified_diff(diff).unwrap();
+    let tree = DiffParser::render_file_tree(&patch_dict);
+    assert_eq!(tree, ".
+└── src/
+    ├── Util.cs [A]
+    └── app/
+        └── Main.cs [M]");
+    
+    // The tree is printed ahead of the diff content
+    let options = OutputOptions { file_tree: true, include_instructions: false, ..Default::default() };
+    let output = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    assert!(output.starts_with(&tree));
+    assert!(output.find("Main.cs [M]").unwrap() < output.find("+++ b/src/app/Main.cs").unwrap());
+}
+
+#[test]
+fn test_reconstruct_patch_legend_explains_markers_in_use() {
+    let hunk = repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,3 +1,3 @@".to_string(),
+        old_start: 1,
+        old_count: 3,
+        new_start: 1,
+        new_count: 3,
+        lines: vec![" a".to_string(), " ⋮----".to_string(), "-old(); ".to_string(), "+new();".to_string()],
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    let options = OutputOptions {
+        legend: true,
+        verbose_markers: true,
+        show_trailing_whitespace: true,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, Some("[]"), &options);
+    let legend = &result[..result.find("diff --git").unwrap()];
+    
+    // The legend replaces the instructions and only lists the markers in use
+    assert!(legend.starts_with("# Legend\n"));
+    assert!(legend.contains("* `[+] ` added line, `[-] ` removed line, four spaces: unchanged line"));
+    assert!(legend.contains("* `·` trailing space, `→` trailing tab"));
+    assert!(legend.contains("* `⋮----` unchanged code left out"));
+    assert!(!legend.contains("`↪`"));
+    assert!(!legend.contains("This file provides a guide"));
+    assert!(result.contains("[-] old();·"));
+}
+
+#[test]
+fn test_reconstruct_patch_as_html_report() {
+    let create_hunk = |lines: &[&str]| repodiff::utils::diff_parser::Hunk {
+        header: "@@ -1,2 +1,2 @@".to_string(),
+        old_start: 1,
+        old_count: 2,
+        new_start: 1,
+        new_count: 2,
+        lines: lines.iter().map(|line| line.to_string()).collect(),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = std::collections::HashMap::from([
+        ("src/Program.cs".to_string(), vec![create_hunk(&[" List<int> items;", "-if (a < b && c)", "+if (a > b && c)"])]),
+        ("index.html".to_string(), vec![create_hunk(&["-<p class=\"old\">", "+<p class='new'>"])]),
+    ]);
+    let options = OutputOptions {
+        format: repodiff::utils::diff_parser::OutputFormat::Html,
+        ..Default::default()
+    };
+    
+    let result = DiffParser::reconstruct_patch_with_options(&patch_dict, None, &options);
+    
+    // A complete document with one section per file, in path order, and no preamble
+    assert!(result.starts_with("<!DOCTYPE html>"));
+    assert!(result.ends_with("</html>"));
+    assert!(result.contains("<style>"));
+    assert!(!result.contains("This file provides a guide"));
+    assert_eq!(result.matches("<details class=\"file\" open>").count(), 2);
+    let index = result.find("<summary>index.html</summary>").unwrap();
+    let program = result.find("<summary>src/Program.cs</summary>").unwrap();
+    assert!(index < program);
+    
+    // Content is escaped and lines are classed by their marker
+    assert!(result.contains("<span class=\"ctx\"> List&lt;int&gt; items;</span>"));
+    assert!(result.contains("<span class=\"del\">-if (a &lt; b &amp;&amp; c)</span>"));
+    assert!(result.contains("<span class=\"add\">+if (a &gt; b &amp;&amp; c)</span>"));
+    assert!(result.contains("<span class=\"del\">-&lt;p class=&quot;old&quot;&gt;</span>"));
+    assert!(result.contains("<span class=\"add\">+&lt;p class=&#39;new&#39;&gt;</span>"));
+    assert!(!result.contains("<p class"));
+}
+
+#[test]
+fn test_language_for_path_uses_file_name_extension() {
+    let map = std::collections::HashMap::from([("cake".to_string(), "csharp".to_string())]);
+    assert_eq!(DiffParser::language_for_path_with_map("build/Build.CAKE", &map), Some("csharp"));
+    assert_eq!(DiffParser::language_for_path("src.v2/Orders.cs"), Some("csharp"));
+    
+    // Dots in directory names are not extensions
+    assert_eq!(DiffParser::language_for_path("dir.v2/Makefile"), None);
+    assert_eq!(DiffParser::language_for_path_with_map("tools.cake/Makefile", &map), None);
+}
@@ -1,7 +1,9 @@
-use repodiff::filters::filter_manager::FilterManager;
-use repodiff::utils::config_manager::FilterRule;
+use repodiff::filters::filter_manager::{FilterManager, MethodChange, OmitReason, UNIFIED_CONTEXT_BUFFER};
+use repodiff::utils::git_operations::FULL_FILE_CONTEXT;
+use repodiff::utils::config_manager::{AccessorGranularity, Config, FilterRule};
 use std::collections::HashMap;
 use repodiff::utils::diff_parser::Hunk;
+use repodiff::error::RepoDiffError;
 
 #[test]
 fn test_new_with_filters() {
@@ -12,24 +14,28 @@ fn test_new_with_filters() {
             context_lines: 10,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
         FilterRule {
             file_pattern: "*Test*.cs".to_string(),
             context_lines: 5,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
         FilterRule {
             file_pattern: "*.xml".to_string(),
             context_lines: 2,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
         FilterRule {
             file_pattern: "*".to_string(),
             context_lines: 3,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
     ];
     
@@ -95,24 +101,28 @@ fn test_post_process_files_with_complex_patterns() {
             context_lines: 10,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
         FilterRule {
             file_pattern: "tests/*_test.rs".to_string(),
             context_lines: 5,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
         FilterRule {
             file_pattern: "**/*.json".to_string(),
             context_lines: 2,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
         FilterRule {
             file_pattern: "*".to_string(),
             context_lines: 3,
             include_method_body: false,
             include_signatures: false,
+            ..Default::default()
         },
     ];
     
@@ -154,6 +164,7 @@ fn test_csharp_method_body_inclusion() {
             context_lines: 3,
             include_method_body: true,
             include_signatures: false,
+            ..Default::default()
         },
     ];
     
@@ -181,6 +192,8 @@ namespace Test {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
     };
     
     patch_dict.insert("Method.cs".to_string(), vec![method_hunk]);
@@ -193,6 +206,330 @@ namespace Test {
     assert!(method_result.lines.iter().any(|l| l.contains("Console.WriteLine(x + 1)")));
 }
 
+#[test]
+fn test_method_name_filter_collapses_other_methods() {
+    let filters = vec![FilterRule {
+        file_pattern: "*.cs".to_string(),
+        context_lines: 0,
+        include_method_body: true,
+        method_name_filter: Some("*Handler".to_string()),
+        ..Default::default()
+    }];
+    let mut filter_manager = FilterManager::new(&filters);
+    
+    let hunk = Hunk {
+        header: "@@ -1,16 +1,16 @@".to_string(),
+        old_start: 1,
+        old_count: 16,
+        new_start: 1,
+        new_count: 16,
+        lines: raw_to_lines(r#"
+public class Events {
+    public void ClickHandler() {
+        int clicks = 1;
+-       Log(clicks);
++       Log(clicks + 1);
+    }
+    
+    public void Render() {
+        int width = 1;
+        int height = 2;
+-       Draw(width);
++       Draw(width, height);
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Events.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Events.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    // The matching method is expanded in full
+    assert!(lines.contains(&"         int clicks = 1;".to_string()));
+    assert!(lines.contains(&"+       Log(clicks + 1);".to_string()));
+    
+    // The other method keeps only its signature and a placeholder
+    let render = lines.iter().position(|l| l.contains("public void Render()")).unwrap();
+    assert_eq!(lines[render + 1], " ⋮----");
+    assert!(!lines.iter().any(|l| l.contains("width")));
+}
+
+#[test]
+fn test_classify_method_changes() {
+    let mut filter_manager = FilterManager::new(&[]);
+    
+    let hunk = Hunk {
+        header: "@@ -1,14 +1,18 @@".to_string(),
+        old_start: 1,
+        old_count: 14,
+        new_start: 1,
+        new_count: 18,
+        lines: raw_to_lines(r#"
+public class Orders {
+-   public void Save(Order order)
++   public void Save(Order order, bool force)
+    {
+        Store(order);
+    }
+
+    public int Count()
+    {
+-       return 1;
++       return 2;
+    }
+
+    public void Unchanged()
+    {
+        Run();
+    }
++
++   internal void Cancel()
++   {
++   }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    
+    let changes = filter_manager.classify_method_changes("Orders.cs", &[hunk]);
+    assert_eq!(changes, vec![
+        MethodChange::SignatureChanged {
+            name: "Save".to_string(),
+            old_signature: "public void Save(Order order)".to_string(),
+            new_signature: "public void Save(Order order, bool force)".to_string(),
+        },
+        MethodChange::BodyOnly { name: "Count".to_string() },
+        MethodChange::Added { name: "Cancel".to_string(), signature: "internal void Cancel()".to_string() },
+    ]);
+    
+    // Files other than C# have no methods to classify
+    assert!(filter_manager.classify_method_changes("notes.txt", &[create_test_hunk()]).is_empty());
+}
+
+#[test]
+fn test_deleted_method_is_identified_from_old_side() {
+    let filters = vec![FilterRule {
+        file_pattern: "*.cs".to_string(),
+        context_lines: 0,
+        include_method_body: true,
+        ..Default::default()
+    }];
+    let mut filter_manager = FilterManager::new(&filters);
+    
+    let hunk = Hunk {
+        header: "@@ -1,12 +1,7 @@".to_string(),
+        old_start: 1,
+        old_count: 12,
+        new_start: 1,
+        new_count: 7,
+        lines: raw_to_lines(r#"
+public class Service {
+-   public void Removed() {
+-       int unused = 1;
+-       Cleanup(unused);
+-   }
+-
+    public void Kept() {
+        int count = 2;
+        Run(count);
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    
+    // Only the removed method changed, even though its lines sit where Kept starts on the new side
+    let signatures = filter_manager.changed_method_signatures("Service.cs", std::slice::from_ref(&hunk));
+    assert_eq!(signatures, vec!["public void Removed() {"]);
+    
+    let patch_dict = HashMap::from([("Service.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Service.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    assert!(lines.contains(&"-       Cleanup(unused);".to_string()));
+    assert!(!lines.iter().any(|l| l.contains("Run(count)")));
+}
+
+#[test]
+fn test_changed_symbols_strip_the_context_marker() {
+    let mut filter_manager = FilterManager::new(&[]);
+    let mut hunk = create_test_hunk();
+    hunk.lines = raw_to_lines(r#"
+def first():
+    return 1
+
+def second():
+-    return 2
++    return 3"#);
+    
+    // Context lines keep their marker in the hunk, but the definitions are still unindented code
+    assert_eq!(filter_manager.changed_symbols("calc.py", &[hunk]), vec!["second"]);
+}
+
+#[test]
+fn test_annotate_method_tokens() {
+    let config = Config {
+        filters: vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 0,
+            include_method_body: true,
+            ..Default::default()
+        }],
+        annotate_method_tokens: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let hunk = Hunk {
+        header: "@@ -1,8 +1,8 @@".to_string(),
+        old_start: 1,
+        old_count: 8,
+        new_start: 1,
+        new_count: 8,
+        lines: raw_to_lines(r#"
+public class Counter {
+    public int Next() {
+        int value = current;
+-       current += 1;
++       current += step;
+        return value;
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Counter.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Counter.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    let signature = lines.iter().find(|l| l.contains("public int Next()")).unwrap();
+    let tokens: usize = signature
+        .rsplit_once(" // ")
+        .and_then(|(_, annotation)| annotation.strip_suffix(" tokens"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(tokens > 0);
+    
+    // Only the signature is annotated
+    assert_eq!(lines.iter().filter(|l| l.ends_with(" tokens")).count(), 1);
+}
+
+#[test]
+fn test_method_line_offsets() {
+    let config = Config {
+        filters: vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 0,
+            include_method_body: true,
+            ..Default::default()
+        }],
+        method_line_offsets: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let hunk = Hunk {
+        header: "@@ -1,8 +1,8 @@".to_string(),
+        old_start: 1,
+        old_count: 8,
+        new_start: 1,
+        new_count: 8,
+        lines: raw_to_lines(r#"
+public class Counter {
+    public int Next() {
+        int value = current;
+-       current += 1;
++       current += step;
+        return value;
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Counter.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Counter.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    // The signature is left alone and body lines count up from it, the diff marker staying first
+    assert_eq!(lines, vec![
+        "     public int Next() {",
+        " +1:        int value = current;",
+        "-+2:       current += 1;",
+        "++2:       current += step;",
+        " +3:        return value;",
+        " +4:    }",
+    ]);
+}
+
+#[test]
+fn test_extension_language_map_routes_to_csharp_parser() {
+    let config = Config {
+        filters: vec![FilterRule {
+            file_pattern: "*.cake".to_string(),
+            context_lines: 0,
+            include_method_body: true,
+            ..Default::default()
+        }],
+        extension_language_map: HashMap::from([("cake".to_string(), "csharp".to_string())]),
+        ..Default::default()
+    };
+    let hunk = Hunk {
+        header: "@@ -1,7 +1,7 @@".to_string(),
+        old_start: 1,
+        old_count: 7,
+        new_start: 1,
+        new_count: 7,
+        lines: raw_to_lines(r#"
+public class Build {
+    public void Run() {
+        Restore();
+-       Compile();
++       Compile(release: true);
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("build.cake".to_string(), vec![hunk])]);
+    
+    // With the mapping, the whole changed method is included
+    let processed = FilterManager::from_config(&config).unwrap().post_process_files(&patch_dict);
+    assert!(processed["build.cake"][0].lines.iter().any(|l| l.contains("Restore();")));
+    
+    // Without it, only the changed lines are kept
+    let config = Config {
+        extension_language_map: HashMap::new(),
+        ..config
+    };
+    let processed = FilterManager::from_config(&config).unwrap().post_process_files(&patch_dict);
+    assert!(!processed["build.cake"][0].lines.iter().any(|l| l.contains("Restore();")));
+}
+
 #[test]
 fn test_csharp_property_body_inclusion() {
     let filters = vec![
@@ -201,6 +538,7 @@ fn test_csharp_property_body_inclusion() {
             context_lines: 3,  // Small context to test boundary
             include_method_body: true,
             include_signatures: false,
+            ..Default::default()
         },
     ];
     
@@ -268,6 +606,8 @@ namespace Test {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
     };
     
     patch_dict.insert("Property.cs".to_string(), vec![property_hunk]);
@@ -308,6 +648,130 @@ namespace Test {
     assert_eq!(outside_lines, 0, "Found {} lines from outside the property when they should have been excluded", outside_lines);
 }
 
+#[test]
+fn test_csharp_accessor_granularity() {
+    let property_hunk = Hunk {
+        header: "@@ -1,24 +1,24 @@".to_string(),
+        old_start: 1,
+        old_count: 24,
+        new_start: 1,
+        new_count: 24,
+        lines: raw_to_lines(r#"
+namespace Test {
+    public class MyClass {
+        public int MyProperty
+        {
+            get
+            {
+                var temp = myField;
+                if (temp < 0)
+                {
+                    temp = 0;
+                }
+                return temp;
+            }
+            set
+            {
+                if (value < 0)
+                {
+                    throw new ArgumentException("Value cannot be negative");
+                }
+-               myField = value;
++               myField = value + 1;
+            }
+        }
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let process = |accessor_granularity| {
+        let filters = vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 1,
+            include_method_body: true,
+            accessor_granularity,
+            ..Default::default()
+        }];
+        let mut patch_dict = HashMap::new();
+        patch_dict.insert("Property.cs".to_string(), vec![property_hunk.clone()]);
+        FilterManager::new(&filters).post_process_files(&patch_dict)["Property.cs"][0].lines.clone()
+    };
+
+    // The whole property is expanded, including the unchanged getter
+    let property_lines = process(AccessorGranularity::Property);
+    assert!(property_lines.iter().any(|l| l.contains("public int MyProperty")));
+    assert!(property_lines.iter().any(|l| l.contains("var temp = myField")));
+    assert!(property_lines.iter().any(|l| l.contains("if (value < 0)")));
+
+    // Only the changed setter is expanded; the getter is too far from the change
+    let accessor_lines = process(AccessorGranularity::Accessor);
+    assert!(accessor_lines.iter().any(|l| l.contains("if (value < 0)")));
+    assert!(accessor_lines.iter().any(|l| l.contains("myField = value + 1")));
+    assert!(!accessor_lines.iter().any(|l| l.contains("var temp = myField")));
+    assert!(!accessor_lines.iter().any(|l| l.contains("return temp")));
+}
+
+#[test]
+fn test_csharp_referenced_fields_inclusion() {
+    let hunk = Hunk {
+        header: "@@ -1,18 +1,18 @@".to_string(),
+        old_start: 1,
+        old_count: 18,
+        new_start: 1,
+        new_count: 18,
+        lines: raw_to_lines(r#"
+public class Counter {
+    private int count;
+    private string unused;
+
+    public void Other() {
+        Run();
+    }
+
+    public void Add(int amount) {
+        Validate(amount);
+        Log(amount);
+        Log(amount);
+        Log(amount);
+-       Total = amount;
++       count += amount;
+    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let process = |include_referenced_fields| {
+        let filters = vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 1,
+            include_method_body: true,
+            include_referenced_fields,
+            ..Default::default()
+        }];
+        let mut patch_dict = HashMap::new();
+        patch_dict.insert("Counter.cs".to_string(), vec![hunk.clone()]);
+        FilterManager::new(&filters).post_process_files(&patch_dict)["Counter.cs"][0].lines.clone()
+    };
+    
+    // The declaration of the field used by the changed method is shown, but not other fields
+    let lines = process(true);
+    assert!(lines.contains(&"     private int count;".to_string()));
+    assert!(!lines.iter().any(|l| l.contains("unused")));
+    assert!(!lines.iter().any(|l| l.contains("Run()")));
+    
+    // By default the field is too far from the change
+    assert!(!process(false).iter().any(|l| l.contains("private int count")));
+}
+
 #[test]
 fn test_csharp_arrow_property_inclusion() {
     let filters = vec![
@@ -316,6 +780,7 @@ fn test_csharp_arrow_property_inclusion() {
             context_lines: 3,
             include_method_body: true,
             include_signatures: false,
+            ..Default::default()
         },
     ];
     
@@ -340,6 +805,8 @@ namespace Test {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
     };
     
     patch_dict.insert("ArrowProperty.cs".to_string(), vec![arrow_property_hunk]);
@@ -372,6 +839,114 @@ fn raw_to_lines(s: &str) -> Vec<String> {
         .collect()
 }
 
+#[test]
+fn test_razor_code_block_inclusion() {
+    let filters = vec![
+        FilterRule {
+            file_pattern: "*.cshtml".to_string(),
+            context_lines: 0,
+            include_method_body: true,
+            ..Default::default()
+        },
+    ];
+    
+    let mut filter_manager = FilterManager::new(&filters);
+    let hunk = Hunk {
+        header: "@@ -1,16 +1,16 @@".to_string(),
+        old_start: 1,
+        old_count: 16,
+        new_start: 1,
+        new_count: 16,
+        lines: raw_to_lines(r#"
+<h1>Counter</h1>
+<p>Current count: @currentCount</p>
+<button @onclick="IncrementCount">Click me</button>
+
+@code {
+    private int currentCount = 0;
+
+    private void IncrementCount()
+    {
+-       currentCount++;
++       currentCount += 2;
+    }
+}
+
+<footer>Unchanged</footer>"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    
+    let patch_dict = HashMap::from([("Pages/Counter.cshtml".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines = &processed["Pages/Counter.cshtml"][0].lines;
+    
+    // The whole @code block is expanded, while the surrounding HTML is only context
+    assert_eq!(lines.first().unwrap(), " @code {");
+    assert!(lines.iter().any(|l| l.contains("private int currentCount = 0;")));
+    assert!(lines.iter().any(|l| l == " }"));
+    assert!(!lines.iter().any(|l| l.contains("<h1>") || l.contains("<footer>")));
+}
+
+#[test]
+fn test_include_signatures_combines_with_method_body() {
+    let process = |include_method_body: bool, include_signatures: bool| {
+        let mut filter_manager = FilterManager::new(&[FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 3,
+            include_method_body,
+            include_signatures,
+            ..Default::default()
+        }]);
+        let mut hunk = create_test_hunk();
+        hunk.lines = raw_to_lines(r#"
+public class Orders {
+    public void Save() {
+        Validate();
+-        store.Write();
++        store.Write(true);
+        Flush();
+    }
+    public void Load() {
+        Open();
+        store.Read();
+        Close();
+    }
+}"#);
+        let patch_dict = HashMap::from([("Orders.cs".to_string(), vec![hunk])]);
+        let processed = filter_manager.post_process_files(&patch_dict);
+        processed["Orders.cs"].iter().flat_map(|h| h.lines.clone()).collect::<Vec<String>>()
+    };
+    
+    // Together, the changed method is emitted in full and the unchanged method near it as its signature
+    let both = process(true, true);
+    assert_eq!(both, vec![
+        " public class Orders {",
+        "     public void Save() {",
+        "         Validate();",
+        "-        store.Write();",
+        "+        store.Write(true);",
+        "         Flush();",
+        "     }",
+        "     public void Load() {",
+        " ⋮----",
+    ]);
+    
+    // Signatures alone collapse the changed method too
+    let signatures = process(false, true);
+    assert!(!signatures.iter().any(|l| l.contains("Validate();")));
+    assert!(signatures.contains(&"     public void Load() {".to_string()));
+    
+    // Method bodies alone show the unchanged method's lines within context_lines, without a placeholder
+    let bodies = process(true, false);
+    assert_eq!(bodies[..7], both[..7]);
+    assert!(!bodies.iter().any(|l| l.ends_with("⋮----")));
+}
+
 #[test]
 fn test_include_signatures_and_method_body() {
     let filters = vec![
@@ -380,6 +955,7 @@ fn test_include_signatures_and_method_body() {
             context_lines: 10,
             include_method_body: true,
             include_signatures: true,
+            ..Default::default()
         },
     ];
     
@@ -455,6 +1031,8 @@ namespace Test {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
     };
     
     patch_dict.insert("test.cs".to_string(), vec![hunk]);
@@ -550,6 +1128,7 @@ fn test_class_declaration_respects_context_lines() {
             context_lines: 3, // Small context to test boundary
             include_method_body: true,
             include_signatures: false,
+            ..Default::default()
         },
     ];
     
@@ -587,6 +1166,8 @@ namespace Test {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
     };
     
     patch_dict.insert("ClassDeclaration.cs".to_string(), vec![hunk.clone()]);
@@ -616,6 +1197,643 @@ namespace Test {
         "Property declaration is missing");
     assert!(result.lines.iter().any(|l| l.contains("field1 = value + 1")), 
         "Changed line is missing");
+    
+    // Opting in makes method bodies bring their enclosing declarations along, but not other lines
+    let filters = vec![FilterRule {
+        include_enclosing_declarations: true,
+        ..filters[0].clone()
+    }];
+    let mut filter_manager = FilterManager::new(&filters);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let result = &processed["ClassDeclaration.cs"][0];
+    assert!(result.lines.iter().any(|l| l.contains("namespace Test {")));
+    assert!(result.lines.iter().any(|l| l.contains("public class MyClass {")));
+    assert!(!result.lines.iter().any(|l| l.contains("private int field2")));
+    assert!(result.lines.iter().any(|l| l.contains("field1 = value + 1")));
+}
+
+#[test]
+fn test_test_files_use_test_context_lines() {
+    let config = Config {
+        test_file_pattern: Some("*Test*.cs".to_string()),
+        test_context_lines: Some(1),
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let mut patch_dict = HashMap::new();
+    patch_dict.insert("FooTest.cs".to_string(), vec![create_test_hunk()]);
+    patch_dict.insert("Foo.cs".to_string(), vec![create_test_hunk()]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    assert!(filter_manager.is_test_file("FooTest.cs"));
+    assert!(!filter_manager.is_test_file("Foo.cs"));
+    
+    // Test file keeps a single line of context around the change
+    assert_eq!(processed["FooTest.cs"][0].lines, vec![
+        " line3".to_string(),
+        "-line4".to_string(),
+        "+line4_modified".to_string(),
+        " line5".to_string(),
+    ]);
+    
+    // Other files keep the matched rule's context
+    assert_eq!(processed["Foo.cs"][0].lines.len(), 8);
+}
+
+#[test]
+fn test_exact_basename_patterns() {
+    assert!(FilterManager::matches_pattern("Dockerfile", "Dockerfile"));
+    assert!(FilterManager::matches_pattern("Dockerfile", "docker/api/Dockerfile"));
+    assert!(FilterManager::matches_pattern("Makefile", "Makefile"));
+    assert!(!FilterManager::matches_pattern("Dockerfile", "Dockerfile.dev"));
+    assert!(!FilterManager::matches_pattern("Dockerfile", "docker/NotADockerfile"));
+    
+    // Patterns with a directory still match the whole path only
+    assert!(FilterManager::matches_pattern("docker/Dockerfile", "docker/Dockerfile"));
+    assert!(!FilterManager::matches_pattern("docker/Dockerfile", "api/docker/Dockerfile"));
+}
+
+#[test]
+fn test_negated_pattern_excludes_files() {
+    let filters = vec![
+        FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 3,
+            include_method_body: false,
+            include_signatures: false,
+            ..Default::default()
+        },
+        FilterRule {
+            file_pattern: "!*Generated.cs".to_string(),
+            context_lines: 3,
+            include_method_body: false,
+            include_signatures: false,
+            ..Default::default()
+        },
+    ];
+    let mut filter_manager = FilterManager::new(&filters);
+    
+    let mut patch_dict = HashMap::new();
+    patch_dict.insert("Foo.cs".to_string(), vec![create_test_hunk()]);
+    patch_dict.insert("FooGenerated.cs".to_string(), vec![create_test_hunk()]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    assert!(processed.contains_key("Foo.cs"));
+    assert!(!processed.contains_key("FooGenerated.cs"));
+}
+
+#[test]
+fn test_later_include_re_includes_excluded_file() {
+    let filters = vec![
+        FilterRule {
+            file_pattern: "!*.designer.cs".to_string(),
+            context_lines: 3,
+            include_method_body: false,
+            include_signatures: false,
+            ..Default::default()
+        },
+        FilterRule {
+            file_pattern: "Important.designer.cs".to_string(),
+            context_lines: 1,
+            include_method_body: false,
+            include_signatures: false,
+            ..Default::default()
+        },
+    ];
+    let mut filter_manager = FilterManager::new(&filters);
+    
+    let mut patch_dict = HashMap::new();
+    patch_dict.insert("Form.designer.cs".to_string(), vec![create_test_hunk()]);
+    patch_dict.insert("Important.designer.cs".to_string(), vec![create_test_hunk()]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    // The last matching rule wins, and the re-including rule supplies the settings
+    assert!(!processed.contains_key("Form.designer.cs"));
+    assert_eq!(processed["Important.designer.cs"][0].lines.len(), 4);
+}
+
+#[test]
+fn test_max_hunks_merges_hunks() {
+    let filters = vec![
+        FilterRule {
+            file_pattern: "*".to_string(),
+            context_lines: 1,
+            max_hunks: Some(3),
+            ..Default::default()
+        },
+    ];
+    let mut filter_manager = FilterManager::new(&filters);
+    
+    // Ten small hunks spread through the file
+    let hunks: Vec<Hunk> = (0..10)
+        .map(|i| {
+            let start = 1 + i * 20;
+            Hunk {
+                header: format!("@@ -{},3 +{},3 @@", start, start),
+                old_start: start,
+                old_count: 3,
+                new_start: start,
+                new_count: 3,
+                lines: vec![
+                    " before".to_string(),
+                    format!("-old{}", i),
+                    format!("+new{}", i),
+                    " after".to_string(),
+                ],
+                is_rename: false,
+                rename_from: None,
+                rename_to: None,
+                similarity_index: None,
+                line_numbers: Vec::new(),
+                annotation: None,
+            }
+        })
+        .collect();
+    
+    let patch_dict = HashMap::from([("file.txt".to_string(), hunks)]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let result = &processed["file.txt"];
+    
+    assert!(result.len() <= 3);
+    
+    // Every change is still present, with placeholders between merged hunks
+    for i in 0..10 {
+        assert!(result.iter().any(|h| h.lines.contains(&format!("+new{}", i))));
+    }
+    assert_eq!(result[0].old_start, 1);
+    assert_eq!(result[0].header, "@@ -1,63 +1,63 @@");
+    assert!(result[0].lines.contains(&" ⋮----".to_string()));
+}
+
+#[test]
+fn test_collapse_blank_runs() {
+    let config = Config {
+        filters: vec![FilterRule {
+            context_lines: 10,
+            ..Default::default()
+        }],
+        collapse_blank_runs: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let hunk = Hunk {
+        header: "@@ -1,10 +1,10 @@".to_string(),
+        old_start: 1,
+        old_count: 10,
+        new_start: 1,
+        new_count: 10,
+        lines: vec![" a", " ", "", " ", "  ", " ", " b", "-c", "+d", " ", " ", " e"]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("file.txt".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    // The run of five blank lines becomes one; the run of two is kept
+    assert_eq!(processed["file.txt"][0].lines, vec![" a", " ", " b", "-c", "+d", " ", " ", " e"]);
+}
+
+#[test]
+fn test_detect_moved_methods() {
+    let config = Config {
+        filters: vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 1,
+            ..Default::default()
+        }],
+        detect_moved_methods: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    // Load is moved to the end unchanged; Save is moved to the end with one line changed
+    let hunk = Hunk {
+        header: "@@ -1,19 +1,19 @@".to_string(),
+        old_start: 1,
+        old_count: 19,
+        new_start: 1,
+        new_count: 19,
+        lines: raw_to_lines(r#"
+public class Store
+{
+-    public void Load()
+-    {
+-        Read();
+-    }
+-
+-    public void Save()
+-    {
+-        Open();
+-        Write();
+-    }
+-
+    public void Keep()
+    {
+    }
++
++    public void Load()
++    {
++        Read();
++    }
++
++    public void Save()
++    {
++        Open();
++        WriteAll();
++    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Store.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Store.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    assert!(lines.contains(&"-[moved] Load: moved elsewhere in this file".to_string()));
+    assert!(lines.contains(&"+[moved] Load: moved here, unchanged".to_string()));
+    assert!(lines.contains(&"-[moved] Save: moved elsewhere in this file".to_string()));
+    assert!(lines.contains(&"+[moved] Save: moved here, with changes:".to_string()));
+    
+    // The unchanged body is dropped, and only the changed line of Save is marked
+    assert!(!lines.iter().any(|l| l.contains("Read();")));
+    assert!(lines.contains(&"         Open();".to_string()));
+    assert!(lines.contains(&"-        Write();".to_string()));
+    assert!(lines.contains(&"+        WriteAll();".to_string()));
+}
+
+#[test]
+fn test_detect_moved_methods_across_hunks() {
+    let config = Config {
+        filters: vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 1,
+            ..Default::default()
+        }],
+        detect_moved_methods: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    // Git needs the whole file to find the methods on both sides
+    assert_eq!(filter_manager.required_unified_context(), FULL_FILE_CONTEXT);
+    
+    // Load is deleted near the top of the file and added near the bottom, in separate hunks
+    let removed = Hunk {
+        header: "@@ -1,8 +1,3 @@".to_string(),
+        old_start: 1,
+        old_count: 8,
+        new_start: 1,
+        new_count: 3,
+        lines: raw_to_lines(r#"
+public class Store
+{
+-    public void Load()
+-    {
+-        Read();
+-    }
+-
+    public void Keep()"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let added = Hunk {
+        header: "@@ -40,2 +35,7 @@".to_string(),
+        old_start: 40,
+        old_count: 2,
+        new_start: 35,
+        new_count: 7,
+        lines: raw_to_lines(r#"
+    }
++
++    public void Load()
++    {
++        Read();
++    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Store.cs".to_string(), vec![removed, added])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Store.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    assert!(lines.contains(&"-[moved] Load: moved elsewhere in this file".to_string()));
+    assert!(lines.contains(&"+[moved] Load: moved here, unchanged".to_string()));
+    assert!(!lines.iter().any(|l| l.contains("Read();")));
+}
+
+#[test]
+fn test_long_moved_method_changes_are_shown_as_delete_and_add() {
+    let config = Config {
+        filters: vec![FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 1,
+            ..Default::default()
+        }],
+        detect_moved_methods: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    // Load is moved with its first and last statements changed, and 1,200 lines between them
+    let body = |first: &str, last: &str| -> Vec<String> {
+        let mut body = vec!["    public void Load()".to_string(), "    {".to_string(), format!("        {}();", first)];
+        body.extend((0..1200).map(|i| format!("        Call{}();", i)));
+        body.extend([format!("        {}();", last), "    }".to_string()]);
+        body
+    };
+    let mut lines = vec![" public class Store".to_string(), " {".to_string()];
+    lines.extend(body("Open", "Close").into_iter().map(|line| format!("-{}", line)));
+    lines.extend(["     public void Keep()".to_string(), "     {".to_string(), "     }".to_string()]);
+    lines.extend(body("OpenAll", "CloseAll").into_iter().map(|line| format!("+{}", line)));
+    lines.push(" }".to_string());
+    let count = lines.iter().filter(|line| !line.starts_with('+')).count();
+    let hunk = Hunk {
+        header: format!("@@ -1,{} +1,{} @@", count, count),
+        old_start: 1,
+        old_count: count,
+        new_start: 1,
+        new_count: count,
+        lines,
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Store.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Store.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    assert!(lines.contains(&"+[moved] Load: moved here, with changes:".to_string()));
+    
+    // The shared signature is context, and the long changed stretch is a plain delete and add
+    assert!(lines.contains(&"     public void Load()".to_string()));
+    assert!(lines.contains(&"-        Open();".to_string()));
+    assert!(lines.contains(&"-        Call600();".to_string()));
+    assert!(lines.contains(&"+        Call600();".to_string()));
+    assert!(lines.contains(&"+        CloseAll();".to_string()));
+}
+
+#[test]
+fn test_moved_methods_are_kept_when_detection_is_off() {
+    let mut filter_manager = FilterManager::from_config(&Config::default()).unwrap();
+    
+    let hunk = Hunk {
+        header: "@@ -1,8 +1,8 @@".to_string(),
+        old_start: 1,
+        old_count: 8,
+        new_start: 1,
+        new_count: 8,
+        lines: raw_to_lines(r#"
+public class Store
+{
+-    public void Load()
+-    {
+-    }
+    public void Keep()
+    {
+    }
++    public void Load()
++    {
++    }
+}"#),
+        is_rename: false,
+        rename_from: None,
+        rename_to: None,
+        similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    };
+    let patch_dict = HashMap::from([("Store.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let lines: Vec<String> = processed["Store.cs"].iter().flat_map(|h| h.lines.clone()).collect();
+    
+    assert!(lines.contains(&"-    public void Load()".to_string()));
+    assert!(lines.contains(&"+    public void Load()".to_string()));
+    assert!(!lines.iter().any(|l| l.contains("[moved]")));
+}
+
+#[test]
+fn test_dedupe_identical_hunks() {
+    let config = Config {
+        dedupe_identical_hunks: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let mut other_hunk = create_test_hunk();
+    other_hunk.lines = vec![" a".to_string(), "-b".to_string(), "+c".to_string()];
+    let patch_dict = HashMap::from([
+        ("b.txt".to_string(), vec![create_test_hunk()]),
+        ("a.txt".to_string(), vec![create_test_hunk()]),
+        ("c.txt".to_string(), vec![other_hunk.clone()]),
+    ]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    // The first file in output order keeps the hunk; the repeat refers to it
+    assert!(processed["a.txt"][0].lines.len() > 1);
+    assert_eq!(processed["b.txt"][0].lines, vec![" (identical change as in a.txt)"]);
+    assert_eq!(processed["c.txt"][0].lines, other_hunk.lines);
+}
+
+#[test]
+fn test_dedupe_occurrence_counts() {
+    let config = Config {
+        dedupe_identical_hunks: true,
+        dedupe_occurrence_counts: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let mut other_hunk = create_test_hunk();
+    other_hunk.lines = vec![" a".to_string(), "-b".to_string(), "+c".to_string()];
+    let patch_dict = HashMap::from([
+        ("C.cs".to_string(), vec![create_test_hunk()]),
+        ("A.cs".to_string(), vec![create_test_hunk()]),
+        ("B.cs".to_string(), vec![create_test_hunk()]),
+        ("D.cs".to_string(), vec![other_hunk.clone()]),
+    ]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    // The kept hunk names every file sharing it; the repeats still refer to it
+    assert_eq!(processed["A.cs"][0].lines[0], " (identical change applied to 3 files: A.cs, B.cs, C.cs)");
+    assert_eq!(processed["A.cs"][0].lines[4], "-line4");
+    assert_eq!(processed["B.cs"][0].lines, vec![" (identical change as in A.cs)"]);
+    assert_eq!(processed["C.cs"][0].lines, vec![" (identical change as in A.cs)"]);
+    // A hunk only one file has is left alone
+    assert_eq!(processed["D.cs"][0].lines, other_hunk.lines);
+    
+    // Long lists are cut short with a count of the rest
+    let patch_dict: HashMap<String, Vec<Hunk>> = (0..14)
+        .map(|i| (format!("File{:02}.cs", i), vec![create_test_hunk()]))
+        .collect();
+    let processed = filter_manager.post_process_files(&patch_dict);
+    assert_eq!(
+        processed["File00.cs"][0].lines[0],
+        " (identical change applied to 14 files: File00.cs, File01.cs, File02.cs, File03.cs, File04.cs, \
+File05.cs, File06.cs, File07.cs, File08.cs, File09.cs and 4 more)"
+    );
+}
+
+#[test]
+fn test_ignore_line_patterns() {
+    let config = Config {
+        ignore_line_patterns: vec![r"^// Generated at \d{4}-\d{2}-\d{2}".to_string()],
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    // A timestamp change next to a real change
+    let mut mixed = create_test_hunk();
+    mixed.lines = vec![
+        "-// Generated at 2024-01-01".to_string(),
+        "+// Generated at 2024-02-01".to_string(),
+        " a".to_string(),
+        "-b".to_string(),
+        "+c".to_string(),
+    ];
+    // Only the timestamp changed
+    let mut timestamp_only = create_test_hunk();
+    timestamp_only.lines = vec![
+        " header".to_string(),
+        "-// Generated at 2024-01-01".to_string(),
+        "+// Generated at 2024-02-01".to_string(),
+    ];
+    let patch_dict = HashMap::from([
+        ("mixed.txt".to_string(), vec![mixed]),
+        ("generated.txt".to_string(), vec![timestamp_only]),
+    ]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    // The timestamp is kept as context with its new value
+    assert_eq!(processed["mixed.txt"][0].lines, vec![" // Generated at 2024-02-01", " a", "-b", "+c"]);
+    // A file with only ignored changes is left out
+    assert!(!processed.contains_key("generated.txt"));
+    
+    // Invalid patterns are reported rather than skipped
+    let invalid = Config {
+        ignore_line_patterns: vec!["Version: (".to_string()],
+        ..Default::default()
+    };
+    let error = FilterManager::from_config(&invalid).err().unwrap();
+    assert!(matches!(error, RepoDiffError::ConfigError(_)));
+    assert!(error.to_string().contains("Version: ("));
+}
+
+#[test]
+fn test_full_file_over_change_ratio() {
+    let filters = vec![FilterRule {
+        context_lines: 0,
+        full_file_over_change_ratio: Some(0.8),
+        ..Default::default()
+    }];
+    let mut filter_manager = FilterManager::new(&filters);
+    assert_eq!(filter_manager.required_unified_context(), FULL_FILE_CONTEXT);
+    
+    // Nine of ten lines rewritten: the whole file is kept
+    let mut rewritten = create_test_hunk();
+    rewritten.lines = (0..9).map(|i| format!("-old{}", i))
+        .chain((0..9).map(|i| format!("+new{}", i)))
+        .chain(std::iter::once(" kept".to_string()))
+        .collect();
+    
+    // One of ten lines changed: context is trimmed as usual
+    let patch_dict = HashMap::from([
+        ("rewritten.txt".to_string(), vec![rewritten.clone()]),
+        ("edited.txt".to_string(), vec![create_test_hunk()]),
+    ]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    assert_eq!(processed["rewritten.txt"][0].lines, rewritten.lines);
+    assert!(processed["edited.txt"][0].lines.iter().all(|l| !l.starts_with(' ')));
+}
+
+#[test]
+fn test_omitted_files_are_counted_by_reason() {
+    let config = Config {
+        filters: vec![
+            FilterRule::default(),
+            FilterRule {
+                file_pattern: "!*.lock".to_string(),
+                ..Default::default()
+            },
+        ],
+        max_file_lines: Some(9),
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let mut large_hunk = create_test_hunk();
+    large_hunk.lines = (0..10).map(|i| format!("+line{}", i)).collect();
+    let patch_dict = HashMap::from([
+        ("small.txt".to_string(), vec![create_test_hunk()]),
+        ("big.txt".to_string(), vec![large_hunk]),
+        ("Cargo.lock".to_string(), vec![create_test_hunk()]),
+    ]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    assert_eq!(processed.len(), 1);
+    assert!(processed.contains_key("small.txt"));
+    assert_eq!(filter_manager.get_omitted_files(), &[
+        ("Cargo.lock".to_string(), OmitReason::Excluded),
+        ("big.txt".to_string(), OmitReason::OverSize),
+    ]);
+    assert_eq!(
+        filter_manager.get_omitted_summary().unwrap(),
+        "(2 files omitted: 1 excluded, 1 over size)"
+    );
+}
+
+#[test]
+fn test_required_unified_context() {
+    let filters = vec![
+        FilterRule {
+            file_pattern: "*.cs".to_string(),
+            context_lines: 10,
+            ..Default::default()
+        },
+        FilterRule {
+            file_pattern: "*".to_string(),
+            context_lines: 2,
+            ..Default::default()
+        },
+    ];
+    let filter_manager = FilterManager::new(&filters);
+    
+    // The largest configured context plus the buffer
+    assert_eq!(filter_manager.required_unified_context(), 10 + UNIFIED_CONTEXT_BUFFER);
+    
+    // Method-aware C# rules need the whole file
+    let filters = vec![FilterRule {
+        file_pattern: "*.cs".to_string(),
+        include_method_body: true,
+        ..Default::default()
+    }];
+    let filter_manager = FilterManager::new(&filters);
+    assert_eq!(filter_manager.required_unified_context(), FULL_FILE_CONTEXT);
 }
 
 // Helper function to create a test hunk
@@ -643,5 +1861,78 @@ fn create_test_hunk() -> Hunk {
         rename_from: None,
         rename_to: None,
         similarity_index: None,
+        line_numbers: Vec::new(),
+        annotation: None,
+    }
+}
+
+#[test]
+fn test_annotate_hunks() {
+    let config = Config {
+        filters: vec![FilterRule { context_lines: 1, ..Default::default() }],
+        annotate_hunks: true,
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    let mut csharp_hunk = create_test_hunk();
+    csharp_hunk.lines = vec![
+        " public class Orders {".to_string(),
+        "     public void Save() {".to_string(),
+        "-        store.Write();".to_string(),
+        "+        store.Validate();".to_string(),
+        "+        store.Write(true);".to_string(),
+        "+        store.Flush();".to_string(),
+        "     }".to_string(),
+        " }".to_string(),
+    ];
+    let mut text_hunk = create_test_hunk();
+    text_hunk.lines = vec![" a".to_string(), "-b".to_string(), "-c".to_string(), "+d".to_string()];
+    let patch_dict = HashMap::from([
+        ("Orders.cs".to_string(), vec![csharp_hunk]),
+        ("notes.txt".to_string(), vec![text_hunk]),
+    ]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    
+    // The annotation is kept apart from the hunk's lines
+    assert_eq!(processed["Orders.cs"][0].annotation.as_deref(), Some("3 additions, 1 deletion in method Save"));
+    assert_eq!(processed["Orders.cs"][0].lines[0], "     public void Save() {");
+    // Files that aren't C# only get the counts
+    assert_eq!(processed["notes.txt"][0].annotation.as_deref(), Some("1 addition, 2 deletions"));
+    assert_eq!(processed["notes.txt"][0].lines, vec![" a", "-b", "-c", "+d"]);
+}
+
+#[test]
+fn test_split_hunks_by_symbol_uses_source_line_numbers() {
+    let config = Config {
+        filters: vec![FilterRule { context_lines: 10, ..Default::default() }],
+        ignore_line_patterns: vec![r"Log\(\);".to_string()],
+        ..Default::default()
+    };
+    let mut filter_manager = FilterManager::from_config(&config).unwrap();
+    
+    // The ignored Log() call in Save becomes context identical to the one in Load
+    let mut hunk = create_test_hunk();
+    hunk.lines = raw_to_lines(r#"
+public class Orders {
+    public void Save() {
++        Log();
+-        store.Write();
++        store.Write(true);
+    }
+    public void Load() {
+        Log();
+-        store.Read();
++        store.Read(true);
     }
-} 
\ No newline at end of file
+}"#);
+    let patch_dict = HashMap::from([("Orders.cs".to_string(), vec![hunk])]);
+    let processed = filter_manager.post_process_files(&patch_dict);
+    let pieces = filter_manager.split_hunks_by_symbol("Orders.cs", &patch_dict["Orders.cs"], &processed["Orders.cs"]);
+    
+    let symbol_of = |line: &str| pieces.iter()
+        .find(|(_, piece)| piece.lines.iter().any(|l| l == line))
+        .and_then(|(symbol, _)| symbol.clone());
+    assert_eq!(symbol_of("+        store.Write(true);").as_deref(), Some("Save"));
+    assert_eq!(symbol_of("+        store.Read(true);").as_deref(), Some("Load"));
+}
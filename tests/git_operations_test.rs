@@ -1,4 +1,4 @@
-use repodiff::utils::git_operations::GitOperations;
+use repodiff::utils::git_operations::{GitOperations, WhitespaceError, EMPTY_TREE_HASH};
 use std::fs;
 use std::process::Command;
 use tempfile::tempdir;
@@ -47,6 +47,35 @@ fn setup_test_repo() -> tempfile::TempDir {
     temp_dir
 }
 
+// Helper function to write a file, commit it and return the new commit hash
+fn commit_file(repo_path: &std::path::Path, file_name: &str, content: &str, message: &str) -> String {
+    let file_path = repo_path.join(file_name);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent).expect("Failed to create directory");
+    }
+    fs::write(&file_path, content).expect("Failed to write file");
+    
+    Command::new("git")
+        .args(["add", file_name])
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to add file");
+    
+    Command::new("git")
+        .args(["commit", "-m", message])
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to commit");
+    
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to get commit hash");
+    
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
 #[test]
 #[ignore] // Ignore by default as it requires git to be installed
 fn test_run_git_diff() {
@@ -87,6 +116,13 @@ fn test_run_git_diff() {
     
     let commit2 = String::from_utf8_lossy(&output.stdout).trim().to_string();
     
+    // The prefixes stay a/ and b/ whatever the user's git config says
+    Command::new("git")
+        .args(["config", "diff.noPrefix", "true"])
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to configure diff.noPrefix");
+    
     // Test the run_git_diff function
     let git_operations = GitOperations::new();
     
@@ -100,7 +136,7 @@ fn test_run_git_diff() {
     std::env::set_current_dir(current_dir).unwrap();
     
     // The diff should contain the file name and the content change
-    assert!(diff.contains("file1.txt"));
+    assert!(diff.contains("diff --git a/file1.txt b/file1.txt"));
     assert!(diff.contains("-Initial content"));
     assert!(diff.contains("+Modified content"));
 }
@@ -266,4 +302,345 @@ fn test_get_previous_commit() {
     
     // The previous commit should be the initial commit
     assert_eq!(previous_commit, initial_commit);
-} 
\ No newline at end of file
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_git_version() {
+    let git_operations = GitOperations::new();
+    
+    let version = git_operations.git_version().unwrap();
+    
+    assert!(version.starts_with("git version"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_run_git_diff_stat() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    
+    let commit1 = commit_file(repo_path, "file1.txt", "Initial content", "No-op commit");
+    let commit2 = commit_file(repo_path, "file1.txt", "Modified content\nSecond line\n", "Second commit");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let stat = git_operations.run_git_diff_stat(&commit1, Some(&commit2)).unwrap();
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    assert!(stat.contains("file1.txt"));
+    assert!(stat.contains("1 file changed"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_list_dir_files() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    
+    commit_file(repo_path, "src/lib.rs", "pub mod a;", "Add lib");
+    commit_file(repo_path, "src/main.rs", "fn main() {}", "Add main");
+    let commit = commit_file(repo_path, "src/nested/mod.rs", "", "Add nested module");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let src_files = git_operations.list_dir_files(&commit, "src").unwrap();
+    let root_files = git_operations.list_dir_files(&commit, "").unwrap();
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    // Subdirectories are not listed, only the files directly inside
+    assert_eq!(src_files, vec!["lib.rs", "main.rs"]);
+    assert_eq!(root_files, vec!["file1.txt"]);
+}
+
+#[test]
+fn test_is_transient_failure() {
+    // Errors that will fail the same way again are not retried
+    assert!(!GitOperations::is_transient_failure(
+        "fatal: ambiguous argument 'nope': unknown revision or path not in the working tree."
+    ));
+    assert!(!GitOperations::is_transient_failure("fatal: bad revision 'nope'"));
+    assert!(!GitOperations::is_transient_failure("fatal: Not a git repository (or any of the parent directories): .git"));
+    assert!(!GitOperations::is_transient_failure("fatal: Not a valid object name nope"));
+    assert!(!GitOperations::is_transient_failure("fatal: No names found, cannot describe anything."));
+    assert!(!GitOperations::is_transient_failure("error: pathspec 'nope' did not match any file(s) known to git"));
+    assert!(!GitOperations::is_transient_failure("fatal: nope...HEAD: no merge base"));
+    assert!(!GitOperations::is_transient_failure(""));
+    
+    // Only failures known to come and go are retried
+    assert!(GitOperations::is_transient_failure("fatal: unable to read tree 1234567: Input/output error"));
+    assert!(GitOperations::is_transient_failure("error: unable to open object pack: Stale file handle"));
+    assert!(GitOperations::is_transient_failure(
+        "fatal: Unable to create '/repo/.git/index.lock': File exists."
+    ));
+    assert!(GitOperations::is_transient_failure("error: could not lock config file .git/config: File exists"));
+    assert!(GitOperations::is_transient_failure("fatal: cannot fork: Resource temporarily unavailable"));
+    assert!(GitOperations::is_transient_failure("fatal: read error: Connection reset by peer"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_unknown_revision_fails_without_retrying() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    
+    let git_operations = GitOperations::with_retry_attempts(5);
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = git_operations.get_previous_commit("does-not-exist");
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    assert!(result.is_err());
+    assert_eq!(git_operations.get_retry_count(), 0);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_files_changed_by_author() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    
+    let base = commit_file(repo_path, "file1.txt", "Initial content", "No-op commit");
+    commit_file(repo_path, "alice.txt", "Alice's change", "Alice's commit");
+    commit_file(repo_path, "shared.txt", "Alice's change", "Alice's second commit");
+    
+    // Switch author for the next commits
+    Command::new("git")
+        .args(["config", "user.name", "Bob"])
+        .current_dir(repo_path)
+        .output()
+        .expect("Failed to configure git user name");
+    commit_file(repo_path, "bob.txt", "Bob's change", "Bob's commit");
+    let head = commit_file(repo_path, "shared.txt", "Bob's change", "Bob's second commit");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let range = format!("{}..{}", base, head);
+    let bob_files = git_operations.files_changed_by_author(&range, "Bob").unwrap();
+    let test_user_files = git_operations.files_changed_by_author(&range, "Test User").unwrap();
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    assert_eq!(bob_files, vec!["bob.txt", "shared.txt"]);
+    assert_eq!(test_user_files, vec!["alice.txt", "shared.txt"]);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_get_latest_tag() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    
+    let tag = |name: &str| {
+        Command::new("git")
+            .args(["tag", name])
+            .current_dir(repo_path)
+            .output()
+            .expect("Failed to create tag");
+    };
+    tag("v1.0");
+    commit_file(repo_path, "file2.txt", "content", "Second commit");
+    tag("nightly-1");
+    commit_file(repo_path, "file3.txt", "content", "Third commit");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let latest = git_operations.get_latest_tag(None).unwrap();
+    let latest_release = git_operations.get_latest_tag(Some("v*")).unwrap();
+    let missing = git_operations.get_latest_tag(Some("release-*"));
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    assert_eq!(latest, "nightly-1");
+    assert_eq!(latest_release, "v1.0");
+    assert!(missing.is_err());
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_list_untracked_files() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    fs::create_dir_all(repo_path.join("dir")).unwrap();
+    fs::write(repo_path.join("dir/new file.txt"), "new").unwrap();
+    fs::write(repo_path.join("file1.txt"), "modified").unwrap();
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let untracked = git_operations.list_untracked_files().unwrap();
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    // Modified tracked files aren't listed, and paths with spaces aren't quoted
+    assert_eq!(untracked, vec!["dir/new file.txt"]);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_blame_lines() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    let first = commit_file(repo_path, "notes.txt", "one\ntwo\nthree\n", "Add notes");
+    let second = commit_file(repo_path, "notes.txt", "one\n2\nthree\n", "Edit notes");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let blame = git_operations.blame_lines("HEAD", "notes.txt", &[(1, 2)]);
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    let blame = blame.unwrap();
+    assert_eq!(blame.len(), 2);
+    assert_eq!((blame[0].line, blame[0].commit.as_str()), (1, &first[..7]));
+    assert_eq!((blame[1].line, blame[1].commit.as_str()), (2, &second[..7]));
+    assert!(blame.iter().all(|line| line.author == "Test User"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_list_commits() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    let first = commit_file(repo_path, "a.txt", "a\n", "Add a");
+    let second = commit_file(repo_path, "b.txt", "b\n", "Add b");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let commits = git_operations.list_commits("HEAD~2..HEAD");
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    // Oldest first, and the start of the range is left out
+    let commits = commits.unwrap();
+    let listed: Vec<(&str, &str)> = commits.iter().map(|c| (c.hash.as_str(), c.subject.as_str())).collect();
+    assert_eq!(listed, vec![(first.as_str(), "Add a"), (second.as_str(), "Add b")]);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_list_commits_from_root_with_unusual_subjects() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    let second = commit_file(repo_path, "a.txt", "a\n", "commit 0123456789abcdef looks like a header");
+    let third = commit_file(repo_path, "b.txt", "b\n", "Tabs\tand  spaces");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let commits = git_operations.list_commits("HEAD");
+    let root_parent = commits.as_ref().ok().and_then(|commits| commits.first())
+        .map(|root| git_operations.get_previous_commit(&root.hash));
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    // Subjects are taken whole, even when they look like git's own headers
+    let commits = commits.unwrap();
+    let listed: Vec<&str> = commits.iter().map(|c| c.subject.as_str()).collect();
+    assert_eq!(listed, vec!["Initial commit", "commit 0123456789abcdef looks like a header", "Tabs\tand  spaces"]);
+    assert_eq!(commits[1].hash, second);
+    assert_eq!(commits[2].hash, third);
+    
+    // The root commit has no parent, so it's compared with the empty tree
+    assert_eq!(root_parent.unwrap().unwrap(), EMPTY_TREE_HASH);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_check_whitespace_errors() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    let first = commit_file(repo_path, "notes.txt", "one\n", "Add notes");
+    let second = commit_file(repo_path, "notes.txt", "one\ntwo  \n", "Add trailing whitespace");
+    
+    let git_operations = GitOperations::with_retry_attempts(5);
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let errors = git_operations.check_whitespace_errors(&first, Some(&second));
+    let clean = git_operations.check_whitespace_errors("HEAD~2", Some(&first));
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    assert_eq!(errors.unwrap(), vec![WhitespaceError {
+        path: "notes.txt".to_string(),
+        line: 2,
+        message: "trailing whitespace.".to_string(),
+    }]);
+    // Git's exit code for found errors isn't mistaken for a failure worth retrying
+    assert_eq!(git_operations.get_retry_count(), 0);
+    assert!(clean.unwrap().is_empty());
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_count_changed_lines() {
+    let temp_dir = setup_test_repo();
+    let repo_path = temp_dir.path();
+    let first = commit_file(repo_path, "app.py", "def run():\n  return 1\n", "Add app");
+    let second = commit_file(repo_path, "app.py", "def run():\n    return 1\n", "Reindent app");
+    
+    let git_operations = GitOperations::new();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let all = git_operations.count_changed_lines(&first, Some(&second), false);
+    let ignoring_whitespace = git_operations.count_changed_lines(&first, Some(&second), true);
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    // The reindented line is one removed and one added line, which disappear when whitespace is ignored
+    assert_eq!(all.unwrap().get("app.py"), Some(&2));
+    assert_eq!(ignoring_whitespace.unwrap().get("app.py").copied().unwrap_or_default(), 0);
+}
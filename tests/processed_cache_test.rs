@@ -0,0 +1,36 @@
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::Duration;
+use repodiff::utils::processed_cache::{ProcessedFiles, ProcessedFilesCache};
+use tempfile::tempdir;
+
+fn processed(path: &str) -> ProcessedFiles {
+    ProcessedFiles {
+        files: HashMap::from([(path.to_string(), Vec::new())]),
+        omitted_files: Vec::new(),
+        opaque_files: HashSet::new(),
+        warnings: Vec::new(),
+    }
+}
+
+#[test]
+fn test_store_removes_least_recently_used_entries() {
+    let temp_dir = tempdir().unwrap();
+    let cache = ProcessedFilesCache::with_max_entries(temp_dir.path().to_str().unwrap(), 2);
+    
+    // Modification times must differ for the order of use to be seen
+    cache.store("first", &processed("a.txt")).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    cache.store("second", &processed("b.txt")).unwrap();
+    thread::sleep(Duration::from_millis(20));
+    
+    // Loading the first entry makes the second the least recently used
+    assert!(cache.load("first").unwrap().files.contains_key("a.txt"));
+    thread::sleep(Duration::from_millis(20));
+    cache.store("third", &processed("c.txt")).unwrap();
+    
+    assert!(cache.load("first").is_some());
+    assert!(cache.load("second").is_none());
+    assert!(cache.load("third").is_some());
+    assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+}
@@ -0,0 +1,32 @@
+use repodiff::filters::python_parser::PythonParser;
+use repodiff::utils::diff_parser::DiffSide;
+
+#[test]
+fn test_parse_file_side_finds_top_level_symbols() {
+    let code = r#"import os
+
+@decorator
+@other(arg=1)
+async def fetch(
+    url,
+):
+    return url
+
+# a comment at the top level
+class Client:
+    def nested(self):
+        pass
+
+
+CONSTANT = 1
+def_not_a_symbol = 2
+"#;
+    let symbols = PythonParser::parse_file_side(code, &[], DiffSide::New);
+    
+    // Decorators start a symbol, while comments and closing brackets don't end one
+    let found: Vec<(&str, usize, usize)> = symbols.iter()
+        .map(|s| (s.name.as_str(), s.start_line, s.end_line))
+        .collect();
+    assert_eq!(found, vec![("fetch", 3, 8), ("Client", 11, 13)]);
+    assert!(symbols.iter().all(|s| !s.has_changes));
+}
@@ -0,0 +1,32 @@
+use repodiff::filters::razor_parser::RazorParser;
+
+#[test]
+fn test_parse_file_finds_code_blocks() {
+    let code = r#"@page "/counter"
+<h1>Counter</h1>
+@{
+    var title = "Braces in strings } are ignored";
+}
+<p>@title</p>
+@functions {
+    void Reset() { count = 0; } // a stray } in a comment
+}
+<div>@{ var inline = 1; }</div>
+@codeBehind is not a block
+"#;
+    let file = RazorParser::parse_file(code, &[]);
+    
+    let blocks: Vec<(&str, usize, usize)> = file.methods.iter()
+        .map(|m| (m.name.as_str(), m.start_line, m.end_line))
+        .collect();
+    assert_eq!(blocks, vec![("@{", 3, 5), ("@functions", 7, 9), ("@{", 10, 10)]);
+    assert!(file.methods.iter().all(|m| !m.has_changes));
+}
+
+#[test]
+fn test_parse_file_ignores_unterminated_block() {
+    let code = "@code {\n    private int count;\n";
+    let file = RazorParser::parse_file(code, &[]);
+    
+    assert!(file.methods.is_empty());
+}
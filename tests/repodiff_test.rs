@@ -0,0 +1,2348 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::tempdir;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+// Import the module to test
+use repodiff::repodiff::RepoDiff;
+use repodiff::utils::diff_parser::OutputFormat;
+use repodiff::utils::config_manager::{Config, DirectoryConfigs, MethodDetail};
+use repodiff::utils::diff_result::ChangeScope;
+
+const SAMPLE_DIFF: &str = "diff --git a/file1.txt b/file1.txt
+--- a/file1.txt
++++ b/file1.txt
+@@ -1,10 +1,10 @@
+ line1
+ line2
+ line3
+ line4
+ line5
+-line6
++line6_modified
+ line7
+ line8
+ line9
+ line10";
+
+// Helper function to create a RepoDiff from a temporary config file
+fn create_repodiff(temp_dir: &tempfile::TempDir) -> RepoDiff {
+    let config_path = temp_dir.path().join("config.json");
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [{"file_pattern": "*", "context_lines": 1}]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    RepoDiff::new(config_path.to_str().unwrap()).unwrap()
+}
+
+#[test]
+fn test_process_raw_diff_applies_filters() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    let output = repodiff.process_raw_diff(SAMPLE_DIFF).unwrap();
+    
+    // Context is trimmed to one line and the preamble is included
+    assert!(output.contains("This file provides a guide to understanding the diff output generated by RepoDiff"));
+    assert!(output.contains(" line5\n-line6\n+line6_modified\n line7"));
+    assert!(!output.contains(" line4"));
+}
+
+#[test]
+fn test_process_raw_diff_in_raw_mode_keeps_all_lines() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_raw(true);
+    
+    let output = repodiff.process_raw_diff(SAMPLE_DIFF).unwrap();
+    
+    // No preamble, and every original hunk line is present
+    assert!(output.starts_with("diff --git a/file1.txt b/file1.txt"));
+    assert!(!output.contains("RepoDiff"));
+    for line in SAMPLE_DIFF.lines().skip(4) {
+        assert!(output.lines().any(|l| l == line), "Missing line: {}", line);
+    }
+}
+
+#[test]
+fn test_process_raw_diff_warns_on_truncated_output() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    repodiff.process_raw_diff(SAMPLE_DIFF).unwrap();
+    assert!(repodiff.get_warnings().is_empty());
+    
+    // Drop the last few lines as if git had been cut off
+    let truncated: Vec<&str> = SAMPLE_DIFF.lines().take(10).collect();
+    repodiff.process_raw_diff(&truncated.join("\n")).unwrap();
+    assert_eq!(repodiff.get_warnings().len(), 1);
+}
+
+#[test]
+fn test_process_raw_diff_warns_on_reformatting() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    // Twelve lines reindented and one real change
+    let mut diff = String::from("diff --git a/src/app.py b/src/app.py\n--- a/src/app.py\n+++ b/src/app.py\n@@ -1,13 +1,13 @@\n");
+    diff.extend((0..12).map(|i| format!("-  value_{} = {}\n", i, i)));
+    diff.extend((0..12).map(|i| format!("+    value_{} = {}\n", i, i)));
+    diff.push_str("-total = 0\n+total = 1");
+    
+    repodiff.process_raw_diff(&diff).unwrap();
+    assert_eq!(repodiff.get_warnings(), vec![
+        "This diff appears to be mostly reformatting: 24 of 26 changed lines only change whitespace".to_string(),
+    ]);
+    
+    // A diff with real changes isn't flagged
+    let edited = diff.replace("+    value_", "+    renamed_");
+    repodiff.process_raw_diff(&edited).unwrap();
+    assert!(repodiff.get_warnings().is_empty());
+}
+
+#[test]
+fn test_process_raw_diff_with_only_pattern() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_only_pattern(Some("src/**/*.sql".to_string()));
+    
+    let diff = ["src/migrations/001_init.sql", "src/app.rs", "scripts/seed.sql"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    assert!(output.contains("diff --git a/src/migrations/001_init.sql b/src/migrations/001_init.sql"));
+    assert!(!output.contains("src/app.rs"));
+    assert!(!output.contains("scripts/seed.sql"));
+}
+
+#[test]
+fn test_process_raw_diff_with_only_language() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_only_language(Some("csharp".to_string()));
+    
+    let diff = ["src/Orders.cs", "src/Views/Index.cshtml", "src/app.js", "README.md"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    assert!(output.contains("diff --git a/src/Orders.cs b/src/Orders.cs"));
+    assert!(!output.contains("Index.cshtml"));
+    assert!(!output.contains("src/app.js"));
+    assert!(!output.contains("README.md"));
+}
+
+#[test]
+fn test_process_raw_diff_with_only_language_in_dotted_directories() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_only_language(Some("csharp".to_string()));
+    
+    // Only the file name's extension counts, not dots in the directories above it
+    let diff = ["src.v2/Orders.cs", "tools.cs/Makefile", "dir.v2/Makefile"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    assert!(output.contains("diff --git a/src.v2/Orders.cs b/src.v2/Orders.cs"));
+    assert!(!output.contains("Makefile"));
+}
+
+#[test]
+fn test_token_budget_accounts_for_reserved_tokens() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    let diff = ["a.txt", "b.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    
+    // Measure the full output to pick a limit that just fits both files
+    let output_file = temp_dir.path().join("output.txt");
+    let max_tokens = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    
+    repodiff.set_max_tokens(Some(max_tokens));
+    let token_count = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    assert!(output.contains("b.txt"));
+    assert!(repodiff.get_omitted_summary().is_none());
+    assert!(token_count <= max_tokens);
+    
+    // Reserving tokens shrinks the budget, so the second file no longer fits
+    repodiff.set_reserved_tokens(25);
+    assert_eq!(repodiff.get_effective_budget(), Some(max_tokens - 25));
+    let token_count = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    assert!(output.contains("a.txt"));
+    assert!(!output.contains("b.txt"));
+    assert_eq!(repodiff.get_omitted_summary().unwrap(), "(1 file omitted: 1 over budget)");
+    assert!(token_count <= max_tokens - 25);
+    
+    // Reserving the whole maximum would leave no budget for any file
+    repodiff.set_reserved_tokens(max_tokens);
+    let error = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap_err();
+    assert!(error.to_string().contains("reserved_tokens"));
+}
+
+#[test]
+fn test_token_budget_counts_separators_headings_and_labels() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let config: Config = serde_json::from_value(json!({
+        "tiktoken_model": "gpt-4o",
+        "file_separator": "// ----- next file -----",
+        "filters": [{"file_pattern": "*", "context_lines": 1}]
+    })).unwrap();
+    let mut repodiff = RepoDiff::from_config(&config).unwrap();
+    repodiff.output_options_mut().group_by_dir = true;
+    repodiff.output_options_mut().label_changes = true;
+    repodiff.output_options_mut().file_ids = true;
+    
+    let diff = ["src/a.txt", "src/b.txt", "tests/c.txt", "tests/d.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let full = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    
+    // Whatever the budget, the output never goes over it
+    for budget in (full / 2..=full).step_by(7).chain([full]) {
+        repodiff.set_max_tokens(Some(budget));
+        let token_count = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+        assert!(token_count <= budget, "{} tokens for a budget of {}", token_count, budget);
+    }
+    assert!(repodiff.get_omitted_summary().is_none());
+}
+
+#[test]
+fn test_max_output_bytes_truncates_at_file_boundary() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    let diff = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    
+    // Measure the full output to pick a limit that leaves out the last file
+    let output_file = temp_dir.path().join("output.txt");
+    repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let max_bytes = fs::read_to_string(&output_file).unwrap().len() - 10;
+    
+    repodiff.set_max_output_bytes(Some(max_bytes));
+    repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    assert!(output.len() <= max_bytes);
+    assert!(output.contains("+line6_modified"));
+    assert!(!output.contains("c.txt"));
+    assert!(output.ends_with(&format!("[Output truncated at {} bytes: 1 more file omitted]", max_bytes)));
+    assert_eq!(repodiff.get_omitted_summary().unwrap(), "(1 file omitted: 1 over output limit)");
+}
+
+#[test]
+fn test_max_output_bytes_counts_the_bytes_written() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let config: Config = serde_json::from_value(json!({
+        "tiktoken_model": "gpt-4o",
+        "output_line_ending": "crlf",
+        "filters": [{"file_pattern": "*", "context_lines": 1}]
+    })).unwrap();
+    let mut repodiff = RepoDiff::from_config(&config).unwrap();
+    repodiff.set_trailing_index(true);
+    
+    // CRLF line endings add a byte per line, which the limit must allow for
+    let diff = ["a.txt", "b.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let full_bytes = fs::read(&output_file).unwrap().len();
+    for max_bytes in [full_bytes - 1, full_bytes / 2, 40, 10] {
+        repodiff.set_max_output_bytes(Some(max_bytes));
+        repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+        let output = fs::read(&output_file).unwrap();
+        assert!(output.len() <= max_bytes, "{} bytes written for a limit of {}", output.len(), max_bytes);
+        assert!(!String::from_utf8(output).unwrap().replace("\r\n", "").contains('\n'));
+    }
+    
+    // The limit is on one output file, so it can't be combined with chunks
+    repodiff.set_chunk_tokens(Some(1000));
+    assert!(repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).is_err());
+}
+
+// Hands out a diff one line per read, noting what the output file holds whenever a file's header is read
+struct ObservingReader {
+    lines: Vec<String>,
+    next: usize,
+    offset: usize,
+    output_file: PathBuf,
+    output_at_headers: Vec<String>,
+}
+
+impl Read for ObservingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(line) = self.lines.get(self.next) else {
+            return Ok(0);
+        };
+        if self.offset == 0 && line.starts_with("diff --git ") {
+            self.output_at_headers.push(fs::read_to_string(&self.output_file).unwrap_or_default());
+        }
+        let rest = &line.as_bytes()[self.offset..];
+        let len = rest.len().min(buf.len());
+        buf[..len].copy_from_slice(&rest[..len]);
+        self.offset += len;
+        if self.offset == line.len() {
+            self.next += 1;
+            self.offset = 0;
+        }
+        Ok(len)
+    }
+}
+
+impl Seek for ObservingReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        assert_eq!(pos, SeekFrom::Start(0), "only rewinding is supported");
+        self.next = 0;
+        self.offset = 0;
+        Ok(0)
+    }
+}
+
+#[test]
+fn test_process_diff_reader_writes_each_file_before_parsing_the_next() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    let diff = ["a.txt", "b.txt", "c.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut reader = BufReader::new(ObservingReader {
+        lines: diff.lines().map(|line| format!("{}\n", line)).collect(),
+        next: 0,
+        offset: 0,
+        output_file: output_file.clone(),
+        output_at_headers: Vec::new(),
+    });
+    repodiff.process_diff_reader(&mut reader, output_file.to_str().unwrap()).unwrap();
+    
+    // A file is complete once the next file's header is parsed, and is written right away
+    let output_at_headers = &reader.get_ref().output_at_headers;
+    assert_eq!(output_at_headers.len(), 3);
+    assert!(!output_at_headers[1].contains("a.txt"));
+    assert!(output_at_headers[2].contains("diff --git a/a.txt b/a.txt"));
+    assert!(output_at_headers[2].contains("+line6_modified"));
+    assert!(!output_at_headers[2].contains("b.txt"));
+    assert!(fs::read_to_string(&output_file).unwrap().contains("diff --git a/c.txt b/c.txt"));
+}
+
+#[test]
+fn test_process_diff_reader_matches_process_diff_text() {
+    let temp_dir = tempdir().unwrap();
+    let config: Config = serde_json::from_value(json!({
+        "tiktoken_model": "gpt-4o",
+        "file_separator": "// ----- next file -----",
+        "filters": [{"file_pattern": "*", "context_lines": 1}, {"file_pattern": "!*.lock"}]
+    })).unwrap();
+    let diff = [
+        SAMPLE_DIFF.replace("file1.txt", "docs/guide.txt"),
+        "diff --git a/img/logo.png b/img/logo.png\nBinary files a/img/logo.png and b/img/logo.png differ".to_string(),
+        SAMPLE_DIFF.replace("file1.txt", "src/app.txt"),
+        SAMPLE_DIFF.replace("file1.txt", "src/deps.lock"),
+        SAMPLE_DIFF.replace("file1.txt", "src/main.txt"),
+    ].join("\n");
+    
+    let mut outputs = Vec::new();
+    for streamed in [false, true] {
+        let mut repodiff = RepoDiff::from_config(&config).unwrap();
+        repodiff.output_options_mut().group_by_dir = true;
+        repodiff.output_options_mut().label_changes = true;
+        let output_file = temp_dir.path().join(format!("output-{}.txt", streamed));
+        let output_file = output_file.to_str().unwrap();
+        if streamed {
+            repodiff.process_diff_reader(Cursor::new(diff.as_bytes()), output_file).unwrap();
+        } else {
+            repodiff.process_diff_text(&diff, output_file).unwrap();
+        }
+        outputs.push((
+            fs::read_to_string(output_file).unwrap(),
+            repodiff.get_output_hash().to_string(),
+            repodiff.get_omitted_summary(),
+            serde_json::to_value(repodiff.get_diff_result().files).unwrap(),
+        ));
+    }
+    
+    assert_eq!(outputs[0], outputs[1]);
+    assert!(outputs[1].0.contains("[Binary]"));
+    assert!(outputs[1].0.contains("# Directory: src/"));
+    assert!(!outputs[1].0.contains("deps.lock"));
+    assert_eq!(outputs[1].3.as_array().unwrap().len(), 4);
+}
+
+#[test]
+fn test_cache_skips_filters_for_identical_diff_and_config() {
+    let temp_dir = tempdir().unwrap();
+    let cache_dir = temp_dir.path().join("cache");
+    let diff = [
+        SAMPLE_DIFF.replace("file1.txt", "src/app.txt"),
+        SAMPLE_DIFF.replace("file1.txt", "src/deps.lock"),
+        SAMPLE_DIFF.replace("file1.txt", "src/main.txt"),
+    ].join("\n");
+    let run = |context_lines: usize, cache: bool| {
+        let config: Config = serde_json::from_value(json!({
+            "tiktoken_model": "gpt-4o",
+            "filters": [{"file_pattern": "*", "context_lines": context_lines}, {"file_pattern": "!*.lock"}]
+        })).unwrap();
+        let mut repodiff = RepoDiff::from_config(&config).unwrap();
+        if cache {
+            repodiff.set_cache_dir(Some(cache_dir.to_string_lossy().to_string()));
+        }
+        let output_file = temp_dir.path().join("output.txt");
+        repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+        (
+            fs::read_to_string(&output_file).unwrap(),
+            repodiff.get_output_hash().to_string(),
+            repodiff.get_omitted_summary(),
+            repodiff.get_filtered_file_count(),
+        )
+    };
+
+    let uncached = run(1, false);
+    let first = run(1, true);
+    let second = run(1, true);
+    assert_eq!(first.3, 3);
+    assert_eq!(second.3, 0);
+    assert_eq!(first.0, uncached.0);
+    assert_eq!(second.0, first.0);
+    assert_eq!(second.1, first.1);
+    assert_eq!(second.2, first.2);
+    assert!(second.2.is_some());
+
+    // A different config doesn't reuse the entry
+    let changed = run(2, true);
+    assert_eq!(changed.3, 3);
+    assert_ne!(changed.0, first.0);
+}
+
+#[test]
+fn test_process_diff_reader_writes_unsorted_diff_in_path_order() {
+    let temp_dir = tempdir().unwrap();
+    let diff = ["src/b.txt", "a.txt", "src/a.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    
+    // A byte limit too large to cut anything only turns streaming off
+    let mut outputs = Vec::new();
+    for max_output_bytes in [None, Some(100_000_000)] {
+        let mut repodiff = create_repodiff(&temp_dir);
+        repodiff.output_options_mut().group_by_dir = true;
+        repodiff.set_max_output_bytes(max_output_bytes);
+        let output_file = temp_dir.path().join("output.txt");
+        let output_file = output_file.to_str().unwrap();
+        let token_count = repodiff.process_diff_reader(Cursor::new(diff.as_bytes()), output_file).unwrap();
+        outputs.push((fs::read_to_string(output_file).unwrap(), repodiff.get_output_hash().to_string(), token_count));
+    }
+    
+    assert_eq!(outputs[0], outputs[1]);
+    let output = &outputs[0].0;
+    let positions: Vec<usize> = ["a/a.txt", "a/src/a.txt", "a/src/b.txt"]
+        .iter()
+        .map(|path| output.find(&format!("diff --git {}", path)).unwrap())
+        .collect();
+    assert!(positions.is_sorted());
+}
+
+#[test]
+fn test_process_diff_text_in_chunks() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().include_instructions = false;
+    
+    let paths = ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"];
+    let diff = paths.iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    
+    // Pick a limit that fits two files per part
+    let total = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let chunk_tokens = total * 2 / 5 + 2;
+    repodiff.set_chunk_tokens(Some(chunk_tokens));
+    repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    
+    let chunks = repodiff.get_chunks();
+    assert_eq!(chunks.len(), 3);
+    assert!(chunks[0].path.ends_with("output.part1.txt"));
+    assert!(chunks[2].path.ends_with("output.part3.txt"));
+    
+    // Each part is under the limit and holds whole files, with every file in exactly one part
+    let mut seen = Vec::new();
+    for chunk in chunks {
+        let content = fs::read_to_string(&chunk.path).unwrap();
+        assert!(chunk.tokens <= chunk_tokens);
+        for file in &chunk.files {
+            assert!(content.contains(&format!("diff --git a/{} b/{}\n--- a/{}", file, file, file)));
+            assert!(content.contains(" line5\n-line6\n+line6_modified\n line7"));
+        }
+        assert_eq!(content.matches("diff --git").count(), chunk.files.len());
+        seen.extend(chunk.files.iter().cloned());
+    }
+    assert_eq!(seen, paths);
+}
+
+#[test]
+fn test_default_output_file_for_branch() {
+    let output_file = RepoDiff::get_default_output_file_for_branch("feature/x");
+    
+    // The branch is part of the file name, with path separators sanitized
+    assert!(output_file.ends_with("repodiff_vs_feature-x.txt"));
+    assert_eq!(
+        std::path::Path::new(&output_file).parent(),
+        std::path::Path::new(&RepoDiff::get_default_output_file()).parent()
+    );
+}
+
+#[test]
+fn test_diff_result_summarizes_files() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let summary_file = temp_dir.path().join("summary.json");
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    let diff = format!("{}
+diff --git a/new.txt b/new.txt
+new file mode 100644
+--- /dev/null
++++ b/new.txt
+@@ -0,0 +1,2 @@
++added1
++added2
+diff --git a/old.txt b/old.txt
+deleted file mode 100644
+--- a/old.txt
++++ /dev/null
+@@ -1 +0,0 @@
+-removed", SAMPLE_DIFF);
+    let total = repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    repodiff.get_diff_result().write_json(summary_file.to_str().unwrap()).unwrap();
+    
+    let summary: serde_json::Value = serde_json::from_str(&fs::read_to_string(&summary_file).unwrap()).unwrap();
+    assert_eq!(summary["model"], "gpt-4o");
+    assert_eq!(summary["total_tokens"], total);
+    assert_eq!(summary["content_tokens"], repodiff.get_content_token_count());
+    assert_eq!(summary["files_changed"], 3);
+    assert_eq!(summary["files_added"], 1);
+    assert_eq!(summary["files_deleted"], 1);
+    assert_eq!(summary["files_renamed"], 0);
+    assert_eq!(summary["files_modified"], 1);
+    
+    // Files are listed in output order with their own token counts
+    let files = summary["files"].as_array().unwrap();
+    let paths: Vec<&str> = files.iter().map(|f| f["path"].as_str().unwrap()).collect();
+    assert_eq!(paths, vec!["file1.txt", "new.txt", "old.txt"]);
+    assert_eq!(files[1]["change_kind"], "added");
+    assert!(files.iter().all(|f| f["tokens"].as_u64().unwrap() > 0));
+    let file_tokens: u64 = files.iter().map(|f| f["tokens"].as_u64().unwrap()).sum();
+    assert!(file_tokens < total as u64);
+}
+
+#[test]
+fn test_diff_result_classifies_change_scope() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let config = Config {
+        test_file_pattern: Some("*Test*.cs".to_string()),
+        ..Default::default()
+    };
+    let mut repodiff = RepoDiff::from_config(&config).unwrap();
+    
+    let test_diff = |path: &str| format!("diff --git a/{0} b/{0}
+--- a/{0}
++++ b/{0}
+@@ -1 +1 @@
+-old
++new", path);
+    let tests_only = format!("{}\n{}", test_diff("tests/OrderTests.cs"), test_diff("tests/CartTest.cs"));
+    repodiff.process_diff_text(&tests_only, output_file.to_str().unwrap()).unwrap();
+    let result = repodiff.get_diff_result();
+    assert_eq!(result.change_scope, Some(ChangeScope::TestsOnly));
+    assert_eq!(result.change_scope.unwrap().to_string(), "tests only");
+    
+    let mixed = format!("{}\n{}", test_diff("tests/OrderTests.cs"), test_diff("src/Order.cs"));
+    repodiff.process_diff_text(&mixed, output_file.to_str().unwrap()).unwrap();
+    assert_eq!(repodiff.get_diff_result().change_scope, Some(ChangeScope::ProductionAndTests));
+    
+    // Without a test file pattern the scope is unknown rather than "production only"
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.process_diff_text(&tests_only, output_file.to_str().unwrap()).unwrap();
+    assert_eq!(repodiff.get_diff_result().change_scope, None);
+}
+
+#[test]
+fn test_process_diff_text_counts_content_tokens() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    let total = repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    let content = repodiff.get_content_token_count();
+    
+    // The preamble accounts for most of the output, but the diff itself is still counted
+    assert!(content > 0);
+    assert!(content < total);
+    
+    // Without a preamble the two counts agree
+    repodiff.set_raw(true);
+    let total = repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    assert_eq!(repodiff.get_content_token_count(), total);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_with_git_stat() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_git_stat(true);
+    
+    // Set up a repository with two commits
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("file1.txt"), "line1\nline2\n").unwrap();
+    git(&["add", "file1.txt"]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("file1.txt"), "line1\nline2_modified\n").unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // The stat histogram comes first, followed by the instructions preamble
+    assert!(output.starts_with(" file1.txt | 2 +-"));
+    assert!(output.contains("1 file changed, 1 insertion(+), 1 deletion(-)"));
+    assert!(output.find("1 file changed").unwrap() < output.find("This file provides a guide").unwrap());
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_for_single_file() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_git_stat(true);
+    repodiff.set_file_path(Some("src/Foo.cs".to_string()));
+    
+    // Set up a repository where two files change over several commits
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::create_dir_all(repo_path.join("src")).unwrap();
+    fs::write(repo_path.join("src/Foo.cs"), "one\n").unwrap();
+    fs::write(repo_path.join("src/Bar.cs"), "bar\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("src/Foo.cs"), "one\ntwo\n").unwrap();
+    fs::write(repo_path.join("src/Bar.cs"), "bar changed\n").unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    fs::write(repo_path.join("src/Foo.cs"), "one\ntwo\nthree\n").unwrap();
+    git(&["commit", "-am", "Third commit"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~2", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // Only the requested file appears, with its changes across the whole range
+    assert!(output.contains("diff --git a/src/Foo.cs b/src/Foo.cs"));
+    assert!(output.contains("+two\n+three"));
+    assert!(output.contains("1 file changed"));
+    assert!(!output.contains("Bar.cs"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_with_blame() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_blame(Some(100));
+    
+    // Set up a repository where lines written by two authors are replaced
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.email", "test@example.com"]);
+    git(&["config", "user.name", "Alice"]);
+    fs::write(repo_path.join("file1.txt"), "line1\nline2\nline3\n").unwrap();
+    git(&["add", "file1.txt"]);
+    git(&["commit", "-m", "Initial commit"]);
+    git(&["config", "user.name", "Bob"]);
+    fs::write(repo_path.join("file1.txt"), "line1\nline2 by bob\nline3\n").unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    git(&["config", "user.name", "Carol"]);
+    fs::write(repo_path.join("file1.txt"), "new1\nnew2\nline3\n").unwrap();
+    git(&["commit", "-am", "Third commit"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // Each removed line names whoever last changed it; added and context lines aren't blamed
+    assert!(output.lines().any(|l| l.starts_with("-line1  [blame ") && l.ends_with(" Alice]")));
+    assert!(output.lines().any(|l| l.starts_with("-line2 by bob  [blame ") && l.ends_with(" Bob]")));
+    assert!(output.contains("+new1\n+new2\n"));
+    assert_eq!(output.matches("[blame ").count(), 2);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_blame_identical_removed_lines_by_line_number() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_blame(Some(100));
+    
+    // Set up a repository where two identical lines written by different authors are removed
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.email", "test@example.com"]);
+    git(&["config", "user.name", "Alice"]);
+    fs::write(repo_path.join("file1.txt"), "dup\na\nb\nc\nd\ne\n").unwrap();
+    git(&["add", "file1.txt"]);
+    git(&["commit", "-m", "Initial commit"]);
+    git(&["config", "user.name", "Bob"]);
+    fs::write(repo_path.join("file1.txt"), "dup\na\nb\nc\nd\ndup\n").unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    git(&["config", "user.name", "Carol"]);
+    fs::write(repo_path.join("file1.txt"), "a\nb\nc\nd\n").unwrap();
+    git(&["commit", "-am", "Third commit"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    let output_file = output_file.to_str().unwrap();
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let all_lines = repodiff.process_diff("HEAD~1", "HEAD", output_file)
+        .map(|_| fs::read_to_string(output_file).unwrap());
+    
+    // Only the second removal is kept, so the first can't be mistaken for it
+    repodiff.set_range_in_file(Some(("file1.txt".to_string(), 4, 4)));
+    let second_line = repodiff.process_diff("HEAD~1", "HEAD", output_file)
+        .map(|_| fs::read_to_string(output_file).unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    let blamed = |output: &str| -> Vec<String> {
+        output.lines().filter(|l| l.starts_with("-dup  [blame ")).map(|l| l.rsplit(' ').next().unwrap().to_string()).collect()
+    };
+    assert_eq!(blamed(&all_lines.unwrap()), vec!["Alice]", "Bob]"]);
+    assert_eq!(blamed(&second_line.unwrap()), vec!["Bob]"]);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_with_unchanged_files() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_include_unchanged_files(true);
+    
+    // Set up a repository where one of several files in a directory changes
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::create_dir_all(repo_path.join("src")).unwrap();
+    fs::write(repo_path.join("src/changed.rs"), "line1\n").unwrap();
+    fs::write(repo_path.join("src/sibling.rs"), "line1\n").unwrap();
+    fs::write(repo_path.join("README.md"), "readme\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("src/changed.rs"), "line1_modified\n").unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // Only the sibling in the changed directory is listed
+    assert!(output.ends_with("# Unchanged files in changed directories:\nsrc/: sibling.rs"));
+    assert!(!output.contains("README.md"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_with_author() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_author(Some("Alice".to_string()));
+    
+    // Set up a repository where two authors change different files
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.email", "test@example.com"]);
+    git(&["config", "user.name", "Alice"]);
+    fs::write(repo_path.join("alice.txt"), "line1\n").unwrap();
+    fs::write(repo_path.join("bob.txt"), "line1\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("alice.txt"), "line1_alice\n").unwrap();
+    git(&["commit", "-am", "Alice's commit"]);
+    git(&["config", "user.name", "Bob"]);
+    fs::write(repo_path.join("bob.txt"), "line1_bob\n").unwrap();
+    git(&["commit", "-am", "Bob's commit"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~2", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    assert!(output.contains("diff --git a/alice.txt b/alice.txt"));
+    assert!(!output.contains("bob.txt"));
+}
+
+#[test]
+fn test_method_bodies_override_expands_changed_methods() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [{"file_pattern": "*.cs", "context_lines": 0, "include_method_body": false}]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let diff = r#"diff --git a/Calc.cs b/Calc.cs
+index 1234567..89abcde 100644
+--- a/Calc.cs
++++ b/Calc.cs
+@@ -1,9 +1,9 @@
+ public class Calc
+ {
+     public int Add(int a, int b)
+     {
+         var sum = a + b;
+-        return sum;
++        return sum + 0;
+     }
+ }
+"#;
+    
+    // Without the override only the changed lines are kept
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(!output.contains("var sum = a + b;"));
+    
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    repodiff.set_method_detail(Some(MethodDetail::Bodies));
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(output.contains("     public int Add(int a, int b)"));
+    assert!(output.contains("         var sum = a + b;"));
+    assert!(output.contains("+        return sum + 0;"));
+}
+
+#[test]
+fn test_process_ranges_rejects_output_dir() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_split_into_dir(true);
+    let ranges = vec![
+        ("HEAD~2".to_string(), "HEAD~1".to_string()),
+        ("HEAD~1".to_string(), "HEAD".to_string()),
+    ];
+    let output_dir = temp_dir.path().join("out");
+    
+    let error = repodiff.process_ranges(&ranges, output_dir.to_str().unwrap()).unwrap_err();
+    assert!(error.to_string().contains("can't be written to an output directory"));
+    assert!(!output_dir.exists());
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_ranges_combines_outputs() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    // Set up a repository with three commits touching different files
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("first.txt"), "one\n").unwrap();
+    fs::write(repo_path.join("second.txt"), "two\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("first.txt"), "one_modified\n").unwrap();
+    git(&["commit", "-am", "Change first"]);
+    fs::write(repo_path.join("second.txt"), "two_modified\n").unwrap();
+    git(&["commit", "-am", "Change second"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    let ranges = vec![
+        ("HEAD~2".to_string(), "HEAD~1".to_string()),
+        ("HEAD~1".to_string(), "HEAD".to_string()),
+    ];
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_ranges(&ranges, output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    let token_count = result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // One preamble, then each range's changes under its own separator
+    assert_eq!(output.matches("This file provides a guide").count(), 1);
+    let first = output.find("# Range: HEAD~2..HEAD~1\n").unwrap();
+    let second = output.find("# Range: HEAD~1..HEAD\n").unwrap();
+    assert!(first < output.find("+one_modified").unwrap());
+    assert!(output.find("+one_modified").unwrap() < second);
+    assert!(second < output.find("+two_modified").unwrap());
+    assert!(!output[second..].contains("first.txt"));
+    assert!(token_count > repodiff.get_content_token_count());
+    // The summary covers the files of every range, not just the last one
+    let paths: Vec<String> = repodiff.get_diff_result().files.into_iter().map(|file| file.path).collect();
+    assert_eq!(paths, vec!["first.txt".to_string(), "second.txt".to_string()]);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_patch_series_includes_root_commit() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().format = OutputFormat::PatchSeries;
+    
+    // Set up a repository with a second, unrelated history whose root isn't reachable from the first
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        let output = Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("notes.txt"), "zero\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    let first_history = git(&["rev-parse", "HEAD"]);
+    git(&["checkout", "--orphan", "other"]);
+    git(&["rm", "-rf", "."]);
+    fs::write(repo_path.join("other.txt"), "root\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Start other history"]);
+    fs::write(repo_path.join("other.txt"), "root\nmore\n").unwrap();
+    git(&["commit", "-am", "Extend other history"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff(&first_history, "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // The root commit is shown as adding its files
+    let sections: Vec<&str> = output.split("# Commit: ").skip(1).collect();
+    assert_eq!(sections.len(), 2);
+    assert!(sections[0].lines().next().unwrap().ends_with("Start other history"));
+    assert!(sections[0].lines().any(|line| line == "+root"));
+    assert!(sections[1].lines().next().unwrap().ends_with("Extend other history"));
+    assert!(sections[1].lines().any(|line| line == "+more"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_as_patch_series() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().format = OutputFormat::PatchSeries;
+    
+    // Set up a repository with three commits on top of the initial one
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("notes.txt"), "zero\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    for (content, message) in [("one", "Write one"), ("two", "Write two"), ("three", "Write three")] {
+        fs::write(repo_path.join("notes.txt"), format!("{}\n", content)).unwrap();
+        git(&["commit", "-am", message]);
+    }
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~3", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // One section per commit, oldest first, each holding only that commit's change
+    assert_eq!(output.matches("This file provides a guide").count(), 1);
+    assert_eq!(output.matches("# Commit: ").count(), 3);
+    let sections: Vec<&str> = output.split("# Commit: ").skip(1).collect();
+    for (section, (removed, added, message)) in sections.iter().zip([
+        ("zero", "one", "Write one"),
+        ("one", "two", "Write two"),
+        ("two", "three", "Write three"),
+    ]) {
+        assert!(section.lines().next().unwrap().ends_with(message));
+        assert!(section.lines().any(|line| line == format!("-{}", removed)));
+        assert!(section.lines().any(|line| line == format!("+{}", added)));
+    }
+}
+
+#[test]
+fn test_output_line_ending() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let mixed_diff = SAMPLE_DIFF.replace("+line6_modified\n", "+line6_modified\r\n");
+    
+    for (setting, crlf) in [("lf", false), ("crlf", true), ("native", cfg!(windows))] {
+        let config_path = temp_dir.path().join(format!("{}.json", setting));
+        let config_content = json!({
+            "tiktoken_model": "gpt-4o",
+            "filters": [{"file_pattern": "*", "context_lines": 1}],
+            "output_line_ending": setting
+        });
+        fs::write(&config_path, config_content.to_string()).unwrap();
+        let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+        
+        repodiff.process_diff_text(&mixed_diff, output_file.to_str().unwrap()).unwrap();
+        let bytes = fs::read(&output_file).unwrap();
+        
+        let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+        let crlfs = bytes.windows(2).filter(|w| w == b"\r\n").count();
+        assert!(newlines > 0);
+        assert_eq!(crlfs, if crlf { newlines } else { 0 }, "output_line_ending {}", setting);
+    }
+}
+
+#[test]
+fn test_process_diff_text_into_output_dir() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_split_into_dir(true);
+    let diff = format!(
+        "{}\n{}",
+        SAMPLE_DIFF.replace("file1.txt", "src/app/main.txt"),
+        SAMPLE_DIFF.replace("file1.txt", "docs/guide.txt")
+    );
+    let output_dir = temp_dir.path().join("out");
+    
+    let token_count = repodiff.process_diff_text(&diff, output_dir.to_str().unwrap()).unwrap();
+    
+    // Each file's diff is written under its own directory
+    let main = fs::read_to_string(output_dir.join("src/app/main.txt.diff")).unwrap();
+    let guide = fs::read_to_string(output_dir.join("docs/guide.txt.diff")).unwrap();
+    assert!(main.starts_with("diff --git a/src/app/main.txt b/src/app/main.txt"));
+    assert!(guide.starts_with("diff --git a/docs/guide.txt b/docs/guide.txt"));
+    assert!(fs::read_to_string(output_dir.join("instructions.txt")).unwrap().contains("This file provides a guide"));
+    
+    // The manifest lists both files with their token counts
+    let manifest: serde_json::Value = serde_json::from_str(
+        &fs::read_to_string(output_dir.join("manifest.json")).unwrap()
+    ).unwrap();
+    assert_eq!(manifest["total_tokens"], token_count);
+    assert_eq!(manifest["files"][0]["path"], "docs/guide.txt");
+    assert_eq!(manifest["files"][1]["path"], "src/app/main.txt");
+    assert!(manifest["files"][1]["tokens"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn test_output_hash_is_stable() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let diff = format!("{}\n{}", SAMPLE_DIFF, SAMPLE_DIFF.replace("file1.txt", "file2.txt"));
+    
+    let mut hashes = Vec::new();
+    for _ in 0..2 {
+        let mut repodiff = create_repodiff(&temp_dir);
+        repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+        hashes.push(repodiff.get_output_hash().to_string());
+    }
+    
+    assert_eq!(hashes[0], hashes[1]);
+    assert_eq!(hashes[0].len(), 64);
+    assert!(hashes[0].chars().all(|c| c.is_ascii_hexdigit()));
+    
+    // A different diff gives a different hash
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    assert_ne!(repodiff.get_output_hash(), hashes[0]);
+}
+
+#[test]
+fn test_output_hash_is_of_the_bytes_written() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let config: Config = serde_json::from_value(json!({
+        "tiktoken_model": "gpt-4o",
+        "output_line_ending": "crlf"
+    })).unwrap();
+    let mut repodiff = RepoDiff::from_config(&config).unwrap();
+    repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    
+    let written = fs::read(&output_file).unwrap();
+    let expected: String = Sha256::digest(&written).iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert!(written.windows(2).any(|pair| pair == b"\r\n"));
+    assert_eq!(repodiff.get_output_hash(), expected);
+}
+
+#[test]
+fn test_output_hash_covers_each_file_written() {
+    let temp_dir = tempdir().unwrap();
+    let diff = ["src/app/main.txt", "docs/guide.txt"]
+        .iter()
+        .map(|path| SAMPLE_DIFF.replace("file1.txt", path))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let hash_files = |dir: &Path, names: &[&str]| -> String {
+        let mut hasher = Sha256::new();
+        for name in names {
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update(fs::read(dir.join(name)).unwrap());
+            hasher.update([0]);
+        }
+        hasher.finalize().iter().map(|byte| format!("{:02x}", byte)).collect()
+    };
+    
+    // An output directory is hashed file by file, the instructions then the diffs in path order
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_split_into_dir(true);
+    let output_dir = temp_dir.path().join("out");
+    repodiff.process_diff_text(&diff, output_dir.to_str().unwrap()).unwrap();
+    let expected = hash_files(&output_dir, &["instructions.txt", "docs/guide.txt.diff", "src/app/main.txt.diff"]);
+    assert_eq!(repodiff.get_output_hash(), expected);
+    
+    // Part files are hashed in order
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().include_instructions = false;
+    repodiff.set_chunk_tokens(Some(1));
+    let output_file = temp_dir.path().join("output.txt");
+    repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let expected = hash_files(temp_dir.path(), &["output.part1.txt", "output.part2.txt"]);
+    assert_eq!(repodiff.get_output_hash(), expected);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_against_working_tree() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    // Set up a repository with a committed change and an uncommitted one
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("committed.txt"), "one\n").unwrap();
+    fs::write(repo_path.join("uncommitted.txt"), "two\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("committed.txt"), "one_committed\n").unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    fs::write(repo_path.join("uncommitted.txt"), "two_uncommitted\n").unwrap();
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff_against_working_tree("HEAD~1", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    assert!(output.contains("+one_committed"));
+    assert!(output.contains("+two_uncommitted"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_streamed_commit_range_matches_buffered_output() {
+    let temp_dir = tempdir().unwrap();
+    
+    // Set up a repository with changes in several directories, a binary file and a rename
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    let lines = |changed: &str| (1..=20).map(|i| if i == 10 { changed.to_string() } else { format!("line{}", i) }).collect::<Vec<_>>().join("\n");
+    fs::create_dir_all(repo_path.join("src")).unwrap();
+    fs::create_dir_all(repo_path.join("docs")).unwrap();
+    fs::write(repo_path.join("src/a.txt"), lines("a")).unwrap();
+    fs::write(repo_path.join("src/b.txt"), lines("b")).unwrap();
+    fs::write(repo_path.join("docs/old.txt"), lines("old")).unwrap();
+    fs::write(repo_path.join("logo.png"), [0u8, 1, 2]).unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("src/a.txt"), lines("a changed")).unwrap();
+    fs::write(repo_path.join("src/b.txt"), lines("b changed")).unwrap();
+    fs::write(repo_path.join("logo.png"), [3u8, 4, 5]).unwrap();
+    git(&["mv", "docs/old.txt", "docs/new.txt"]);
+    git(&["commit", "-am", "Second commit"]);
+    
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    // A token budget needs every file first, so it turns streaming off without changing the output
+    let mut outputs = Vec::new();
+    for max_tokens in [None, Some(1_000_000)] {
+        let mut repodiff = create_repodiff(&temp_dir);
+        repodiff.output_options_mut().group_by_dir = true;
+        repodiff.output_options_mut().label_changes = true;
+        repodiff.set_max_tokens(max_tokens);
+        let output_file = temp_dir.path().join("output.txt");
+        let result = repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap());
+        outputs.push(result.map(|_| (fs::read_to_string(&output_file).unwrap(), repodiff.get_output_hash().to_string())));
+    }
+    
+    // Files git lists out of path order are collected instead, keeping the output the same
+    fs::write(repo_path.join(".git/order.txt"), "src/b.txt\n*\n").unwrap();
+    git(&["config", "diff.orderFile", ".git/order.txt"]);
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().group_by_dir = true;
+    repodiff.output_options_mut().label_changes = true;
+    let output_file = temp_dir.path().join("output.txt");
+    let result = repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap());
+    outputs.push(result.map(|_| (fs::read_to_string(&output_file).unwrap(), repodiff.get_output_hash().to_string())));
+    
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    let streamed = outputs.remove(0).unwrap();
+    let buffered = outputs.remove(0).unwrap();
+    let reordered = outputs.remove(0).unwrap();
+    assert_eq!(streamed, buffered);
+    assert_eq!(reordered, buffered);
+    assert!(streamed.0.contains("[Binary]"));
+    assert!(streamed.0.contains("+b changed"));
+    assert!(streamed.0.contains("# Directory: src/"));
+}
+
+#[test]
+fn test_signature_map_lists_changed_methods() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().format = OutputFormat::SignatureMap;
+    let diff = r#"diff --git a/Calc.cs b/Calc.cs
+index 1234567..89abcde 100644
+--- a/Calc.cs
++++ b/Calc.cs
+@@ -1,13 +1,13 @@
+ public class Calc
+ {
+     public int Add(int a, int b)
+     {
+-        return a + b;
++        return checked(a + b);
+     }
+ 
+     public int Negate(int a)
+     {
+         return -a;
+     }
+ }
+diff --git a/notes.txt b/notes.txt
+index 1234567..89abcde 100644
+--- a/notes.txt
++++ b/notes.txt
+@@ -1 +1 @@
+-old
++new
+"#;
+    
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    
+    // Only the changed method's signature is listed, with no bodies or preamble
+    assert_eq!(output, "Calc.cs\n  public int Add(int a, int b)\nnotes.txt");
+}
+
+#[test]
+fn test_process_raw_diff_with_only_paths() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_only_paths(Some(HashSet::from([
+        "src/owned.txt".to_string(),
+        "src/not_in_diff.txt".to_string(),
+    ])));
+    let diff = format!(
+        "{}\n{}",
+        SAMPLE_DIFF.replace("file1.txt", "src/owned.txt"),
+        SAMPLE_DIFF.replace("file1.txt", "src/other.txt")
+    );
+    
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    assert!(output.contains("diff --git a/src/owned.txt b/src/owned.txt"));
+    assert!(!output.contains("src/other.txt"));
+    assert!(!output.contains("src/not_in_diff.txt"));
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_against_working_tree_with_untracked_files() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_include_untracked(true);
+    
+    // Set up a repository with an untracked and an ignored file
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join(".gitignore"), "*.log\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::create_dir_all(repo_path.join("src")).unwrap();
+    fs::write(repo_path.join("src/new.txt"), "first\nsecond\n").unwrap();
+    fs::write(repo_path.join("debug.log"), "ignored\n").unwrap();
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff_against_working_tree("HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    assert!(output.contains("diff --git a/src/new.txt b/src/new.txt\n--- a/src/new.txt\n+++ b/src/new.txt\n+first\n+second"));
+    assert!(!output.contains("debug.log"));
+    assert_eq!(repodiff.get_diff_result().files_added, 1);
+}
+
+#[cfg(unix)]
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_untracked_links_executables_and_unterminated_files() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_include_untracked(true);
+    repodiff.set_show_mode_changes(true);
+    
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("README.md"), "readme\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::create_dir_all(repo_path.join("sub")).unwrap();
+    fs::write(repo_path.join("sub/kept.txt"), "kept\n").unwrap();
+    symlink("missing.txt", repo_path.join("dangling")).unwrap();
+    symlink("sub", repo_path.join("dir_link")).unwrap();
+    fs::write(repo_path.join("run.sh"), "#!/bin/sh\n").unwrap();
+    fs::set_permissions(repo_path.join("run.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(repo_path.join("unterminated.txt"), "first\nlast").unwrap();
+    
+    let output_file = temp_dir.path().join("output.txt");
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    let result = repodiff.process_diff_against_working_tree("HEAD", output_file.to_str().unwrap());
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    // Links are added with their target, whether it exists or is a directory
+    assert!(output.contains("symlink dangling now points to missing.txt\ndiff --git a/dangling b/dangling"));
+    assert!(output.contains("symlink dir_link now points to sub\ndiff --git a/dir_link b/dir_link"));
+    assert!(output.contains("+#!/bin/sh"));
+    assert!(output.contains("+last\n\\ No newline at end of file"));
+    assert_eq!(repodiff.get_diff_result().files_added, 5);
+}
+
+#[test]
+fn test_process_diff_text_anonymizes_paths() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_anonymize_paths(true);
+    
+    let diff = format!("{}\n{}", SAMPLE_DIFF, "diff --git a/src/Internal/Old.cs b/src/Internal/New.cs
+similarity index 90%
+rename from src/Internal/Old.cs
+rename to src/Internal/New.cs
+--- a/src/Internal/Old.cs
++++ b/src/Internal/New.cs
+@@ -1,3 +1,3 @@
+ line1
+-line2
++line2_modified
+ line3");
+    let output_file = temp_dir.path().join("output.txt");
+    repodiff.process_diff_text(&diff, output_file.to_str().unwrap()).unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    // Names follow path order, and both sides of the rename are anonymized
+    assert!(output.contains("diff --git a/file_1.txt b/file_1.txt\n--- a/file_1.txt\n+++ b/file_1.txt"));
+    assert!(output.contains("diff --git a/file_3.cs b/file_2.cs\nsimilarity index 90%\nrename from file_3.cs\nrename to file_2.cs"));
+    assert!(!output.contains("Internal"));
+    assert!(!output.contains("file1.txt"));
+    
+    // The sidecar maps each original path to its name
+    let sidecar = fs::read_to_string(RepoDiff::path_map_file_name(output_file.to_str().unwrap(), false)).unwrap();
+    let mapping: serde_json::Value = serde_json::from_str(&sidecar).unwrap();
+    assert_eq!(mapping, json!({
+        "file1.txt": "file_1.txt",
+        "src/Internal/New.cs": "file_2.cs",
+        "src/Internal/Old.cs": "file_3.cs"
+    }));
+    assert_eq!(repodiff.get_path_map().len(), 3);
+}
+
+#[test]
+fn test_method_query_emits_only_that_method() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_method_query(Some(("src/Orders.cs".to_string(), "Save".to_string())));
+    
+    let diff = "diff --git a/src/Orders.cs b/src/Orders.cs
+--- a/src/Orders.cs
++++ b/src/Orders.cs
+@@ -1,12 +1,12 @@
+ public class Orders {
+     public void Save() {
+         Validate();
+-        store.Write(order);
++        store.Write(order, true);
+         Log();
+     }
+ 
+     public void Load() {
+-        store.Read();
++        store.Read(true);
+     }
+ }";
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    
+    // The whole body of the queried method is shown, even lines outside the trimmed context
+    assert!(output.contains("     public void Save() {\n         Validate();\n-        store.Write(order);\n+        store.Write(order, true);\n         Log();\n     }"));
+    assert!(!output.contains("Load"));
+    assert!(!output.contains("public class Orders"));
+    
+    // Asking for a method that doesn't exist is an error
+    repodiff.set_method_query(Some(("src/Orders.cs".to_string(), "Delete".to_string())));
+    assert!(repodiff.process_raw_diff(diff).is_err());
+}
+
+#[test]
+fn test_instructions_file_holds_preamble() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    let instructions_file = temp_dir.path().join("prompt").join("instructions.txt");
+    repodiff.set_instructions_file(Some(instructions_file.to_string_lossy().to_string()));
+    
+    let output_file = temp_dir.path().join("output.txt");
+    repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    
+    // The preamble goes to the sidecar and the output starts with the diff itself
+    let instructions = fs::read_to_string(&instructions_file).unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    assert!(instructions.contains("This file provides a guide to understanding the diff output generated by RepoDiff"));
+    assert!(!output.contains("This file provides a guide"));
+    assert!(output.starts_with("diff --git a/file1.txt b/file1.txt"));
+    assert_eq!(repodiff.get_content_token_count(), repodiff.get_diff_result().total_tokens);
+}
+
+#[test]
+fn test_no_instructions_file_keeps_preamble_setting() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().include_instructions = false;
+    repodiff.set_instructions_file(None);
+    
+    // Not giving a file doesn't bring back a preamble that was turned off
+    let output = repodiff.process_raw_diff(SAMPLE_DIFF).unwrap();
+    assert!(!output.contains("This file provides a guide"));
+}
+
+#[test]
+fn test_trailing_index_lists_changed_files_and_methods() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_trailing_index(true);
+    
+    let diff = format!("{}\n{}", SAMPLE_DIFF, "diff --git a/src/Orders.cs b/src/Orders.cs
+--- a/src/Orders.cs
++++ b/src/Orders.cs
+@@ -1,11 +1,11 @@
+ public class Orders {
+     public void Save() {
+-        store.Write(order);
++        store.Write(order, true);
+     }
+ 
+     public void Load() {
+         store.Read();
+     }
+ }");
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    // The index comes after the diff content, with only the changed method listed
+    let index = output.find("# Changes index").unwrap();
+    assert!(index > output.find("+        store.Write(order, true);").unwrap());
+    assert!(output[index..].starts_with("# Changes index
+file1.txt [Modified] +1 -1
+src/Orders.cs [Modified] +1 -1
+  public void Save() {"));
+    assert!(!output[index..].contains("Load"));
+}
+
+#[test]
+fn test_signature_changes_summary() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_signature_changes(true);
+    
+    let diff = "diff --git a/src/Orders.cs b/src/Orders.cs
+--- a/src/Orders.cs
++++ b/src/Orders.cs
+@@ -1,9 +1,9 @@
+ public class Orders {
+-    public void Save(Order order) {
++    public void Save(Order order, bool force) {
+         store.Write(order);
+     }
+ 
+     public void Load() {
+-        store.Read();
++        store.Read(true);
+     }
+ }";
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    
+    // Only the signature change is listed; the body-only change is counted
+    let summary = output.find("# Signature changes").unwrap();
+    assert_eq!(&output[summary..], "# Signature changes
+src/Orders.cs
+  - public void Save(Order order) {
+  + public void Save(Order order, bool force) {
+(1 changed method with body-only changes)");
+}
+
+#[test]
+fn test_cache_friendly_output_has_stable_prefix() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().cache_friendly = true;
+    
+    // Two runs over the same files with different changes
+    let first = repodiff.process_raw_diff(SAMPLE_DIFF).unwrap();
+    let second = repodiff.process_raw_diff(&SAMPLE_DIFF.replace("line6_modified", "line6_changed_again")).unwrap();
+    
+    // Everything before the first diff is static: the preamble, then the file list
+    let prefix = |output: &str| output[..output.find("diff --git a/file1.txt").unwrap()].to_string();
+    assert_eq!(prefix(&first), prefix(&second));
+    assert!(prefix(&first).starts_with("This file provides a guide"));
+    assert!(prefix(&first).ends_with("# Files\nfile1.txt\n\n"));
+    assert_ne!(first, second);
+}
+
+#[test]
+fn test_file_ids_are_stable_across_runs() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().file_ids = true;
+    
+    let first_output = repodiff.process_raw_diff(SAMPLE_DIFF).unwrap();
+    let first_id = repodiff.get_diff_result().files[0].id.clone();
+    
+    // A different change to the same file keeps its identifier
+    let second_output = repodiff.process_raw_diff(&SAMPLE_DIFF.replace("line6_modified", "line6_changed")).unwrap();
+    assert_eq!(repodiff.get_diff_result().files[0].id, first_id);
+    assert_eq!(first_id.len(), 12);
+    
+    // The text output carries the same identifier just before the file's section
+    let marker = format!("// FILE-ID: {}\ndiff --git a/file1.txt b/file1.txt", first_id);
+    assert!(first_output.contains(&marker));
+    assert!(second_output.contains(&marker));
+    
+    // Other paths get other identifiers
+    repodiff.process_raw_diff(&SAMPLE_DIFF.replace("file1.txt", "file2.txt")).unwrap();
+    assert_ne!(repodiff.get_diff_result().files[0].id, first_id);
+}
+
+#[test]
+fn test_show_mode_changes_labels_symlinks_and_permissions() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    let diff = "diff --git a/current b/current
+index 1234567..89abcde 120000
+--- a/current
++++ b/current
+@@ -1 +1 @@
+-releases/v1
+\\ No newline at end of file
++releases/v2
+\\ No newline at end of file
+diff --git a/deploy.sh b/deploy.sh
+old mode 100644
+new mode 100755
+";
+    
+    // Without the option, nothing beyond the diff itself is shown
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(!output.contains("symlink current"));
+    assert!(!output.contains("mode deploy.sh"));
+    
+    repodiff.set_show_mode_changes(true);
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(output.contains("symlink current now points to releases/v2\ndiff --git a/current b/current"));
+    assert!(output.contains("mode deploy.sh changed from 100644 to 100755\ndiff --git a/deploy.sh b/deploy.sh"));
+}
+
+#[test]
+fn test_parallel_token_counts_match_single_threaded_counts() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    let diff: String = (0..200)
+        .map(|i| format!(
+            "diff --git a/src/file{i}.txt b/src/file{i}.txt\n--- a/src/file{i}.txt\n+++ b/src/file{i}.txt\n@@ -1,3 +1,3 @@\n line1\n-old line {i}\n+{} new line\n line3\n",
+            "word ".repeat(i % 17)
+        ))
+        .collect();
+    repodiff.process_raw_diff(&diff).unwrap();
+    
+    let parallel = repodiff.get_diff_result();
+    let single_threaded = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap()
+        .install(|| repodiff.get_diff_result());
+    
+    assert_eq!(parallel.files.len(), 200);
+    let parallel_tokens: Vec<usize> = parallel.files.iter().map(|file| file.tokens).collect();
+    let single_threaded_tokens: Vec<usize> = single_threaded.files.iter().map(|file| file.tokens).collect();
+    assert_eq!(parallel_tokens, single_threaded_tokens);
+    assert_eq!(parallel.total_tokens, single_threaded.total_tokens);
+}
+
+#[test]
+fn test_directory_config_overrides_root_filters() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_directory_configs(Some(DirectoryConfigs::new(temp_dir.path(), "config.json")));
+    
+    // The nearest directory config wins, and its patterns are relative to its directory
+    fs::create_dir_all(temp_dir.path().join("services/billing/api")).unwrap();
+    fs::write(
+        temp_dir.path().join("services/config.json"),
+        json!({"filters": [{"file_pattern": "*", "context_lines": 0}]}).to_string(),
+    ).unwrap();
+    fs::write(
+        temp_dir.path().join("services/billing/config.json"),
+        json!({"filters": [{"file_pattern": "api/*.txt", "context_lines": 3}]}).to_string(),
+    ).unwrap();
+    
+    let diff = [
+        SAMPLE_DIFF.to_string(),
+        SAMPLE_DIFF.replace("file1.txt", "services/other.txt"),
+        SAMPLE_DIFF.replace("file1.txt", "services/billing/api/file.txt"),
+    ].join("\n");
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    let context_lines = |path: &str| -> usize {
+        let header = format!("diff --git a/{} b/{}\n", path, path);
+        let section = output.split_once(&header).unwrap().1;
+        let section = section.split("diff --git").next().unwrap();
+        section.lines().filter(|line| line.starts_with(' ')).count()
+    };
+    
+    assert_eq!(context_lines("file1.txt"), 2);
+    assert_eq!(context_lines("services/other.txt"), 0);
+    assert_eq!(context_lines("services/billing/api/file.txt"), 6);
+}
+
+#[test]
+fn test_group_by_symbol_collects_same_named_methods() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().include_instructions = false;
+    repodiff.set_group_by_symbol(true);
+    
+    let diff = "diff --git a/src/Orders.cs b/src/Orders.cs
+--- a/src/Orders.cs
++++ b/src/Orders.cs
+@@ -1,11 +1,11 @@
+ public class Orders {
+     public void Save(Order order) {
+-        store.Write(order);
++        store.Write(order, true);
+     }
+ 
+     public void Load() {
+         var x = 1;
+         var y = 2;
+-        store.Read();
++        store.Read(true);
+     }
+ }
+diff --git a/src/Billing.cs b/src/Billing.cs
+--- a/src/Billing.cs
++++ b/src/Billing.cs
+@@ -1,5 +1,5 @@
+ public class Billing {
+     public void Save(Invoice invoice) {
+-        ledger.Write(invoice);
++        ledger.Write(invoice, true);
+     }
+ }
+diff --git a/notes.txt b/notes.txt
+--- a/notes.txt
++++ b/notes.txt
+@@ -1 +1 @@
+-old
++new";
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    
+    // Both Save changes share a group, ahead of Load's in name order, and non-C# files come last
+    let load = output.find("# Symbol: Load").unwrap();
+    let save = output.find("# Symbol: Save").unwrap();
+    let other = output.find("# Other changes").unwrap();
+    assert!(load < save && save < other);
+    
+    let save_group = &output[save..other];
+    assert!(save_group.contains("diff --git a/src/Billing.cs b/src/Billing.cs"));
+    assert!(save_group.contains("diff --git a/src/Orders.cs b/src/Orders.cs"));
+    assert!(save_group.contains("+        store.Write(order, true);"));
+    assert!(!save_group.contains("store.Read(true)"));
+    
+    let load_group = &output[load..save];
+    assert!(load_group.contains("+        store.Read(true);"));
+    assert!(!load_group.contains("Billing.cs"));
+    assert!(output[other..].contains("diff --git a/notes.txt b/notes.txt"));
+}
+
+#[test]
+fn test_opaque_files_hide_their_content() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [
+            {"file_pattern": "secrets.json", "opaque": true},
+            {"file_pattern": "*", "context_lines": 1}
+        ]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    repodiff.set_trailing_index(true);
+    
+    let diff = "diff --git a/secrets.json b/secrets.json
+--- a/secrets.json
++++ b/secrets.json
+@@ -1,3 +1,3 @@
+ {
+-  \"api_key\": \"old-secret\"
++  \"api_key\": \"new-secret\"
+ }
+".to_string() + SAMPLE_DIFF;
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    // The file is noted as changed, without headers or lines
+    assert!(output.contains("\nsecrets.json changed (content hidden)\n"));
+    assert!(!output.contains("secret\""));
+    assert!(!output.contains("api_key"));
+    assert!(!output.contains("diff --git a/secrets.json"));
+    // Other files are unaffected
+    assert!(output.contains(" line5\n-line6\n+line6_modified\n line7"));
+}
+
+#[test]
+fn test_opaque_files_stay_hidden_in_raw_and_method_output() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [
+            {"file_pattern": "Secrets.cs", "opaque": true},
+            {"file_pattern": "*", "context_lines": 1}
+        ]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    
+    let diff = "diff --git a/Secrets.cs b/Secrets.cs
+--- a/Secrets.cs
++++ b/Secrets.cs
+@@ -1,5 +1,5 @@
+ public class Secrets {
+     public string Key() {
+-        return \"old-secret\";
++        return \"new-secret\";
+     }
+ }
+";
+    
+    // Raw mode ignores the filters, but not the opaque ones
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    repodiff.set_raw(true);
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(output.contains("Secrets.cs changed (content hidden)"));
+    assert!(!output.contains("secret\""));
+    
+    // A method query doesn't reveal the method either
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    repodiff.set_method_query(Some(("Secrets.cs".to_string(), "Key".to_string())));
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(output.contains("Secrets.cs changed (content hidden)"));
+    assert!(!output.contains("secret\""));
+}
+
+#[test]
+fn test_estimated_cost_uses_model_price() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    let config_content = json!({
+        "tiktoken_model": "gpt-4o",
+        "model_pricing": {"gpt-4o": 0.0025, "gpt-4": 0.03},
+        "filters": [{"file_pattern": "*", "context_lines": 1}]
+    });
+    fs::write(&config_path, config_content.to_string()).unwrap();
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    
+    let output_file = temp_dir.path().join("output.txt");
+    let tokens = repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    let expected = tokens as f64 / 1000.0 * 0.0025;
+    assert!((repodiff.get_estimated_cost().unwrap() - expected).abs() < 1e-12);
+    assert_eq!(repodiff.get_diff_result().estimated_cost, repodiff.get_estimated_cost());
+    
+    // Without a price for the model there is no estimate
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.process_diff_text(SAMPLE_DIFF, output_file.to_str().unwrap()).unwrap();
+    assert_eq!(repodiff.get_estimated_cost(), None);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_check_whitespace_appends_errors() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_check_whitespace(true);
+    
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::write(repo_path.join("notes.txt"), "one\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("notes.txt"), "one\ntwo  \n").unwrap();
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff_against_working_tree("HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    
+    assert!(output.ends_with("# Whitespace errors\nnotes.txt:2: trailing whitespace."));
+    assert_eq!(repodiff.get_warnings(), ["git diff --check found 1 whitespace error"]);
+}
+
+#[test]
+fn test_auto_tunes_filters_for_predominant_language() {
+    let temp_dir = tempdir().unwrap();
+    let diff = r#"diff --git a/Calc.cs b/Calc.cs
+index 1234567..89abcde 100644
+--- a/Calc.cs
++++ b/Calc.cs
+@@ -1,14 +1,14 @@
+ public class Calc
+ {
+     public int Add(int a, int b)
+     {
+         var first = a;
+         var second = b;
+         var third = 0;
+         var fourth = 0;
+         var sum = first + second;
+-        return sum;
++        return sum + third + fourth;
+     }
+ }
+diff --git a/Stats.cs b/Stats.cs
+index 1234567..89abcde 100644
+--- a/Stats.cs
++++ b/Stats.cs
+@@ -1,3 +1,3 @@
+ public class Stats
+ {
+-    public int Count;
++    public long Count;
+diff --git a/notes.txt b/notes.txt
+index 1234567..89abcde 100644
+--- a/notes.txt
++++ b/notes.txt
+@@ -1,1 +1,1 @@
+-old
++new
+"#;
+    
+    // No config file, so the filters are tuned for C# and whole methods are kept
+    let config_path = temp_dir.path().join("missing.json");
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(output.contains("     public int Add(int a, int b)"));
+    assert!(output.contains("         var first = a;"));
+    assert!(output.contains("+        return sum + third + fourth;"));
+    
+    // Auto-tuning can be turned off, leaving the default context
+    let config_path = temp_dir.path().join("config.json");
+    fs::write(&config_path, json!({"tiktoken_model": "gpt-4o", "auto_tune_filters": false}).to_string()).unwrap();
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    assert!(!output.contains("         var first = a;"));
+    assert!(output.contains("+        return sum + third + fourth;"));
+}
+
+#[test]
+fn test_range_in_file_keeps_overlapping_hunks() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.set_range_in_file(Some(("Foo.cs".to_string(), 100, 200)));
+    
+    let diff = "diff --git a/Foo.cs b/Foo.cs
+--- a/Foo.cs
++++ b/Foo.cs
+@@ -10,3 +10,3 @@
+ int a = 1;
+-int b = 2;
++int b = 3;
+@@ -150,3 +150,3 @@
+ int c = 1;
+-int d = 2;
++int d = 3;
+".to_string() + SAMPLE_DIFF;
+    let output = repodiff.process_raw_diff(&diff).unwrap();
+    
+    // Only the hunk within lines 100-200 is kept, and other files are unaffected
+    assert!(output.contains("+int d = 3;"));
+    assert!(!output.contains("+int b = 3;"));
+    assert!(output.contains("+line6_modified"));
+}
+
+#[test]
+fn test_range_in_file_keeps_changes_within_a_whole_file_hunk() {
+    let temp_dir = tempdir().unwrap();
+    let config_path = temp_dir.path().join("config.json");
+    fs::write(&config_path, json!({
+        "tiktoken_model": "gpt-4o",
+        "filters": [{"file_pattern": "*.cs", "context_lines": 500, "include_method_body": true}]
+    }).to_string()).unwrap();
+    let mut repodiff = RepoDiff::new(config_path.to_str().unwrap()).unwrap();
+    repodiff.set_range_in_file(Some(("Calc.cs".to_string(), 6, 8)));
+    
+    // Git gives whole-method rules the whole file, in one hunk
+    let diff = "diff --git a/Calc.cs b/Calc.cs
+--- a/Calc.cs
++++ b/Calc.cs
+@@ -1,9 +1,9 @@
+ public class Calc {
+     public int Add(int a, int b) {
+-        return a + b;
++        return checked(a + b);
+     }
+ 
+     public int Negate(int a) {
+-        return -a;
++        return checked(-a);
+     }
+ }
+";
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    
+    // Only the change within lines 6-8 is shown as a change
+    assert!(output.contains("+        return checked(-a);"));
+    assert!(!output.contains("+        return checked(a + b);"));
+    assert!(!output.contains("-        return a + b;"));
+    assert!(repodiff.get_warnings().is_empty());
+    
+    // A file that isn't changed, or not within the range, is warned about
+    repodiff.set_range_in_file(Some(("Other.cs".to_string(), 1, 5)));
+    repodiff.process_raw_diff(diff).unwrap();
+    assert_eq!(repodiff.get_warnings(), vec!["Other.cs is not changed in this diff".to_string()]);
+    repodiff.set_range_in_file(Some(("Calc.cs".to_string(), 8, 9)));
+    repodiff.process_raw_diff(diff).unwrap();
+    assert_eq!(repodiff.get_warnings(), vec!["Calc.cs has no changes in lines 8-9".to_string()]);
+}
+
+#[test]
+fn test_symbol_index_lists_changed_symbols_across_languages() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    repodiff.output_options_mut().format = OutputFormat::SymbolIndex;
+    let diff = r#"diff --git a/Calc.cs b/Calc.cs
+index 1234567..89abcde 100644
+--- a/Calc.cs
++++ b/Calc.cs
+@@ -1,13 +1,13 @@
+ public class Calc
+ {
+     public int Add(int a, int b)
+     {
+-        return a + b;
++        return checked(a + b);
+     }
+ 
+     public int Negate(int a)
+     {
+         return -a;
+     }
+ }
+diff --git a/stats.py b/stats.py
+index 1234567..89abcde 100644
+--- a/stats.py
++++ b/stats.py
+@@ -1,12 +1,12 @@
+ import math
+ 
+ def unchanged(values):
+     return len(values)
+ 
+ @cache
+ def mean(
+     values,
+ ):
+-    return sum(values) / len(values)
++    return math.fsum(values) / len(values)
+ 
+ class Summary:
+diff --git a/notes.txt b/notes.txt
+index 1234567..89abcde 100644
+--- a/notes.txt
++++ b/notes.txt
+@@ -1 +1 @@
+-old
++new
+"#;
+    
+    let output = repodiff.process_raw_diff(diff).unwrap();
+    
+    // Each file lists its changed symbols, whatever the language, and others are listed alone
+    assert_eq!(output, "Calc.cs\n  Add\nnotes.txt\nstats.py\n  mean");
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_directory_config_context_is_requested_from_git() {
+    // Set up a repository with a directory config that wants more context than the defaults give
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    fs::create_dir_all(repo_path.join("services/api")).unwrap();
+    fs::write(
+        repo_path.join("services/api/dir-test-config.json"),
+        json!({"filters": [{"file_pattern": "*.txt", "context_lines": 10}]}).to_string(),
+    ).unwrap();
+    let lines: Vec<String> = (1..=40).map(|i| format!("line{}", i)).collect();
+    fs::write(repo_path.join("services/api/file.txt"), lines.join("\n") + "\n").unwrap();
+    git(&["add", "."]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(
+        repo_path.join("services/api/file.txt"),
+        lines.join("\n").replace("line20\n", "line20_modified\n") + "\n",
+    ).unwrap();
+    git(&["commit", "-am", "Second commit"]);
+    
+    // Run from a subdirectory, so the directory configs must be found from the repository root
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path.join("services")).unwrap();
+    
+    let result = RepoDiff::new("dir-test-config.json").and_then(|mut repodiff| {
+        repodiff.output_options_mut().include_instructions = false;
+        repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap())
+    });
+    
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    let output = fs::read_to_string(&output_file).unwrap();
+    let context_lines = output.lines().filter(|line| line.starts_with(" line")).count();
+    assert_eq!(context_lines, 20);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_warns_on_reformatting() {
+    let temp_dir = tempdir().unwrap();
+    let mut repodiff = create_repodiff(&temp_dir);
+    
+    // Twelve lines reindented and one real change, which git's diff hides since it ignores whitespace
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    let lines = |indent: &str, total: usize| -> String {
+        (0..12).map(|i| format!("{}value_{} = {}\n", indent, i, i)).collect::<String>() + &format!("total = {}\n", total)
+    };
+    fs::write(repo_path.join("app.py"), lines("  ", 0)).unwrap();
+    git(&["add", "app.py"]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("app.py"), lines("    ", 1)).unwrap();
+    git(&["commit", "-am", "Reindent"]);
+    
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let result = repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap());
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    result.unwrap();
+    assert_eq!(repodiff.get_warnings(), vec![
+        "This diff appears to be mostly reformatting: 24 of 26 changed lines only change whitespace".to_string(),
+    ]);
+}
+
+#[test]
+#[ignore] // Ignore by default as it requires git to be installed
+fn test_process_diff_counts_whitespace_changes_with_git_only_when_asked() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    
+    // The same reindented file, diffed without directory configs, so nothing else needs git's count
+    let repo_dir = tempdir().unwrap();
+    let repo_path = repo_dir.path();
+    let git = |args: &[&str]| {
+        Command::new("git").args(args).current_dir(repo_path).output().expect("Failed to run git");
+    };
+    git(&["init"]);
+    git(&["config", "user.name", "Test User"]);
+    git(&["config", "user.email", "test@example.com"]);
+    let lines = |indent: &str, total: usize| -> String {
+        (0..12).map(|i| format!("{}value_{} = {}\n", indent, i, i)).collect::<String>() + &format!("total = {}\n", total)
+    };
+    fs::write(repo_path.join("app.py"), lines("  ", 0)).unwrap();
+    git(&["add", "app.py"]);
+    git(&["commit", "-m", "Initial commit"]);
+    fs::write(repo_path.join("app.py"), lines("    ", 1)).unwrap();
+    git(&["commit", "-am", "Reindent"]);
+    
+    let warnings = |count_whitespace_changes: bool| {
+        let config: Config = serde_json::from_value(json!({
+            "tiktoken_model": "gpt-4o",
+            "count_whitespace_changes": count_whitespace_changes,
+            "filters": [{"file_pattern": "*", "context_lines": 1}]
+        })).unwrap();
+        let mut repodiff = RepoDiff::from_config(&config).unwrap();
+        repodiff.process_diff("HEAD~1", "HEAD", output_file.to_str().unwrap()).unwrap();
+        repodiff.get_warnings().to_vec()
+    };
+    
+    // Change to the repo directory for the test
+    let current_dir = std::env::current_dir().unwrap();
+    std::env::set_current_dir(repo_path).unwrap();
+    
+    let (counted_from_hunks, counted_by_git) = (warnings(false), warnings(true));
+    
+    // Change back to the original directory
+    std::env::set_current_dir(current_dir).unwrap();
+    
+    // The hunks only show the real change, while git also counts the reindented lines
+    assert!(counted_from_hunks.is_empty());
+    assert_eq!(counted_by_git, vec![
+        "This diff appears to be mostly reformatting: 24 of 26 changed lines only change whitespace".to_string(),
+    ]);
+}
+
+#[test]
+fn test_configured_unified_context_is_raised_to_what_filters_need() {
+    let temp_dir = tempdir().unwrap();
+    let output_file = temp_dir.path().join("output.txt");
+    let output_file = output_file.to_str().unwrap();
+    let resolved_unified_context = |config: serde_json::Value| {
+        let config: Config = serde_json::from_value(config).unwrap();
+        let mut repodiff = RepoDiff::from_config(&config).unwrap();
+        repodiff.set_write_config(true);
+        repodiff.process_diff_text(SAMPLE_DIFF, output_file).unwrap();
+        let written = fs::read_to_string(RepoDiff::resolved_config_file_name(output_file, false)).unwrap();
+        serde_json::from_str::<serde_json::Value>(&written).unwrap()["git_unified_context"].clone()
+    };
+    
+    // Too little context for the filters' context lines is raised, with git's buffer
+    let filters = json!([{"file_pattern": "*", "context_lines": 10}]);
+    assert_eq!(
+        resolved_unified_context(json!({"tiktoken_model": "gpt-4o", "filters": filters, "git_unified_context": 1})),
+        13
+    );
+    
+    // More context than needed is kept
+    assert_eq!(
+        resolved_unified_context(json!({"tiktoken_model": "gpt-4o", "filters": filters, "git_unified_context": 50})),
+        50
+    );
+}
@@ -1,6 +1,7 @@
 use repodiff::utils::token_counter::TokenCounter;
 
 #[test]
+#[allow(clippy::manual_range_contains)]
 fn test_count_tokens() {
     // Create the TokenCounter with the default model
     let token_counter = TokenCounter::new("gpt-4o").unwrap();